@@ -1,7 +1,7 @@
 use anyhow::Result;
 use tokio::process::Command;
 
-use shared::log;
+use shared::{log, ws::types::MessageSeverity};
 
 // We have created a separate gui helper because on linux
 // at session close the X windows (xrdp for example) destroys de X server.
@@ -16,16 +16,29 @@ const GUI_HELPER_EXE: &str = if cfg!(windows) {
 };
 const SIGNAL_FILE: &str = "uds-actor-gui-close-all";
 
+// gui-helper exit codes
+const EXIT_ACKNOWLEDGED: i32 = 0;
+const EXIT_DISMISSED: i32 = 2;
+
 pub async fn message_dialog(title: &str, message: &str) -> Result<()> {
     let title = title.to_string();
     let message = message.to_string();
     tokio::spawn(async move {
-        exec_message_dialog(&title, &message).await.ok();
+        message_dialog_wait(&title, &message, MessageSeverity::Info, None)
+            .await
+            .ok();
     });
     Ok(())
 }
 
-async fn exec_message_dialog(title: &str, message: &str) -> Result<()> {
+/// Shows the message and waits until it is closed.
+/// Returns true if the user acknowledged it (clicked OK), false if dismissed any other way
+pub async fn message_dialog_wait(
+    title: &str,
+    message: &str,
+    severity: MessageSeverity,
+    timeout: Option<u64>,
+) -> Result<bool> {
     log::debug!("Showing message dialog: {} - {}", title, message);
     let signal_file = std::env::temp_dir().join(SIGNAL_FILE);
     log::debug!("Using signal file: {:?}", signal_file);
@@ -39,20 +52,28 @@ async fn exec_message_dialog(title: &str, message: &str) -> Result<()> {
 
     log::debug!("Using gui path: {:?}", gui_path);
 
-    let status = Command::new(gui_path)
+    let mut command = Command::new(gui_path);
+    command
         .arg("message-dialog")
         .arg(title)
         .arg(message)
-        .status()
-        .await?;
+        .arg(severity.as_str());
+    if let Some(timeout) = timeout {
+        command.arg(timeout.to_string());
+    }
+    let status = command.status().await?;
+    dialog_outcome(status.code())
+}
 
-    if status.success() {
-        Ok(())
-    } else {
-        Err(anyhow::anyhow!(
+// Whether the user acknowledged the dialog, from the gui-helper exit code
+fn dialog_outcome(code: Option<i32>) -> Result<bool> {
+    match code {
+        Some(EXIT_ACKNOWLEDGED) => Ok(true),
+        Some(EXIT_DISMISSED) => Ok(false),
+        code => Err(anyhow::anyhow!(
             "Failed to show message dialog, exit code: {:?}",
-            status.code()
-        ))
+            code
+        )),
     }
 }
 
@@ -65,3 +86,16 @@ pub async fn close_all_windows() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dialog_outcome() {
+        assert!(dialog_outcome(Some(EXIT_ACKNOWLEDGED)).unwrap());
+        assert!(!dialog_outcome(Some(EXIT_DISMISSED)).unwrap());
+        assert!(dialog_outcome(Some(1)).is_err());
+        assert!(dialog_outcome(None).is_err()); // Killed by a signal
+    }
+}
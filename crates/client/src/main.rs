@@ -79,6 +79,12 @@ async fn run(platform: platform::Platform) {
     // Monitoring tasks, can stop the app (and session itself)
     let idle_task = tokio::spawn(tasks::idle::task(login_info.max_idle, platform.clone()));
     let deadline_task = tokio::spawn(tasks::deadline::task(login_info.deadline, platform.clone()));
+    // Session timers for the service metrics, ends with the session
+    tokio::spawn(tasks::stats::task(
+        login_info.max_idle,
+        login_info.deadline,
+        platform.clone(),
+    ));
 
     // Await for session end
     platform.stop().wait().await;
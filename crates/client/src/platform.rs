@@ -4,7 +4,10 @@ use std::sync::Arc;
 use shared::{
     sync::OnceSignal,
     system,
-    ws::client::{WsClient, websocket_client_tasks},
+    ws::{
+        client::{WsClient, websocket_client_tasks},
+        types::MessageRequest,
+    },
 };
 
 use crate::{
@@ -24,11 +27,17 @@ pub struct Platform {
 
 impl Platform {
     pub async fn new(port: u16) -> Result<Self> {
+        let operations = shared::system::new_system();
         // If cannot connect, do not initialize the rest of the platform
-        let ws_client = websocket_client_tasks(port, 32).await?;
+        let ws_client = websocket_client_tasks(
+            port,
+            32,
+            &operations.get_current_user()?,
+            crate::ws_workers::CAPABILITIES,
+        )
+        .await?;
         let stop = OnceSignal::new();
         let session_manager = crate::session::new_session_manager(stop.clone()).await;
-        let operations = shared::system::new_system();
         // Requester needs a few things
         let ws_requester = Arc::new(WsRequester::new(
             operations.clone(),
@@ -70,6 +79,12 @@ impl Platform {
         gui::message_dialog("uds-actor Notification", &message).await
     }
 
+    /// Shows a broker message, returning whether the user acknowledged it
+    pub async fn show_message(&self, req: &MessageRequest) -> Result<bool> {
+        let title = req.title.as_deref().unwrap_or("uds-actor Notification");
+        gui::message_dialog_wait(title, &req.message, req.severity, req.timeout).await
+    }
+
     pub async fn dismiss_user_notifications(&self) -> Result<()> {
         gui::close_all_windows().await
     }
@@ -95,7 +110,13 @@ impl Platform {
         let ws_client = if let Some(ws) = ws {
             ws
         } else {
-            websocket_client_tasks(port, 32).await?
+            websocket_client_tasks(
+                port,
+                32,
+                &operations.get_current_user()?,
+                crate::ws_workers::CAPABILITIES,
+            )
+            .await?
         };
 
         let ws_requester = if let Some(wsr) = ws_requester {
@@ -121,6 +121,7 @@ pub async fn task(
                         level: LogLevel::Info,
                         message: message.clone(),
                     }),
+                    client: None,
                 })
                 .await;
 
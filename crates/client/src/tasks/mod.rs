@@ -1,3 +1,4 @@
 // pub mod alive;
 pub mod deadline;
 pub mod idle;
+pub mod stats;
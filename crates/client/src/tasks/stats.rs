@@ -0,0 +1,142 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use std::time::{Duration, Instant};
+
+use crate::platform;
+use shared::log;
+use shared::ws::types::{RpcEnvelope, RpcMessage, SessionStats};
+
+// Seconds between reports of the session timers to the service
+const STATS_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Reports idle time and remaining time to deadline to the service (for its metrics),
+/// until the session stops
+pub async fn task(max_idle: Option<u64>, deadline: Option<u64>, platform: platform::Platform) {
+    report(max_idle, deadline, platform, STATS_INTERVAL).await
+}
+
+async fn report(
+    max_idle: Option<u64>,
+    deadline: Option<u64>,
+    platform: platform::Platform,
+    interval: Duration,
+) {
+    let started = Instant::now();
+    let stop = platform.stop();
+    let ws_client = platform.ws_client();
+    let operations = platform.system();
+    // Idle timer is only initialized (by the idle task) when there is a max idle
+    let max_idle = max_idle.filter(|secs| *secs > 0);
+    let deadline = deadline.filter(|secs| *secs > 0);
+
+    while stop.wait_timeout(interval).await.is_err() {
+        // Older services do not know about it
+        if !ws_client.service_supports("SessionStats") {
+            continue;
+        }
+        let stats = SessionStats {
+            idle: max_idle
+                .and_then(|_| operations.get_idle_duration().ok())
+                .map(|idle| idle.as_secs()),
+            deadline: deadline.map(|secs| secs.saturating_sub(started.elapsed().as_secs())),
+        };
+        if let Err(e) = ws_client
+            .to_ws
+            .send(RpcEnvelope {
+                id: None,
+                msg: RpcMessage::SessionStats(stats),
+                client: None,
+            })
+            .await
+        {
+            log::debug!("Cannot send session stats: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::mock::mock_platform;
+
+    #[tokio::test]
+    async fn test_stats_task() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let (platform, calls, _, mut to_ws) = mock_platform(None, None, None, None, 43905).await;
+        let stop = platform.stop();
+
+        let handle = tokio::spawn(report(
+            Some(600),
+            Some(3600),
+            platform,
+            Duration::from_millis(100),
+        ));
+        let env = tokio::time::timeout(Duration::from_secs(2), to_ws.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let RpcMessage::SessionStats(stats) = env.msg else {
+            panic!("Unexpected message: {:?}", env.msg);
+        };
+        // Idle is always 300 in our fake
+        assert_eq!(stats.idle, Some(300));
+        assert!(stats.deadline.is_some_and(|d| (3590..=3600).contains(&d)));
+        calls.assert_called("operations::get_idle_duration()");
+
+        stop.set();
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .expect("Stats task did not stop")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stats_task_without_timers() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let (platform, calls, _, mut to_ws) = mock_platform(None, None, None, None, 43906).await;
+        let stop = platform.stop();
+
+        let handle = tokio::spawn(report(None, None, platform, Duration::from_millis(100)));
+        let env = tokio::time::timeout(Duration::from_secs(2), to_ws.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            env.msg,
+            RpcMessage::SessionStats(SessionStats {
+                idle: None,
+                deadline: None
+            })
+        ));
+        // Idle timer not initialized, so not even asked
+        calls.assert_not_called("operations::get_idle_duration()");
+
+        stop.set();
+        handle.await.unwrap();
+    }
+}
@@ -90,7 +90,7 @@ pub async fn mock_platform(
         broadcast::channel::<shared::ws::types::RpcEnvelope<shared::ws::types::RpcMessage>>(32);
     let (to_ws, to_ws_receiver) =
         mpsc::channel::<shared::ws::types::RpcEnvelope<shared::ws::types::RpcMessage>>(32);
-    let ws_client = WsClient { from_ws, to_ws };
+    let ws_client = WsClient::new(from_ws, to_ws);
 
     let ws_requester =
         ws_requester.unwrap_or_else(|| std::sync::Arc::new(WsReqsMock::new(calls.clone())));
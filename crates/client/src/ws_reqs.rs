@@ -4,12 +4,16 @@ use shared::{
     sync::OnceSignal,
     system,
     ws::{
+        call,
         client::WsClient,
-        types::{LoginRequest, LoginResponse, LogoutRequest, RpcEnvelope, RpcMessage},
-        wait_message_arrival,
+        types::{
+            LoginRequest, LoginResponse, LogoutRequest, ResumeSession, RpcEnvelope, RpcMessage,
+        },
     },
 };
 
+const LOGIN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
 #[async_trait::async_trait]
 pub trait WsReqs: Send + Sync {
     async fn login(&self) -> anyhow::Result<LoginResponse>;
@@ -41,27 +45,32 @@ impl WsReqs for WsRequester {
         let ws_client = self.ws_client.clone();
         let stop = self.stop.clone();
 
-        ws_client
-            .to_ws
-            .send(RpcEnvelope {
-                id: Some(19720701), // Some arbitrary id
-                msg: RpcMessage::LoginRequest(LoginRequest {
-                    username: username.clone(),
-                    session_type: session_type.clone(),
-                }),
-            })
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to send login message: {}", e))?;
+        let request = LoginRequest {
+            username: username.clone(),
+            session_type: session_type.clone(),
+        };
+        // Broker may be slow, but not forever. Stopping cancels the call too.
+        let response = tokio::select! {
+            res = call::<_, LoginResponse>(&ws_client, request, LOGIN_TIMEOUT) => res,
+            _ = stop.wait() => anyhow::bail!("Stopped while waiting for login response"),
+        }
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Login failed for user {}: {} ({})",
+                username,
+                e.message,
+                e.code
+            )
+        })?;
 
-        // Wait for response
-        let mut rx = ws_client.from_ws.subscribe();
-        let envelope = wait_message_arrival::<LoginResponse>(&mut rx, Some(stop))
-            .await
-            .ok_or_else(|| {
-                anyhow::anyhow!("Failed to receive login response for user {}", username)
-            })?;
+        // If the service restarts, the connection will resume this session instead of logging in again
+        ws_client.set_session(Some(ResumeSession {
+            username,
+            session_type,
+            session_id: response.session_id.clone(),
+        }));
 
-        Ok(envelope.msg)
+        Ok(response)
     }
 
     async fn logout(&self, session_id: Option<&str>) -> anyhow::Result<()> {
@@ -72,15 +81,17 @@ impl WsReqs for WsRequester {
         ws_client
             .to_ws
             .send(RpcEnvelope {
-                id: Some(19720701), // Some arbitrary id
+                id: None, // Notification, no response
                 msg: RpcMessage::LogoutRequest(LogoutRequest {
                     username: username.clone(),
                     session_type: session_type.clone(),
                     session_id: session_id.unwrap_or_default().to_string(),
                 }),
+                client: None,
             })
             .await
             .map_err(|e| anyhow::anyhow!("Failed to send logout message: {}", e))?;
+        ws_client.set_session(None);
 
         Ok(())
     }
@@ -16,6 +16,7 @@ async fn _worker(platform: platform::Platform, timeout: std::time::Duration) ->
             .send(shared::ws::types::RpcEnvelope {
                 id: None,
                 msg: shared::ws::types::RpcMessage::Ping(Ping(b"ping".to_vec())),
+                client: None,
             })
             .await?;
         log::debug!("Sent ping request");
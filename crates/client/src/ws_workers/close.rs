@@ -71,6 +71,7 @@ mod tests {
         let msg = RpcEnvelope::<RpcMessage> {
             id: None,
             msg: RpcMessage::Ping(shared::ws::types::Ping(b"test".to_vec())),
+            client: None,
         };
         from_ws.send(msg).unwrap();
         // Wait a bit to ensure message is processed and ignored
@@ -83,6 +84,7 @@ mod tests {
         let msg = RpcEnvelope::<RpcMessage> {
             id: None,
             msg: RpcMessage::Close(Close),
+            client: None,
         };
         from_ws.send(msg).unwrap();
 
@@ -73,6 +73,7 @@ mod tests {
         let msg = RpcEnvelope::<RpcMessage> {
             id: None,
             msg: RpcMessage::Ping(shared::ws::types::Ping(b"test".to_vec())),
+            client: None,
         };
         from_ws.send(msg).unwrap();
         // Wait a bit to ensure message is processed and ignored
@@ -84,6 +85,7 @@ mod tests {
         let msg = RpcEnvelope::<RpcMessage> {
             id: None,
             msg: RpcMessage::LogoffRequest(LogoffRequest {}),
+            client: None,
         };
         from_ws.send(msg).unwrap();
 
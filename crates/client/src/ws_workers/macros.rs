@@ -16,3 +16,27 @@ macro_rules! spawn_workers((
         }
     )*
 }});
+
+// Defines the workers of the client: the ones handling a message kind (announced to the
+// service as CAPABILITIES, so both can not diverge) and the ones that only send
+#[macro_export]
+macro_rules! client_workers((
+    [ $( ($name:literal, $kind:ident, $func:path) ),* $(,)? ],
+    [ $( ($other_name:literal, $other_func:path) ),* $(,)? ] $(,)?
+) => {
+    // Message kinds handled by the workers, announced to the service on connection
+    pub const CAPABILITIES: &[&str] = &[ $( stringify!($kind) ),* ];
+
+    // Every announced kind must be a message, carrying the type of the same name
+    const _: () = {
+        $( let _ = shared::ws::types::RpcMessage::$kind as fn($kind) -> shared::ws::types::RpcMessage; )*
+    };
+
+    #[allow(dead_code)]
+    pub async fn setup_workers(platform: platform::Platform) {
+        $crate::spawn_workers!(
+            platform,
+            [ $( ($name, $func), )* $( ($other_name, $other_func), )* ],
+        );
+    }
+});
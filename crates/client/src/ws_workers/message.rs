@@ -0,0 +1,143 @@
+use anyhow::Result;
+
+use shared::{
+    log,
+    ws::{
+        types::{ERR_INTERNAL, MessageAck, MessageRequest, RpcEnvelope, RpcError, RpcMessage},
+        wait_message_arrival,
+    },
+};
+
+use crate::platform;
+
+// Shows the broker messages to the user
+pub async fn worker(platform: platform::Platform) -> Result<()> {
+    let mut rx = platform.ws_client().from_ws.subscribe();
+    while let Some(env) =
+        wait_message_arrival::<MessageRequest>(&mut rx, Some(platform.stop())).await
+    {
+        log::info!("Received message request ({})", env.msg.severity.as_str());
+        // Dialog stays until closed, so do not block the next messages
+        tokio::spawn(show(platform.clone(), env));
+    }
+
+    Ok(())
+}
+
+async fn show(platform: platform::Platform, env: RpcEnvelope<MessageRequest>) {
+    let req = env.msg;
+    let result = platform.show_message(&req).await;
+    match &result {
+        Ok(true) => log::info!("Message acknowledged by user: {}", req.message),
+        Ok(false) => log::info!("Message dismissed without acknowledgement: {}", req.message),
+        Err(e) => log::error!("Message could not be shown ({}): {}", e, req.message),
+    }
+    // Without id, nobody is waiting for the acknowledgement
+    let Some(id) = env.id else {
+        return;
+    };
+    if !req.require_ack {
+        return;
+    }
+    if let Err(e) = platform.ws_client().to_ws.send(ack_reply(id, result)).await {
+        log::error!("Failed to send message acknowledgement: {}", e);
+    }
+}
+
+// Answer to the service for a message that requires acknowledgement
+fn ack_reply(id: u64, result: Result<bool>) -> RpcEnvelope<RpcMessage> {
+    let msg = match result {
+        Ok(acknowledged) => RpcMessage::MessageAck(MessageAck { acknowledged }),
+        Err(e) => RpcMessage::Error(RpcError {
+            code: ERR_INTERNAL,
+            message: e.to_string(),
+        }),
+    };
+    RpcEnvelope {
+        id: Some(id),
+        msg,
+        client: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::testing::mock::mock_platform;
+
+    use super::*;
+
+    fn request(id: Option<u64>, require_ack: bool) -> RpcEnvelope<RpcMessage> {
+        RpcEnvelope {
+            id,
+            msg: RpcMessage::MessageRequest(MessageRequest {
+                message: "test message".into(),
+                require_ack,
+                ..Default::default()
+            }),
+            client: None,
+        }
+    }
+
+    #[test]
+    fn test_ack_reply() {
+        // Acknowledged (gui-helper exit code 0)
+        let env = ack_reply(3, Ok(true));
+        assert_eq!(env.id, Some(3));
+        let RpcMessage::MessageAck(ack) = env.msg else {
+            panic!("Unexpected message: {:?}", env.msg);
+        };
+        assert!(ack.acknowledged);
+
+        // Dismissed (gui-helper exit code 2)
+        let env = ack_reply(4, Ok(false));
+        assert_eq!(env.id, Some(4));
+        assert!(matches!(
+            env.msg,
+            RpcMessage::MessageAck(MessageAck {
+                acknowledged: false
+            })
+        ));
+
+        let env = ack_reply(5, Err(anyhow::anyhow!("no display")));
+        assert_eq!(env.id, Some(5));
+        assert!(matches!(
+            env.msg,
+            RpcMessage::Error(RpcError {
+                code: ERR_INTERNAL,
+                ..
+            })
+        ));
+    }
+
+    // No gui-helper next to the test binary, so the dialog always fails to show
+    #[tokio::test]
+    async fn test_message_worker_replies_with_request_id() {
+        shared::log::setup_logging("debug", shared::log::LogType::Tests);
+        let (platform, _calls, _, mut to_ws) = mock_platform(None, None, None, None, 43909).await;
+        let from_ws = platform.ws_client().from_ws.clone();
+        let stop = platform.stop();
+        let worker_handle = tokio::spawn(super::worker(platform));
+        while from_ws.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // Not answered if not required, or if nobody waits for it
+        from_ws.send(request(Some(1), false)).unwrap();
+        from_ws.send(request(None, true)).unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(to_ws.try_recv().is_err());
+
+        from_ws.send(request(Some(7), true)).unwrap();
+        let env = tokio::time::timeout(Duration::from_secs(5), to_ws.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(env.id, Some(7));
+        assert!(matches!(env.msg, RpcMessage::Error(_)));
+
+        stop.set();
+        let _ = worker_handle.await;
+    }
+}
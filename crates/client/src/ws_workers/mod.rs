@@ -6,21 +6,44 @@ mod macros;
 mod alive;
 mod close;
 mod logoff;
+mod message;
 mod pong;
 mod screenshot;
+mod script;
 
-use crate::spawn_workers;
+use shared::ws::types::{
+    Close, LogoffRequest, MessageRequest, Pong, ScreenshotRequest, ScriptExecRequest,
+};
 
-#[allow(dead_code)]
-pub async fn setup_workers(platform: platform::Platform) {
-    spawn_workers!(
-        platform,
-        [
-            ("Logoff", logoff::worker),
-            ("Screenshot", screenshot::worker),
-            ("Alive", alive::worker),
-            ("Pong", pong::worker),
-            ("Close", close::worker)
-        ],
-    );
+use crate::client_workers;
+
+client_workers!(
+    [
+        ("Logoff", LogoffRequest, logoff::worker),
+        ("Message", MessageRequest, message::worker),
+        ("Screenshot", ScreenshotRequest, screenshot::worker),
+        ("Script", ScriptExecRequest, script::worker),
+        ("Pong", Pong, pong::worker),
+        ("Close", Close, close::worker),
+    ],
+    // Only send
+    [("Alive", alive::worker)],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_match_workers() {
+        // Everything the service may ask a session client for has a worker, and is announced
+        for kind in [
+            "LogoffRequest",
+            "MessageRequest",
+            "ScreenshotRequest",
+            "ScriptExecRequest",
+        ] {
+            assert!(CAPABILITIES.contains(&kind), "{} not announced", kind);
+        }
+    }
 }
@@ -0,0 +1,171 @@
+use anyhow::Result;
+
+use shared::{
+    log, script,
+    ws::{
+        types::{
+            ERR_INTERNAL, ERR_NOT_SUPPORTED, RpcEnvelope, RpcError, RpcMessage, ScriptContext,
+            ScriptExecRequest,
+        },
+        wait_message_arrival,
+    },
+};
+
+use crate::platform;
+
+// Runs the scripts for the user session, answering the service if it waits for the result
+pub async fn worker(platform: platform::Platform) -> Result<()> {
+    let mut rx = platform.ws_client().from_ws.subscribe();
+    while let Some(env) =
+        wait_message_arrival::<ScriptExecRequest>(&mut rx, Some(platform.stop())).await
+    {
+        log::info!(
+            "Received script execution request ({})",
+            env.msg.script_type
+        );
+        // Scripts may last long, so every one runs on its own task
+        tokio::spawn(execute(platform.clone(), env));
+    }
+
+    Ok(())
+}
+
+async fn execute(platform: platform::Platform, env: RpcEnvelope<ScriptExecRequest>) {
+    let msg = if !script::is_supported(&env.msg.script_type) {
+        log::warn!("Unsupported script type: {}", env.msg.script_type);
+        RpcMessage::Error(RpcError {
+            code: ERR_NOT_SUPPORTED,
+            message: format!("unsupported script type: {}", env.msg.script_type),
+        })
+    } else {
+        match script::run(&env.msg, ScriptContext::User).await {
+            Ok(res) => {
+                log::info!(
+                    "Script finished with exit code {:?} in {:.2}s",
+                    res.exit_code,
+                    res.duration
+                );
+                RpcMessage::ScriptExecResponse(res)
+            }
+            Err(e) => {
+                log::error!("Failed to run script: {}", e);
+                RpcMessage::Error(RpcError {
+                    code: ERR_INTERNAL,
+                    message: e.to_string(),
+                })
+            }
+        }
+    };
+    // Without id, nobody is waiting for the result
+    let Some(id) = env.id else {
+        return;
+    };
+    let reply = RpcEnvelope {
+        id: Some(id),
+        msg,
+        client: None,
+    };
+    if let Err(e) = platform.ws_client().to_ws.send(reply).await {
+        log::error!("Failed to send script result: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::testing::mock::mock_platform;
+
+    use super::*;
+
+    fn request(id: Option<u64>, script_type: &str, script: &str) -> RpcEnvelope<RpcMessage> {
+        RpcEnvelope {
+            id,
+            msg: RpcMessage::ScriptExecRequest(ScriptExecRequest {
+                script_type: script_type.into(),
+                script: script.into(),
+                service: false,
+                timeout: Some(5),
+            }),
+            client: None,
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_script_worker_replies() {
+        shared::log::setup_logging("debug", shared::log::LogType::Tests);
+        let (platform, _calls, _, mut to_ws) = mock_platform(None, None, None, None, 43907).await;
+        let from_ws = platform.ws_client().from_ws.clone();
+        let stop = platform.stop();
+        let worker_handle = tokio::spawn(super::worker(platform));
+        while from_ws.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        from_ws.send(request(Some(7), "sh", "echo $0")).unwrap();
+        let env = tokio::time::timeout(Duration::from_secs(5), to_ws.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(env.id, Some(7));
+        let RpcMessage::ScriptExecResponse(res) = env.msg else {
+            panic!("Unexpected message: {:?}", env.msg);
+        };
+        assert_eq!(res.context, ScriptContext::User);
+        assert_eq!(res.exit_code, Some(0));
+        assert!(res.stdout.trim_end().ends_with(".sh"));
+
+        stop.set();
+        let _ = worker_handle.await;
+    }
+
+    #[tokio::test]
+    async fn test_script_worker_unsupported_type() {
+        shared::log::setup_logging("debug", shared::log::LogType::Tests);
+        let (platform, _calls, _, mut to_ws) = mock_platform(None, None, None, None, 43910).await;
+        let from_ws = platform.ws_client().from_ws.clone();
+        let stop = platform.stop();
+        let worker_handle = tokio::spawn(super::worker(platform));
+        while from_ws.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // Not a failure to run it, we just do not know how
+        from_ws.send(request(Some(8), "cobol", "")).unwrap();
+        let env = tokio::time::timeout(Duration::from_secs(5), to_ws.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(env.id, Some(8));
+        assert!(matches!(
+            env.msg,
+            RpcMessage::Error(RpcError {
+                code: ERR_NOT_SUPPORTED,
+                ..
+            })
+        ));
+
+        stop.set();
+        let _ = worker_handle.await;
+    }
+
+    #[tokio::test]
+    async fn test_script_worker_no_reply_without_id() {
+        shared::log::setup_logging("debug", shared::log::LogType::Tests);
+        let (platform, _calls, _, mut to_ws) = mock_platform(None, None, None, None, 43908).await;
+        let from_ws = platform.ws_client().from_ws.clone();
+        let stop = platform.stop();
+        let worker_handle = tokio::spawn(super::worker(platform));
+        while from_ws.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        from_ws.send(request(None, "cobol", "")).unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(to_ws.try_recv().is_err());
+
+        stop.set();
+        let _ = worker_handle.await;
+    }
+}
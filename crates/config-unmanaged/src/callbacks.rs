@@ -17,13 +17,25 @@ pub fn bnt_save_clicked(ui: &AppWindow) {
         return;
     }
 
-    let final_cfg = config::ActorConfiguration {
-        broker_url: format!("https://{}/uds/rest/", uds_server),
+    // TLS trust and client certificate are not editable from the GUI, keep the stored ones
+    let stored = config::new_config_storage()
+        .config(false)
+        .unwrap_or_default();
+    let mut final_cfg = config::ActorConfiguration {
+        broker_url: String::new(),
+        fallback_broker_urls: Vec::new(),
         verify_ssl: ui.get_verify_ssl(),
+        ca_bundle: stored.ca_bundle,
+        broker_pins: stored.broker_pins,
+        client_cert: stored.client_cert,
+        client_key: stored.client_key,
+        client_key_password: stored.client_key_password,
         actor_type: config::ActorType::Unmanaged,
         master_token: if token.is_empty() { None } else { Some(token) },
         own_token: None,
         restrict_net: if net.is_empty() { None } else { Some(net) },
+        allowed_networks: stored.allowed_networks,
+        metrics_address: stored.metrics_address,
         pre_command: None,
         runonce_command: None,
         post_command: None,
@@ -38,6 +50,7 @@ pub fn bnt_save_clicked(ui: &AppWindow) {
         },
         data: None,
     };
+    final_cfg.set_broker_hosts(&uds_server);
 
     let mut config_storage = config::new_config_storage();
     let res = config_storage.save_config(&final_cfg);
@@ -91,7 +104,11 @@ pub fn btn_test_clicked(ui: &AppWindow) {
             actor_cfg,
             Some(std::time::Duration::from_millis(1500)),
         ) {
-            Ok(msg) => {
+            Ok((msg, trust_path)) => {
+                let msg = match trust_path {
+                    Some(path) => format!("{} (trusted via {})", msg, path),
+                    None => msg,
+                };
                 let _ = slint::invoke_from_event_loop(move || {
                     if let Some(ui) = ui_handle.upgrade() {
                         ui.set_loading(false);
@@ -12,12 +12,8 @@ pub fn fill_window_fields(ui: &AppWindow) {
         ui.set_verify_ssl(actor_cfg.verify_ssl);
 
         if !actor_cfg.broker_url.is_empty() {
-            // Remove https:// and /uds/rest/ if present
-            let url = actor_cfg
-                .broker_url
-                .trim_start_matches("https://")
-                .trim_end_matches("/uds/rest/");
-            ui.set_server_host(url.into());
+            // Shown as a comma separated list of hosts, without https:// and /uds/rest/
+            ui.set_server_host(actor_cfg.broker_hosts().into());
         }
 
         ui.set_service_token(
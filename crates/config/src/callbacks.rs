@@ -180,12 +180,20 @@ pub fn btn_register_clicked(
                         log::debug!("Registration successful");
 
                         let final_cfg = config::ActorConfiguration {
-                            broker_url: format!("https://{}/uds/rest/", hostname),
+                            broker_url: actor_cfg.broker_url.clone(),
+                            fallback_broker_urls: actor_cfg.fallback_broker_urls.clone(),
                             verify_ssl: actor_cfg.verify_ssl,
+                            ca_bundle: actor_cfg.ca_bundle.clone(),
+                            broker_pins: actor_cfg.broker_pins.clone(),
+                            client_cert: actor_cfg.client_cert.clone(),
+                            client_key: actor_cfg.client_key.clone(),
+                            client_key_password: actor_cfg.client_key_password.clone(),
                             actor_type: config::ActorType::Managed,
                             master_token: Some(master_token),
                             own_token: None,
                             restrict_net: None,
+                            allowed_networks: actor_cfg.allowed_networks.clone(),
+                            metrics_address: actor_cfg.metrics_address.clone(),
                             pre_command: reg_auth.commands.pre_command,
                             runonce_command: reg_auth.commands.runonce_command,
                             post_command: reg_auth.commands.post_command,
@@ -277,7 +285,11 @@ pub fn btn_test_clicked(ui: &AppWindow) {
             actor_cfg,
             Some(std::time::Duration::from_millis(1500)),
         ) {
-            Ok(msg) => {
+            Ok((msg, trust_path)) => {
+                let msg = match trust_path {
+                    Some(path) => format!("{} (trusted via {})", msg, path),
+                    None => msg,
+                };
                 let _ = slint::invoke_from_event_loop(move || {
                     if let Some(ui) = ui_handle.upgrade() {
                         ui.set_loading(false);
@@ -6,13 +6,26 @@ pub fn broker_api_config(
     verify_ssl: bool,
     ciphers: &str,
 ) -> config::ActorConfiguration {
-    config::ActorConfiguration {
-        broker_url: format!("https://{hostname}/uds/rest/"),
+    // TLS trust and client certificate are not editable from the GUI, keep the stored ones
+    let stored = config::new_config_storage()
+        .config(false)
+        .unwrap_or_default();
+    // Hostname can be a comma separated list of brokers, first one is the primary
+    let mut cfg = config::ActorConfiguration {
+        broker_url: String::new(),
+        fallback_broker_urls: Vec::new(),
         verify_ssl,
+        ca_bundle: stored.ca_bundle,
+        broker_pins: stored.broker_pins,
+        client_cert: stored.client_cert,
+        client_key: stored.client_key,
+        client_key_password: stored.client_key_password,
         actor_type: config::ActorType::Managed,
         master_token: None,
         own_token: None,
         restrict_net: None,
+        allowed_networks: stored.allowed_networks,
+        metrics_address: stored.metrics_address,
         pre_command: None,
         runonce_command: None,
         post_command: None,
@@ -26,7 +39,9 @@ pub fn broker_api_config(
             ..Default::default()
         },
         data: None,
-    }
+    };
+    cfg.set_broker_hosts(hostname);
+    cfg
 }
 
 pub fn fill_window_fields(ui: &AppWindow) {
@@ -43,12 +58,8 @@ pub fn fill_window_fields(ui: &AppWindow) {
         ui.set_verify_ssl(actor_cfg.verify_ssl);
 
         if !actor_cfg.broker_url.is_empty() {
-            // Remove https:// and /uds/rest/ if present
-            let url = actor_cfg
-                .broker_url
-                .trim_start_matches("https://")
-                .trim_end_matches("/uds/rest/");
-            ui.set_server_host(url.into());
+            // Shown as a comma separated list of hosts, without https:// and /uds/rest/
+            ui.set_server_host(actor_cfg.broker_hosts().into());
         }
 
         let log_level: types::LogLevel = actor_cfg.log_level.into();
@@ -0,0 +1,54 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use anyhow::Result;
+
+use shared::tls::CertificateInfo;
+
+/// Self signed certificate for the given names (dns names or ips)
+pub fn self_signed(names: &[String]) -> Result<CertificateInfo> {
+    let certified = rcgen::generate_simple_self_signed(names.to_vec())?;
+    Ok(CertificateInfo {
+        key: certified.signing_key.serialize_pem(),
+        certificate: certified.cert.pem(),
+        password: None,
+        ciphers: None,
+    })
+}
+
+/// Base64 SHA-256 pin of the certificate, as used on `broker_pins` config
+pub fn spki_pin(cert_info: &CertificateInfo) -> Result<String> {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+    use rustls::pki_types::{CertificateDer, pem::PemObject};
+
+    let cert = CertificateDer::from_pem_slice(cert_info.certificate.as_bytes())?;
+    Ok(format!(
+        "sha256/{}",
+        STANDARD.encode(shared::tls::trust::spki_sha256(&cert)?)
+    ))
+}
@@ -0,0 +1,115 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+//! Fake UDS broker, for running actors locally without an OpenUDS server.
+//!
+//! Serves the actor REST api over https with a self signed certificate, answering as a
+//! well behaved broker would. Responses can be scripted per endpoint (POST /fake/script)
+//! and every request is recorded (GET /fake/calls, and optionally a json lines file).
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+
+use shared::{log, tls};
+
+mod certs;
+mod server;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8443";
+
+struct Args {
+    listen: String,
+    script: Option<PathBuf>,
+    calls_file: Option<PathBuf>,
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--listen ADDR] [--script FILE] [--calls-file FILE]",
+        program
+    );
+    eprintln!(
+        "  --listen ADDR      Address to listen on (default {})",
+        DEFAULT_LISTEN
+    );
+    eprintln!("  --script FILE      JSON file with scripted responses, by endpoint");
+    eprintln!("  --calls-file FILE  Append received calls to FILE, as json lines");
+    std::process::exit(1);
+}
+
+fn parse_args() -> Args {
+    let mut args = std::env::args();
+    let program = args.next().unwrap_or_else(|| "udsactor-fake-broker".into());
+    let mut parsed = Args {
+        listen: DEFAULT_LISTEN.into(),
+        script: None,
+        calls_file: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage(&program));
+        match arg.as_str() {
+            "--listen" => parsed.listen = value(),
+            "--script" => parsed.script = Some(value().into()),
+            "--calls-file" => parsed.calls_file = Some(value().into()),
+            _ => {
+                eprintln!("Unknown option: {}", arg);
+                usage(&program);
+            }
+        }
+    }
+    parsed
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = parse_args();
+
+    log::setup_logging("info", log::LogType::Tests);
+    tls::init_tls(None);
+
+    let broker_cert = certs::self_signed(&server::cert_names(&args.listen))?;
+    // Certificate returned to actors on ready, as a real broker generates one per actor
+    let actor_cert = certs::self_signed(&["localhost".to_string()])?;
+
+    let broker = Arc::new(server::FakeBroker::new(actor_cert, args.calls_file));
+    if let Some(path) = &args.script {
+        let script: server::Script = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        broker.add_script(script);
+    }
+
+    let listener = std::net::TcpListener::bind(&args.listen)?;
+    let addr = listener.local_addr()?;
+    println!("Fake broker listening on https://{}/uds/rest/", addr);
+    println!("Broker SPKI pin: {}", certs::spki_pin(&broker_cert)?);
+    log::info!("Fake broker listening on {}", addr);
+
+    server::serve(listener, broker, broker_cert).await
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,340 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use shared::{log, tls::CertificateInfo};
+
+// Base path of broker REST api, as configured on actors (https://host/uds/rest/)
+const REST_BASE: &str = "/uds/rest/";
+
+/// A response to return instead of the default one
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ScriptedResponse {
+    pub status: Option<u16>,   // Defaults to 200
+    pub result: Option<Value>, // Wrapped on the broker envelope, {"result": ..., "error": ...}
+    pub error: Option<String>,
+    pub body: Option<Value>, // Raw body, not wrapped. Has precedence over result/error
+    pub headers: HashMap<String, String>,
+    pub delay_ms: u64,
+    pub times: Option<u32>, // Times this response is used, defaults to 1. 0 means forever
+}
+
+/// Scripted responses, by endpoint (i.e. "initialize", "auth/auths"). Used in order
+pub type Script = HashMap<String, Vec<ScriptedResponse>>;
+
+/// A request received by the fake broker
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Call {
+    pub seq: u64,
+    pub timestamp: u64, // Unix timestamp, seconds
+    pub method: String,
+    pub endpoint: String,
+    pub body: Value,
+    pub status: u16, // Status returned
+}
+
+pub struct FakeBroker {
+    script: Mutex<HashMap<String, VecDeque<ScriptedResponse>>>,
+    calls: Mutex<Vec<Call>>,
+    calls_file: Option<PathBuf>, // If set, calls are also appended here, as json lines
+    cert_info: CertificateInfo,  // Returned on ready, unmanaged and ipchange
+}
+
+impl FakeBroker {
+    pub fn new(cert_info: CertificateInfo, calls_file: Option<PathBuf>) -> Self {
+        Self {
+            script: Mutex::new(HashMap::new()),
+            calls: Mutex::new(Vec::new()),
+            calls_file,
+            cert_info,
+        }
+    }
+
+    pub fn add_script(&self, script: Script) {
+        let mut queues = self.script.lock().unwrap();
+        for (endpoint, responses) in script {
+            queues.entry(endpoint).or_default().extend(responses);
+        }
+    }
+
+    pub fn clear_script(&self) {
+        self.script.lock().unwrap().clear();
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn clear_calls(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+
+    // Next scripted response for the endpoint, if any
+    fn next_scripted(&self, endpoint: &str) -> Option<ScriptedResponse> {
+        let mut queues = self.script.lock().unwrap();
+        let queue = queues.get_mut(endpoint)?;
+        let front = queue.front_mut()?;
+        let response = front.clone();
+        match front.times {
+            Some(0) => {} // Forever
+            Some(n) if n > 1 => front.times = Some(n - 1),
+            _ => {
+                queue.pop_front();
+            }
+        }
+        Some(response)
+    }
+
+    fn record(&self, method: &Method, endpoint: &str, body: Value, status: StatusCode) {
+        let mut calls = self.calls.lock().unwrap();
+        let call = Call {
+            seq: calls.len() as u64 + 1,
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            method: method.to_string(),
+            endpoint: endpoint.to_string(),
+            body,
+            status: status.as_u16(),
+        };
+        log::info!("{} {} -> {}", call.method, call.endpoint, call.status);
+        if let Some(path) = &self.calls_file {
+            let line = serde_json::to_string(&call).unwrap_or_default();
+            if let Err(e) = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut f| writeln!(f, "{}", line))
+            {
+                log::warn!("Could not write call to {}: {}", path.display(), e);
+            }
+        }
+        calls.push(call);
+    }
+
+    // What a (well behaved) broker would answer
+    fn default_response(&self, endpoint: &str, body: &Value) -> (StatusCode, Value) {
+        let ok = |result: Value| (StatusCode::OK, json!({"result": result, "error": null}));
+        match endpoint {
+            "capabilities" => ok(json!({
+                "version": "fake",
+                "api_versions": ["v3"],
+                "features": [],
+            })),
+            "auth/auths" => (
+                StatusCode::OK,
+                json!([{
+                    "id": "fake-auth", "auth_id": "fake-auth",
+                    "label": "Fake authenticator", "auth_label": "Fake authenticator",
+                    "name": "fake", "auth": "fake",
+                    "type": "InternalDBAuth", "priority": 1, "custom": false,
+                }]),
+            ),
+            "auth/login" => (
+                StatusCode::OK,
+                json!({"result": "ok", "error": null, "token": random_token("api")}),
+            ),
+            "register" => ok(json!(random_token("master"))),
+            "initialize" => {
+                let unique_id = body["id"][0]["mac"].as_str().map(str::to_string);
+                let managed = body["type"].as_str() != Some("unmanaged");
+                ok(json!({
+                    // On unmanaged, master token is kept
+                    "master_token": if managed { Value::Null } else { body["token"].clone() },
+                    "token": random_token("own"),
+                    "unique_id": unique_id,
+                    "os": null,
+                }))
+            }
+            "ready" | "unmanaged" | "ipchange" => {
+                ok(serde_json::to_value(&self.cert_info).unwrap_or_default())
+            }
+            "login" => ok(json!({
+                "ip": "127.0.0.1",
+                "hostname": "fake-client",
+                "deadline": null,
+                "max_idle": null,
+                "session_id": random_token("session"),
+            })),
+            "logout" | "log" | "log_batch" | "test" | "heartbeat" => ok(json!("ok")),
+            _ => (
+                StatusCode::NOT_FOUND,
+                json!({"result": null, "error": format!("Unknown endpoint {}", endpoint)}),
+            ),
+        }
+    }
+}
+
+fn random_token(prefix: &str) -> String {
+    let suffix: String = rand::rng()
+        .sample_iter(&rand::distr::Alphanumeric)
+        .take(24)
+        .map(char::from)
+        .collect();
+    format!("fake-{}-{}", prefix, suffix)
+}
+
+/// Endpoint name from the request path, i.e. "/uds/rest/actor/v3/initialize" -> "initialize"
+pub fn endpoint_from_path(path: &str) -> String {
+    let path = path
+        .strip_prefix(REST_BASE)
+        .unwrap_or(path.trim_start_matches('/'));
+    let path = path.trim_end_matches('/');
+    if let Some(method) = path
+        .strip_prefix("actor/v3/")
+        .or_else(|| path.strip_prefix("actor/v4/"))
+        .or_else(|| path.strip_prefix("actor/"))
+    {
+        method.to_string()
+    } else {
+        path.to_string()
+    }
+}
+
+async fn broker_handler(
+    State(broker): State<Arc<FakeBroker>>,
+    method: Method,
+    uri: Uri,
+    body: Bytes,
+) -> Response {
+    let endpoint = endpoint_from_path(uri.path());
+    let body: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    let (status, headers, response_body) = match broker.next_scripted(&endpoint) {
+        Some(scripted) => {
+            if scripted.delay_ms > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(scripted.delay_ms)).await;
+            }
+            let status = StatusCode::from_u16(scripted.status.unwrap_or(200))
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            let response_body = scripted
+                .body
+                .unwrap_or_else(|| json!({"result": scripted.result, "error": scripted.error}));
+            (status, scripted.headers, response_body)
+        }
+        None => {
+            let (status, response_body) = broker.default_response(&endpoint, &body);
+            (status, HashMap::new(), response_body)
+        }
+    };
+    broker.record(&method, &endpoint, body, status);
+
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            header_map.insert(name, value);
+        }
+    }
+    (status, header_map, Json(response_body)).into_response()
+}
+
+async fn get_calls(State(broker): State<Arc<FakeBroker>>) -> Json<Vec<Call>> {
+    Json(broker.calls())
+}
+
+async fn clear_calls(State(broker): State<Arc<FakeBroker>>) -> StatusCode {
+    broker.clear_calls();
+    StatusCode::NO_CONTENT
+}
+
+async fn add_script(
+    State(broker): State<Arc<FakeBroker>>,
+    Json(script): Json<Script>,
+) -> StatusCode {
+    broker.add_script(script);
+    StatusCode::NO_CONTENT
+}
+
+async fn clear_script(State(broker): State<Arc<FakeBroker>>) -> StatusCode {
+    broker.clear_script();
+    StatusCode::NO_CONTENT
+}
+
+/// Broker endpoints, plus the control ones under /fake/
+pub fn router(broker: Arc<FakeBroker>) -> Router {
+    Router::new()
+        .route("/fake/calls", get(get_calls).delete(clear_calls))
+        .route(
+            "/fake/script",
+            axum::routing::post(add_script).delete(clear_script),
+        )
+        .fallback(broker_handler)
+        .with_state(broker)
+}
+
+/// Serves the fake broker over https on the listener, until the process ends
+pub async fn serve(
+    listener: std::net::TcpListener,
+    broker: Arc<FakeBroker>,
+    server_cert: CertificateInfo,
+) -> Result<()> {
+    listener.set_nonblocking(true)?;
+    let tls_config = shared::tls::certool::rustls_config_from_pem(server_cert)?;
+    axum_server::from_tcp_rustls(listener, tls_config)?
+        .serve(router(broker).into_make_service())
+        .await?;
+    Ok(())
+}
+
+/// Names for the broker certificate: localhost, plus the host we listen on
+pub fn cert_names(listen: &str) -> Vec<String> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    let host = listen
+        .rsplit_once(':')
+        .map_or(listen, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if !host.is_empty() && host != "0.0.0.0" && host != "::" && !names.iter().any(|n| n == host) {
+        names.push(host.to_string());
+    }
+    names
+}
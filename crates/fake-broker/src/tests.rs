@@ -0,0 +1,252 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use std::sync::Arc;
+
+use serde_json::json;
+
+use shared::{
+    broker::api::{BrokerApi, UdsBrokerApi, types::LogLevel},
+    config::{ActorConfiguration, ActorType},
+    system::NetworkInterface,
+};
+
+use crate::{certs, server};
+
+// Starts a fake broker on a random port. Returns its base url (https://127.0.0.1:port/)
+async fn start_broker() -> (String, Arc<server::FakeBroker>) {
+    shared::tls::init_tls(None);
+    let broker_cert = certs::self_signed(&server::cert_names("127.0.0.1:0")).unwrap();
+    let actor_cert = certs::self_signed(&["localhost".to_string()]).unwrap();
+    let broker = Arc::new(server::FakeBroker::new(actor_cert, None));
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("https://{}/", listener.local_addr().unwrap());
+    tokio::spawn(server::serve(listener, broker.clone(), broker_cert));
+    // Wait for server to be ready
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(url.trim_start_matches("https://").trim_end_matches('/'))
+            .await
+            .is_ok()
+        {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    (url, broker)
+}
+
+fn api(url: &str, actor_type: ActorType) -> UdsBrokerApi {
+    let config = ActorConfiguration {
+        broker_url: format!("{}uds/rest/", url),
+        verify_ssl: false,
+        master_token: Some("master".to_string()),
+        actor_type,
+        ..Default::default()
+    };
+    let mut api = UdsBrokerApi::new(config, true, None);
+    api.set_retry_params(0, std::time::Duration::from_millis(0));
+    api
+}
+
+fn interfaces() -> Vec<NetworkInterface> {
+    vec![NetworkInterface {
+        name: "eth0".to_string(),
+        mac: "00:11:22:33:44:55".to_string(),
+        ip_addr: "10.0.0.1".to_string(),
+    }]
+}
+
+#[test]
+fn test_endpoint_from_path() {
+    assert_eq!(
+        server::endpoint_from_path("/uds/rest/actor/v3/initialize"),
+        "initialize"
+    );
+    assert_eq!(
+        server::endpoint_from_path("/uds/rest/actor/v4/ready/"),
+        "ready"
+    );
+    assert_eq!(
+        server::endpoint_from_path("/uds/rest/actor/capabilities"),
+        "capabilities"
+    );
+    assert_eq!(
+        server::endpoint_from_path("/uds/rest/auth/auths"),
+        "auth/auths"
+    );
+}
+
+#[test]
+fn test_cert_names() {
+    assert_eq!(
+        server::cert_names("0.0.0.0:8443"),
+        vec!["localhost", "127.0.0.1"]
+    );
+    assert_eq!(
+        server::cert_names("broker.test:8443"),
+        vec!["localhost", "127.0.0.1", "broker.test"]
+    );
+}
+
+#[tokio::test]
+async fn test_managed_flow() {
+    let (url, broker) = start_broker().await;
+    let mut api = api(&url, ActorType::Managed);
+
+    let init = api.initialize(&interfaces()).await.unwrap();
+    assert_eq!(init.unique_id.as_deref(), Some("00:11:22:33:44:55"));
+    let token = init.token.unwrap();
+    assert!(token.starts_with("fake-own-"));
+    api.set_token(&token);
+
+    let cert = api.ready("10.0.0.1", 43910).await.unwrap();
+    // Certificate must be usable by the actor webserver
+    shared::tls::certool::rustls_config_from_pem(cert).unwrap();
+
+    let login = api.login(&interfaces(), "user", "RDP").await.unwrap();
+    assert!(login.session_id.is_some());
+    api.logout(&interfaces(), "user", "RDP", &login.session_id.unwrap())
+        .await
+        .unwrap();
+
+    let calls = broker.calls();
+    let endpoints: Vec<_> = calls.iter().map(|c| c.endpoint.as_str()).collect();
+    assert_eq!(endpoints, vec!["initialize", "ready", "login", "logout"]);
+    assert_eq!(calls[1].body["token"], json!(token));
+    assert_eq!(calls[1].body["port"], json!(43910));
+    assert!(calls.iter().all(|c| c.status == 200 && c.method == "POST"));
+}
+
+#[tokio::test]
+async fn test_unmanaged_keeps_master_token() {
+    let (url, _broker) = start_broker().await;
+    let api = api(&url, ActorType::Unmanaged);
+
+    let init = api.initialize(&interfaces()).await.unwrap();
+    assert_eq!(init.master_token.as_deref(), Some("master"));
+    api.unmanaged_ready(&interfaces(), 43910).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_auth_endpoints() {
+    let (url, _broker) = start_broker().await;
+    let api = api(&url, ActorType::Managed);
+
+    let auths = api.enumerate_authenticators().await.unwrap();
+    assert_eq!(auths.len(), 1);
+    assert_eq!(auths[0].label, "Fake authenticator");
+    let token = api.api_login("fake", "admin", "admin").await.unwrap();
+    assert!(token.starts_with("fake-api-"));
+}
+
+#[tokio::test]
+async fn test_scripted_responses() {
+    let (url, broker) = start_broker().await;
+    let api = api(&url, ActorType::Managed);
+
+    let script: server::Script = serde_json::from_value(json!({
+        "test": [
+            {"result": "", "error": "Scripted failure", "times": 2},
+            {"status": 503, "headers": {"Retry-After": "1"}},
+        ],
+    }))
+    .unwrap();
+    broker.add_script(script);
+
+    for _ in 0..2 {
+        let err = api.test().await.unwrap_err();
+        assert!(err.to_string().contains("Scripted failure"), "{:?}", err);
+    }
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.status, Some(503));
+    assert!(err.is_retryable());
+    assert_eq!(err.retry_after, Some(std::time::Duration::from_secs(1)));
+    // Script consumed, back to defaults once broker is not considered down anymore
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    assert_eq!(api.test().await.unwrap(), "ok");
+
+    let statuses: Vec<_> = broker.calls().iter().map(|c| c.status).collect();
+    assert_eq!(statuses, vec![200, 200, 503, 200]);
+}
+
+#[tokio::test]
+async fn test_control_endpoints() {
+    let (url, _broker) = start_broker().await;
+    let api = api(&url, ActorType::Managed);
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+
+    client
+        .post(format!("{}fake/script", url))
+        .json(&json!({"log": [{"status": 500, "times": 0}]}))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    for _ in 0..3 {
+        assert!(api.log(LogLevel::Info, "message").await.is_err());
+    }
+
+    let calls: Vec<server::Call> = client
+        .get(format!("{}fake/calls", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(calls.len(), 3);
+    assert!(calls.iter().all(|c| c.endpoint == "log" && c.status == 500));
+    assert_eq!(calls[2].seq, 3);
+    assert_eq!(calls[0].body["message"], json!("message"));
+
+    client
+        .delete(format!("{}fake/script", url))
+        .send()
+        .await
+        .unwrap();
+    client
+        .delete(format!("{}fake/calls", url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(api.log(LogLevel::Info, "message").await.unwrap(), "ok");
+    let calls: Vec<server::Call> = client
+        .get(format!("{}fake/calls", url))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(calls.len(), 1);
+}
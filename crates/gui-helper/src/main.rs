@@ -27,7 +27,7 @@ Author: Adolfo Gómez, dkmaster at dkmon dot com
 #![cfg_attr(not(test), windows_subsystem = "windows")]
 
 use slint::Timer;
-use std::rc::Rc;
+use std::{cell::Cell, rc::Rc};
 
 const SIGNAL_FILE: &str = "uds-actor-gui-close-all";
 
@@ -50,38 +50,71 @@ Communication is minimal:
 - To show a message, the main app launches this binary with arguments.
 - To request all windows to close, it creates a temp file named `uds-actor-gui-close-all`.
 - This binary checks for that file periodically and exits if found.
+- The exit code tells if the user acknowledged the message (clicked OK) or not.
 */
+const EXIT_ACKNOWLEDGED: i32 = 0;
+const EXIT_USAGE: i32 = 1;
+const EXIT_DISMISSED: i32 = 2; // Closed without OK (timeout, close all signal or window closed)
+
 #[tokio::main]
 async fn main() {
     // Get title and message from args
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 4 {
-        // program name, command, title, message
-        eprintln!("Usage: gui-helper [message-dialog] <title> <message>");
-        std::process::exit(1);
+    if !(4..=6).contains(&args.len()) {
+        // program name, command, title, message, [severity], [timeout]
+        eprintln!(
+            "Usage: gui-helper [message-dialog] <title> <message> [info|warning|error] [timeout secs]"
+        );
+        std::process::exit(EXIT_USAGE);
     }
 
     let command = &args[1];
     if command != "message-dialog" {
         eprintln!("Unknown command: {}", command);
-        std::process::exit(1);
+        std::process::exit(EXIT_USAGE);
     }
     let title = args[2].clone();
     let message = args[3].clone();
+    let severity = args.get(4).map(String::as_str).unwrap_or("info");
+    let timeout = match args.get(5).map(|t| t.parse::<u64>()) {
+        None => None,
+        Some(Ok(secs)) => Some(std::time::Duration::from_secs(secs)),
+        Some(Err(_)) => {
+            eprintln!("Invalid timeout: {}", args[5]);
+            std::process::exit(EXIT_USAGE);
+        }
+    };
 
-    show_messagebox(&title, &message);
+    let acknowledged = show_messagebox(&title, &message, severity, timeout);
+    std::process::exit(if acknowledged {
+        EXIT_ACKNOWLEDGED
+    } else {
+        EXIT_DISMISSED
+    });
 }
 
-fn show_messagebox(title: &str, message: &str) {
+// Returns true if the user clicked OK
+fn show_messagebox(
+    title: &str,
+    message: &str,
+    severity: &str,
+    timeout: Option<std::time::Duration>,
+) -> bool {
     let ui = AppWindow::new().unwrap();
 
     ui.set_title_text(title.into());
     ui.set_message_text(message.into());
+    ui.set_severity(severity.into());
 
+    let acknowledged = Rc::new(Cell::new(false));
     let ui_handle = ui.as_weak();
-    ui.on_ok_clicked(move || {
-        if let Some(ui) = ui_handle.upgrade() {
-            ui.hide().unwrap();
+    ui.on_ok_clicked({
+        let acknowledged = acknowledged.clone();
+        move || {
+            acknowledged.set(true);
+            if let Some(ui) = ui_handle.upgrade() {
+                ui.hide().unwrap();
+            }
         }
     });
 
@@ -101,7 +134,19 @@ fn show_messagebox(title: &str, message: &str) {
         },
     );
 
+    // Auto dismiss
+    let dismiss_timer = Timer::default();
+    if let Some(timeout) = timeout {
+        let ui_handle3 = ui.as_weak();
+        dismiss_timer.start(slint::TimerMode::SingleShot, timeout, move || {
+            if let Some(ui) = ui_handle3.upgrade() {
+                ui.hide().unwrap();
+            }
+        });
+    }
+
     ui.run().unwrap();
+    acknowledged.get()
 }
 
 #[cfg(test)]
@@ -115,6 +160,11 @@ mod tests {
         let title = "Test Title";
         let message = "This is a test message to verify that the message dialog works correctly.\n\
                        It should handle multiple lines and proper word wrapping.";
-        show_messagebox(title, message);
+        show_messagebox(
+            title,
+            message,
+            "warning",
+            Some(std::time::Duration::from_secs(30)),
+        );
     }
 }
@@ -0,0 +1,184 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use std::time::{Duration, Instant};
+
+use shared::{
+    broker::api::types::{ActorStatus, RestErrorKind},
+    log,
+    ws::server::ServerContext,
+};
+
+use crate::platform;
+
+// Default seconds between heartbeats, if not set by config nor broker
+const HEARTBEAT_INTERVAL_SECS: u64 = 300;
+
+// Interval from config, None if heartbeats are disabled.
+// Read on every beat, so a new one from broker (on initialize) is honoured
+async fn interval(platform: &platform::Platform) -> Option<Duration> {
+    match platform
+        .config()
+        .read()
+        .await
+        .config
+        .heartbeat_interval
+        .unwrap_or(HEARTBEAT_INTERVAL_SECS)
+    {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+pub async fn actor_status(
+    platform: &platform::Platform,
+    server_info: &ServerContext,
+    uptime: Duration,
+) -> ActorStatus {
+    let (user, session_type) = match platform.get_user_info().read().await.as_ref() {
+        Some(info) => (Some(info.username.clone()), Some(info.session_type.clone())),
+        None => (None, None),
+    };
+    ActorStatus {
+        uptime: uptime.as_secs(),
+        user,
+        session_type,
+        version: shared::consts::VERSION.to_string(),
+        build: shared::consts::BUILD.to_string(),
+        os_version: platform.system().get_os_version().ok(),
+        client_connected: server_info.is_client_connected(),
+    }
+}
+
+/// Posts the actor status to broker every heartbeat interval, until stop is signaled.
+/// Brokers without the heartbeat endpoint stop it.
+pub async fn task(platform: platform::Platform, server_info: ServerContext) {
+    let started = Instant::now();
+    let stop = platform.get_stop();
+
+    loop {
+        let Some(interval) = interval(&platform).await else {
+            log::info!("Heartbeat disabled");
+            return;
+        };
+        // wait_timeout returns Ok if signaled, Err if timeout elapsed
+        if stop.wait_timeout(interval).await.is_ok() {
+            break;
+        }
+
+        let status = actor_status(&platform, &server_info, started.elapsed()).await;
+        match platform.broker_api().read().await.heartbeat(&status).await {
+            Ok(_) => log::debug!("Heartbeat sent: {:?}", status),
+            Err(e) if e.kind == RestErrorKind::NotFound => {
+                log::info!("Broker does not support heartbeat, stopping it");
+                return;
+            }
+            Err(e) => log::warn!("Failed to send heartbeat to broker: {}", e),
+        }
+    }
+    log::debug!("Heartbeat task stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shared::broker::api::types::RestError;
+
+    use crate::{platform::UserInfo, testing::mock};
+
+    #[tokio::test]
+    async fn test_heartbeat_sends_status() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let calls = mocked_platform.calls.clone();
+        let server_info = mock::mock_server_info().await;
+        platform.config().write().await.config.heartbeat_interval = Some(1);
+
+        let handle = tokio::spawn(task(platform.clone(), server_info.clone()));
+        tokio::time::sleep(Duration::from_millis(1300)).await;
+        calls.assert_called("broker_api::heartbeat(None, false)");
+
+        platform.get_user_info().write().await.replace(UserInfo {
+            username: "user".into(),
+            session_type: "RDP".into(),
+            session_id: None,
+        });
+        let (_client, _client_rx) = server_info.clients.register("user");
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        calls.assert_called("broker_api::heartbeat(Some(\"user\"), true)");
+
+        platform.get_stop().set();
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .expect("Heartbeat task did not stop")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_status_payload() {
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let server_info = mock::mock_server_info().await;
+
+        let status = actor_status(&platform, &server_info, Duration::from_secs(42)).await;
+        assert_eq!(status.uptime, 42);
+        assert_eq!(status.version, shared::consts::VERSION);
+        assert_eq!(status.build, shared::consts::BUILD);
+        assert!(status.os_version.is_some());
+        mocked_platform
+            .calls
+            .assert_called("operations::get_os_version()");
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_disabled_or_unsupported() {
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let server_info = mock::mock_server_info().await;
+        platform.config().write().await.config.heartbeat_interval = Some(0);
+        // Returns immediately
+        tokio::time::timeout(
+            Duration::from_millis(100),
+            task(platform.clone(), server_info.clone()),
+        )
+        .await
+        .expect("Disabled heartbeat task did not return");
+
+        platform.config().write().await.config.heartbeat_interval = Some(1);
+        mocked_platform.broker_api.write().await.fail_with = Some(RestError::from_status(404, ""));
+        tokio::time::timeout(Duration::from_secs(2), task(platform, server_info))
+            .await
+            .expect("Heartbeat task did not stop on unsupported broker");
+        assert_eq!(
+            mocked_platform.calls.count_calls("broker_api::heartbeat("),
+            1
+        );
+    }
+}
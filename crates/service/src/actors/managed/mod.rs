@@ -1,6 +1,13 @@
 use anyhow::Result;
 
-use shared::{config::ActorOsAction, log, ws::server};
+use shared::{
+    config::ActorOsAction,
+    log,
+    ws::{
+        credentials::{ClientCredentials, credentials_dir},
+        server,
+    },
+};
 
 use crate::{common, platform, workers};
 
@@ -9,6 +16,7 @@ pub async fn run(platform: platform::Platform) -> Result<()> {
 
     // Ensure we have all requisites to start
     common::wait_for_readyness(&platform).await?;
+    common::probe_capabilities(&platform).await;
 
     log::debug!("Platform initialized with config: {:?}", platform.config());
 
@@ -140,8 +148,8 @@ pub async fn run(platform: platform::Platform) -> Result<()> {
         .ready(ip.as_str(), shared::consts::UDS_PORT)
         .await
         .map_err(|e| {
-            log::error!("Failed to initialize with broker: {:?}", e);
-            anyhow::anyhow!("Failed to initialize with broker: {:?}", e)
+            log::error!("Failed to initialize with broker: {}", e);
+            anyhow::anyhow!("Failed to initialize with broker: {}", e)
         })?;
 
     // Spawn the webserver/websocket server
@@ -163,6 +171,8 @@ pub async fn run(platform: platform::Platform) -> Result<()> {
             .await
             .ssl_ciphers()
             .map(|s| s.to_string()),
+        ClientCredentials::new(credentials_dir(), platform.system()),
+        workers::CAPABILITIES,
     )
     .await?;
 
@@ -185,6 +195,12 @@ pub async fn run(platform: platform::Platform) -> Result<()> {
     // Create workers for requests, wsclient communication, etc.
     workers::create_workers(server_info.clone(), platform.clone()).await;
 
+    // Periodic status to broker, so it can tell a hung actor from an idle one
+    tokio::spawn(super::heartbeat::task(
+        platform.clone(),
+        server_info.clone(),
+    ));
+
     log::debug!("Workers started, waiting for stop signal");
 
     // Simply wait here until stop is signaled
@@ -14,6 +14,12 @@ async fn test_managed_basic_and_stop() -> Result<()> {
 
     log::info!("Calls: {:?}", test_setup.calls.dump());
     assert!(test_setup.calls.count_calls("operations::force_time_sync") == 1);
+    assert!(
+        test_setup
+            .calls
+            .count_calls("broker_api::probe_capabilities")
+            == 1
+    );
     assert!(test_setup.calls.count_calls("broker_api::initialize") == 1);
     assert!(test_setup.calls.count_calls("broker_api::ready") == 1);
     Ok(())
@@ -34,6 +40,8 @@ async fn test_managed_rename_should_rename() -> Result<()> {
                 name: "new_actor_name".into(),
                 custom: None,
             }),
+            heartbeat_interval: None,
+            allowed_networks: None,
         };
     // Signal the run function to start
     test_setup.notify.notify_one();
@@ -63,6 +71,8 @@ async fn test_managed_rename_should_not_rename() -> Result<()> {
                 name: computer_name.clone(),
                 custom: None,
             }),
+            heartbeat_interval: None,
+            allowed_networks: None,
         };
     // Signal the run function to start
     test_setup.notify.notify_one();
@@ -98,6 +108,8 @@ async fn test_managed_join_domain_should_join() -> Result<()> {
                     "password": "password"
                 })),
             }),
+            heartbeat_interval: None,
+            allowed_networks: None,
         };
     // Signal the run function to start
     test_setup.notify.notify_one();
@@ -140,6 +152,8 @@ async fn test_managed_join_domain_should_not_join() -> Result<()> {
                     "password": "password"
                 })),
             }),
+            heartbeat_interval: None,
+            allowed_networks: None,
         };
     // Signal the run function to start
     test_setup.notify.notify_one();
@@ -1,3 +1,4 @@
+pub mod heartbeat;
 pub mod managed;
 pub mod unmanaged;
 
@@ -1,6 +1,12 @@
 use anyhow::Result;
 
-use shared::{log, ws::server};
+use shared::{
+    log,
+    ws::{
+        credentials::{ClientCredentials, credentials_dir},
+        server,
+    },
+};
 
 use crate::{common, platform, workers};
 
@@ -9,6 +15,7 @@ pub async fn run(platform: platform::Platform) -> Result<()> {
 
     // Ensure we have all requisites to start
     common::wait_for_readyness(&platform).await?;
+    common::probe_capabilities(&platform).await;
 
     let broker = platform.broker_api();
     log::debug!("Platform initialized with config: {:?}", platform.config());
@@ -23,8 +30,8 @@ pub async fn run(platform: platform::Platform) -> Result<()> {
         .unmanaged_ready(known_interfaces.as_slice(), shared::consts::UDS_PORT)
         .await
         .map_err(|e| {
-            log::error!("Failed to initialize with broker: {:?}", e);
-            anyhow::anyhow!("Failed to initialize with broker: {:?}", e)
+            log::error!("Failed to initialize with broker: {}", e);
+            anyhow::anyhow!("Failed to initialize with broker: {}", e)
         })?;
 
     // Initialize the Webserver/Websocket server (webserver for public part, websocket for local client comms)
@@ -45,6 +52,8 @@ pub async fn run(platform: platform::Platform) -> Result<()> {
             .await
             .ssl_ciphers()
             .map(|s| s.to_string()),
+        ClientCredentials::new(credentials_dir(), platform.system()),
+        workers::CAPABILITIES,
     )
     .await?;
 
@@ -12,6 +12,12 @@ async fn test_managed_basic_and_stop() -> Result<()> {
     test_setup.stop_and_wait_task(1).await?;
 
     log::info!("Calls: {:?}", test_setup.calls.dump());
+    assert!(
+        test_setup
+            .calls
+            .count_calls("broker_api::probe_capabilities")
+            == 1
+    );
     assert!(test_setup.calls.count_calls("broker_api::unmanaged_ready") == 1);
     Ok(())
 }
@@ -5,6 +5,7 @@ use anyhow::{Context, Result};
 use shared::{
     log,
     utils::network::{network_interfaces_changed, network_interfaces_in_subnet},
+    ws::server::ServerContext,
 };
 
 use crate::platform;
@@ -44,6 +45,15 @@ pub async fn wait_for_readyness(platform: &platform::Platform) -> Result<()> {
     Ok(())
 }
 
+// Negotiates API version and features with broker. A failure is not fatal,
+// defaults (v3, no features) are kept
+pub async fn probe_capabilities(platform: &platform::Platform) {
+    let broker_api = platform.broker_api();
+    if let Err(e) = broker_api.read().await.probe_capabilities().await {
+        log::warn!("Could not probe broker capabilities, using defaults: {}", e);
+    }
+}
+
 // Invokes initialization and updates config accordingly
 pub async fn initialize(platform: &platform::Platform) -> Result<()> {
     let cfg_guard = platform.config();
@@ -65,73 +75,122 @@ pub async fn initialize(platform: &platform::Platform) -> Result<()> {
         "{:?} actor not initialized, initializing with broker",
         actor_type
     );
-    if let Ok(response) = broker_api_guard.initialize(interfaces.as_slice()).await {
-        // If token on response is none, this is not a managed host,continue until next request
-        if response.token.is_none() {
-            log::error!(
-                "{:?} actor initialization did not return a token, cannot continue login",
-                actor_type
-            );
+    let response = match broker_api_guard.initialize(interfaces.as_slice()).await {
+        Ok(response) => response,
+        Err(e) if e.is_auth() => {
+            // If broker rejected our own_token, it has been revoked (i.e. userservice removed on broker)
+            // Clear it, so it is not used again on next start
+            if cfg_guard.own_token.as_deref() == Some(initial_token.as_str()) {
+                log::error!(
+                    "{:?} actor token rejected by broker ({}), clearing it",
+                    actor_type,
+                    e
+                );
+                cfg_guard.own_token = None;
+                if let Err(e) = platform.config_storage().save_config(&cfg_guard) {
+                    log::error!("Failed to save config after clearing own token: {}", e);
+                }
+            }
             return Err(anyhow::anyhow!(
-                "{:?} actor initialization did not return a token",
-                actor_type
+                "{:?} actor not authorized by broker: {}",
+                actor_type,
+                e
             ));
         }
+        Err(e) => {
+            // Transient or unexpected errors, keep current config and continue
+            log::warn!(
+                "{:?} actor initialization failed, continuing with current config: {}",
+                actor_type,
+                e
+            );
+            return Ok(());
+        }
+    };
 
-        // If master token is present on response, and is different of current, update it
-        // but if actor_type is managed, master_token must be cleared
-        if actor_type == shared::config::ActorType::Unmanaged
-            && let Some(master_token) = response.master_token
-            && cfg_guard.master_token.as_ref() != Some(&master_token)
-        {
-            log::info!("Master token updated from broker");
-            cfg_guard.master_token = Some(master_token);
+    // If token on response is none, this is not a managed host,continue until next request
+    if response.token.is_none() {
+        log::error!(
+            "{:?} actor initialization did not return a token, cannot continue login",
+            actor_type
+        );
+        return Err(anyhow::anyhow!(
+            "{:?} actor initialization did not return a token",
+            actor_type
+        ));
+    }
+
+    // If master token is present on response, and is different of current, update it
+    // but if actor_type is managed, master_token must be cleared
+    if actor_type == shared::config::ActorType::Unmanaged
+        && let Some(master_token) = response.master_token
+        && cfg_guard.master_token.as_ref() != Some(&master_token)
+    {
+        log::info!("Master token updated from broker");
+        cfg_guard.master_token = Some(master_token);
+    }
+
+    if actor_type == shared::config::ActorType::Managed {
+        // On managed, master_token must be cleared so subsequent restarts
+        // authenticate with the per-deployment `own_token` rather than the
+        // deployment-wide master token.
+        if cfg_guard.master_token.take().is_some() {
+            log::info!("Cleared master token on managed actor");
         }
+    }
+    cfg_guard.own_token = response.token;
+    cfg_guard.config.unique_id = response.unique_id;
+    cfg_guard.config.os = response.os;
+    if response.heartbeat_interval.is_some() {
+        cfg_guard.config.heartbeat_interval = response.heartbeat_interval;
+    }
+    if response.allowed_networks.is_some() {
+        cfg_guard.config.allowed_networks = response.allowed_networks;
+    }
 
+    // Update stored config.
+    // Note that in fact, on unmanaged, we do not need to store own_token or unique_id,
+    // On managed, it's needed, but we store it anyway on both for simplicity,
+    // because it's volatile, but we do it anyway for simplicity as it really does not harm
+    let mut saver = platform.config_storage();
+    if let Err(e) = saver.save_config(&cfg_guard) {
+        log::error!("Failed to save updated config with new master_token: {}", e);
+        // If type is managed, we cannot continue without saving the config beceuse
+        // it contains the token
         if actor_type == shared::config::ActorType::Managed {
-            // On managed, master_token must be cleared so subsequent restarts
-            // authenticate with the per-deployment `own_token` rather than the
-            // deployment-wide master token.
-            if cfg_guard.master_token.take().is_some() {
-                log::info!("Cleared master token on managed actor");
-            }
-        }
-        cfg_guard.own_token = response.token;
-        cfg_guard.config.unique_id = response.unique_id;
-        cfg_guard.config.os = response.os;
-
-        // Update stored config.
-        // Note that in fact, on unmanaged, we do not need to store own_token or unique_id,
-        // On managed, it's needed, but we store it anyway on both for simplicity,
-        // because it's volatile, but we do it anyway for simplicity as it really does not harm
-        let mut saver = platform.config_storage();
-        if let Err(e) = saver.save_config(&cfg_guard) {
-            log::error!("Failed to save updated config with new master_token: {}", e);
-            // If type is managed, we cannot continue without saving the config beceuse
-            // it contains the token
-            if actor_type == shared::config::ActorType::Managed {
-                return Err(anyhow::anyhow!(
-                    "Failed to save updated config with new master_token: {}",
-                    e
-                ));
-            }
-            // Continue anyway, we have the token in our in-memory config
-        }
-        // Note: right here we are storing all de config, including that one not needed for in fact
-
-        // Now, set the broker_api token to the new own_token
-        if let Some(own_token) = cfg_guard.own_token.clone() {
-            broker_api_guard.set_token(&own_token);
-            // Wire the log forwarder so service-side tracing events (>= WARN by
-            // default) get pushed to the broker via POST actor/v3/log.
-            // Only LogType::Service forwards (see LogForwardLayer::for_type); the
-            // service's own log_type is hard-coded here.
-            shared::log_forward::set_log_forwarder(platform.broker_api_for_forwarder());
+            return Err(anyhow::anyhow!(
+                "Failed to save updated config with new master_token: {}",
+                e
+            ));
         }
+        // Continue anyway, we have the token in our in-memory config
+    }
+    // Note: right here we are storing all de config, including that one not needed for in fact
+
+    // Now, set the broker_api token to the new own_token
+    if let Some(own_token) = cfg_guard.own_token.clone() {
+        broker_api_guard.set_token(&own_token);
+        // Wire the log forwarder so service-side tracing events (>= WARN by
+        // default) get shipped to the broker (batched, see log_shipper).
+        // Only LogType::Service forwards (see LogForwardLayer::for_type); the
+        // service's own log_type is hard-coded here.
+        shared::log_forward::set_log_forwarder(platform.log_shipper());
     }
     Ok(())
 }
 
+/// Restricts the server to the allowed networks, as configured locally and by broker.
+/// If any of them is invalid, only local clients are allowed
+pub async fn apply_allowed_networks(platform: &platform::Platform, server_info: &ServerContext) {
+    match platform.config().read().await.allowed_networks() {
+        Ok(networks) => server_info.set_allowed_networks(networks),
+        Err(e) => {
+            log::error!("{:#}, denying remote access", e);
+            server_info.set_local_only();
+        }
+    }
+}
+
 // Watch for interface ip changes
 // On current implementation, we simply stop the service
 // And the system (Windows, systemd, launchd) will restart it
@@ -230,6 +289,92 @@ mod tests {
         log::info!("calls: {:?}", calls.dump());
     }
 
+    #[tokio::test]
+    async fn test_initialize_allowed_networks() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        platform.config().write().await.master_token = Some("mastertoken".into());
+        mocked_platform
+            .broker_api
+            .write()
+            .await
+            .init_response
+            .allowed_networks = Some(vec!["10.0.0.0/8".into()]);
+        initialize(&platform).await.unwrap();
+        let networks = platform.config().read().await.allowed_networks().unwrap();
+        assert_eq!(networks, vec!["10.0.0.0/8".parse().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_initialize_revoked_token() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        // set UDS_ACTOR_TEST to make config use /tmp/udsactor_test_config.cfg
+        unsafe {
+            std::env::set_var("UDS_ACTOR_TEST", "1");
+        }
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let calls = mocked_platform.calls.clone();
+        platform.config().write().await.own_token = Some("revokedtoken".into());
+        mocked_platform.broker_api.write().await.init_error =
+            Some(shared::broker::api::types::RestError::from_status(
+                403,
+                r#"{"error": "Invalid token"}"#,
+            ));
+
+        // Managed, no master token to fallback to
+        let result = initialize(&platform).await;
+        assert!(result.is_err());
+        assert!(platform.config().read().await.own_token.is_none());
+        assert_eq!(calls.count_calls("broker_api::initialize("), 1);
+    }
+
+    #[tokio::test]
+    async fn test_initialize_master_token_rejected() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        unsafe {
+            std::env::set_var("UDS_ACTOR_TEST", "1");
+        }
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        {
+            let cfg = platform.config();
+            let mut cfg = cfg.write().await;
+            cfg.actor_type = shared::config::ActorType::Unmanaged;
+            cfg.master_token = Some("mastertoken".into());
+            cfg.own_token = Some("owntoken".into());
+        }
+        mocked_platform.broker_api.write().await.init_error =
+            Some(shared::broker::api::types::RestError::from_status(403, ""));
+
+        // Master token was used, so own token is kept
+        let result = initialize(&platform).await;
+        assert!(result.is_err());
+        assert_eq!(
+            platform.config().read().await.own_token.as_deref(),
+            Some("owntoken")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_initialize_transient_error_keeps_token() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        platform.config().write().await.own_token = Some("owntoken".into());
+        mocked_platform.broker_api.write().await.init_error = Some(
+            shared::broker::api::types::RestError::transport("connection refused"),
+        );
+
+        let result = initialize(&platform).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            platform.config().read().await.own_token.as_deref(),
+            Some("owntoken")
+        );
+    }
+
     #[tokio::test]
     async fn test_interfaces_watch() {
         log::setup_logging("debug", shared::log::LogType::Tests);
@@ -18,6 +18,7 @@ use shared::{
 mod actors;
 mod common;
 mod computer;
+mod outbox;
 mod platform;
 
 mod workers;
@@ -35,6 +36,19 @@ fn executor(
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
+    // Shows broker notifications pending delivery, only the json so it can be piped
+    if args.get(1).map(String::as_str) == Some("--outbox") {
+        let entries = outbox::Outbox::open(outbox::outbox_file()).entries();
+        match serde_json::to_string_pretty(&entries) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to read outbox: {}", e);
+                std::process::exit(1);
+            }
+        }
+        std::process::exit(0);
+    }
+
     if args.len() > 1 {
         println!("Service installer options detected: {}", args[1]);
         match args[1].as_str() {
@@ -58,7 +72,7 @@ fn main() {
             }
             _ => {
                 eprintln!("Unknown option: {}", args[1]);
-                eprintln!("Usage: {} [--install|--uninstall]", args[0]);
+                eprintln!("Usage: {} [--install|--uninstall|--outbox]", args[0]);
             }
         }
         std::process::exit(1);
@@ -0,0 +1,539 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use std::{
+    collections::VecDeque,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use shared::{
+    broker::api::types::{LogLevel, RestError},
+    log,
+};
+
+use crate::platform;
+
+// Max queued entries. When full, oldest log entries are discarded first
+const MAX_ENTRIES: usize = 1000;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Broker notifications that can be delivered later
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum OutboxItem {
+    Logout {
+        username: String,
+        session_type: String,
+        session_id: String,
+    },
+    // Logs not shipped by log_shipper when the service stopped
+    Log {
+        level: LogLevel,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub id: u64,
+    pub queued_at: i64, // Unix timestamp
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    #[serde(flatten)]
+    pub item: OutboxItem,
+}
+
+/// Disk backed queue of broker notifications that could not be delivered
+/// because broker was unreachable. Entries are replayed in order by `replay_task`
+pub struct Outbox {
+    path: Option<PathBuf>, // None for in-memory only (tests)
+    entries: Mutex<VecDeque<OutboxEntry>>,
+    notify: tokio::sync::Notify,
+}
+
+pub fn outbox_file() -> PathBuf {
+    if std::env::var("UDS_ACTOR_TEST").is_ok() {
+        PathBuf::from("/tmp/udsactor_test_outbox.json")
+    } else if cfg!(target_os = "windows") {
+        let program_data =
+            std::env::var("ProgramData").unwrap_or_else(|_| "C:\\ProgramData".to_string());
+        Path::new(&program_data)
+            .join("UDSActor")
+            .join("outbox.json")
+    } else if cfg!(debug_assertions) {
+        let home = std::env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
+        Path::new(&home).join(".config/udsactor/outbox.json")
+    } else {
+        PathBuf::from("/var/lib/udsactor/outbox.json")
+    }
+}
+
+impl Outbox {
+    /// Opens the outbox stored at `path`, loading any pending entries
+    pub fn open(path: PathBuf) -> Self {
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                log::error!("Invalid outbox file {}, discarding: {}", path.display(), e);
+                VecDeque::new()
+            }),
+            Err(_) => VecDeque::new(),
+        };
+        if !entries.is_empty() {
+            log::info!("Loaded {} pending broker notifications", entries.len());
+        }
+        Self {
+            path: Some(path),
+            entries: Mutex::new(entries),
+            notify: tokio::sync::Notify::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            entries: Mutex::new(VecDeque::new()),
+            notify: tokio::sync::Notify::new(),
+        }
+    }
+
+    pub fn push(&self, item: OutboxItem) {
+        self.push_all(vec![item]);
+    }
+
+    /// Queues several items, persisting the outbox only once
+    pub fn push_all(&self, items: Vec<OutboxItem>) {
+        if items.is_empty() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        for item in items {
+            if entries.len() >= MAX_ENTRIES {
+                let discard = entries
+                    .iter()
+                    .position(|e| matches!(e.item, OutboxItem::Log { .. }))
+                    .unwrap_or(0);
+                log::warn!("Outbox full, discarding {:?}", entries[discard].item);
+                entries.remove(discard);
+            }
+            let id = entries.back().map(|e| e.id + 1).unwrap_or(1);
+            entries.push_back(OutboxEntry {
+                id,
+                queued_at: chrono::Utc::now().timestamp(),
+                attempts: 0,
+                last_error: None,
+                item,
+            });
+        }
+        self.persist(&entries);
+        drop(entries);
+        self.notify.notify_one();
+    }
+
+    pub fn front(&self) -> Option<OutboxEntry> {
+        self.entries.lock().unwrap().front().cloned()
+    }
+
+    pub fn remove(&self, id: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|e| e.id != id);
+        self.persist(&entries);
+    }
+
+    pub fn record_failure(&self, id: u64, error: &RestError) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.iter_mut().find(|e| e.id == id) {
+            entry.attempts += 1;
+            entry.last_error = Some(error.to_string());
+        }
+        self.persist(&entries);
+    }
+
+    pub fn entries(&self) -> Vec<OutboxEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().is_empty()
+    }
+
+    fn persist(&self, entries: &VecDeque<OutboxEntry>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        // Write to a temp file and rename, so a crash never leaves a truncated outbox
+        let result = (|| -> anyhow::Result<()> {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let tmp = path.with_extension("tmp");
+            _ = std::fs::remove_file(&tmp);
+            // Contains usernames and session ids, only for us
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(target_family = "unix")]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut file = options.open(&tmp)?;
+            #[cfg(target_os = "windows")]
+            shared::system::new_system().protect_file_for_owner_only(&tmp.to_string_lossy())?;
+            file.write_all(&serde_json::to_vec(entries)?)?;
+            drop(file);
+            std::fs::rename(&tmp, path)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            log::error!("Failed to persist outbox to {}: {}", path.display(), e);
+        }
+    }
+}
+
+async fn deliver(platform: &platform::Platform, item: &OutboxItem) -> Result<(), RestError> {
+    let broker_api = platform.broker_api();
+    match item {
+        OutboxItem::Logout {
+            username,
+            session_type,
+            session_id,
+        } => {
+            let interfaces = platform
+                .system()
+                .get_network_info()
+                .map_err(|e| RestError::transport(e.to_string()))?;
+            broker_api
+                .read()
+                .await
+                .logout(interfaces.as_slice(), username, session_type, session_id)
+                .await?;
+        }
+        OutboxItem::Log { level, message } => {
+            broker_api.read().await.log(*level, message).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends the notification to the broker. If broker is not reachable, or there are
+/// already queued notifications (to keep order), it is queued for later delivery.
+/// Only non transient errors (i.e. rejected by broker) are returned.
+pub async fn send_or_queue(
+    platform: &platform::Platform,
+    item: OutboxItem,
+) -> Result<(), RestError> {
+    let outbox = platform.outbox();
+    if outbox.is_empty() {
+        match deliver(platform, &item).await {
+            Err(e) if e.is_retryable() => {
+                log::warn!("Broker not reachable ({}), queueing {:?}", e, item);
+            }
+            result => return result,
+        }
+    }
+    outbox.push(item);
+    Ok(())
+}
+
+/// Replays queued notifications in order, with exponential backoff while broker is unreachable
+pub async fn replay_task(platform: platform::Platform) {
+    let outbox = platform.outbox();
+    let stop = platform.get_stop();
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let Some(entry) = outbox.front() else {
+            tokio::select! {
+                _ = outbox.notify.notified() => continue,
+                _ = stop.wait() => break,
+            }
+        };
+
+        match deliver(&platform, &entry.item).await {
+            Ok(()) => {
+                log::info!("Delivered queued {:?}", entry.item);
+                outbox.remove(entry.id);
+                backoff = INITIAL_BACKOFF;
+            }
+            Err(e) if e.is_retryable() => {
+                log::debug!(
+                    "Queued notification not delivered ({}), retrying in {:?}",
+                    e,
+                    backoff
+                );
+                outbox.record_failure(entry.id, &e);
+                // wait_timeout returns Ok if signaled, Err if timeout elapsed
+                if stop.wait_timeout(backoff).await.is_ok() {
+                    break;
+                }
+                backoff = std::cmp::min(backoff * 2, MAX_BACKOFF);
+            }
+            Err(e) => {
+                // Rejected by broker, will not succeed on retry
+                log::error!("Broker rejected queued {:?}, discarding: {}", entry.item, e);
+                outbox.remove(entry.id);
+            }
+        }
+    }
+    log::debug!("Outbox replay task stopped");
+}
+
+/// Runs the log shipper until stop. Logs it could not deliver are queued here,
+/// so they are sent once broker is reachable again, even after a restart
+pub async fn log_shipper_task(platform: platform::Platform) {
+    let undelivered = platform.log_shipper().run(platform.get_stop()).await;
+    if undelivered.is_empty() {
+        return;
+    }
+    log::info!("Queueing {} undelivered log entries", undelivered.len());
+    platform.outbox().push_all(
+        undelivered
+            .into_iter()
+            .map(|entry| OutboxItem::Log {
+                level: entry.level,
+                message: entry.message,
+            })
+            .collect(),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock;
+    use shared::testing::mock::{Calls, OperationsMock};
+    use std::sync::Arc;
+
+    fn logout_item(n: u32) -> OutboxItem {
+        OutboxItem::Logout {
+            username: format!("user{}", n),
+            session_type: "test".into(),
+            session_id: format!("session{}", n),
+        }
+    }
+
+    #[test]
+    fn test_outbox_survives_restart() {
+        let path = std::env::temp_dir().join("udsactor_test_outbox_restart.json");
+        _ = std::fs::remove_file(&path);
+        {
+            let outbox = Outbox::open(path.clone());
+            outbox.push(logout_item(1));
+            outbox.push(OutboxItem::Log {
+                level: LogLevel::Warn,
+                message: "msg".into(),
+            });
+        }
+        let outbox = Outbox::open(path.clone());
+        let entries = outbox.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].item, logout_item(1));
+        outbox.remove(entries[0].id);
+        assert_eq!(Outbox::open(path.clone()).entries().len(), 1);
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_outbox_file_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join("udsactor_test_outbox_mode.json");
+        _ = std::fs::remove_file(&path);
+        Outbox::open(path.clone()).push(logout_item(1));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_outbox_full_discards_logs_first() {
+        let outbox = Outbox::in_memory();
+        outbox.push(logout_item(0));
+        for i in 1..MAX_ENTRIES {
+            outbox.push(OutboxItem::Log {
+                level: LogLevel::Info,
+                message: format!("log{}", i),
+            });
+        }
+        outbox.push(logout_item(1));
+        let entries = outbox.entries();
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0].item, logout_item(0));
+        assert_eq!(entries[MAX_ENTRIES - 1].item, logout_item(1));
+    }
+
+    #[tokio::test]
+    async fn test_undelivered_logs_are_queued_on_stop() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        mocked_platform.broker_api.write().await.fail_with =
+            Some(RestError::transport("connection refused"));
+
+        let handle = tokio::spawn(log_shipper_task(platform.clone()));
+        platform.log_shipper().push(
+            shared::log_shipper::LogSource::Client,
+            LogLevel::Warn,
+            "not delivered",
+        );
+        platform.get_stop().set();
+        handle.await.unwrap();
+
+        let items: Vec<OutboxItem> = platform
+            .outbox()
+            .entries()
+            .into_iter()
+            .map(|e| e.item)
+            .collect();
+        assert!(items.contains(&OutboxItem::Log {
+            level: LogLevel::Warn,
+            message: "not delivered".into(),
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_send_or_queue_unreachable() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        mocked_platform.broker_api.write().await.fail_with =
+            Some(RestError::transport("connection refused"));
+
+        assert!(send_or_queue(&platform, logout_item(1)).await.is_ok());
+        assert!(send_or_queue(&platform, logout_item(2)).await.is_ok());
+        // Second one is queued directly, to keep order
+        assert_eq!(mocked_platform.calls.count_calls("broker_api::logout("), 1);
+        assert_eq!(platform.outbox().entries().len(), 2);
+
+        // Rejected by broker, returned and not queued
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        mocked_platform.broker_api.write().await.fail_with = Some(RestError::from_status(403, ""));
+        assert!(send_or_queue(&platform, logout_item(1)).await.is_err());
+        assert!(platform.outbox().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_send_or_queue_tries_once() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", mockito::Matcher::Any)
+            .with_status(503)
+            .expect(1)
+            .create_async()
+            .await;
+        let mut config = mock::mock_platform()
+            .await
+            .platform
+            .config()
+            .read()
+            .await
+            .clone();
+        config.broker_url = server.url() + "/";
+        config.own_token = Some("token".into());
+        let broker_api = Arc::new(tokio::sync::RwLock::new(platform::new_broker_api(
+            config.clone(),
+        )));
+        let platform = platform::Platform::new_with_params(
+            Some(config),
+            Some(Arc::new(OperationsMock::new(Calls::new()))),
+            Some(broker_api),
+        );
+
+        // Retries are left to replay_task, so the item is queued right away
+        assert!(send_or_queue(&platform, logout_item(1)).await.is_ok());
+        assert_eq!(platform.outbox().entries().len(), 1);
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_replay_in_order() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let calls = mocked_platform.calls.clone();
+        for i in 0..3 {
+            platform.outbox().push(logout_item(i));
+        }
+
+        let handle = tokio::spawn(replay_task(platform.clone()));
+        for _ in 0..50 {
+            if platform.outbox().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(platform.outbox().is_empty());
+        let logouts: Vec<String> = calls
+            .dump()
+            .into_iter()
+            .filter(|c| c.starts_with("broker_api::logout("))
+            .collect();
+        assert_eq!(logouts.len(), 3);
+        for (i, call) in logouts.iter().enumerate() {
+            assert!(call.contains(&format!("user{}", i)));
+        }
+
+        platform.get_stop().set();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_retries_until_reachable() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        mocked_platform.broker_api.write().await.fail_with =
+            Some(RestError::transport("connection refused"));
+        platform.outbox().push(logout_item(1));
+
+        let handle = tokio::spawn(replay_task(platform.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let entries = platform.outbox().entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].attempts, 1);
+        assert!(entries[0].last_error.is_some());
+
+        // Broker comes back, stop must not wait for backoff
+        mocked_platform.broker_api.write().await.fail_with = None;
+        platform.get_stop().set();
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(platform.outbox().entries().len(), 1);
+    }
+}
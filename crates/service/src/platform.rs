@@ -1,7 +1,11 @@
 use std::sync::{Arc, atomic::AtomicBool};
 use tokio::sync::RwLock;
 
-use shared::sync::OnceSignal;
+use shared::{
+    log_shipper::LogShipper,
+    sync::OnceSignal,
+    ws::server::{ServiceStatus, StatusSource},
+};
 
 #[derive(Clone)]
 pub struct UserInfo {
@@ -19,6 +23,8 @@ pub struct Platform {
     stop: OnceSignal,
     user_info: Arc<RwLock<Option<UserInfo>>>,
     restart_flag: Arc<AtomicBool>,
+    outbox: Arc<crate::outbox::Outbox>, // Broker notifications pending delivery
+    log_shipper: Arc<LogShipper>,
 }
 
 impl Platform {
@@ -30,16 +36,20 @@ impl Platform {
         let config = Arc::new(tokio::sync::RwLock::new(cfg.clone()));
 
         let system = shared::system::new_system();
+        protect_client_cert(system.as_ref(), &cfg);
         // Release compilation will fail, because testing is not allowed in release builds, so if we forget this
-        let broker_api = shared::broker::api::UdsBrokerApi::new(cfg, false, None);
+        let broker_api: Arc<RwLock<dyn shared::broker::api::BrokerApi>> =
+            Arc::new(RwLock::new(new_broker_api(cfg)));
 
         Self {
             config,
             system,
-            broker_api: Arc::new(tokio::sync::RwLock::new(broker_api)),
+            log_shipper: Arc::new(LogShipper::new(broker_api.clone())),
+            broker_api,
             stop,
             user_info: Arc::new(RwLock::new(None)),
             restart_flag,
+            outbox: Arc::new(crate::outbox::Outbox::open(crate::outbox::outbox_file())),
         }
     }
 
@@ -51,16 +61,10 @@ impl Platform {
         self.broker_api.clone()
     }
 
-    /// Returns the **same** `Arc<RwLock<dyn BrokerApi>>` returned by
-    /// `broker_api()`. The log forwarder holds its own `Arc` clone and
-    /// takes the read lock briefly when forwarding an event. Because the
-    /// forwarder only fires on `WARN`/`ERROR` (≤ a few per minute even
-    /// under load, capped by the flood guard), the extra lock contention
-    /// is negligible compared to the cost of a broker round-trip.
-    pub fn broker_api_for_forwarder(
-        &self,
-    ) -> Arc<tokio::sync::RwLock<dyn shared::broker::api::BrokerApi>> {
-        self.broker_api.clone()
+    /// Batching shipper for logs sent to broker, shared by the service log
+    /// forwarder and the WS logger worker. Its flush task is started with the workers.
+    pub fn log_shipper(&self) -> Arc<LogShipper> {
+        self.log_shipper.clone()
     }
 
     pub fn config(&self) -> Arc<tokio::sync::RwLock<shared::config::ActorConfiguration>> {
@@ -83,6 +87,10 @@ impl Platform {
         self.restart_flag.clone()
     }
 
+    pub fn outbox(&self) -> Arc<crate::outbox::Outbox> {
+        self.outbox.clone()
+    }
+
     // Only for tests
     #[allow(dead_code)]
     #[cfg(test)]
@@ -99,19 +107,85 @@ impl Platform {
         };
         let config = Arc::new(tokio::sync::RwLock::new(cfg.clone()));
         let operations = operations.unwrap_or_else(|| shared::system::new_system());
-        let broker_api = broker_api.unwrap_or_else(|| {
-            Arc::new(tokio::sync::RwLock::new(
-                shared::broker::api::UdsBrokerApi::new(cfg, false, None),
-            ))
-        });
+        protect_client_cert(operations.as_ref(), &cfg);
+        let broker_api =
+            broker_api.unwrap_or_else(|| Arc::new(tokio::sync::RwLock::new(new_broker_api(cfg))));
 
         Self {
             system: operations,
+            log_shipper: Arc::new(LogShipper::new(broker_api.clone())),
             broker_api,
             config,
             stop: OnceSignal::new(),
             user_info: Arc::new(RwLock::new(None)),
             restart_flag: Arc::new(AtomicBool::new(false)),
+            outbox: Arc::new(crate::outbox::Outbox::in_memory()),
         }
     }
 }
+
+// Service side of the status endpoint
+#[async_trait::async_trait]
+impl StatusSource for Platform {
+    async fn service_status(&self) -> ServiceStatus {
+        let user_info = self.user_info.read().await.clone();
+        ServiceStatus {
+            actor_type: Some(self.config.read().await.actor_type.clone()),
+            username: user_info.as_ref().map(|info| info.username.clone()),
+            session_type: user_info.as_ref().map(|info| info.session_type.clone()),
+            session_id: user_info.and_then(|info| info.session_id),
+            last_broker_call: self.broker_api.read().await.last_call(),
+        }
+    }
+}
+
+pub fn new_broker_api(
+    cfg: shared::config::ActorConfiguration,
+) -> shared::broker::api::UdsBrokerApi {
+    let mut broker_api = shared::broker::api::UdsBrokerApi::new(cfg, false, None);
+    // Logouts go through the outbox, that retries them with its own backoff
+    broker_api.set_retry_policy("logout", shared::broker::api::retry::RetryPolicy::never());
+    broker_api
+}
+
+// Client certificate (and mainly its key) must only be readable by us
+fn protect_client_cert(
+    system: &dyn shared::system::System,
+    cfg: &shared::config::ActorConfiguration,
+) {
+    if let Some((cert, key)) = cfg.client_cert_paths() {
+        for path in [cert, key] {
+            if let Err(e) = system.protect_file_for_owner_only(&path.to_string_lossy()) {
+                shared::log::warn!("Could not protect {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock;
+
+    #[tokio::test]
+    async fn test_service_status() {
+        let platform = mock::mock_platform().await.platform;
+        let status = platform.service_status().await;
+        assert!(status.username.is_none());
+        assert!(status.last_broker_call.is_none());
+
+        platform.get_user_info().write().await.replace(UserInfo {
+            username: "user".into(),
+            session_type: "RDP".into(),
+            session_id: Some("session".into()),
+        });
+        let status = platform.service_status().await;
+        assert_eq!(status.username.as_deref(), Some("user"));
+        assert_eq!(status.session_type.as_deref(), Some("RDP"));
+        assert_eq!(status.session_id.as_deref(), Some("session"));
+        assert_eq!(
+            status.actor_type,
+            Some(platform.config().read().await.actor_type.clone())
+        );
+    }
+}
@@ -7,7 +7,7 @@ use shared::{
     testing::mock::{BrokerApiMock, Calls, OperationsMock},
     ws::{
         request_tracker::RequestTracker,
-        server::ServerContext,
+        server::{ServerContext, WsClients},
         types::{RpcEnvelope, RpcMessage},
     },
 };
@@ -22,11 +22,19 @@ pub struct MockedPlatform {
 pub async fn mock_platform() -> MockedPlatform {
     let config = ActorConfiguration {
         broker_url: "https://localhost".to_string(),
+        fallback_broker_urls: Vec::new(),
         verify_ssl: true,
+        ca_bundle: None,
+        broker_pins: Vec::new(),
+        client_cert: None,
+        client_key: None,
+        client_key_password: None,
         actor_type: ActorType::Managed,
         master_token: None,
         own_token: None,
         restrict_net: None,
+        allowed_networks: Vec::new(),
+        metrics_address: None,
         pre_command: None,
         runonce_command: None,
         post_command: None,
@@ -55,11 +63,12 @@ pub async fn mock_server_info() -> ServerContext {
     let (wsclient_to_workers, _) = broadcast::channel::<RpcEnvelope<RpcMessage>>(128);
     let tracker = RequestTracker::new();
 
-    ServerContext {
-        to_ws: workers_tx,
-        from_ws: wsclient_to_workers.clone(),
+    ServerContext::new(
+        workers_tx,
+        wsclient_to_workers.clone(),
         tracker,
-    }
+        WsClients::new(),
+    )
 }
 
 pub async fn mock_server_info_with_worker_rx()
@@ -70,11 +79,25 @@ pub async fn mock_server_info_with_worker_rx()
     let tracker = RequestTracker::new();
 
     (
-        ServerContext {
-            to_ws: workers_tx,
-            from_ws: wsclient_to_workers.clone(),
+        ServerContext::new(
+            workers_tx,
+            wsclient_to_workers.clone(),
             tracker,
-        },
+            WsClients::new(),
+        ),
         wsclient_to_workers_rx,
     )
 }
+
+// Also returns the receiving end of the messages sent to the WS clients
+pub async fn mock_server_info_with_client_rx()
+-> (ServerContext, mpsc::Receiver<RpcEnvelope<RpcMessage>>) {
+    let (workers_tx, workers_rx) = mpsc::channel::<RpcEnvelope<RpcMessage>>(128);
+    let (wsclient_to_workers, _) = broadcast::channel::<RpcEnvelope<RpcMessage>>(128);
+    let tracker = RequestTracker::new();
+
+    (
+        ServerContext::new(workers_tx, wsclient_to_workers, tracker, WsClients::new()),
+        workers_rx,
+    )
+}
@@ -40,3 +40,26 @@ async fn test_async_main_stops_unmanaged() {
     let result = async_main(platform).await;
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_client_cert_is_protected() {
+    log::setup_logging("debug", log::LogType::Tests);
+
+    let calls = shared::testing::mock::Calls::new();
+    let operations = std::sync::Arc::new(shared::testing::mock::OperationsMock::new(calls.clone()));
+    let broker_api = std::sync::Arc::new(tokio::sync::RwLock::new(
+        shared::testing::mock::BrokerApiMock::new(calls.clone()),
+    ));
+    let config = shared::config::ActorConfiguration {
+        client_cert: Some("/etc/udsactor/client.pem".to_string()),
+        client_key: Some("/etc/udsactor/client_key.pem".to_string()),
+        ..Default::default()
+    };
+    let _platform = crate::platform::Platform::new_with_params(
+        Some(config),
+        Some(operations),
+        Some(broker_api),
+    );
+    calls.assert_called("operations::protect_file_for_owner_only(/etc/udsactor/client.pem)");
+    calls.assert_called("operations::protect_file_for_owner_only(/etc/udsactor/client_key.pem)");
+}
@@ -10,7 +10,7 @@ use crate::platform;
 pub async fn worker(server_info: ServerContext, platform: platform::Platform) -> Result<()> {
     // Note that logoff is a simple notification. No response expected (in fact, will return "ok" immediately)
     let mut rx = server_info.from_ws.subscribe();
-    while let Some(_env) =
+    while let Some(env) =
         wait_message_arrival::<LogoffRequest>(&mut rx, Some(platform.get_stop())).await
     {
         log::debug!("Received LogoffRequest");
@@ -18,6 +18,7 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
         let envelope = shared::ws::types::RpcEnvelope {
             id: None,
             msg: shared::ws::types::RpcMessage::LogoffRequest(LogoffRequest),
+            client: env.client,
         };
         if let Err(e) = server_info.to_ws.send(envelope).await {
             log::error!("Failed to send LogoffRequest to wsclient: {}", e);
@@ -78,6 +79,7 @@ mod tests {
             let req = RpcEnvelope {
                 id: None,
                 msg: RpcMessage::LogoffRequest(LogoffRequest),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send LogoutRequest: {}", e);
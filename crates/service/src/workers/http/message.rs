@@ -1,25 +1,48 @@
+use std::time::Duration;
+
 use anyhow::Result;
 
 use shared::{
+    broker::api::types::LogLevel,
     log,
-    ws::{server::ServerContext, types::MessageRequest, wait_message_arrival},
+    log_shipper::LogSource,
+    ws::{
+        call_client,
+        server::ServerContext,
+        types::{ClientId, MessageAck, MessageRequest, RpcEnvelope, RpcMessage},
+        wait_message_arrival,
+    },
 };
 
 use crate::platform;
 
+// How long we wait for the user on messages that never time out
+const ACK_WAIT: Duration = Duration::from_secs(3600);
+// Extra time over the message timeout, for the client to close the dialog and answer
+const CLIENT_GRACE: Duration = Duration::from_secs(3);
+
 // Owned ServerInfo and Platform
 pub async fn worker(server_info: ServerContext, platform: platform::Platform) -> Result<()> {
     let mut rx = server_info.from_ws.subscribe();
-    if let Some(env) =
+    while let Some(env) =
         wait_message_arrival::<MessageRequest>(&mut rx, Some(platform.get_stop())).await
     {
         log::debug!("Received MessageRequest");
-        // Send logoff to wsclient
-        let envelope = shared::ws::types::RpcEnvelope {
+        if env.msg.require_ack {
+            // User may take long to answer, so every one waits on its own task
+            tokio::spawn(wait_ack(
+                server_info.clone(),
+                platform.clone(),
+                env.client,
+                env.msg,
+            ));
+            continue;
+        }
+        // Send message to wsclient, that displays it
+        let envelope = RpcEnvelope {
             id: None,
-            msg: shared::ws::types::RpcMessage::MessageRequest(MessageRequest {
-                message: env.msg.message,
-            }),
+            msg: RpcMessage::MessageRequest(env.msg),
+            client: env.client,
         };
         if let Err(e) = server_info.to_ws.send(envelope).await {
             log::error!("Failed to send MessageRequest to wsclient: {}", e);
@@ -30,13 +53,44 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
     Ok(())
 }
 
+// Sends the message to the client and reports its acknowledgement (or its lack) to broker
+async fn wait_ack(
+    server_info: ServerContext,
+    platform: platform::Platform,
+    client: Option<ClientId>,
+    req: MessageRequest,
+) {
+    let message = req.message.clone();
+    let timeout = req.timeout.map(Duration::from_secs).unwrap_or(ACK_WAIT) + CLIENT_GRACE;
+    let (level, report) =
+        match call_client::<_, MessageAck>(&server_info, client, req, timeout).await {
+            Ok(MessageAck { acknowledged: true }) => (
+                LogLevel::Info,
+                format!("Message acknowledged by user: {}", message),
+            ),
+            Ok(MessageAck {
+                acknowledged: false,
+            }) => (
+                LogLevel::Warn,
+                format!("Message dismissed without acknowledgement: {}", message),
+            ),
+            Err(e) => (
+                LogLevel::Error,
+                format!("Message not acknowledged ({}): {}", e.message, message),
+            ),
+        };
+    log::info!("{}", report);
+    platform
+        .log_shipper()
+        .push(LogSource::Client, level, &report);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::testing::mock;
     use std::{sync::Arc, time::Duration};
 
-    use shared::ws::types::{RpcEnvelope, RpcMessage};
     use tokio::sync::RwLock;
 
     #[tokio::test]
@@ -80,7 +134,9 @@ mod tests {
                 id: None,
                 msg: RpcMessage::MessageRequest(MessageRequest {
                     message: "test message".into(),
+                    ..Default::default()
                 }),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send MessageRequest: {}", e);
@@ -95,4 +151,80 @@ mod tests {
         log::info!("logged_msgs: {:?}", logged_msgs);
         assert!(logged_msgs.len() == 3);
     }
+
+    async fn ack_result(acknowledged: Option<bool>) -> String {
+        let (server_info, mut to_client) = mock::mock_server_info_with_client_rx().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let tracker = server_info.tracker.clone();
+        let _handle = tokio::spawn(worker(server_info, platform.clone()));
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        wsclient_to_workers
+            .send(RpcEnvelope {
+                id: None,
+                msg: RpcMessage::MessageRequest(MessageRequest {
+                    message: "test message".into(),
+                    require_ack: true,
+                    timeout: Some(0),
+                    ..Default::default()
+                }),
+                client: None,
+            })
+            .unwrap();
+
+        // Sent with an id, so the client can answer it
+        let env = tokio::time::timeout(Duration::from_secs(2), to_client.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(env.msg, RpcMessage::MessageRequest(_)));
+        let id = env.id.expect("MessageRequest without id");
+        if let Some(acknowledged) = acknowledged {
+            tracker
+                .resolve_ok(id, RpcMessage::MessageAck(MessageAck { acknowledged }))
+                .await
+                .unwrap();
+        }
+
+        let shipper = platform.log_shipper();
+        for _ in 0..100 {
+            if shipper.pending() > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        shipper.flush().await;
+        mocked_platform
+            .calls
+            .dump()
+            .into_iter()
+            .find(|c| c.starts_with("broker_api::log("))
+            .expect("Acknowledgement not reported to broker")
+    }
+
+    #[tokio::test]
+    async fn test_message_acknowledged() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let call = ack_result(Some(true)).await;
+        assert!(call.contains("Info, Message acknowledged by user: test message"));
+    }
+
+    #[tokio::test]
+    async fn test_message_dismissed() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let call = ack_result(Some(false)).await;
+        assert!(call.contains("Warn, Message dismissed without acknowledgement"));
+    }
+
+    #[tokio::test]
+    async fn test_message_not_answered() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let call = ack_result(None).await;
+        assert!(call.contains("Error, Message not acknowledged"));
+    }
 }
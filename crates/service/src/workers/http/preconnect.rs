@@ -69,6 +69,7 @@ mod tests {
                     hostname: Some("testhost".into()),
                     udsuser: Some("udsuser".into()),
                 }),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send MessageRequest: {}", e);
@@ -3,9 +3,10 @@ use anyhow::Result;
 use shared::{
     log,
     ws::{
+        call_client,
         server::ServerContext,
-        types::{ScreenshotRequest, ScreenshotResponse},
-        wait_message_arrival, wait_response,
+        types::{RpcMessage, ScreenshotRequest, ScreenshotResponse},
+        wait_message_arrival,
     },
 };
 
@@ -28,40 +29,26 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
             continue;
         };
 
-        // Register the request
-        let (resolver_rx, id) = tracker.register().await;
-
-        // Send screenshot request to wsclient
-        let envelope: shared::ws::types::RpcEnvelope<shared::ws::types::RpcMessage> =
-            shared::ws::types::RpcEnvelope {
-                id: Some(id),
-                msg: shared::ws::types::RpcMessage::ScreenshotRequest(ScreenshotRequest),
-            };
-
-        if let Err(e) = server_info.to_ws.send(envelope).await {
-            log::error!("Failed to send ScreenshotRequest to wsclient: {}", e);
-            tracker.deregister(id).await;
-        } else {
-            log::info!("Sent ScreenshotRequest to wsclient with id {}", id);
-        }
-
-        // Wait for response
-        let response = wait_response::<ScreenshotResponse>(
-            resolver_rx,
-            None,
-            Some(std::time::Duration::from_secs(3)),
+        // Ask the wsclient, and send its answer (or failure) back to broker
+        let response = call_client::<_, ScreenshotResponse>(
+            &server_info,
+            env.client,
+            ScreenshotRequest,
+            std::time::Duration::from_secs(3),
         )
         .await;
-        if let Ok(screenshot_response) = response {
-            // Send response back to broker
-            tracker
-                .resolve_ok(
-                    req_id,
-                    shared::ws::types::RpcMessage::ScreenshotResponse(screenshot_response.0),
-                )
-                .await
-                .ok(); // Consume error silently since request may be already deregistered
-        }
+        let result = match response {
+            Ok(screenshot_response) => {
+                tracker
+                    .resolve_ok(req_id, RpcMessage::ScreenshotResponse(screenshot_response))
+                    .await
+            }
+            Err(e) => {
+                log::warn!("Screenshot request failed: {}", e.message);
+                tracker.resolve_err(req_id, e.code, e.message).await
+            }
+        };
+        result.ok(); // Consume error silently since request may be already deregistered
     }
     Ok(())
 }
@@ -115,6 +102,7 @@ mod tests {
             let req = RpcEnvelope {
                 id: None,
                 msg: RpcMessage::ScreenshotRequest(ScreenshotRequest),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send MessageRequest: {}", e);
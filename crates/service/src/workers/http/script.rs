@@ -1,34 +1,113 @@
+use std::time::Duration;
+
 use anyhow::Result;
 
 use shared::{
-    log,
-    ws::{server::ServerContext, types::ScriptExecRequest, wait_message_arrival},
+    log, script,
+    ws::{
+        call_client,
+        server::ServerContext,
+        types::{
+            ERR_FORBIDDEN, ERR_INTERNAL, ERR_NOT_SUPPORTED, RpcEnvelope, RpcError, RpcMessage,
+            ScriptContext, ScriptExecRequest, ScriptExecResponse,
+        },
+        wait_message_arrival,
+    },
 };
 
 use crate::platform;
 
+// Extra time over the script timeout, for the client to kill the script and answer
+const CLIENT_GRACE: Duration = Duration::from_secs(3);
+
 // Owned ServerInfo and Platform
 pub async fn worker(server_info: ServerContext, platform: platform::Platform) -> Result<()> {
     let mut rx = server_info.from_ws.subscribe();
-    if let Some(env) =
+    while let Some(env) =
         wait_message_arrival::<ScriptExecRequest>(&mut rx, Some(platform.get_stop())).await
     {
         log::debug!("Received ScriptExecRequest");
-        // Send logoff to wsclient
-        let envelope = shared::ws::types::RpcEnvelope {
-            id: None,
-            msg: shared::ws::types::RpcMessage::ScriptExecRequest(ScriptExecRequest {
-                script_type: env.msg.script_type,
-                script: env.msg.script,
+        // Scripts may last long, so every one runs on its own task
+        tokio::spawn(execute(server_info.clone(), env));
+    }
+    Ok(())
+}
+
+async fn execute(server_info: ServerContext, env: RpcEnvelope<ScriptExecRequest>) {
+    let req = env.msg;
+    // Service context (root/SYSTEM) is only for the broker, never for a WS client
+    if req.service && env.client.is_some() {
+        log::warn!(
+            "Refused service script requested by WS client {:?}",
+            env.client
+        );
+        let reply = RpcEnvelope {
+            id: env.id,
+            msg: RpcMessage::Error(RpcError {
+                code: ERR_FORBIDDEN,
+                message: "Service scripts can only be requested by broker".to_string(),
             }),
+            client: env.client,
         };
-        if let Err(e) = server_info.to_ws.send(envelope).await {
-            log::error!("Failed to send ScriptExecRequest to wsclient: {}", e);
-        } else {
-            log::info!("Sent ScriptExecRequest to wsclient");
+        if env.id.is_some() && server_info.to_ws.send(reply).await.is_err() {
+            log::error!("Failed to answer WS client");
         }
+        return;
     }
-    Ok(())
+    // Without id nobody waits for the result. Scripts for the user session are just forwarded
+    let Some(req_id) = env.id else {
+        if req.service {
+            match script::run(&req, ScriptContext::Service).await {
+                Ok(res) => log::info!(
+                    "Script finished with exit code {:?} in {:.2}s",
+                    res.exit_code,
+                    res.duration
+                ),
+                Err(e) => log::error!("Failed to run script: {}", e),
+            }
+        } else {
+            let envelope = RpcEnvelope {
+                id: None,
+                msg: RpcMessage::ScriptExecRequest(req),
+                client: env.client,
+            };
+            if let Err(e) = server_info.to_ws.send(envelope).await {
+                log::error!("Failed to send ScriptExecRequest to wsclient: {}", e);
+            } else {
+                log::info!("Sent ScriptExecRequest to wsclient");
+            }
+        }
+        return;
+    };
+
+    let result = if req.service && !script::is_supported(&req.script_type) {
+        Err((
+            ERR_NOT_SUPPORTED,
+            format!("unsupported script type: {}", req.script_type),
+        ))
+    } else if req.service {
+        script::run(&req, ScriptContext::Service)
+            .await
+            .map_err(|e| (ERR_INTERNAL, e.to_string()))
+    } else {
+        let timeout = script::timeout(&req) + CLIENT_GRACE;
+        call_client::<_, ScriptExecResponse>(&server_info, env.client, req, timeout)
+            .await
+            .map_err(|e| (e.code, e.message))
+    };
+    let tracker = &server_info.tracker;
+    let result = match result {
+        Ok(res) => {
+            tracker
+                .resolve_ok(req_id, RpcMessage::ScriptExecResponse(res))
+                .await
+        }
+        Err((code, message)) => {
+            log::warn!("Script execution failed: {}", message);
+            tracker.resolve_err(req_id, code, message).await
+        }
+    };
+    result.ok(); // Consume error silently since request may be already deregistered
 }
 
 #[cfg(test)]
@@ -82,7 +161,9 @@ mod tests {
                 msg: RpcMessage::ScriptExecRequest(ScriptExecRequest {
                     script_type: "test".into(),
                     script: "test script".into(),
+                    ..Default::default()
                 }),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send ScriptExecRequest: {}", e);
@@ -97,4 +178,165 @@ mod tests {
         log::info!("logged_msgs: {:?}", logged_msgs);
         assert!(logged_msgs.len() == 3);
     }
+
+    #[tokio::test]
+    async fn test_script_worker_defaults_to_user_session() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let (server_info, mut to_client) = mock::mock_server_info_with_client_rx().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let _handle = tokio::spawn(async move {
+            worker(server_info, platform).await.unwrap();
+        });
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // As sent by brokers that know nothing about the execution context
+        let req: ScriptExecRequest =
+            serde_json::from_str(r#"{"type": "sh", "script": "id"}"#).unwrap();
+        assert!(!req.service);
+        wsclient_to_workers
+            .send(RpcEnvelope {
+                id: Some(1),
+                msg: RpcMessage::ScriptExecRequest(req),
+                client: None,
+            })
+            .unwrap();
+
+        // Goes to the client, not run here
+        let env = tokio::time::timeout(Duration::from_secs(2), to_client.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let RpcMessage::ScriptExecRequest(forwarded) = env.msg else {
+            panic!("Unexpected message: {:?}", env.msg);
+        };
+        assert_eq!(forwarded.script, "id");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[tokio::test]
+    async fn test_script_worker_service_result() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let server_info = mock::mock_server_info().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let tracker = server_info.tracker.clone();
+
+        let _handle = tokio::spawn(async move {
+            worker(server_info, platform).await.unwrap();
+        });
+
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let (recv, id) = tracker.register().await;
+        wsclient_to_workers
+            .send(RpcEnvelope {
+                id: Some(id),
+                msg: RpcMessage::ScriptExecRequest(ScriptExecRequest {
+                    script_type: "sh".into(),
+                    script: "echo done".into(),
+                    service: true,
+                    timeout: Some(5),
+                }),
+                client: None,
+            })
+            .unwrap();
+
+        let msg = tokio::time::timeout(Duration::from_secs(5), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        let RpcMessage::ScriptExecResponse(res) = msg else {
+            panic!("Unexpected message: {:?}", msg);
+        };
+        assert_eq!(res.context, ScriptContext::Service);
+        assert_eq!(res.exit_code, Some(0));
+        assert_eq!(res.stdout, "done\n");
+    }
+
+    #[tokio::test]
+    async fn test_script_worker_service_unsupported_type() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let server_info = mock::mock_server_info().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let tracker = server_info.tracker.clone();
+        let _handle = tokio::spawn(worker(server_info, platform));
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let (recv, id) = tracker.register().await;
+        wsclient_to_workers
+            .send(RpcEnvelope {
+                id: Some(id),
+                msg: RpcMessage::ScriptExecRequest(ScriptExecRequest {
+                    script_type: "cobol".into(),
+                    service: true,
+                    ..Default::default()
+                }),
+                client: None,
+            })
+            .unwrap();
+
+        let msg = tokio::time::timeout(Duration::from_secs(5), recv)
+            .await
+            .unwrap()
+            .unwrap();
+        let RpcMessage::Error(err) = msg else {
+            panic!("Unexpected message: {:?}", msg);
+        };
+        assert_eq!(err.code, ERR_NOT_SUPPORTED);
+    }
+
+    #[tokio::test]
+    async fn test_script_worker_refuses_service_script_from_ws_client() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let (server_info, mut to_client) = mock::mock_server_info_with_client_rx().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let _handle = tokio::spawn(worker(server_info, platform));
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let marker = std::env::temp_dir().join("udsactor_test_refused_service_script");
+        _ = std::fs::remove_file(&marker);
+        wsclient_to_workers
+            .send(RpcEnvelope {
+                id: Some(5),
+                msg: RpcMessage::ScriptExecRequest(ScriptExecRequest {
+                    script_type: "sh".into(),
+                    script: format!("touch {}", marker.display()),
+                    service: true,
+                    ..Default::default()
+                }),
+                client: Some(1),
+            })
+            .unwrap();
+
+        let env = tokio::time::timeout(Duration::from_secs(2), to_client.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(env.id, Some(5));
+        assert_eq!(env.client, Some(1));
+        let RpcMessage::Error(err) = env.msg else {
+            panic!("Unexpected message: {:?}", env.msg);
+        };
+        assert_eq!(err.code, ERR_FORBIDDEN);
+        assert!(!marker.exists());
+    }
 }
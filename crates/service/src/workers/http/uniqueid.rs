@@ -90,6 +90,7 @@ mod tests {
             let req = RpcEnvelope {
                 id: Some(id),
                 msg: RpcMessage::UUidRequest(UUidRequest),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send MessageRequest: {}", e);
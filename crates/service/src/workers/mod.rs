@@ -26,7 +26,7 @@
 /*!
 Author: Adolfo Gómez, dkmaster at dkmon dot com
 */
-use shared::ws::server::ServerContext;
+use shared::{log, ws::server::ServerContext};
 
 use crate::platform;
 
@@ -38,8 +38,28 @@ mod ws;
 // Workers for http handling
 mod http;
 
+pub use ws::CAPABILITIES;
+
 #[allow(dead_code)]
 pub async fn create_workers(server_info: ServerContext, platform: platform::Platform) {
+    // Logged in user and broker state for the status endpoint
+    server_info.set_status_source(std::sync::Arc::new(platform.clone()));
+    crate::common::apply_allowed_networks(&platform, &server_info).await;
+    // Replays broker notifications queued while broker was unreachable
+    tokio::spawn(crate::outbox::replay_task(platform.clone()));
+    // Ships service and client logs to broker, undelivered ones go to the outbox on stop
+    tokio::spawn(crate::outbox::log_shipper_task(platform.clone()));
+    // Opt-in metrics endpoint, apart from the actor port
+    let metrics_address = platform.config().read().await.metrics_address();
+    if let Some(addr) = metrics_address {
+        let server_info = server_info.clone();
+        let stop = platform.get_stop();
+        tokio::spawn(async move {
+            if let Err(e) = shared::ws::server::serve_metrics(server_info, addr, stop).await {
+                log::error!("Metrics endpoint on {} failed: {}", addr, e);
+            }
+        });
+    }
     ws::create_workers(server_info.clone(), platform.clone()).await;
     http::create_workers(server_info, platform).await;
 }
@@ -26,38 +26,62 @@
 /*!
 Author: Adolfo Gómez, dkmaster at dkmon dot com
 */
+use std::time::Duration;
+
 use anyhow::Result;
+use tokio::sync::broadcast;
 
 use shared::{
     log,
-    ws::{server::ServerContext, types::Close, wait_message_arrival},
+    ws::{
+        server::{ServerContext, SessionIdentity},
+        types::{Close, ResumeSession, RpcEnvelope, RpcMessage},
+        wait_message_arrival,
+    },
+};
+
+use crate::{
+    outbox::{self, OutboxItem},
+    platform,
 };
 
-use crate::platform;
+// Longer than the first reconnection delay of clients (1 second), so a client that only
+// lost its connection can resume its session before it is logged out
+const LOGOUT_GRACE: Duration = Duration::from_secs(10);
 
 pub async fn worker(server_info: ServerContext, platform: platform::Platform) -> Result<()> {
+    close_worker(server_info, platform, LOGOUT_GRACE).await
+}
+
+async fn close_worker(
+    server_info: ServerContext,
+    platform: platform::Platform,
+    grace: Duration,
+) -> Result<()> {
     // Note that logout is a simple notification. No response expected (in fact, will return "ok" immediately)
     let mut rx = server_info.from_ws.subscribe();
-    let broker_api = platform.broker_api();
     while let Some(env) = wait_message_arrival::<Close>(&mut rx, Some(platform.get_stop())).await {
         log::debug!("Received LogoutRequest with id {:?}", env.id);
-        let user_info = platform.get_user_info().write().await.take();
-        if let Some(user) = user_info {
-            let interfaces = platform.system().get_network_info()?;
-            if let Err(err) = broker_api
-                .write()
-                .await
-                .logout(
-                    interfaces.as_slice(),
-                    (user.username.clone() + " (closed)").as_str(),
-                    user.session_type.as_str(),
-                    user.session_id.as_deref().unwrap_or(""),
-                )
-                .await
-            {
-                log::error!("Logout failed for user {}: {:?}", user.username, err);
-            } else {
-                log::debug!("Processed LogoutRequest for user {}", user.username);
+        // Closed client: log out the session it was tagged with, once the grace period expires.
+        // If no client is known, fall back to the last logged in user
+        match env.client {
+            Some(client) => {
+                if let Some(session) = server_info.clients.take_session(client) {
+                    // Subscribed now, so a resume arriving before the task starts is not missed
+                    let resumes = server_info.from_ws.subscribe();
+                    tokio::spawn(delayed_logout(
+                        server_info.clone(),
+                        platform.clone(),
+                        session,
+                        resumes,
+                        grace,
+                    ));
+                }
+            }
+            None => {
+                if let Some(user) = platform.get_user_info().write().await.take() {
+                    logout(&platform, user).await;
+                }
             }
         }
     }
@@ -65,14 +89,74 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
+// Logs out the session of a closed client, unless a reconnected client resumes it within grace.
+// If the service stops meanwhile, the logout is sent right away
+async fn delayed_logout(
+    server_info: ServerContext,
+    platform: platform::Platform,
+    session: SessionIdentity,
+    mut rx: broadcast::Receiver<RpcEnvelope<RpcMessage>>,
+    grace: Duration,
+) {
+    let resumed = tokio::time::timeout(grace, async {
+        while let Some(env) =
+            wait_message_arrival::<ResumeSession>(&mut rx, Some(platform.get_stop())).await
+        {
+            if env.msg.username == session.username
+                && env.msg.session_id == session.session_id
+                && env
+                    .client
+                    .is_some_and(|client| server_info.clients.is_user(client, &session.username))
+            {
+                return true;
+            }
+        }
+        false
+    })
+    .await;
+    if resumed == Ok(true) {
+        log::info!(
+            "Session of {} resumed by a reconnected client, not logging it out",
+            session.username
+        );
+        return;
+    }
+
+    let current_user = platform.get_user_info();
+    {
+        let mut user_info = current_user.write().await;
+        if user_info.as_ref().map(|u| &u.username) == Some(&session.username) {
+            user_info.take();
+        }
+    }
+    logout(
+        &platform,
+        platform::UserInfo {
+            username: session.username,
+            session_type: session.session_type,
+            session_id: session.session_id,
+        },
+    )
+    .await;
+}
 
-    use shared::ws::types::{RpcEnvelope, RpcMessage};
+async fn logout(platform: &platform::Platform, user: platform::UserInfo) {
+    let item = OutboxItem::Logout {
+        username: user.username.clone() + " (closed)",
+        session_type: user.session_type.clone(),
+        session_id: user.session_id.clone().unwrap_or_default(),
+    };
+    if let Err(err) = outbox::send_or_queue(platform, item).await {
+        log::error!("Logout failed for user {}: {}", user.username, err);
+    } else {
+        log::debug!("Processed LogoutRequest for user {}", user.username);
+    }
+}
 
+#[cfg(test)]
+mod tests {
     use super::*;
     use crate::testing::mock;
-    use std::time::Duration;
 
     #[tokio::test]
     async fn test_logout_worker() {
@@ -110,6 +194,7 @@ mod tests {
             let req = RpcEnvelope {
                 id: None,
                 msg: RpcMessage::Close(Close),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send LogoutRequest: {}", e);
@@ -123,4 +208,134 @@ mod tests {
 
         assert!(calls.count_calls("broker_api::logout(") == 1);
     }
+
+    #[tokio::test]
+    async fn test_close_logs_out_client_session() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let server_info = mock::mock_server_info().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let calls = mocked_platform.calls.clone();
+
+        // Two clients, each one on its own session
+        let clients = server_info.clients.clone();
+        let mut ids = Vec::new();
+        for user in ["user-a", "user-b"] {
+            let (id, _rx) = clients.register(user);
+            clients.set_session(
+                id,
+                Some(SessionIdentity {
+                    username: user.into(),
+                    session_type: "RDP".into(),
+                    session_id: Some(format!("{user}-session")),
+                }),
+            );
+            clients.disconnect(id);
+            ids.push(id);
+        }
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let _handle = tokio::spawn(async move {
+            close_worker(server_info, platform, Duration::from_millis(100))
+                .await
+                .unwrap();
+        });
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        // Closing the first client twice only logs out its own session once
+        for _ in 0..2 {
+            wsclient_to_workers
+                .send(RpcEnvelope {
+                    id: None,
+                    msg: RpcMessage::Close(Close),
+                    client: Some(ids[0]),
+                })
+                .unwrap();
+        }
+        // Not logged out before the grace period
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(calls.count_calls("broker_api::logout("), 0);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        assert_eq!(calls.count_calls("broker_api::logout("), 1);
+        assert!(
+            calls
+                .dump()
+                .iter()
+                .any(|c| c.ends_with("user-a (closed), RDP, user-a-session)"))
+        );
+        assert!(clients.session(ids[1]).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_close_then_resume_does_not_log_out() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let server_info = mock::mock_server_info().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let calls = mocked_platform.calls.clone();
+        platform
+            .get_user_info()
+            .write()
+            .await
+            .replace(platform::UserInfo {
+                username: "alice".into(),
+                session_type: "RDP".into(),
+                session_id: Some("alice-session".into()),
+            });
+
+        // Connection dropped
+        let clients = server_info.clients.clone();
+        let (dropped, _rx) = clients.register("alice");
+        clients.set_session(
+            dropped,
+            Some(SessionIdentity {
+                username: "alice".into(),
+                session_type: "RDP".into(),
+                session_id: Some("alice-session".into()),
+            }),
+        );
+        clients.disconnect(dropped);
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let _handle = tokio::spawn({
+            let platform = platform.clone();
+            async move {
+                close_worker(server_info, platform, Duration::from_millis(300))
+                    .await
+                    .unwrap();
+            }
+        });
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        wsclient_to_workers
+            .send(RpcEnvelope {
+                id: None,
+                msg: RpcMessage::Close(Close),
+                client: Some(dropped),
+            })
+            .unwrap();
+
+        // Client reconnects and resumes its session within the grace period
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let (reconnected, _rx) = clients.register("alice");
+        wsclient_to_workers
+            .send(RpcEnvelope {
+                id: None,
+                msg: RpcMessage::ResumeSession(ResumeSession {
+                    username: "alice".into(),
+                    session_type: "RDP".into(),
+                    session_id: Some("alice-session".into()),
+                }),
+                client: Some(reconnected),
+            })
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(600)).await;
+
+        assert_eq!(calls.count_calls("broker_api::logout("), 0);
+        assert!(platform.get_user_info().read().await.is_some());
+    }
 }
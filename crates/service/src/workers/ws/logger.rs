@@ -27,73 +27,38 @@
 Author: Adolfo Gómez, dkmaster at dkmon dot com
 */
 use anyhow::Result;
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
 
 use shared::{
     log,
+    log_shipper::LogSource,
     ws::{server::ServerContext, types::LogRequest, wait_message_arrival},
 };
 
 use crate::platform;
 
-/// FloodGuard: simple rate limiter (60 logs / 60s)
-pub struct FloodGuard {
-    count: usize,
-    window_start: Instant,
-}
-
-impl FloodGuard {
-    pub fn new() -> Self {
-        Self {
-            count: 0,
-            window_start: Instant::now(),
-        }
-    }
-
-    pub fn allow(&mut self) -> bool {
-        let now = Instant::now();
-        if now.duration_since(self.window_start) > Duration::from_secs(60) {
-            self.count = 0;
-            self.window_start = now;
-        }
-        if self.count < 60 {
-            self.count += 1;
-            true
-        } else {
-            false
-        }
-    }
-}
-
 // Owned ServerInfo and Platform
 pub async fn worker(server_info: ServerContext, platform: platform::Platform) -> Result<()> {
     let mut rx = server_info.from_ws.subscribe();
-    let flood_guard = Arc::new(Mutex::new(FloodGuard::new()));
+    let shipper = platform.log_shipper();
 
     while let Some(env) =
         wait_message_arrival::<LogRequest>(&mut rx, Some(platform.get_stop())).await
     {
-        let mut guard = flood_guard.lock().await;
-        if guard.allow() {
+        // Kept for the logs endpoint too
+        log::log_buffer().push(
+            LogSource::Client,
+            env.msg.level.into(),
+            "client",
+            &env.msg.message,
+        );
+        // Queued, the shipper sends them to broker in batches
+        if shipper.push(LogSource::Client, env.msg.level, &env.msg.message) {
             log::debug!(
                 "Client log (id {:?}, level: {:?}, message: {})",
                 env.id,
                 env.msg.level,
                 env.msg.message
             );
-            let api = platform.broker_api();
-            if let Err(e) = api
-                .write()
-                .await
-                .log(env.msg.level, env.msg.message.as_str())
-                .await
-            {
-                log::error!("Failed to send log to broker: {:?}", e);
-            } else {
-                log::debug!("Sent log to broker for id {:?}", env.id);
-            }
         } else {
             log::warn!("Flood detected: dropping log from client (id {:?})", env.id);
         }
@@ -106,30 +71,11 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
 mod tests {
     use super::*;
 
+    use std::time::Duration;
+
     use crate::testing::mock;
     use shared::ws::types::{LogLevel, RpcEnvelope, RpcMessage};
 
-    #[tokio::test]
-    async fn flood_guard_allows_up_to_60_per_minute() {
-        log::setup_logging("debug", shared::log::LogType::Tests);
-        let mut guard = FloodGuard::new();
-
-        // First 60 should be allowed
-        for _ in 0..60 {
-            assert!(guard.allow());
-        }
-
-        // The 61st should be rejected
-        assert!(!guard.allow());
-
-        // Advance the clock artificially (if using tokio::time::pause/advance)
-        // or manipulate window_start to simulate the passage of time
-        guard.window_start -= Duration::from_secs(61);
-
-        // Now it should reset and allow again
-        assert!(guard.allow());
-    }
-
     #[tokio::test]
     async fn handle_log_respects_flood_guard() {
         log::setup_logging("debug", shared::log::LogType::Tests);
@@ -156,6 +102,7 @@ mod tests {
                     level: LogLevel::Info,
                     message: format!("msg {i}"),
                 }),
+                client: None,
             };
             wsclient_to_workers.send(req).unwrap();
         }
@@ -163,8 +110,11 @@ mod tests {
         // Wait a bit to let processing happen
         tokio::time::sleep(Duration::from_millis(200)).await;
 
-        // Inspect dummy broker_api
+        // Only 60 are accepted, rest are dropped by flood guard
+        assert_eq!(platform.log_shipper().pending(), 60);
+        platform.log_shipper().flush().await;
         log::info!("calls: {:?}", calls.dump());
+        assert_eq!(calls.count_calls("broker_api::log("), 60);
     }
 
     #[tokio::test]
@@ -193,16 +143,26 @@ mod tests {
                     level,
                     message: "Test log message".into(),
                 }),
+                client: None,
             };
             wsclient_to_workers.send(req).unwrap();
         }
 
         // Wait a bit to let processing happen
         tokio::time::sleep(Duration::from_millis(200)).await;
+        platform.log_shipper().flush().await;
         log::info!("calls: {:?}", calls.dump());
 
         calls.assert_called("broker_api::log(Info, Test log message)");
         calls.assert_called("broker_api::log(Warn, Test log message)");
         calls.assert_called("broker_api::log(Error, Test log message)");
+
+        // Also on the log buffer, as client records
+        let records = log::log_buffer().records(Some(log::Level::WARN), None);
+        assert!(
+            records
+                .iter()
+                .any(|r| r.source == LogSource::Client && r.message == "Test log message")
+        );
     }
 }
@@ -31,7 +31,7 @@ use anyhow::Result;
 use shared::{
     log,
     ws::{
-        server::ServerContext,
+        server::{ServerContext, SessionIdentity},
         types::{LoginRequest, RpcEnvelope, RpcMessage},
         wait_message_arrival,
     },
@@ -46,6 +46,16 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
         wait_message_arrival::<LoginRequest>(&mut rx, Some(platform.get_stop())).await
     {
         log::debug!("Received LoginRequest with id {:?}", env.id);
+        // Local clients can only act on behalf of the user they authenticated as
+        if let Some(client) = env.client
+            && !server_info.clients.is_user(client, &env.msg.username)
+        {
+            log::warn!(
+                "Rejected LoginRequest for {} from a client of another user",
+                env.msg.username
+            );
+            continue;
+        }
         let broker_api = platform.broker_api();
 
         let interfaces = platform.system().get_network_info()?;
@@ -68,9 +78,21 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
                     session_type: env.msg.session_type.clone(),
                     session_id: response.session_id.clone(),
                 });
+            // Tag the WS client with its session, so broker requests can be routed to it
+            if let Some(client) = env.client {
+                server_info.clients.set_session(
+                    client,
+                    Some(SessionIdentity {
+                        username: env.msg.username.clone(),
+                        session_type: env.msg.session_type.clone(),
+                        session_id: response.session_id.clone(),
+                    }),
+                );
+            }
             let response_env = RpcEnvelope {
                 id: env.id,
                 msg: RpcMessage::LoginResponse(response),
+                client: env.client,
             };
             if let Err(e) = server_info.to_ws.send(response_env).await {
                 log::error!("Failed to send LoginResponse: {}", e);
@@ -119,6 +141,7 @@ mod tests {
                     username: format!("user{}", i),
                     session_type: "test".into(),
                 }),
+                client: None,
             };
             wsclient_to_workers.send(req).unwrap();
         }
@@ -130,4 +153,44 @@ mod tests {
         log::info!("calls: {:?}", calls.dump());
         assert!(calls.count_calls("broker_api::login(") == 3);
     }
+
+    #[tokio::test]
+    async fn test_login_rejects_other_user() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let server_info = mock::mock_server_info().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let calls = mocked_platform.calls.clone();
+
+        // Client authenticated as alice
+        let clients = server_info.clients.clone();
+        let (client, _client_rx) = clients.register("alice");
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let _handle = tokio::spawn(async move {
+            worker(server_info, platform).await.unwrap();
+        });
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        for username in ["bob", "alice"] {
+            wsclient_to_workers
+                .send(RpcEnvelope {
+                    id: None,
+                    msg: RpcMessage::LoginRequest(LoginRequest {
+                        username: username.into(),
+                        session_type: "test".into(),
+                    }),
+                    client: Some(client),
+                })
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Only alice reached the broker, and the client is tagged with her session
+        assert_eq!(calls.count_calls("broker_api::login("), 1);
+        assert!(calls.dump().iter().any(|c| c.ends_with(", alice, test)")));
+        assert_eq!(clients.session(client).unwrap().username, "alice");
+    }
 }
@@ -32,7 +32,7 @@ use shared::{
     broker::api::BrokerApi,
     log,
     ws::{
-        server::ServerContext,
+        server::{ServerContext, SessionIdentity},
         types::{LoginRequest, RpcEnvelope, RpcMessage},
         wait_message_arrival,
     },
@@ -51,6 +51,16 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
         wait_message_arrival::<LoginRequest>(&mut rx, Some(platform.get_stop())).await
     {
         log::debug!("Received LoginRequest with id {:?}", env.id);
+        // Local clients can only act on behalf of the user they authenticated as
+        if let Some(client) = env.client
+            && !server_info.clients.is_user(client, &env.msg.username)
+        {
+            log::warn!(
+                "Rejected LoginRequest for {} from a client of another user",
+                env.msg.username
+            );
+            continue;
+        }
         let broker_api: std::sync::Arc<tokio::sync::RwLock<dyn BrokerApi>> = platform.broker_api();
 
         let interfaces = platform.system().get_network_info()?;
@@ -59,8 +69,10 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
             log::error!("Failed to initialize unmanaged actor prior to login: {}", e);
             continue;
         }
+        // Broker may have sent the allowed networks on initialize
+        common::apply_allowed_networks(&platform, &server_info).await;
 
-        if let Ok(response) = broker_api
+        match broker_api
             .write()
             .await
             .login(
@@ -70,26 +82,41 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
             )
             .await
         {
-            platform
-                .get_user_info()
-                .write()
-                .await
-                .replace(platform::UserInfo {
-                    username: env.msg.username.clone(),
-                    session_type: env.msg.session_type.clone(),
-                    session_id: response.session_id.clone(),
-                });
-            let response_env = RpcEnvelope {
-                id: env.id,
-                msg: RpcMessage::LoginResponse(response),
-            };
-            if let Err(e) = server_info.to_ws.send(response_env).await {
-                log::error!("Failed to send LoginResponse: {}", e);
-            } else {
-                log::debug!("Sent LoginResponse for id {:?}", env.id);
+            Ok(response) => {
+                platform
+                    .get_user_info()
+                    .write()
+                    .await
+                    .replace(platform::UserInfo {
+                        username: env.msg.username.clone(),
+                        session_type: env.msg.session_type.clone(),
+                        session_id: response.session_id.clone(),
+                    });
+                // Tag the WS client with its session, so broker requests can be routed to it
+                if let Some(client) = env.client {
+                    server_info.clients.set_session(
+                        client,
+                        Some(SessionIdentity {
+                            username: env.msg.username.clone(),
+                            session_type: env.msg.session_type.clone(),
+                            session_id: response.session_id.clone(),
+                        }),
+                    );
+                }
+                let response_env = RpcEnvelope {
+                    id: env.id,
+                    msg: RpcMessage::LoginResponse(response),
+                    client: env.client,
+                };
+                if let Err(e) = server_info.to_ws.send(response_env).await {
+                    log::error!("Failed to send LoginResponse: {}", e);
+                } else {
+                    log::debug!("Sent LoginResponse for id {:?}", env.id);
+                }
+            }
+            Err(e) => {
+                log::error!("Login failed for user {}: {}", env.msg.username, e);
             }
-        } else {
-            log::error!("Login failed for user {}", env.msg.username);
         }
     }
 
@@ -131,6 +158,7 @@ mod tests {
                     username: format!("user{}", i),
                     session_type: "test".into(),
                 }),
+                client: None,
             };
             log::info!("Sending login request for user{}", i);
             wsclient_to_workers.send(req).unwrap();
@@ -33,35 +33,51 @@ use shared::{
     ws::{server::ServerContext, types::LogoutRequest, wait_message_arrival},
 };
 
-use crate::platform;
+use crate::{
+    outbox::{self, OutboxItem},
+    platform,
+};
 
 pub async fn worker(server_info: ServerContext, platform: platform::Platform) -> Result<()> {
     // Note that logout is a simple notification. No response expected (in fact, will return "ok" immediately)
     let mut rx = server_info.from_ws.subscribe();
-    let broker_api = platform.broker_api();
     while let Some(env) =
         wait_message_arrival::<LogoutRequest>(&mut rx, Some(platform.get_stop())).await
     {
         log::debug!("Received LogoutRequest with id {:?}", env.id);
-        if platform.get_user_info().read().await.is_none() {
+        // Local clients can only act on behalf of the user they authenticated as
+        if let Some(client) = env.client
+            && !server_info.clients.is_user(client, &env.msg.username)
+        {
+            log::warn!(
+                "Rejected LogoutRequest for {} from a client of another user",
+                env.msg.username
+            );
+            continue;
+        }
+        // The client session is untagged, so closing the client later does not log it out again
+        let session = env.client.and_then(|c| server_info.clients.take_session(c));
+        if session.is_none() && platform.get_user_info().read().await.is_none() {
             log::warn!("Received LogoutRequest but no user is logged in");
             continue;
         }
-        let interfaces = platform.system().get_network_info()?;
-        if let Err(err) = broker_api
-            .write()
-            .await
-            .logout(
-                interfaces.as_slice(),
-                env.msg.username.as_str(),
-                env.msg.session_type.as_str(),
-                env.msg.session_id.as_str(),
-            )
-            .await
-        {
-            log::error!("Logout failed for user {}: {:?}", env.msg.username, err);
+        // If broker is not reachable, logout is queued and delivered later
+        let item = OutboxItem::Logout {
+            username: env.msg.username.clone(),
+            session_type: env.msg.session_type.clone(),
+            session_id: env.msg.session_id.clone(),
+        };
+        if let Err(err) = outbox::send_or_queue(&platform, item).await {
+            log::error!("Logout failed for user {}: {}", env.msg.username, err);
         } else {
-            platform.get_user_info().write().await.take(); // Clear user info on logout success
+            // Clear user info on logout success (or queued), unless it belongs to another client session
+            let current_user = platform.get_user_info();
+            let mut user_info = current_user.write().await;
+            if session.is_none()
+                || user_info.as_ref().map(|u| &u.username) == Some(&env.msg.username)
+            {
+                user_info.take();
+            }
             log::debug!("Processed LogoutRequest for user {}", env.msg.username);
         }
     }
@@ -118,6 +134,7 @@ mod tests {
                     session_type: "test".into(),
                     session_id: format!("session{}", i),
                 }),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send LogoutRequest: {}", e);
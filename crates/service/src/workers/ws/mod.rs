@@ -37,9 +37,21 @@ mod logger;
 mod login_managed;
 mod login_unmanaged;
 mod logout;
+mod resume;
+mod stats;
 
 use crate::spawn_workers;
 
+// Message kinds handled by the workers, announced to the clients on connection
+pub const CAPABILITIES: &[&str] = &[
+    "LoginRequest",
+    "LogoutRequest",
+    "LogRequest",
+    "Ping",
+    "ResumeSession",
+    "SessionStats",
+];
+
 #[allow(dead_code)]
 pub async fn create_workers(server_info: ServerContext, platform: platform::Platform) {
     spawn_workers!(
@@ -49,7 +61,9 @@ pub async fn create_workers(server_info: ServerContext, platform: platform::Plat
             ("Log", logger::worker),
             ("Logout", logout::worker),
             ("Close", close::worker),
-            ("Ping", ping::worker)
+            ("Ping", ping::worker),
+            ("Resume", resume::worker),
+            ("Stats", stats::worker)
         ],
         // Managed only workers
         [("Login", login_managed::worker),],
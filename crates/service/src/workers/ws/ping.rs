@@ -50,6 +50,7 @@ pub async fn worker(server_info: ServerContext, platform: platform::Platform) ->
             .send(RpcEnvelope {
                 id: env.id,
                 msg: RpcMessage::Pong(Pong(env.msg.0)),
+                client: env.client,
             })
             .await?;
         log::debug!("Sent Pong response");
@@ -92,6 +93,7 @@ mod tests {
             let req = RpcEnvelope {
                 id: None,
                 msg: RpcMessage::Ping(Ping(Vec::new())),
+                client: None,
             };
             if let Err(e) = wsclient_to_workers.send(req) {
                 log::error!("Failed to send LogoutRequest: {}", e);
@@ -0,0 +1,142 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use anyhow::Result;
+
+use shared::{
+    log,
+    ws::{
+        server::{ServerContext, SessionIdentity},
+        types::ResumeSession,
+        wait_message_arrival,
+    },
+};
+
+use crate::platform;
+
+// A client that lost its connection (i.e. we were restarted) binds its new connection
+// to the session it already logged in, without a new broker login
+pub async fn worker(server_info: ServerContext, platform: platform::Platform) -> Result<()> {
+    let mut rx = server_info.from_ws.subscribe();
+    while let Some(env) =
+        wait_message_arrival::<ResumeSession>(&mut rx, Some(platform.get_stop())).await
+    {
+        let Some(client) = env.client else {
+            log::warn!("Received ResumeSession not coming from a WS client");
+            continue;
+        };
+        if !server_info.clients.is_user(client, &env.msg.username) {
+            log::warn!(
+                "Rejected ResumeSession for {} from a client of another user",
+                env.msg.username
+            );
+            continue;
+        }
+        server_info.clients.set_session(
+            client,
+            Some(SessionIdentity {
+                username: env.msg.username.clone(),
+                session_type: env.msg.session_type.clone(),
+                session_id: env.msg.session_id.clone(),
+            }),
+        );
+        // After a restart we do not know about the user anymore
+        let current_user = platform.get_user_info();
+        let mut user_info = current_user.write().await;
+        if user_info.is_none() {
+            user_info.replace(platform::UserInfo {
+                username: env.msg.username.clone(),
+                session_type: env.msg.session_type.clone(),
+                session_id: env.msg.session_id.clone(),
+            });
+        }
+        log::info!(
+            "Resumed session of {} on WS client {}",
+            env.msg.username,
+            client
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shared::ws::types::{RpcEnvelope, RpcMessage};
+
+    use crate::testing::mock;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_resume_session() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let server_info = mock::mock_server_info().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+        let calls = mocked_platform.calls.clone();
+
+        let clients = server_info.clients.clone();
+        let (client, _client_rx) = clients.register("alice");
+
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let _handle = tokio::spawn({
+            let platform = platform.clone();
+            async move {
+                worker(server_info, platform).await.unwrap();
+            }
+        });
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        for username in ["bob", "alice"] {
+            wsclient_to_workers
+                .send(RpcEnvelope {
+                    id: None,
+                    msg: RpcMessage::ResumeSession(ResumeSession {
+                        username: username.into(),
+                        session_type: "RDP".into(),
+                        session_id: Some(format!("{username}-session")),
+                    }),
+                    client: Some(client),
+                })
+                .unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Only alice is resumed, without going through the broker
+        assert_eq!(calls.count_calls("broker_api::login("), 0);
+        let session = clients.session(client).unwrap();
+        assert_eq!(session.username, "alice");
+        assert_eq!(session.session_id.as_deref(), Some("alice-session"));
+        let user_info = platform.get_user_info().read().await.clone().unwrap();
+        assert_eq!(user_info.username, "alice");
+    }
+}
@@ -0,0 +1,96 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use anyhow::Result;
+
+use shared::{
+    log,
+    ws::{server::ServerContext, types::SessionStats, wait_message_arrival},
+};
+
+use crate::platform;
+
+// Session timers reported by the clients, kept for the metrics endpoint
+pub async fn worker(server_info: ServerContext, platform: platform::Platform) -> Result<()> {
+    let mut rx = server_info.from_ws.subscribe();
+    while let Some(env) =
+        wait_message_arrival::<SessionStats>(&mut rx, Some(platform.get_stop())).await
+    {
+        log::debug!("Received SessionStats: {:?}", env.msg);
+        if let Some(client) = env.client {
+            server_info.clients.set_stats(client, env.msg);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shared::ws::types::{RpcEnvelope, RpcMessage};
+
+    use crate::testing::mock;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_stats_worker() {
+        log::setup_logging("debug", shared::log::LogType::Tests);
+        let server_info = mock::mock_server_info().await;
+        let mocked_platform = mock::mock_platform().await;
+        let platform = mocked_platform.platform.clone();
+
+        let (client, _client_rx) = server_info.clients.register("user");
+        let wsclient_to_workers = server_info.from_ws.clone();
+        let _handle = tokio::spawn(worker(server_info.clone(), platform));
+
+        // Wait to have at least one receiver
+        while wsclient_to_workers.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let stats = SessionStats {
+            idle: Some(120),
+            deadline: Some(3600),
+        };
+        wsclient_to_workers
+            .send(RpcEnvelope {
+                id: None,
+                msg: RpcMessage::SessionStats(stats),
+                client: Some(client),
+            })
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(
+            server_info.clients.stats(),
+            vec![(client, "user".to_string(), stats)]
+        );
+    }
+}
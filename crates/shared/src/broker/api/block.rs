@@ -11,6 +11,7 @@ use super::BrokerApi;
 use super::UdsBrokerApi;
 use super::types;
 use crate::config::ActorConfiguration;
+use crate::tls::trust::TrustPath;
 
 /// Create a small Tokio runtime (current-thread) with default options.
 fn create_runtime() -> Result<Runtime> {
@@ -37,17 +38,22 @@ pub fn register(
     api.set_header("X-Auth-Token", token);
 
     let res = rt.block_on(async { api.register(req).await });
-    res.map_err(|e| anyhow!(format!("{:?}", e)))
+    res.map_err(|e| anyhow!(e))
 }
 
 /// Synchronous wrapper for `UdsBrokerApi::test`.
-pub fn test(cfg: ActorConfiguration, timeout: Option<std::time::Duration>) -> Result<String> {
+/// Also returns how the broker certificate was trusted
+pub fn test(
+    cfg: ActorConfiguration,
+    timeout: Option<std::time::Duration>,
+) -> Result<(String, Option<TrustPath>)> {
     let rt = create_runtime()?;
     let mut api = UdsBrokerApi::new(cfg, false, timeout);
     api.set_retry_params(0, std::time::Duration::from_millis(0));
 
     let res = rt.block_on(async { api.test().await });
-    res.map_err(|e| anyhow!(format!("{:?}", e)))
+    res.map(|msg| (msg, api.trust_path()))
+        .map_err(|e| anyhow!(e))
 }
 
 /// Synchronous wrapper for `UdsBrokerApi::enumerate_authenticators`.
@@ -60,7 +66,7 @@ pub fn enumerate_authenticators(
     api.set_retry_params(0, std::time::Duration::from_millis(0));
 
     let res = rt.block_on(async { api.enumerate_authenticators().await });
-    res.map_err(|e| anyhow!(format!("{:?}", e)))
+    res.map_err(|e| anyhow!(e))
 }
 
 /// Synchronous wrapper for `UdsBrokerApi::api_login`.
@@ -75,5 +81,5 @@ pub fn api_login(
     api.set_retry_params(0, std::time::Duration::from_millis(0));
 
     let res = rt.block_on(async { api.api_login(auth, username, password).await });
-    res.map_err(|e| anyhow!(format!("{:?}", e)))
+    res.map_err(|e| anyhow!(e))
 }
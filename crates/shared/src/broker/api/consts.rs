@@ -26,7 +26,18 @@
 /*!
 Author: Adolfo Gómez, dkmaster at dkmon dot com
 */
+use super::types::ApiVersion;
+
 pub const REST_ACTOR_PATH: &str = "actor/v3/";
+pub const REST_ACTOR_V4_PATH: &str = "actor/v4/";
+// Version independent, so it can be probed before knowing the version to use
+pub const REST_CAPABILITIES_PATH: &str = "actor/capabilities";
+
+// Broker accepts several log entries on a single request (log_batch endpoint)
+pub const FEATURE_LOG_BATCH: &str = "log_batch";
+
+// API versions this actor can speak. Add V4 once its request/response shapes are implemented
+pub const SUPPORTED_API_VERSIONS: &[ApiVersion] = &[ApiVersion::V3];
 
 // Must end in /
 pub const UDS_ACTOR_AGENT: &str = concat!(
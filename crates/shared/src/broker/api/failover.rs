@@ -0,0 +1,149 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Time a broker is considered unhealthy after a failure
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(30);
+
+struct PoolState {
+    current: usize,                   // Broker in use, sticky until it fails
+    down_until: Vec<Option<Instant>>, // Unhealthy brokers, until the instant stored
+}
+
+/// Ordered list of broker urls with health-aware, sticky selection.
+/// The broker in use is kept until it fails, then next healthy one (in configured order) is used.
+/// Unhealthy brokers are still tried as last resort, so we never run out of candidates.
+pub(super) struct BrokerPool {
+    urls: Vec<String>,
+    state: Mutex<PoolState>,
+    cooldown: Duration,
+}
+
+impl BrokerPool {
+    pub fn new(urls: Vec<String>) -> Self {
+        let down_until = vec![None; urls.len()];
+        Self {
+            urls,
+            state: Mutex::new(PoolState {
+                current: 0,
+                down_until,
+            }),
+            cooldown: UNHEALTHY_COOLDOWN,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn url(&self, idx: usize) -> &str {
+        &self.urls[idx]
+    }
+
+    pub fn current_url(&self) -> &str {
+        self.urls
+            .get(self.state.lock().unwrap().current)
+            .map(|s| s.as_str())
+            .unwrap_or_default()
+    }
+
+    /// Indexes of brokers to try, in order: current broker if healthy, then healthy ones
+    /// in configured order, and finally the unhealthy ones
+    pub fn candidates(&self) -> Vec<usize> {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let is_healthy = |idx: usize| state.down_until[idx].is_none_or(|until| until <= now);
+
+        let ordered = std::iter::once(state.current)
+            .chain((0..self.urls.len()).filter(|idx| *idx != state.current))
+            .filter(|idx| *idx < self.urls.len());
+        let (healthy, unhealthy): (Vec<usize>, Vec<usize>) =
+            ordered.partition(|idx| is_healthy(*idx));
+        healthy.into_iter().chain(unhealthy).collect()
+    }
+
+    pub fn mark_ok(&self, idx: usize) {
+        let mut state = self.state.lock().unwrap();
+        if state.current != idx {
+            crate::log::info!("Switched to broker {}", self.urls[idx]);
+        }
+        state.current = idx;
+        state.down_until[idx] = None;
+    }
+
+    pub fn mark_failed(&self, idx: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.down_until[idx] = Some(Instant::now() + self.cooldown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> BrokerPool {
+        BrokerPool::new(vec!["a".into(), "b".into(), "c".into()])
+    }
+
+    #[test]
+    fn test_initial_order() {
+        assert_eq!(pool().candidates(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_failed_goes_last() {
+        let pool = pool();
+        pool.mark_failed(0);
+        assert_eq!(pool.candidates(), vec![1, 2, 0]);
+        pool.mark_failed(1);
+        assert_eq!(pool.candidates(), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn test_sticky() {
+        let pool = pool();
+        pool.mark_failed(0);
+        pool.mark_ok(1);
+        assert_eq!(pool.current_url(), "b");
+        // Even if first one is healthy again, we keep using current one
+        pool.mark_ok(0);
+        pool.mark_ok(1);
+        assert_eq!(pool.candidates(), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_cooldown_expires() {
+        let pool = pool().with_cooldown(Duration::from_millis(0));
+        pool.mark_failed(0);
+        assert_eq!(pool.candidates(), vec![0, 1, 2]);
+    }
+}
@@ -27,7 +27,7 @@
 Author: Adolfo Gómez, dkmaster at dkmon dot com
 */
 use rand::prelude::*;
-use reqwest::{Client, ClientBuilder, retry};
+use reqwest::{Client, ClientBuilder};
 use serde::{Deserialize, Serialize};
 
 use crate::log;
@@ -37,10 +37,16 @@ pub mod types;
 
 pub mod block;
 
+mod failover;
+pub mod retry;
+
 use anyhow::Result;
 use async_trait::async_trait;
 
-use crate::tls::CertificateInfo;
+use crate::tls::{
+    CertificateInfo,
+    trust::{self, TrustPath},
+};
 
 /// Trait that contains the public API methods of BrokerApi (everything except `new`)
 #[async_trait]
@@ -100,20 +106,41 @@ pub trait BrokerApi: Send + Sync {
 
     async fn log(&self, level: types::LogLevel, message: &str) -> Result<String, types::RestError>;
 
+    // Several log entries on one request. Only if broker has the log_batch feature
+    async fn log_batch(&self, entries: &[types::LogEntry]) -> Result<String, types::RestError>;
+
     async fn test(&self) -> Result<String, types::RestError>;
+
+    // Periodic status, so broker can tell a hung actor from an idle one
+    async fn heartbeat(&self, status: &types::ActorStatus) -> Result<String, types::RestError>;
+
+    // Asks broker for supported API versions and features, and keeps them for next requests.
+    // Brokers without the probe are considered v3 only
+    async fn probe_capabilities(&self) -> Result<types::BrokerCapabilities, types::RestError>;
+
+    fn capabilities(&self) -> types::BrokerCapabilities;
+
+    /// Result of the last request sent to broker, if any
+    fn last_call(&self) -> Option<types::BrokerCallResult>;
 }
 
 /// Client for REST API
 pub struct UdsBrokerApi {
     client: Client,
-    api_url: String,
+    brokers: failover::BrokerPool,
+    capabilities: std::sync::RwLock<types::BrokerCapabilities>, // Negotiated on probe_capabilities
+    verify_ssl: bool,
+    verifier: Option<std::sync::Arc<trust::BrokerVerifier>>,
+    // If TLS settings are invalid, no request is sent (fail closed)
+    config_error: Option<String>,
     secret: Option<String>,
     token: String,
     actor_type: crate::config::ActorType,
     custom_headers: reqwest::header::HeaderMap,
     // For retries
-    retries: u8,
-    initial_backoff: std::time::Duration,
+    retry_policies: retry::RetryPolicies,
+    breaker: retry::CircuitBreaker,
+    last_call: std::sync::Mutex<Option<types::BrokerCallResult>>,
 }
 
 impl UdsBrokerApi {
@@ -122,11 +149,10 @@ impl UdsBrokerApi {
         skip_proxy: bool,
         timeout: Option<std::time::Duration>,
     ) -> Self {
-        let policy = retry::for_host(cfg.broker_url.clone()).max_retries_per_request(5);
-
+        // Retries are handled by us (see do_request), so they do not multiply
         let mut builder = ClientBuilder::new()
             .use_rustls_tls() // Use rustls for TLS
-            .retry(policy)
+            .retry(reqwest::retry::never())
             .timeout(timeout.unwrap_or(std::time::Duration::from_secs(2)))
             .connection_verbose(cfg!(debug_assertions))
             .danger_accept_invalid_certs(!cfg.verify_ssl);
@@ -135,6 +161,22 @@ impl UdsBrokerApi {
             builder = builder.no_proxy();
         }
 
+        // Custom CA bundle, SPKI pins and/or client certificate (mTLS) replace the default
+        // TLS configuration
+        let mut verifier = None;
+        let mut config_error = None;
+        match Self::custom_tls(&cfg) {
+            Ok(Some((tls_config, v))) => {
+                builder = builder.tls_backend_preconfigured(tls_config);
+                verifier = Some(v);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("Invalid broker TLS configuration: {}", e);
+                config_error = Some(format!("Invalid broker TLS configuration: {}", e));
+            }
+        }
+
         // panic if client cannot be built, as this is a programming error (invalid URL, etc)
         let client = builder.build().unwrap();
 
@@ -146,21 +188,43 @@ impl UdsBrokerApi {
                 .map(char::from)
                 .collect(),
         );
-        let api_url = cfg.broker_url.clone();
+        let brokers = failover::BrokerPool::new(cfg.broker_urls());
         let actor_type = cfg.actor_type.clone();
 
         Self {
-            api_url,
+            brokers,
+            capabilities: std::sync::RwLock::new(types::BrokerCapabilities::default()),
+            verify_ssl: cfg.verify_ssl,
+            verifier,
+            config_error,
             client,
             secret,
             token: cfg.token(),
             actor_type,
             custom_headers: reqwest::header::HeaderMap::new(),
-            retries: 3,
-            initial_backoff: std::time::Duration::from_millis(500),
+            retry_policies: retry::RetryPolicies::default(),
+            breaker: retry::CircuitBreaker::default(),
+            last_call: std::sync::Mutex::new(None),
         }
     }
 
+    // Builds the rustls config if anything but the default verification is needed
+    fn custom_tls(
+        cfg: &crate::config::ActorConfiguration,
+    ) -> Result<Option<(rustls::ClientConfig, std::sync::Arc<trust::BrokerVerifier>)>> {
+        let client_cert = cfg.client_certinfo()?;
+        if cfg.ca_bundle.is_none() && cfg.broker_pins.is_empty() && client_cert.is_none() {
+            return Ok(None);
+        }
+        let verifier = std::sync::Arc::new(trust::BrokerVerifier::new(
+            cfg.ca_bundle.as_deref(),
+            &cfg.broker_pins,
+            cfg.verify_ssl,
+        )?);
+        let tls_config = trust::client_config(verifier.clone(), client_cert.as_ref())?;
+        Ok(Some((tls_config, verifier)))
+    }
+
     fn headers(&self) -> reqwest::header::HeaderMap {
         use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue, USER_AGENT};
         let mut headers = HeaderMap::new();
@@ -176,17 +240,51 @@ impl UdsBrokerApi {
         headers
     }
 
+    /// Sets the retry policy for endpoints without a specific one
     pub fn set_retry_params(&mut self, retries: u8, initial_backoff: std::time::Duration) {
-        self.retries = retries;
-        self.initial_backoff = initial_backoff;
+        self.retry_policies.set_default(retry::RetryPolicy {
+            max_retries: retries,
+            initial_backoff,
+            ..retry::RetryPolicy::standard()
+        });
+    }
+
+    /// Sets the retry policy for an endpoint (i.e. "logout")
+    pub fn set_retry_policy(&mut self, endpoint: &str, policy: retry::RetryPolicy) {
+        self.retry_policies.set(endpoint, policy);
     }
 
-    fn api_url(&self, method: &str) -> String {
-        // if / is on url, do not transform (already a path), else add consts::REST_ACTOR_PATH
+    pub fn set_breaker_params(&mut self, threshold: u32, cooldown: std::time::Duration) {
+        self.breaker = retry::CircuitBreaker::new(threshold, cooldown);
+    }
+
+    fn api_url(&self, broker: usize, method: &str) -> String {
+        let base = self.brokers.url(broker);
+        // if / is on url, do not transform (already a path), else add the actor api path
         if method.contains('/') {
-            self.api_url.clone() + method
+            base.to_string() + method
         } else {
-            self.api_url.clone() + consts::REST_ACTOR_PATH + method
+            base.to_string() + self.api_version().path() + method
+        }
+    }
+
+    /// Actor API version negotiated with broker (v3 until probed)
+    pub fn api_version(&self) -> types::ApiVersion {
+        self.capabilities.read().unwrap().api_version()
+    }
+
+    /// Url of the broker currently in use
+    pub fn broker_url(&self) -> &str {
+        self.brokers.current_url()
+    }
+
+    /// How the broker certificate was trusted. With custom TLS settings, this is
+    /// the path that validated the last handshake (None until a connection is made)
+    pub fn trust_path(&self) -> Option<TrustPath> {
+        match &self.verifier {
+            Some(verifier) => verifier.last_path(),
+            None if self.verify_ssl => Some(TrustPath::SystemRoots { pinned: false }),
+            None => Some(TrustPath::Unverified),
         }
     }
 
@@ -194,100 +292,155 @@ impl UdsBrokerApi {
         self.secret.clone()
     }
 
-    async fn do_post<T: for<'de> Deserialize<'de>, P: Serialize>(
-        &self,
-        method: &str,
-        payload: &P,
+    // Converts a response into T, or into a typed RestError if not successful or not decodable
+    async fn process_response<T: for<'de> Deserialize<'de>>(
+        resp: reqwest::Response,
     ) -> Result<T, types::RestError> {
-        log::debug!("POST to {}", self.api_url(method));
+        let status = resp.status();
+        let retry_after = match status.as_u16() {
+            429 | 503 => resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(retry::parse_retry_after),
+            _ => None,
+        };
+        let body = resp.text().await?;
+        if !status.is_success() {
+            return Err(
+                types::RestError::from_status(status.as_u16(), &body).with_retry_after(retry_after)
+            );
+        }
+        serde_json::from_str::<T>(&body).map_err(|e| types::RestError::decode(e.to_string(), &body))
+    }
 
-        let mut backoff = self.initial_backoff;
+    // Tries the request on every broker, starting with the current one, until one answers.
+    // Brokers failing with transient errors (connection refused, timeouts, 503, ...) are
+    // marked as unhealthy, and next one is tried.
+    async fn try_brokers<T: for<'de> Deserialize<'de>, P: Serialize>(
+        &self,
+        method: &reqwest::Method,
+        path: &str,
+        payload: Option<&P>,
+    ) -> Result<T, types::RestError> {
+        let mut last_error = None;
+        for broker in self.brokers.candidates() {
+            let url = self.api_url(broker, path);
+            log::debug!("{} to {}", method, url);
 
-        for attempt in 0..=self.retries {
-            let resp = self
+            let mut req = self
                 .client
-                .post(self.api_url(method))
-                .headers(self.headers())
-                .json(payload)
-                .send()
-                .await;
-
-            match resp {
-                Ok(resp) if resp.status().is_success() => {
-                    let json = resp
-                        .json::<T>()
-                        .await
-                        .map_err(|e| types::RestError::Other(e.to_string()))?;
-                    return Ok(json);
-                }
-                Ok(resp) => {
-                    let txt = resp.text().await.unwrap_or_default();
-                    return Err(types::RestError::Other(txt));
+                .request(method.clone(), url)
+                .headers(self.headers());
+            if let Some(payload) = payload {
+                req = req.json(payload);
+            }
+
+            let result = match req.send().await {
+                Ok(resp) => Self::process_response::<T>(resp).await,
+                Err(e) => Err(e.into()),
+            };
+
+            match result {
+                Err(e) if e.is_retryable() => {
+                    log::warn!("Broker {} failed: {}", self.brokers.url(broker), e);
+                    self.brokers.mark_failed(broker);
+                    last_error = Some(e);
                 }
-                Err(e) if e.is_timeout() || e.is_connect() => {
-                    if attempt < self.retries {
-                        log::warn!("POST failed ({}), retrying in {:?}...", e, backoff);
-                        tokio::time::sleep(backoff).await;
-                        backoff = std::cmp::min(backoff * 2, std::time::Duration::from_secs(8));
-                        continue;
-                    } else {
-                        return Err(types::RestError::Connection(e.to_string()));
-                    }
+                result => {
+                    self.brokers.mark_ok(broker);
+                    return result;
                 }
-                Err(e) => return Err(types::RestError::Connection(e.to_string())),
             }
         }
-        unreachable!()
+        Err(last_error.unwrap_or_else(|| types::RestError::other("No broker url configured")))
     }
 
-    async fn do_get<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T, types::RestError> {
-        log::debug!("GET to {}", url);
+    async fn do_request<T: for<'de> Deserialize<'de>, P: Serialize>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        payload: Option<&P>,
+    ) -> Result<T, types::RestError> {
+        let started = std::time::Instant::now();
+        let result = self.retrying_request(method, path, payload).await;
+        crate::metrics::metrics().record_broker_call(
+            path,
+            result.as_ref().map_or_else(|e| e.kind.as_str(), |_| "ok"),
+            started.elapsed(),
+        );
+        *self.last_call.lock().unwrap() = Some(types::BrokerCallResult {
+            endpoint: path.to_string(),
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            timestamp: chrono::Utc::now().timestamp(),
+        });
+        result
+    }
 
-        let mut backoff = self.initial_backoff;
-        for attempt in 0..=self.retries {
-            let resp = self
-                .client
-                .get(self.api_url(url))
-                .headers(self.headers())
-                .send()
-                .await;
-
-            log::debug!("GET response: {:?}", resp);
-
-            match resp {
-                Ok(resp) if resp.status().is_success() => {
-                    let json = resp
-                        .json::<T>()
-                        .await
-                        .map_err(|e| types::RestError::Other(e.to_string()))?;
-                    return Ok(json);
-                }
-                Ok(resp) => {
-                    let txt = resp.text().await.unwrap_or_default();
-                    return Err(types::RestError::Other(txt));
-                }
-                Err(e) if e.is_timeout() || e.is_connect() => {
-                    log::warn!("GET attempt {} failed: {}", attempt + 1, e);
-                    if attempt < self.retries {
-                        log::warn!("GET failed ({}), retrying in {:?}...", e, backoff);
-                        tokio::time::sleep(backoff).await;
-                        backoff = std::cmp::min(backoff * 2, std::time::Duration::from_secs(8));
-                        continue;
-                    } else {
-                        return Err(types::RestError::Connection(e.to_string()));
-                    }
+    // Sends the request, retrying transient errors as the endpoint policy says
+    async fn retrying_request<T: for<'de> Deserialize<'de>, P: Serialize>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        payload: Option<&P>,
+    ) -> Result<T, types::RestError> {
+        if let Some(err) = &self.config_error {
+            return Err(types::RestError::other(err));
+        }
+        let policy = self.retry_policies.get(path);
+        let mut attempt = 0;
+
+        // Broker known to be down, do not even try. Once started, the retries of the
+        // policy are done anyway (a persistent one must not be cut by the breaker)
+        if let Err(retry_in) = self.breaker.allow() {
+            return Err(types::RestError::unavailable(retry_in));
+        }
+        loop {
+            let result = self.try_brokers(&method, path, payload).await;
+            // Errors that are not transient means that broker is up
+            match &result {
+                Err(e) if e.is_retryable() => self.breaker.record_failure(e.retry_after),
+                _ => self.breaker.record_success(),
+            }
+            match result {
+                Err(e) if e.is_retryable() && attempt < policy.max_retries => {
+                    let Some(wait) = policy.backoff(attempt, e.retry_after) else {
+                        return Err(e);
+                    };
+                    log::warn!(
+                        "{} {} failed ({}), retrying in {:?}...",
+                        method,
+                        path,
+                        e,
+                        wait
+                    );
+                    tokio::time::sleep(wait).await;
+                    attempt += 1;
                 }
-                Err(e) => return Err(types::RestError::Connection(e.to_string())),
+                result => return result,
             }
         }
+    }
+
+    async fn do_post<T: for<'de> Deserialize<'de>, P: Serialize>(
+        &self,
+        method: &str,
+        payload: &P,
+    ) -> Result<T, types::RestError> {
+        self.do_request(reqwest::Method::POST, method, Some(payload))
+            .await
+    }
 
-        unreachable!()
+    async fn do_get<T: for<'de> Deserialize<'de>>(&self, url: &str) -> Result<T, types::RestError> {
+        self.do_request::<T, ()>(reqwest::Method::GET, url, None)
+            .await
     }
 
     pub fn get_token(&self) -> Result<String, types::RestError> {
         let token = self.token.clone();
         if token.is_empty() {
-            return Err(types::RestError::Other("No token set".to_string()));
+            return Err(types::RestError::other("No token set"));
         }
         Ok(token)
     }
@@ -303,7 +456,7 @@ impl BrokerApi for UdsBrokerApi {
         self.secret
             .as_ref()
             .map(|s| s.as_ref())
-            .ok_or_else(|| types::RestError::Other("No secret set".to_string()))
+            .ok_or_else(|| types::RestError::other("No secret set"))
     }
 
     // Will be overriden on first call to initialize
@@ -470,6 +623,22 @@ impl BrokerApi for UdsBrokerApi {
         response.result()
     }
 
+    async fn log_batch(&self, entries: &[types::LogEntry]) -> Result<String, types::RestError> {
+        let token = self.get_token()?;
+        let payload: Vec<types::LogRequest> = entries
+            .iter()
+            .map(|entry| types::LogRequest {
+                token: &token,
+                level: entry.level,
+                message: &entry.message,
+                timestamp: entry.timestamp,
+            })
+            .collect();
+
+        let response: types::ApiResponse<String> = self.do_post("log_batch", &payload).await?;
+        response.result()
+    }
+
     async fn test(&self) -> Result<String, types::RestError> {
         let payload = types::TestRequest {
             actor_type: self.actor_type(),
@@ -479,6 +648,45 @@ impl BrokerApi for UdsBrokerApi {
         let response: types::ApiResponse<String> = self.do_post("test", &payload).await?;
         response.result()
     }
+
+    async fn heartbeat(&self, status: &types::ActorStatus) -> Result<String, types::RestError> {
+        let payload = types::HeartbeatRequest {
+            token: &self.get_token()?,
+            status,
+        };
+
+        let response: types::ApiResponse<String> = self.do_post("heartbeat", &payload).await?;
+        response.result()
+    }
+
+    async fn probe_capabilities(&self) -> Result<types::BrokerCapabilities, types::RestError> {
+        let capabilities = match self
+            .do_get::<types::ApiResponse<types::BrokerCapabilities>>(consts::REST_CAPABILITIES_PATH)
+            .await
+        {
+            Ok(response) => response.result()?,
+            Err(e) if e.kind == types::RestErrorKind::NotFound => {
+                log::info!("Broker does not support capabilities probe, using v3 API");
+                types::BrokerCapabilities::default()
+            }
+            Err(e) => return Err(e),
+        };
+        log::info!(
+            "Broker capabilities: {:?}, using API {:?}",
+            capabilities,
+            capabilities.api_version()
+        );
+        *self.capabilities.write().unwrap() = capabilities.clone();
+        Ok(capabilities)
+    }
+
+    fn capabilities(&self) -> types::BrokerCapabilities {
+        self.capabilities.read().unwrap().clone()
+    }
+
+    fn last_call(&self) -> Option<types::BrokerCallResult> {
+        self.last_call.lock().unwrap().clone()
+    }
 }
 
 #[cfg(test)]
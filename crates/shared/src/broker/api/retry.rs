@@ -0,0 +1,328 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::prelude::*;
+
+// Consecutive failed attempts that open the breaker
+const BREAKER_THRESHOLD: u32 = 3;
+// Time the breaker stays open before letting a trial request through
+const BREAKER_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// How a request is retried on transient errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u8,
+    pub initial_backoff: Duration,
+    // Upper bound of a single wait. A Retry-After longer than this is not waited for
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub const fn never() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    pub const fn standard() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+        }
+    }
+
+    // For notifications that must reach the broker (i.e. logout)
+    pub const fn persistent() -> Self {
+        Self {
+            max_retries: 8,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    /// Wait before retry number `attempt` (0 based).
+    /// Full jitter: random between 0 and the exponential backoff, so actors do not retry in sync.
+    /// If broker sent a Retry-After, it is honoured (None if longer than max_backoff)
+    pub fn backoff(&self, attempt: u8, retry_after: Option<Duration>) -> Option<Duration> {
+        if let Some(retry_after) = retry_after {
+            return (retry_after <= self.max_backoff).then_some(retry_after);
+        }
+        let ceiling = self
+            .initial_backoff
+            .saturating_mul(1u32 << attempt.min(16))
+            .min(self.max_backoff);
+        Some(ceiling.mul_f64(rand::rng().random::<f64>()))
+    }
+}
+
+/// Parses a Retry-After header value, either delay-seconds or an HTTP date
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    httpdate::parse_http_date(value).ok().map(|when| {
+        when.duration_since(std::time::SystemTime::now())
+            .unwrap_or_default()
+    })
+}
+
+/// Retry policies, by endpoint (the method path, i.e. "logout")
+#[derive(Debug, Clone)]
+pub struct RetryPolicies {
+    default: RetryPolicy,
+    endpoints: Vec<(String, RetryPolicy)>,
+}
+
+impl Default for RetryPolicies {
+    fn default() -> Self {
+        Self {
+            default: RetryPolicy::standard(),
+            endpoints: vec![
+                // Logs are not important enough to retry, and can be a lot
+                ("log".to_string(), RetryPolicy::never()),
+                ("log_batch".to_string(), RetryPolicy::never()),
+                // Next heartbeat will come soon anyway
+                ("heartbeat".to_string(), RetryPolicy::never()),
+                // Losing a logout leaves the userservice "in use" on broker
+                ("logout".to_string(), RetryPolicy::persistent()),
+            ],
+        }
+    }
+}
+
+impl RetryPolicies {
+    pub fn get(&self, endpoint: &str) -> RetryPolicy {
+        self.endpoints
+            .iter()
+            .find(|(name, _)| name == endpoint)
+            .map(|(_, policy)| *policy)
+            .unwrap_or(self.default)
+    }
+
+    pub fn set_default(&mut self, policy: RetryPolicy) {
+        self.default = policy;
+    }
+
+    pub fn set(&mut self, endpoint: &str, policy: RetryPolicy) {
+        self.endpoints.retain(|(name, _)| name != endpoint);
+        self.endpoints.push((endpoint.to_string(), policy));
+    }
+}
+
+struct BreakerState {
+    failures: u32,                // Consecutive failures
+    open_until: Option<Instant>,  // If open, until when
+    trial_since: Option<Instant>, // Half open, a trial request is in flight
+}
+
+/// Circuit breaker. After some consecutive failures (or a Retry-After from broker), calls are
+/// short-circuited until cooldown expires. Then, a single trial request is let through,
+/// closing the breaker if it succeeds or opening it again if not.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(BREAKER_THRESHOLD, BREAKER_COOLDOWN)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::new(BreakerState {
+                failures: 0,
+                open_until: None,
+                trial_since: None,
+            }),
+        }
+    }
+
+    /// Ok if the call can proceed, Err with remaining time if the breaker is open
+    pub fn allow(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let Some(open_until) = state.open_until else {
+            return Ok(());
+        };
+        if now < open_until {
+            return Err(open_until - now);
+        }
+        // Half open, only one trial at a time (a trial lost, i.e. cancelled, expires on cooldown)
+        match state.trial_since {
+            Some(since) if now < since + self.cooldown => Err(since + self.cooldown - now),
+            _ => {
+                state.trial_since = Some(now);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.open_until.is_some() {
+            crate::log::info!("Broker is reachable again, circuit breaker closed");
+        }
+        state.failures = 0;
+        state.open_until = None;
+        state.trial_since = None;
+    }
+
+    pub fn record_failure(&self, retry_after: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = state.failures.saturating_add(1);
+        let trial_failed = state.trial_since.take().is_some();
+        if trial_failed || retry_after.is_some() || state.failures >= self.threshold {
+            let open_for = retry_after.unwrap_or(self.cooldown);
+            if state.open_until.is_none() {
+                crate::log::warn!("Broker is down, circuit breaker open for {:?}", open_for);
+            }
+            state.open_until = Some(Instant::now() + open_for);
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open_until.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_full_jitter() {
+        let policy = RetryPolicy::standard();
+        for attempt in 0..10 {
+            let ceiling = policy
+                .initial_backoff
+                .saturating_mul(1 << attempt)
+                .min(policy.max_backoff);
+            let wait = policy.backoff(attempt, None).unwrap();
+            assert!(wait <= ceiling, "{:?} > {:?}", wait, ceiling);
+        }
+    }
+
+    #[test]
+    fn test_backoff_retry_after() {
+        let policy = RetryPolicy::standard();
+        assert_eq!(
+            policy.backoff(0, Some(Duration::from_secs(2))),
+            Some(Duration::from_secs(2))
+        );
+        // Too long, not waited for
+        assert_eq!(policy.backoff(0, Some(Duration::from_secs(60))), None);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 5 "), Some(Duration::from_secs(5)));
+        // Dates in the past mean "now"
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let future =
+            httpdate::fmt_http_date(std::time::SystemTime::now() + Duration::from_secs(3600));
+        let parsed = parse_retry_after(&future).unwrap();
+        assert!(parsed > Duration::from_secs(3500) && parsed <= Duration::from_secs(3600));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_endpoint_policies() {
+        let mut policies = RetryPolicies::default();
+        assert_eq!(policies.get("log"), RetryPolicy::never());
+        assert_eq!(policies.get("logout"), RetryPolicy::persistent());
+        assert_eq!(policies.get("test"), RetryPolicy::standard());
+        policies.set_default(RetryPolicy::never());
+        policies.set("logout", RetryPolicy::standard());
+        assert_eq!(policies.get("test"), RetryPolicy::never());
+        assert_eq!(policies.get("logout"), RetryPolicy::standard());
+    }
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.record_failure(None);
+        assert!(breaker.allow().is_ok());
+        breaker.record_failure(None);
+        assert!(breaker.is_open());
+        assert!(breaker.allow().is_err());
+    }
+
+    #[test]
+    fn test_breaker_success_resets() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.record_failure(None);
+        breaker.record_success();
+        breaker.record_failure(None);
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn test_breaker_retry_after_opens() {
+        let breaker = CircuitBreaker::new(5, Duration::from_secs(60));
+        breaker.record_failure(Some(Duration::from_secs(10)));
+        let remaining = breaker.allow().unwrap_err();
+        assert!(remaining <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_breaker_half_open() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        breaker.record_failure(None);
+        assert!(breaker.allow().is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        // One trial is allowed, the rest wait for its result
+        assert!(breaker.allow().is_ok());
+        assert!(breaker.allow().is_err());
+        // Trial failed, open again
+        breaker.record_failure(None);
+        assert!(breaker.allow().is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.allow().is_ok());
+        breaker.record_success();
+        assert!(!breaker.is_open());
+        assert!(breaker.allow().is_ok());
+        assert!(breaker.allow().is_ok());
+    }
+}
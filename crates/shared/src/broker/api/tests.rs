@@ -118,6 +118,121 @@ async fn test_enumerate_authenticators() {
     assert_eq!(auths[1].id, "auth2");
 }
 
+#[test]
+fn test_authenticator_field_sets() {
+    // 4.x field names
+    let old: types::Authenticator = serde_json::from_str(
+        r#"{"auth_id": "a1", "auth_label": "Auth One", "auth": "auth1", "type": "t", "priority": 1, "custom": false}"#,
+    )
+    .unwrap();
+    // 5.x field names
+    let new: types::Authenticator = serde_json::from_str(
+        r#"{"id": "a1", "label": "Auth One", "name": "auth1", "type": "t", "priority": 1, "custom": false}"#,
+    )
+    .unwrap();
+    // 5.0 servers send both of them
+    let both: types::Authenticator = serde_json::from_str(
+        r#"{"id": "a1", "auth_id": "a1", "label": "Auth One", "auth_label": "Auth One", "name": "auth1", "auth": "auth1", "type": "t", "priority": 1, "custom": false}"#,
+    )
+    .unwrap();
+    for auth in [old, new, both] {
+        assert_eq!(auth.id, "a1");
+        assert_eq!(auth.label, "Auth One");
+        assert_eq!(auth.name, "auth1");
+        assert_eq!(auth.auth_type, "t");
+    }
+    // Id is required
+    assert!(
+        serde_json::from_str::<types::Authenticator>(r#"{"name": "auth1", "type": "t"}"#).is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_probe_capabilities() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let _m = server
+        .mock("GET", format!("/{}", consts::REST_CAPABILITIES_PATH).as_str())
+        .with_body(
+            r#"{"result": {"version": "5.1.0", "api_versions": ["v3", "v4", "v9"], "features": ["heartbeat"]}, "error": null}"#,
+        )
+        .with_status(200)
+        .create_async()
+        .await;
+
+    let caps = api.probe_capabilities().await.unwrap();
+    assert_eq!(caps.version.as_deref(), Some("5.1.0"));
+    assert!(caps.has_feature("heartbeat"));
+    assert!(!caps.has_feature("other"));
+    assert_eq!(api.capabilities(), caps);
+    // Highest version known by both
+    assert_eq!(
+        api.api_version(),
+        *consts::SUPPORTED_API_VERSIONS.iter().max().unwrap()
+    );
+
+    // And requests go to the negotiated version path
+    let m = server
+        .mock(
+            "POST",
+            format!("/{}test", api.api_version().path()).as_str(),
+        )
+        .with_body(r#"{"result": "ok", "error": null}"#)
+        .with_status(200)
+        .create_async()
+        .await;
+    assert!(api.test().await.is_ok());
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_probe_capabilities_legacy_broker() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let _m = server
+        .mock(
+            "GET",
+            format!("/{}", consts::REST_CAPABILITIES_PATH).as_str(),
+        )
+        .with_status(404)
+        .create_async()
+        .await;
+
+    let caps = api.probe_capabilities().await.unwrap();
+    assert_eq!(caps, types::BrokerCapabilities::default());
+    assert_eq!(api.api_version(), types::ApiVersion::V3);
+}
+
+#[tokio::test]
+async fn test_probe_capabilities_error_keeps_defaults() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let _m = server
+        .mock(
+            "GET",
+            format!("/{}", consts::REST_CAPABILITIES_PATH).as_str(),
+        )
+        .with_status(403)
+        .create_async()
+        .await;
+
+    assert!(api.probe_capabilities().await.is_err());
+    assert_eq!(api.capabilities(), types::BrokerCapabilities::default());
+}
+
+#[test]
+fn test_capabilities_api_version() {
+    let caps = |versions: &[&str]| types::BrokerCapabilities {
+        api_versions: versions.iter().map(|v| v.to_string()).collect(),
+        ..Default::default()
+    };
+    assert_eq!(caps(&[]).api_version(), types::ApiVersion::V3);
+    assert_eq!(caps(&["v9"]).api_version(), types::ApiVersion::V3);
+    assert_eq!(caps(&["V3"]).api_version(), types::ApiVersion::V3);
+    assert_eq!(types::ApiVersion::V3.path(), consts::REST_ACTOR_PATH);
+    assert_eq!(types::ApiVersion::V4.path(), consts::REST_ACTOR_V4_PATH);
+}
+
 #[tokio::test]
 async fn test_api_login() {
     log::setup_logging("debug", log::LogType::Tests);
@@ -208,6 +323,8 @@ async fn test_initialize() {
                 name: "linux".to_string(),
                 custom: None,
             }),
+            heartbeat_interval: None,
+            allowed_networks: None,
         },
         error: None,
     };
@@ -408,6 +525,79 @@ async fn test_log() {
     assert!(response.is_ok(), "Log failed: {:?}", response);
 }
 
+#[tokio::test]
+async fn test_log_batch() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let result = types::ApiResponse::<String> {
+        result: "ok".to_string(),
+        error: None,
+    };
+    let entries = vec![
+        types::LogEntry {
+            level: types::LogLevel::Info,
+            message: "first".into(),
+            timestamp: 1234567890,
+        },
+        types::LogEntry {
+            level: types::LogLevel::Error,
+            message: "second".into(),
+            timestamp: 1234567891,
+        },
+    ];
+    let _m = server
+        .mock("POST", rest_actor_path("log_batch").as_str())
+        .match_header("content-type", "application/json")
+        .match_body(Matcher::Json(serde_json::json!([
+            {"token": "token", "level": 30000, "message": "first", "timestamp": 1234567890},
+            {"token": "token", "level": 50000, "message": "second", "timestamp": 1234567891},
+        ])))
+        .with_body(serde_json::to_string(&result).unwrap())
+        .with_status(200)
+        .create_async()
+        .await;
+    let response = api.log_batch(&entries).await;
+    assert!(response.is_ok(), "Log batch failed: {:?}", response);
+}
+
+#[tokio::test]
+async fn test_heartbeat() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let result = types::ApiResponse::<String> {
+        result: "ok".to_string(),
+        error: None,
+    };
+    let status = types::ActorStatus {
+        uptime: 3600,
+        user: Some("user".into()),
+        session_type: Some("RDP".into()),
+        version: "5.0.0".into(),
+        build: "1".into(),
+        os_version: Some("Linux".into()),
+        client_connected: true,
+    };
+    let _m = server
+        .mock("POST", rest_actor_path("heartbeat").as_str())
+        .match_header("content-type", "application/json")
+        .match_body(Matcher::Json(serde_json::json!({
+            "token": "token",
+            "uptime": 3600,
+            "user": "user",
+            "session_type": "RDP",
+            "version": "5.0.0",
+            "build": "1",
+            "os_version": "Linux",
+            "client_connected": true,
+        })))
+        .with_body(serde_json::to_string(&result).unwrap())
+        .with_status(200)
+        .create_async()
+        .await;
+    let response = api.heartbeat(&status).await;
+    assert!(response.is_ok(), "Heartbeat failed: {:?}", response);
+}
+
 #[tokio::test]
 async fn test_test_managed() {
     log::setup_logging("debug", log::LogType::Tests);
@@ -460,3 +650,602 @@ async fn test_test_unmanaged() {
     let response = api.test().await;
     assert!(response.is_ok(), "Test unmanaged failed: {:?}", response);
 }
+
+#[tokio::test]
+async fn test_error_auth() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let _m = server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_body(r#"{"result": null, "error": "Invalid token"}"#)
+        .with_status(403)
+        .create_async()
+        .await;
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Auth);
+    assert_eq!(err.status, Some(403));
+    assert_eq!(err.code.as_deref(), Some("Invalid token"));
+    assert!(err.is_auth());
+    assert!(!err.is_retryable());
+}
+
+#[tokio::test]
+async fn test_error_not_found() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let _m = server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_body("<html>Not found</html>")
+        .with_status(404)
+        .create_async()
+        .await;
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::NotFound);
+    assert_eq!(err.status, Some(404));
+    assert!(err.code.is_none());
+    assert_eq!(err.body.as_deref(), Some("<html>Not found</html>"));
+}
+
+#[tokio::test]
+async fn test_last_call_is_recorded() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    assert!(api.last_call().is_none());
+
+    let m = server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_body(r#"{"result": "ok", "error": null}"#)
+        .with_status(200)
+        .create_async()
+        .await;
+    api.test().await.unwrap();
+    let last = api.last_call().unwrap();
+    assert_eq!(last.endpoint, "test");
+    assert!(last.ok);
+    assert!(last.error.is_none());
+    m.remove_async().await;
+
+    let _m = server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_status(404)
+        .create_async()
+        .await;
+    api.test().await.unwrap_err();
+    let last = api.last_call().unwrap();
+    assert!(!last.ok);
+    assert!(last.error.unwrap().contains("404"));
+}
+
+#[tokio::test]
+async fn test_error_server_is_retried() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, mut api) = setup_server_and_api(None).await;
+    api.set_retry_params(2, std::time::Duration::from_millis(10));
+    let m = server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_body("x".repeat(4096))
+        .with_status(503)
+        .expect(3)
+        .create_async()
+        .await;
+    let err = api.test().await.unwrap_err();
+    m.assert_async().await;
+    assert_eq!(err.kind, types::RestErrorKind::Server);
+    assert_eq!(err.status, Some(503));
+    assert!(err.is_retryable());
+    // Body is an excerpt, not the full response
+    assert!(err.body.unwrap().len() < 4096);
+}
+
+#[tokio::test]
+async fn test_error_broker_reported() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let _m = server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_body(r#"{"result": "", "error": "Service not found"}"#)
+        .with_status(200)
+        .create_async()
+        .await;
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Server);
+    assert_eq!(err.status, None);
+    assert_eq!(err.code.as_deref(), Some("Service not found"));
+    assert!(!err.is_retryable());
+}
+
+#[tokio::test]
+async fn test_error_decode() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let _m = server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_body("this is not json")
+        .with_status(200)
+        .create_async()
+        .await;
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Decode);
+    assert_eq!(err.body.as_deref(), Some("this is not json"));
+}
+
+#[tokio::test]
+async fn test_error_transport() {
+    log::setup_logging("debug", log::LogType::Tests);
+    // Nothing listens on port 1
+    let config = ActorConfiguration {
+        broker_url: "http://127.0.0.1:1/".to_string(),
+        master_token: Some("token".to_string()),
+        ..Default::default()
+    };
+    let mut api = UdsBrokerApi::new(config, true, None);
+    api.set_retry_params(0, std::time::Duration::from_millis(0));
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Transport);
+    assert!(err.status.is_none());
+    assert!(err.is_retryable());
+}
+
+// Returns an url where nothing is listening, so connections are refused
+fn refused_url() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    format!("http://127.0.0.1:{}/", port)
+}
+
+fn failover_api(urls: Vec<String>) -> UdsBrokerApi {
+    let config = ActorConfiguration {
+        broker_url: urls[0].clone(),
+        fallback_broker_urls: urls[1..].to_vec(),
+        master_token: Some("token".to_string()),
+        ..Default::default()
+    };
+    let mut api = UdsBrokerApi::new(config, true, None);
+    api.set_retry_params(0, std::time::Duration::from_millis(0));
+    api
+}
+
+async fn mock_test_ok(server: &mut mockito::ServerGuard, hits: usize) -> mockito::Mock {
+    server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_body(r#"{"result": "ok", "error": null}"#)
+        .with_status(200)
+        .expect(hits)
+        .create_async()
+        .await
+}
+
+#[tokio::test]
+async fn test_failover_skips_refused_brokers() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let mut server = Server::new_async().await;
+    let m = mock_test_ok(&mut server, 2).await;
+
+    let api = failover_api(vec![refused_url(), refused_url(), server.url() + "/"]);
+    assert!(api.test().await.is_ok());
+    assert_eq!(api.broker_url(), server.url() + "/");
+    // Sticky, goes directly to the working broker
+    assert!(api.test().await.is_ok());
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_failover_is_sticky() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let mut primary = Server::new_async().await;
+    let mut secondary = Server::new_async().await;
+    // Primary is unavailable once, then recovers
+    let m_primary_down = primary
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_status(503)
+        .expect(1)
+        .create_async()
+        .await;
+    let m_secondary = mock_test_ok(&mut secondary, 3).await;
+
+    let api = failover_api(vec![primary.url() + "/", secondary.url() + "/"]);
+    for _ in 0..3 {
+        assert!(api.test().await.is_ok());
+    }
+    m_primary_down.assert_async().await;
+    m_secondary.assert_async().await;
+    assert_eq!(api.broker_url(), secondary.url() + "/");
+}
+
+#[tokio::test]
+async fn test_failover_does_not_hide_broker_errors() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let mut primary = Server::new_async().await;
+    let mut secondary = Server::new_async().await;
+    let _m_primary = primary
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_body(r#"{"result": null, "error": "Invalid token"}"#)
+        .with_status(403)
+        .create_async()
+        .await;
+    let m_secondary = mock_test_ok(&mut secondary, 0).await;
+
+    // An answer from broker, even an error, is not a reason to failover
+    let api = failover_api(vec![primary.url() + "/", secondary.url() + "/"]);
+    let err = api.test().await.unwrap_err();
+    assert!(err.is_auth());
+    m_secondary.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_failover_all_refused() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let api = failover_api(vec![refused_url(), refused_url()]);
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Transport);
+}
+
+// Starts a local HTTPS broker (certificate signed by the testing CA) answering to "test"
+async fn tls_broker_url() -> String {
+    crate::tls::init_tls(None);
+    let tls_config = crate::tls::certool::rustls_config_from_pem(
+        crate::testing::test_certs::test_broker_certinfo(),
+    )
+    .unwrap();
+    serve_tls(tls_config)
+}
+
+// Same as tls_broker_url, but requiring a client certificate signed by the testing CA
+async fn mtls_broker_url() -> String {
+    use rustls_pki_types::pem::PemObject;
+
+    crate::tls::init_tls(None);
+    let (chain, key) = crate::tls::certool::certified_key_from_pem(
+        &crate::testing::test_certs::test_broker_certinfo(),
+    )
+    .unwrap();
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_pki_types::CertificateDer::pem_file_iter(crate::testing::test_certs::CA_PATH)
+        .unwrap()
+    {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let client_verifier = rustls::server::WebPkiClientVerifier::builder(std::sync::Arc::new(roots))
+        .build()
+        .unwrap();
+    let server_config = rustls::ServerConfig::builder()
+        .with_client_cert_verifier(client_verifier)
+        .with_single_cert(chain, key)
+        .unwrap();
+    serve_tls(axum_server::tls_rustls::RustlsConfig::from_config(
+        std::sync::Arc::new(server_config),
+    ))
+}
+
+fn serve_tls(tls_config: axum_server::tls_rustls::RustlsConfig) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let app = axum::Router::new().route(
+        &rest_actor_path("test"),
+        axum::routing::post(|| async { r#"{"result": "ok", "error": null}"# }),
+    );
+    let server = axum_server::from_tcp_rustls(listener, tls_config).unwrap();
+    tokio::spawn(async move {
+        server.serve(app.into_make_service()).await.unwrap();
+    });
+    format!("https://localhost:{}/", port)
+}
+
+fn tls_api(
+    url: String,
+    ca_bundle: Option<&str>,
+    pins: Vec<String>,
+    verify_ssl: bool,
+) -> UdsBrokerApi {
+    let config = ActorConfiguration {
+        broker_url: url,
+        verify_ssl,
+        ca_bundle: ca_bundle.map(|s| s.to_string()),
+        broker_pins: pins,
+        master_token: Some("token".to_string()),
+        ..Default::default()
+    };
+    let mut api = UdsBrokerApi::new(config, true, None);
+    api.set_retry_params(0, std::time::Duration::from_millis(0));
+    api
+}
+
+fn broker_pin() -> String {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+    use rustls_pki_types::pem::PemObject;
+
+    let pem = crate::testing::test_certs::test_broker_certinfo().certificate;
+    let cert = rustls_pki_types::CertificateDer::from_pem_slice(pem.as_bytes()).unwrap();
+    format!(
+        "sha256/{}",
+        STANDARD.encode(trust::spki_sha256(&cert).unwrap())
+    )
+}
+
+#[tokio::test]
+async fn test_tls_ca_bundle() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = tls_broker_url().await;
+    let api = tls_api(url, Some(crate::testing::test_certs::CA_PATH), vec![], true);
+    assert_eq!(api.trust_path(), None);
+    assert!(api.test().await.is_ok());
+    assert_eq!(
+        api.trust_path(),
+        Some(TrustPath::CaBundle { pinned: false })
+    );
+}
+
+#[tokio::test]
+async fn test_tls_ca_bundle_and_pin() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = tls_broker_url().await;
+    let api = tls_api(
+        url,
+        Some(crate::testing::test_certs::CA_PATH),
+        vec![broker_pin()],
+        true,
+    );
+    assert!(api.test().await.is_ok());
+    assert_eq!(api.trust_path(), Some(TrustPath::CaBundle { pinned: true }));
+}
+
+#[tokio::test]
+async fn test_tls_pin_only() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = tls_broker_url().await;
+    // Chain is not validated, but the pin is
+    let api = tls_api(url, None, vec![broker_pin()], false);
+    assert!(api.test().await.is_ok());
+    assert_eq!(api.trust_path(), Some(TrustPath::SpkiPin));
+}
+
+#[tokio::test]
+async fn test_tls_wrong_pin() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = tls_broker_url().await;
+    let wrong_pin = format!("sha256/{}", "A".repeat(43) + "=");
+    let api = tls_api(
+        url,
+        Some(crate::testing::test_certs::CA_PATH),
+        vec![wrong_pin],
+        true,
+    );
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Transport);
+    assert_eq!(api.trust_path(), None);
+}
+
+#[tokio::test]
+async fn test_tls_untrusted_ca() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = tls_broker_url().await;
+    // Testing CA is not on system roots
+    let api = tls_api(url, None, vec![], true);
+    assert!(api.test().await.is_err());
+    assert_eq!(
+        api.trust_path(),
+        Some(TrustPath::SystemRoots { pinned: false })
+    );
+}
+
+#[tokio::test]
+async fn test_tls_invalid_trust_config_fails_closed() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = tls_broker_url().await;
+    let api = tls_api(url, Some("/nonexistent/ca.pem"), vec![], false);
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Other);
+}
+
+fn mtls_api(url: String, client_key: Option<&str>, password: Option<&str>) -> UdsBrokerApi {
+    let config = ActorConfiguration {
+        broker_url: url,
+        verify_ssl: true,
+        ca_bundle: Some(crate::testing::test_certs::CA_PATH.to_string()),
+        client_cert: client_key.map(|_| crate::testing::test_certs::CLIENT_CERT_PATH.to_string()),
+        client_key: client_key.map(|s| s.to_string()),
+        client_key_password: password.map(|s| s.to_string()),
+        master_token: Some("token".to_string()),
+        ..Default::default()
+    };
+    let mut api = UdsBrokerApi::new(config, true, None);
+    api.set_retry_params(0, std::time::Duration::from_millis(0));
+    api
+}
+
+#[tokio::test]
+async fn test_mtls_client_cert() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = mtls_broker_url().await;
+    let api = mtls_api(url, Some(crate::testing::test_certs::CLIENT_KEY_PATH), None);
+    assert!(api.test().await.is_ok());
+    assert_eq!(
+        api.trust_path(),
+        Some(TrustPath::CaBundle { pinned: false })
+    );
+}
+
+#[tokio::test]
+async fn test_mtls_encrypted_client_key() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = mtls_broker_url().await;
+    let api = mtls_api(
+        url,
+        Some(crate::testing::test_certs::CLIENT_KEY_PASS_PATH),
+        Some(crate::testing::test_certs::KEY_PASSWORD),
+    );
+    assert!(api.test().await.is_ok());
+}
+
+#[tokio::test]
+async fn test_mtls_without_client_cert_is_rejected() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = mtls_broker_url().await;
+    let api = mtls_api(url, None, None);
+    assert!(api.test().await.is_err());
+}
+
+#[tokio::test]
+async fn test_mtls_wrong_key_password_fails_closed() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let url = mtls_broker_url().await;
+    let api = mtls_api(
+        url,
+        Some(crate::testing::test_certs::CLIENT_KEY_PASS_PATH),
+        Some("wrong_password"),
+    );
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Other);
+}
+
+async fn mock_unavailable(
+    server: &mut mockito::ServerGuard,
+    method: &str,
+    retry_after: Option<&str>,
+    hits: usize,
+) -> mockito::Mock {
+    let mut mock = server
+        .mock("POST", rest_actor_path(method).as_str())
+        .with_status(503)
+        .expect(hits);
+    if let Some(retry_after) = retry_after {
+        mock = mock.with_header("Retry-After", retry_after);
+    }
+    mock.create_async().await
+}
+
+#[tokio::test]
+async fn test_retry_after_is_honoured() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, mut api) = setup_server_and_api(None).await;
+    api.set_retry_params(1, std::time::Duration::from_millis(1));
+    let m = mock_unavailable(&mut server, "test", Some("1"), 2).await;
+
+    let start = std::time::Instant::now();
+    let err = api.test().await.unwrap_err();
+    // Waited for Retry-After, not for the (tiny) backoff
+    assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    assert_eq!(err.retry_after, Some(std::time::Duration::from_secs(1)));
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_retry_after_too_long_is_not_waited() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let m = mock_unavailable(&mut server, "test", Some("120"), 1).await;
+
+    let start = std::time::Instant::now();
+    let err = api.test().await.unwrap_err();
+    assert!(start.elapsed() < std::time::Duration::from_secs(5));
+    assert_eq!(err.retry_after, Some(std::time::Duration::from_secs(120)));
+    m.assert_async().await;
+
+    // And broker is considered down for that time
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Unavailable);
+    assert!(err.is_retryable());
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_log_is_never_retried() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    let m = mock_unavailable(&mut server, "log", None, 1).await;
+
+    let err = api.log(types::LogLevel::Info, "message").await.unwrap_err();
+    assert_eq!(err.status, Some(503));
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_logout_retries_hard() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, api) = setup_server_and_api(None).await;
+    // Retry-After: 0 so the test does not wait for the backoff
+    let m = mock_unavailable(
+        &mut server,
+        "logout",
+        Some("0"),
+        retry::RetryPolicy::persistent().max_retries as usize + 1,
+    )
+    .await;
+
+    let err = api
+        .logout(create_test_id().as_slice(), "user", "session", "id")
+        .await
+        .unwrap_err();
+    assert_eq!(err.status, Some(503));
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_logout_retries_refused() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let config = ActorConfiguration {
+        broker_url: refused_url(),
+        master_token: Some("token".to_string()),
+        ..Default::default()
+    };
+    let mut api = UdsBrokerApi::new(config, true, None);
+    // As persistent, but with short waits. Default breaker opens after 3 failures
+    api.set_retry_policy(
+        "logout",
+        retry::RetryPolicy {
+            initial_backoff: std::time::Duration::from_millis(1),
+            max_backoff: std::time::Duration::from_millis(5),
+            ..retry::RetryPolicy::persistent()
+        },
+    );
+
+    // All retries are done, the breaker does not cut them (nor the error)
+    let err = api
+        .logout(create_test_id().as_slice(), "user", "session", "id")
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Transport);
+
+    // But the next call finds it open
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Unavailable);
+}
+
+#[tokio::test]
+async fn test_breaker_short_circuits() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, mut api) = setup_server_and_api(None).await;
+    api.set_retry_params(0, std::time::Duration::from_millis(0));
+    api.set_breaker_params(2, std::time::Duration::from_secs(60));
+    let m = mock_unavailable(&mut server, "test", None, 2).await;
+
+    for _ in 0..2 {
+        let err = api.test().await.unwrap_err();
+        assert_eq!(err.status, Some(503));
+    }
+    // Broker is known to be down, request is not sent
+    let err = api.test().await.unwrap_err();
+    assert_eq!(err.kind, types::RestErrorKind::Unavailable);
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_breaker_not_opened_by_broker_errors() {
+    log::setup_logging("debug", log::LogType::Tests);
+    let (mut server, mut api) = setup_server_and_api(None).await;
+    api.set_breaker_params(1, std::time::Duration::from_secs(60));
+    let m = server
+        .mock("POST", rest_actor_path("test").as_str())
+        .with_status(403)
+        .expect(3)
+        .create_async()
+        .await;
+
+    // Broker answers, so it's up
+    for _ in 0..3 {
+        assert!(api.test().await.unwrap_err().is_auth());
+    }
+    m.assert_async().await;
+}
@@ -28,13 +28,174 @@ Author: Adolfo Gómez, dkmaster at dkmon dot com
 */
 use serde::{Deserialize, Serialize};
 
+use super::consts;
 use crate::config::{ActorOsConfiguration, ActorType};
 
+// Max size of the response body kept on errors, to avoid logging huge html pages
+const BODY_EXCERPT_LEN: usize = 256;
+
+/// Classification of REST errors, so callers can act on them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestErrorKind {
+    Auth,        // 401/403, token invalid or revoked
+    NotFound,    // 404, wrong url or api path
+    Server,      // 5xx, 408, 429 and errors reported by broker on the `error` field
+    Transport,   // Connection refused, timeout, tls, etc..
+    Decode,      // Response received, but could not be decoded
+    Unavailable, // Not sent, circuit breaker is open because broker is known to be down
+    Other,       // Local errors (no token, no secret, ...) and unexpected status codes
+}
+
+impl RestErrorKind {
+    /// Name used on metrics labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestErrorKind::Auth => "auth",
+            RestErrorKind::NotFound => "not_found",
+            RestErrorKind::Server => "server",
+            RestErrorKind::Transport => "transport",
+            RestErrorKind::Decode => "decode",
+            RestErrorKind::Unavailable => "unavailable",
+            RestErrorKind::Other => "other",
+        }
+    }
+}
+
 /// Possible errors in REST operations
-#[derive(Debug)]
-pub enum RestError {
-    Connection(String),
-    Other(String),
+#[derive(Debug, Clone)]
+pub struct RestError {
+    pub kind: RestErrorKind,
+    pub status: Option<u16>,  // HTTP status, if a response was received
+    pub code: Option<String>, // Broker `error` field, if present
+    pub body: Option<String>, // Excerpt of response body, if any
+    pub retry_after: Option<std::time::Duration>, // Retry-After header, if any
+    pub message: String,
+}
+
+impl RestError {
+    pub fn new(kind: RestErrorKind, message: impl Into<String>) -> Self {
+        RestError {
+            kind,
+            status: None,
+            code: None,
+            body: None,
+            retry_after: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn other(message: impl Into<String>) -> Self {
+        Self::new(RestErrorKind::Other, message)
+    }
+
+    pub fn transport(message: impl Into<String>) -> Self {
+        Self::new(RestErrorKind::Transport, message)
+    }
+
+    pub fn decode(message: impl Into<String>, body: &str) -> Self {
+        RestError {
+            body: Some(excerpt(body)),
+            ..Self::new(RestErrorKind::Decode, message)
+        }
+    }
+
+    /// Error reported by broker on the `error` field of a successful response
+    pub fn broker(code: &str) -> Self {
+        RestError {
+            code: Some(code.to_string()),
+            ..Self::new(RestErrorKind::Server, code)
+        }
+    }
+
+    /// Builds the error from a non-success http response.
+    /// If the body is a broker response, the `error` field is extracted as code
+    pub fn from_status(status: u16, body: &str) -> Self {
+        let kind = match status {
+            401 | 403 => RestErrorKind::Auth,
+            404 => RestErrorKind::NotFound,
+            408 | 429 | 500..=599 => RestErrorKind::Server,
+            _ => RestErrorKind::Other,
+        };
+        let code = serde_json::from_str::<ApiResponse<serde_json::Value>>(body)
+            .ok()
+            .and_then(|r| r.error)
+            .filter(|e| !e.is_empty());
+        let message = match &code {
+            Some(code) => code.clone(),
+            None => format!("HTTP status {}", status),
+        };
+        RestError {
+            kind,
+            status: Some(status),
+            code,
+            body: Some(excerpt(body)),
+            retry_after: None,
+            message,
+        }
+    }
+
+    /// Broker is known to be down, request was not even sent
+    pub fn unavailable(retry_in: std::time::Duration) -> Self {
+        RestError {
+            retry_after: Some(retry_in),
+            ..Self::new(
+                RestErrorKind::Unavailable,
+                format!("broker is down, not retrying for {:?}", retry_in),
+            )
+        }
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Option<std::time::Duration>) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    pub fn is_auth(&self) -> bool {
+        self.kind == RestErrorKind::Auth
+    }
+
+    /// True if the operation may succeed if retried later (transient errors)
+    pub fn is_retryable(&self) -> bool {
+        match self.kind {
+            RestErrorKind::Transport | RestErrorKind::Unavailable => true,
+            RestErrorKind::Server => matches!(self.status, Some(408 | 429 | 502 | 503 | 504)),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for RestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} error", self.kind)?;
+        if let Some(status) = self.status {
+            write!(f, " (HTTP {})", status)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for RestError {}
+
+impl From<reqwest::Error> for RestError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            Self::new(RestErrorKind::Decode, e.to_string())
+        } else if let Some(status) = e.status() {
+            RestError {
+                message: e.to_string(),
+                ..Self::from_status(status.as_u16(), "")
+            }
+        } else {
+            Self::transport(e.to_string())
+        }
+    }
+}
+
+fn excerpt(body: &str) -> String {
+    match body.char_indices().nth(BODY_EXCERPT_LEN) {
+        Some((idx, _)) => format!("{}...", &body[..idx]),
+        None => body.to_string(),
+    }
 }
 
 // ************
@@ -116,6 +277,42 @@ pub struct LogRequest<'a> {
     pub timestamp: i64,
 }
 
+/// Actor status, periodically sent to broker on heartbeat
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ActorStatus {
+    pub uptime: u64, // Seconds since actor started
+    pub user: Option<String>,
+    pub session_type: Option<String>,
+    pub version: String,
+    pub build: String,
+    pub os_version: Option<String>,
+    pub client_connected: bool, // Whether the user session client is connected to our websocket
+}
+
+/// Outcome of the last request made to broker, for the status endpoint
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BrokerCallResult {
+    pub endpoint: String,
+    pub ok: bool,
+    pub error: Option<String>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HeartbeatRequest<'a> {
+    pub token: &'a str,
+    #[serde(flatten)]
+    pub status: &'a ActorStatus,
+}
+
+// Log entry waiting to be shipped to broker (see log_shipper)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEntry {
+    pub level: LogLevel,
+    pub message: String,
+    pub timestamp: i64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct TestRequest<'a> {
     #[serde(rename = "type")]
@@ -139,6 +336,10 @@ pub struct InitializationResponse {
     pub token: Option<String>, // For managed only. Will replace master_token by a new unique token provided by server
     pub unique_id: Option<String>, // Unique ID assigned by server to this
     pub os: Option<ActorOsConfiguration>,
+    #[serde(default)]
+    pub heartbeat_interval: Option<u64>, // Seconds between heartbeats, 0 disables them. None keeps current
+    #[serde(default)]
+    pub allowed_networks: Option<Vec<String>>, // CIDRs allowed to reach us. None keeps current
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -167,9 +368,9 @@ impl<T: Serialize> ApiResponse<T> {
         }
     }
 
-    // Return the error as a reqwest::Error (using a generic error for demonstration)
+    // Return the broker reported error as a RestError
     pub fn error(&self) -> RestError {
-        RestError::Other(self.error.clone().unwrap_or_default())
+        RestError::broker(self.error.as_deref().unwrap_or_default())
     }
 
     pub fn result(self) -> anyhow::Result<T, RestError> {
@@ -199,18 +400,17 @@ impl From<crate::system::NetworkInterface> for InterfaceInfo {
     }
 }
 
-// TODO: On a future, use the new authenticator structure from server
-// when server is updated to a version that supports it
-// Note that renamed fields are already present on 5.0 servers
-// But initally, we will use the old ones for compatibility with older servers
-// So we can use it now on 4.x. Will rename the fields to the new ones asap
+// Authenticators are serialized with the 4.x field names, so older servers/tools keep working.
+// On deserialization, both 4.x (auth_id, auth_label, auth) and 5.x (id, label, name)
+// field sets are accepted. 5.x fields have precedence if both are present
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "AuthenticatorFields")]
 pub struct Authenticator {
-    #[serde(rename = "auth_id")] // On future releases, this will be "id"
+    #[serde(rename = "auth_id")]
     pub id: String,
-    #[serde(rename = "auth_label")] // On future releases, this will be "label"
+    #[serde(rename = "auth_label")]
     pub label: String,
-    #[serde(rename = "auth")] // On future releases, this will be "name"
+    #[serde(rename = "auth")]
     pub name: String,
     #[serde(rename = "type")] // "type" is a reserved word, so we use "auth_type" in struct
     pub auth_type: String,
@@ -218,6 +418,114 @@ pub struct Authenticator {
     pub custom: bool,
 }
 
+// Wire representation of an authenticator, with both field sets
+#[derive(Deserialize)]
+struct AuthenticatorFields {
+    id: Option<String>,
+    auth_id: Option<String>,
+    label: Option<String>,
+    auth_label: Option<String>,
+    name: Option<String>,
+    auth: Option<String>,
+    #[serde(rename = "type")]
+    auth_type: String,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    custom: bool,
+}
+
+impl TryFrom<AuthenticatorFields> for Authenticator {
+    type Error = String;
+
+    fn try_from(fields: AuthenticatorFields) -> Result<Self, Self::Error> {
+        let id = fields
+            .id
+            .or(fields.auth_id)
+            .ok_or("missing field `id` (or `auth_id`)")?;
+        let name = fields
+            .name
+            .or(fields.auth)
+            .ok_or("missing field `name` (or `auth`)")?;
+        Ok(Authenticator {
+            // Label is cosmetic, use name if not present
+            label: fields
+                .label
+                .or(fields.auth_label)
+                .unwrap_or_else(|| name.clone()),
+            id,
+            name,
+            auth_type: fields.auth_type,
+            priority: fields.priority,
+            custom: fields.custom,
+        })
+    }
+}
+
+/// Actor REST API versions. Paths, requests and responses shapes depend on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum ApiVersion {
+    #[default]
+    V3,
+    V4,
+}
+
+impl ApiVersion {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "v3" => Some(ApiVersion::V3),
+            "v4" => Some(ApiVersion::V4),
+            _ => None,
+        }
+    }
+
+    /// Path of the actor REST API for this version, relative to broker url
+    pub fn path(&self) -> &'static str {
+        match self {
+            ApiVersion::V3 => consts::REST_ACTOR_PATH,
+            ApiVersion::V4 => consts::REST_ACTOR_V4_PATH,
+        }
+    }
+}
+
+/// What the broker supports, as reported by the capabilities probe.
+/// Brokers without the probe endpoint (4.x and early 5.x) only support v3
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BrokerCapabilities {
+    #[serde(default)]
+    pub version: Option<String>, // Broker version, informative
+    #[serde(default)]
+    pub api_versions: Vec<String>, // i.e. ["v3", "v4"]. Unknown ones are ignored
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+impl Default for BrokerCapabilities {
+    fn default() -> Self {
+        Self {
+            version: None,
+            api_versions: vec!["v3".to_string()],
+            features: Vec::new(),
+        }
+    }
+}
+
+impl BrokerCapabilities {
+    /// Highest API version supported by both, broker and actor
+    pub fn api_version(&self) -> ApiVersion {
+        self.api_versions
+            .iter()
+            .filter_map(|v| ApiVersion::from_name(v))
+            .filter(|v| consts::SUPPORTED_API_VERSIONS.contains(v))
+            .max()
+            .unwrap_or_default()
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct RegisterCommands {
     pub pre_command: Option<String>,
@@ -271,6 +579,18 @@ impl From<tracing::Level> for LogLevel {
     }
 }
 
+/// And back, for client logs kept on the log buffer
+impl From<LogLevel> for tracing::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Debug => tracing::Level::DEBUG,
+            LogLevel::Info | LogLevel::Other => tracing::Level::INFO,
+            LogLevel::Warn => tracing::Level::WARN,
+            LogLevel::Error | LogLevel::Fatal => tracing::Level::ERROR,
+        }
+    }
+}
+
 impl From<LogLevel> for u8 {
     fn from(level: LogLevel) -> Self {
         match level {
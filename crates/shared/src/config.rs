@@ -1,8 +1,13 @@
-use std::ops::Deref;
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
+use crate::tls::CertificateInfo;
+
 /// Actor types
 #[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -62,16 +67,30 @@ pub struct ActorDataConfiguration {
     pub unique_id: Option<String>,
     pub os: Option<ActorOsConfiguration>,
     pub ssl_ciphers: Option<String>,
+    pub heartbeat_interval: Option<u64>, // Seconds between heartbeats to broker, 0 disables them
+    pub allowed_networks: Option<Vec<String>>, // CIDRs pushed by broker, added to the local ones
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 pub struct ActorConfiguration {
     pub broker_url: String,
+    #[serde(default)]
+    pub fallback_broker_urls: Vec<String>, // Tried in order if broker_url is not reachable
     pub verify_ssl: bool,
+    pub ca_bundle: Option<String>, // PEM file with CAs trusted for broker connections
+    #[serde(default)]
+    pub broker_pins: Vec<String>, // SHA-256 SPKI pins (base64) for broker certificate
+    pub client_cert: Option<String>, // PEM client certificate (chain) presented to broker (mTLS)
+    pub client_key: Option<String>, // PEM private key of client_cert, PKCS#8 encrypted if password
+    pub client_key_password: Option<String>,
     pub actor_type: ActorType,
     pub master_token: Option<String>, // Configured master token. Will be replaced by unique one if unmanaged
     pub own_token: Option<String>, // On unmanaged, master_token will be cleared and this will be used (unique provided by server)
     pub restrict_net: Option<String>,
+    #[serde(default)]
+    pub allowed_networks: Vec<String>, // CIDRs allowed to reach our endpoints, empty allows any
+    #[serde(default)]
+    pub metrics_address: Option<String>, // ip:port to serve metrics on (plain http), None disables it
     pub pre_command: Option<String>,
     pub runonce_command: Option<String>,
     pub post_command: Option<String>,
@@ -85,11 +104,19 @@ impl Default for ActorConfiguration {
     fn default() -> Self {
         Self {
             broker_url: String::new(),
+            fallback_broker_urls: Vec::new(),
             verify_ssl: false,
+            ca_bundle: None,
+            broker_pins: Vec::new(),
+            client_cert: None,
+            client_key: None,
+            client_key_password: None,
             actor_type: ActorType::default(),
             master_token: None,
             own_token: None,
             restrict_net: None,
+            allowed_networks: Vec::new(),
+            metrics_address: None,
             pre_command: None,
             runonce_command: None,
             post_command: None,
@@ -100,6 +127,37 @@ impl Default for ActorConfiguration {
     }
 }
 
+// Client key password is never shown, so configuration can be safely logged
+impl std::fmt::Debug for ActorConfiguration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ActorConfiguration")
+            .field("broker_url", &self.broker_url)
+            .field("fallback_broker_urls", &self.fallback_broker_urls)
+            .field("verify_ssl", &self.verify_ssl)
+            .field("ca_bundle", &self.ca_bundle)
+            .field("broker_pins", &self.broker_pins)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field(
+                "client_key_password",
+                &self.client_key_password.as_ref().map(|_| "<redacted>"),
+            )
+            .field("actor_type", &self.actor_type)
+            .field("master_token", &self.master_token)
+            .field("own_token", &self.own_token)
+            .field("restrict_net", &self.restrict_net)
+            .field("allowed_networks", &self.allowed_networks)
+            .field("metrics_address", &self.metrics_address)
+            .field("pre_command", &self.pre_command)
+            .field("runonce_command", &self.runonce_command)
+            .field("post_command", &self.post_command)
+            .field("log_level", &self.log_level)
+            .field("config", &self.config)
+            .field("data", &self.data)
+            .finish()
+    }
+}
+
 impl ActorConfiguration {
     pub fn token(&self) -> String {
         // Master token has precedence over master token
@@ -112,6 +170,41 @@ impl ActorConfiguration {
         }
     }
 
+    /// Ordered list of broker urls, primary first, skipping empty and duplicated ones
+    pub fn broker_urls(&self) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for url in std::iter::once(&self.broker_url).chain(self.fallback_broker_urls.iter()) {
+            if !url.is_empty() && !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+        urls
+    }
+
+    /// Comma separated list of broker hosts, as shown on config GUIs
+    pub fn broker_hosts(&self) -> String {
+        self.broker_urls()
+            .iter()
+            .map(|url| {
+                url.trim_start_matches("https://")
+                    .trim_end_matches("/uds/rest/")
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Sets broker_url and fallback_broker_urls from a comma separated list of hosts,
+    /// as entered on config GUIs
+    pub fn set_broker_hosts(&mut self, hosts: &str) {
+        let mut urls = hosts
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(|host| format!("https://{}/uds/rest/", host));
+        self.broker_url = urls.next().unwrap_or_default();
+        self.fallback_broker_urls = urls.collect();
+    }
+
     pub fn is_valid(&self) -> bool {
         !self.broker_url.is_empty() && !self.token().is_empty()
     }
@@ -123,6 +216,71 @@ impl ActorConfiguration {
     pub fn ssl_ciphers(&self) -> Option<&str> {
         self.config.ssl_ciphers.as_deref()
     }
+
+    /// Networks allowed to reach the actor endpoints, the local ones plus the ones from broker.
+    /// Empty if not restricted. Any invalid entry is an error, so callers can fail closed
+    pub fn allowed_networks(&self) -> Result<Vec<ipnetwork::IpNetwork>> {
+        self.allowed_networks
+            .iter()
+            .chain(self.config.allowed_networks.iter().flatten())
+            .map(|net| {
+                net.trim()
+                    .parse()
+                    .with_context(|| format!("Invalid allowed network {:?}", net))
+            })
+            .collect()
+    }
+
+    /// Address of the metrics endpoint, None if disabled (or invalid)
+    pub fn metrics_address(&self) -> Option<std::net::SocketAddr> {
+        let address = self.metrics_address.as_deref()?.trim();
+        if address.is_empty() {
+            return None;
+        }
+        address
+            .parse()
+            .inspect_err(|e| crate::log::error!("Invalid metrics address {:?}: {}", address, e))
+            .ok()
+    }
+
+    /// Paths of client certificate and key, if configured.
+    /// Relative paths are relative to the config folder (files stored next to the config)
+    pub fn client_cert_paths(&self) -> Option<(PathBuf, PathBuf)> {
+        let resolve = |path: &str| {
+            let path = Path::new(path);
+            if path.is_absolute() {
+                path.to_path_buf()
+            } else {
+                config_dir().join(path)
+            }
+        };
+        match (self.client_cert.as_deref(), self.client_key.as_deref()) {
+            (Some(cert), Some(key)) if !cert.is_empty() && !key.is_empty() => {
+                Some((resolve(cert), resolve(key)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Loads the client certificate for mTLS with broker, if configured
+    pub fn client_certinfo(&self) -> Result<Option<CertificateInfo>> {
+        let Some((cert_path, key_path)) = self.client_cert_paths() else {
+            if self.client_cert.is_some() || self.client_key.is_some() {
+                anyhow::bail!("Both client_cert and client_key must be configured");
+            }
+            return Ok(None);
+        };
+        let read = |path: &Path| {
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))
+        };
+        Ok(Some(CertificateInfo {
+            certificate: read(&cert_path)?,
+            key: read(&key_path)?,
+            password: self.client_key_password.clone(),
+            ciphers: self.config.ssl_ciphers.clone(),
+        }))
+    }
 }
 
 pub trait Configuration: Send + Sync + 'static {
@@ -141,10 +299,10 @@ pub trait Configuration: Send + Sync + 'static {
 }
 
 #[cfg(target_os = "windows")]
-pub use crate::windows::config::new_config_storage;
+pub use crate::windows::config::{config_dir, new_config_storage};
 
 #[cfg(target_family = "unix")]
-pub use crate::unix::config::new_config_storage;
+pub use crate::unix::config::{config_dir, new_config_storage};
 
 #[cfg(test)]
 mod tests {
@@ -154,11 +312,19 @@ mod tests {
     fn get_test_config() -> ActorConfiguration {
         ActorConfiguration {
             broker_url: "https://example.com".to_string(),
+            fallback_broker_urls: vec!["https://fallback.example.com".to_string()],
             verify_ssl: true,
+            ca_bundle: Some("/etc/udsactor/ca.pem".to_string()),
+            broker_pins: vec!["sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string()],
+            client_cert: Some("client.pem".to_string()),
+            client_key: Some("client_key.pem".to_string()),
+            client_key_password: Some("secret".to_string()),
             actor_type: ActorType::default(),
             master_token: Some("master123".to_string()),
             own_token: None,
             restrict_net: Some("192.168.1.0/24".to_string()),
+            allowed_networks: vec!["10.0.0.0/8".to_string()],
+            metrics_address: Some("127.0.0.1:9100".to_string()),
             pre_command: None,
             runonce_command: None,
             post_command: None,
@@ -181,11 +347,19 @@ mod tests {
         }
 
         a.broker_url == b.broker_url
+            && a.fallback_broker_urls == b.fallback_broker_urls
             && a.verify_ssl == b.verify_ssl
+            && a.ca_bundle == b.ca_bundle
+            && a.broker_pins == b.broker_pins
+            && a.client_cert == b.client_cert
+            && a.client_key == b.client_key
+            && a.client_key_password == b.client_key_password
             && a.actor_type == b.actor_type
             && a.master_token == b.master_token
             && a.own_token == b.own_token
             && a.restrict_net == b.restrict_net
+            && a.allowed_networks == b.allowed_networks
+            && a.metrics_address == b.metrics_address
             && a.pre_command == b.pre_command
             && a.runonce_command == b.runonce_command
             && a.post_command == b.post_command
@@ -214,4 +388,107 @@ mod tests {
             "Cleared config is not default"
         );
     }
+
+    #[test]
+    fn test_broker_urls() {
+        let mut cfg = ActorConfiguration::default();
+        cfg.set_broker_hosts("broker1.example.com, broker2.example.com,,broker1.example.com");
+        assert_eq!(cfg.broker_url, "https://broker1.example.com/uds/rest/");
+        assert_eq!(
+            cfg.broker_urls(),
+            vec![
+                "https://broker1.example.com/uds/rest/".to_string(),
+                "https://broker2.example.com/uds/rest/".to_string(),
+            ]
+        );
+        assert_eq!(
+            cfg.broker_hosts(),
+            "broker1.example.com, broker2.example.com"
+        );
+    }
+
+    #[test]
+    fn test_load_without_fallback_brokers() {
+        // Configs stored by older versions do not have fallback_broker_urls
+        let mut value = serde_json::to_value(get_test_config()).unwrap();
+        value
+            .as_object_mut()
+            .unwrap()
+            .remove("fallback_broker_urls");
+        let cfg: ActorConfiguration = serde_json::from_value(value).unwrap();
+        assert!(cfg.fallback_broker_urls.is_empty());
+        assert_eq!(cfg.broker_urls(), vec!["https://example.com".to_string()]);
+    }
+
+    #[test]
+    fn test_allowed_networks() {
+        let mut cfg = get_test_config();
+        cfg.config.allowed_networks = Some(vec!["2001:db8::/32".to_string()]);
+        let networks = cfg.allowed_networks().unwrap();
+        assert_eq!(networks.len(), 2);
+        assert!(networks[0].contains("10.1.2.3".parse().unwrap()));
+        assert!(networks[1].contains("2001:db8::1".parse().unwrap()));
+        assert!(
+            ActorConfiguration::default()
+                .allowed_networks()
+                .unwrap()
+                .is_empty()
+        );
+
+        // A single invalid entry invalidates the whole list
+        cfg.allowed_networks.push("not a network".to_string());
+        assert!(cfg.allowed_networks().is_err());
+    }
+
+    #[test]
+    fn test_metrics_address() {
+        let mut cfg = get_test_config();
+        assert_eq!(
+            cfg.metrics_address(),
+            Some("127.0.0.1:9100".parse().unwrap())
+        );
+        cfg.metrics_address = Some("localhost".to_string());
+        assert!(cfg.metrics_address().is_none());
+        assert!(ActorConfiguration::default().metrics_address().is_none());
+    }
+
+    #[test]
+    fn test_client_cert_paths() {
+        let mut cfg = ActorConfiguration::default();
+        assert!(cfg.client_cert_paths().is_none());
+        assert!(cfg.client_certinfo().unwrap().is_none());
+
+        // Only one of them is a configuration error
+        let abs_cert = std::env::temp_dir().join("client.pem");
+        cfg.client_cert = Some(abs_cert.to_string_lossy().into_owned());
+        assert!(cfg.client_cert_paths().is_none());
+        assert!(cfg.client_certinfo().is_err());
+
+        // Relative paths are next to the config
+        cfg.client_key = Some("client_key.pem".to_string());
+        let (cert, key) = cfg.client_cert_paths().unwrap();
+        assert_eq!(cert, abs_cert);
+        assert_eq!(key, config_dir().join("client_key.pem"));
+    }
+
+    #[test]
+    fn test_client_certinfo() {
+        let cfg = ActorConfiguration {
+            client_cert: Some(crate::testing::test_certs::CLIENT_CERT_PATH.to_string()),
+            client_key: Some(crate::testing::test_certs::CLIENT_KEY_PASS_PATH.to_string()),
+            client_key_password: Some(crate::testing::test_certs::KEY_PASSWORD.to_string()),
+            ..Default::default()
+        };
+        let cert_info = cfg.client_certinfo().unwrap().unwrap();
+        assert!(cert_info.certificate.contains("BEGIN CERTIFICATE"));
+        assert!(cert_info.key.contains("BEGIN ENCRYPTED PRIVATE KEY"));
+        assert!(crate::tls::certool::certified_key_from_pem(&cert_info).is_ok());
+
+        // Neither the key nor its password are shown on Debug
+        let password = crate::testing::test_certs::KEY_PASSWORD;
+        let shown = format!("{:?} {:?}", cfg, cert_info);
+        assert!(!shown.contains(password));
+        assert!(!shown.contains("PRIVATE KEY"));
+        assert!(shown.contains("BEGIN CERTIFICATE"));
+    }
 }
@@ -33,7 +33,11 @@ pub mod config;
 pub mod consts;
 pub mod installer;
 pub mod log;
+pub mod log_buffer;
 pub mod log_forward;
+pub mod log_shipper;
+pub mod metrics;
+pub mod script;
 pub mod service;
 pub mod sync;
 pub mod system;
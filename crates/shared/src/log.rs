@@ -38,13 +38,16 @@ use tracing_subscriber::{
     EnvFilter, Layer, Registry, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt,
 };
 
+use crate::log_buffer::LogBufferLayer;
 use crate::log_forward::LogForwardLayer;
 
 #[cfg(target_os = "windows")]
 use crate::windows::eventlog::EventLogLayer;
 
 // Reexport to avoid using crate names for tracing
-pub use tracing::{debug, error, info, trace, warn};
+pub use tracing::{Level, debug, error, info, trace, warn};
+
+pub use crate::log_buffer::{LogRecord, log_buffer};
 
 static LOGGER_INIT: OnceLock<()> = OnceLock::new();
 static RELOAD_HANDLE: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
@@ -384,6 +387,9 @@ let main_layer = main_layer.and_then(
 // a layer whose `enabled()` is false, which is a no-op.
 let main_layer = main_layer.and_then(LogForwardLayer::for_type(&log_type));
 
+// Recent records kept in memory for the logs endpoint (service only, as the forwarder)
+let main_layer = main_layer.and_then(LogBufferLayer::for_type(&log_type));
+
 #[cfg(target_os = "windows")]
 let main_layer = main_layer.and_then(EventLogLayer::for_type(&log_type));
 
@@ -0,0 +1,190 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions in the documentation and/or other materials
+//      provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+
+//! In-memory ring buffer with the most recent log records, so they can be
+//! pulled remotely (`GET /actor/{secret}/logs`) without logging into the VM.
+//!
+//! Service records arrive through `LogBufferLayer`, client ones through the
+//! WS logger worker. Only the service keeps the buffer; for every other log
+//! type the layer is a no-op.
+
+use std::{collections::VecDeque, sync::Mutex, sync::OnceLock};
+
+use serde::Serialize;
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
+
+use crate::log::LogType;
+use crate::log_forward::MessageVisitor;
+use crate::log_shipper::LogSource;
+
+/// Number of records kept, oldest ones are discarded first
+pub const LOG_BUFFER_SIZE: usize = 2000;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogRecord {
+    pub timestamp: i64, // Unix seconds
+    pub level: String,  // As tracing names it (ERROR, WARN, INFO, ...)
+    pub source: LogSource,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogRecord {
+    fn level(&self) -> Level {
+        crate::log::parse_level(&self.level).unwrap_or(Level::INFO)
+    }
+}
+
+pub struct LogBuffer {
+    capacity: usize,
+    records: Mutex<VecDeque<LogRecord>>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn push(&self, source: LogSource, level: Level, target: &str, message: &str) {
+        let mut records = self.records.lock().unwrap();
+        if records.len() >= self.capacity {
+            records.pop_front();
+        }
+        records.push_back(LogRecord {
+            timestamp: chrono::Utc::now().timestamp(),
+            level: level.as_str().to_string(),
+            source,
+            target: target.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Records at least as severe as `level`, logged at or after `since` (unix seconds),
+    /// oldest first
+    pub fn records(&self, level: Option<Level>, since: Option<i64>) -> Vec<LogRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| level.is_none_or(|level| r.level() <= level))
+            .filter(|r| since.is_none_or(|since| r.timestamp >= since))
+            .cloned()
+            .collect()
+    }
+}
+
+/// The process wide buffer
+pub fn log_buffer() -> &'static LogBuffer {
+    static LOG_BUFFER: OnceLock<LogBuffer> = OnceLock::new();
+    LOG_BUFFER.get_or_init(|| LogBuffer::new(LOG_BUFFER_SIZE))
+}
+
+/// `tracing_subscriber::Layer` that keeps the service events on the log buffer
+pub struct LogBufferLayer {
+    enabled: bool,
+}
+
+impl LogBufferLayer {
+    pub fn for_type(log_type: &LogType) -> Self {
+        Self {
+            enabled: matches!(log_type, LogType::Service),
+        }
+    }
+}
+
+impl<S> Layer<S> for LogBufferLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, metadata: &tracing::Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+        self.enabled && *metadata.level() <= crate::log::get_active_log_level()
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+        if visitor.0.is_empty() {
+            return;
+        }
+        let metadata = event.metadata();
+        log_buffer().push(
+            LogSource::Service,
+            *metadata.level(),
+            metadata.target(),
+            &visitor.0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_discards_oldest() {
+        let buffer = LogBuffer::new(3);
+        for i in 0..5 {
+            buffer.push(LogSource::Service, Level::INFO, "test", &format!("msg {i}"));
+        }
+        let records = buffer.records(None, None);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].message, "msg 2");
+        assert_eq!(records[2].message, "msg 4");
+    }
+
+    #[test]
+    fn test_filter_by_level_and_since() {
+        let buffer = LogBuffer::new(10);
+        buffer.push(LogSource::Service, Level::DEBUG, "test", "debug");
+        buffer.push(LogSource::Client, Level::WARN, "client", "warn");
+        buffer.push(LogSource::Service, Level::ERROR, "test", "error");
+
+        let records = buffer.records(Some(Level::WARN), None);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].source, LogSource::Client);
+        assert_eq!(records[1].level, "ERROR");
+        assert_eq!(buffer.records(Some(Level::TRACE), None).len(), 3);
+
+        let now = chrono::Utc::now().timestamp();
+        assert_eq!(buffer.records(None, Some(now - 60)).len(), 3);
+        assert!(buffer.records(None, Some(now + 60)).is_empty());
+    }
+
+    #[test]
+    fn test_for_type_only_enables_service() {
+        for log_type in [LogType::Client, LogType::Config, LogType::Tests] {
+            assert!(!LogBufferLayer::for_type(&log_type).enabled);
+        }
+        assert!(LogBufferLayer::for_type(&LogType::Service).enabled);
+    }
+}
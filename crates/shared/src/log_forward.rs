@@ -32,70 +32,31 @@ Author: Adolfo Gómez, dkmaster at dkmon dot com
 //! `logger.info/warn/error` was also posted to the remote broker.
 //!
 //! Design: a single `OnceLock` that the service sets once after the platform
-//! has been constructed, holding an `Arc<RwLock<dyn BrokerApi>>` clone.
+//! has been constructed, holding the platform `LogShipper`.
 //! The `Layer` reads it per-event; if it is empty (e.g. before init, in
-//! the client, or in tests), the layer is a no-op. The layer spawns a
-//! one-shot `tokio` task to call `broker_api.log(...)` so the tracing event
-//! itself never blocks on the broker round-trip.
+//! the client, or in tests), the layer is a no-op. Events are only queued
+//! on the shipper, which batches them to the broker on its own task, so the
+//! tracing event itself never blocks on the broker round-trip.
 //!
-//! A lock-free `flood_allow` (60 events / 60 s) protects the broker from
-//! log storms. State is a single `AtomicU64` packing count + window start.
+//! Flood protection (60 events / 60 s) is done by the shipper, see
+//! `log_shipper::FloodGuard`.
 
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::OnceLock;
 
 use tracing::{Event, Subscriber};
 use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
 
-use crate::broker::api::{BrokerApi, types::LogLevel};
+use crate::broker::api::types::LogLevel;
 use crate::log::LogType;
+use crate::log_shipper::{LogShipper, LogSource};
 
-/// Shared broker reference; set once after the platform is built.
-static FORWARD_TARGET: OnceLock<std::sync::Arc<tokio::sync::RwLock<dyn BrokerApi>>> =
-    OnceLock::new();
+/// Shared shipper reference; set once after the platform is built.
+static FORWARD_TARGET: OnceLock<std::sync::Arc<LogShipper>> = OnceLock::new();
 
-/// Per-process flood guard. Atomic, no Mutex needed.
-///
-/// Packs `count` in the low 32 bits and `window_start_unix_secs` in the high
-/// 32 bits. `allow()` does a single CAS loop, so concurrent log events from
-/// different threads never block each other.
-static FLOOD_PACK: AtomicU64 = AtomicU64::new(0);
-
-const MAX_PER_WINDOW: u32 = 60;
-const WINDOW_SECS: u64 = 60;
-
-/// Install the broker reference. Called once by the service after the
+/// Install the shipper. Called once by the service after the
 /// platform is ready. Subsequent calls are no-ops.
-pub fn set_log_forwarder(api: std::sync::Arc<tokio::sync::RwLock<dyn BrokerApi>>) {
-    let _ = FORWARD_TARGET.set(api);
-}
-
-/// Returns true exactly `MAX_PER_WINDOW` times per `WINDOW_SECS`-second
-/// sliding window. After that, returns false until the window slides.
-fn flood_allow(now_unix_secs: u64) -> bool {
-    let now = now_unix_secs;
-    loop {
-        let pack = FLOOD_PACK.load(Ordering::Relaxed);
-        let count = pack as u32;
-        let win_start = pack >> 32;
-
-        let new_pack = if win_start == 0 || now.saturating_sub(win_start) >= WINDOW_SECS {
-            // Reset window.
-            (now << 32) | 1
-        } else if count < MAX_PER_WINDOW {
-            (win_start << 32) | (count as u64 + 1)
-        } else {
-            return false;
-        };
-
-        if FLOOD_PACK
-            .compare_exchange(pack, new_pack, Ordering::Relaxed, Ordering::Relaxed)
-            .is_ok()
-        {
-            return true;
-        }
-        // CAS failed: another thread updated the pack. Reload and retry.
-    }
+pub fn set_log_forwarder(shipper: std::sync::Arc<LogShipper>) {
+    let _ = FORWARD_TARGET.set(shipper);
 }
 
 /// Quick check: is the forwarder wired up?
@@ -146,22 +107,13 @@ where
     }
 
     fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
-        // Cheap early-out: if no broker has been registered yet, do nothing.
-        let Some(api_lock) = FORWARD_TARGET.get() else {
+        // Cheap early-out: if no shipper has been registered yet, do nothing.
+        let Some(shipper) = FORWARD_TARGET.get() else {
             return;
         };
 
-        // Flood guard: max 60 events / 60 s. Atomic, lock-free.
-        let now_unix = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
-        if !flood_allow(now_unix) {
-            return;
-        }
-
-        // Capture the event into a String. We don't have async access here
-        // (on_event is sync), so we collect synchronously and then spawn.
+        // Capture the event into a String. on_event is sync, so we collect
+        // synchronously and let the shipper task do the round-trip.
         let level = *event.metadata().level();
         let mut visitor = MessageVisitor::default();
         event.record(&mut visitor);
@@ -170,27 +122,14 @@ where
             return;
         }
 
-        // Take a short-lived read lock and clone the inner `Arc<dyn>` so
-        // the spawned task holds its own reference (no contention with the
-        // service's mutating operations on the same RwLock).
-        let api_lock = api_lock.clone();
-        if let Ok(Some(_h)) = std::panic::catch_unwind(|| {
-            tokio::runtime::Handle::try_current().ok()
-        }) {
-            tokio::spawn(async move {
-                let api = api_lock.read().await;
-                if let Err(e) = api.log(LogLevel::from(level), message.as_str()).await {
-                    // We can't recurse into tracing without risking a loop;
-                    // write to stderr as a last resort.
-                    eprintln!("udsactor: log forward to broker failed: {e:?}");
-                }
-            });
-        }
+        // Dropped silently if flooding; logging it would feed the flood.
+        shipper.push(LogSource::Service, LogLevel::from(level), &message);
     }
 }
 
+// Also used by the log buffer layer
 #[derive(Default)]
-struct MessageVisitor(String);
+pub(crate) struct MessageVisitor(pub String);
 
 impl tracing::field::Visit for MessageVisitor {
     fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
@@ -230,26 +169,6 @@ mod tests {
         assert_eq!(parse_level("nope"), None);
     }
 
-    #[test]
-    fn flood_guard_quota_is_enforced() {
-        // Within one second, MAX_PER_WINDOW calls should pass and the next
-        // one should fail. Tests share FLOOD_PACK globally so this is
-        // best-effort: if other tests run concurrently they may have used
-        // some of the quota already.
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        // Hammer the counter; at least one of these must be allowed.
-        let mut allowed = 0;
-        for _ in 0..MAX_PER_WINDOW {
-            if flood_allow(now) {
-                allowed += 1;
-            }
-        }
-        assert!(allowed > 0, "flood_allow should permit at least one event");
-    }
-
     #[test]
     fn forwarder_default_is_empty_unless_set() {
         // Tests share the global FORWARD_TARGET; we only assert that the
@@ -281,58 +200,6 @@ mod tests {
         let _layer = LogForwardLayer::for_type(&LogType::Service);
     }
 
-    // The flood guard uses a *global* AtomicU64 (intentionally, so it
-// survives across all layers in the same process). That makes it
-// non-hermetic: parallel tests in this file (and other tests calling
-// `flood_allow`) can race on the same pack. We mark the tests
-// `#[ignore]` so they only run when explicitly invoked, single-threaded:
-//
-//     cargo test -p shared flood -- --ignored --test-threads=1
-//
-// The non-ignored test below verifies the core invariant using a
-// single call (so it never interacts with parallel state).
-#[test]
-#[ignore = "requires --test-threads=1"]
-fn flood_guard_first_call_is_allowed_or_rejected_consistently() {
-    // Whatever the global state is, repeated calls with the *same*
-    // timestamp must eventually settle: once we hit a rejected call, the
-    // next one with the same (or smaller) timestamp must also be rejected,
-    // until WINDOW_SECS elapses.
-    let ts = (u64::MAX / 4) ^ 0xFEED_BEEF_CAFE_BABE;
-    // Drain. We bound the loop to MAX_PER_WINDOW + a safety margin so a
-    // broken implementation can't loop forever.
-    let mut allowed = 0;
-    while flood_allow(ts) && allowed < 1000 {
-        allowed += 1;
-    }
-    assert!(
-        allowed >= 1,
-        "flood_allow granted an unexpected number of calls ({allowed}) within the window"
-    );
-    // After saturation, another call must be rejected.
-    assert!(!flood_allow(ts));
-}
-
-#[test]
-#[ignore = "requires --test-threads=1; see comment above"]
-fn flood_guard_resets_after_window_slides() {
-    // Use a far-future timestamp so we definitely start in a fresh window.
-    let t0 = (u64::MAX / 2) ^ 0xDEAD_BEEF_CAFE_BABE;
-    let t1 = t0 + WINDOW_SECS + 1;
-
-    // Saturate t0.
-    let mut allowed = 0;
-    while allowed <= MAX_PER_WINDOW && flood_allow(t0) {
-        allowed += 1;
-    }
-    assert!((1..=MAX_PER_WINDOW).contains(&allowed));
-    // After saturation, t0 keeps rejecting.
-    assert!(!flood_allow(t0));
-
-    // t1 is past the window: must be allowed again.
-    assert!(flood_allow(t1));
-}
-
     #[tokio::test]
     async fn for_service_with_mock_broker_forwards_warn_event() {
         use crate::testing::mock::{BrokerApiMock, Calls};
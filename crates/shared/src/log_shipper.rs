@@ -0,0 +1,439 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions and the following disclaimer in the documentation
+//      and/or other materials provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+
+//! Batching log shipper, shared by the service log forwarder and the WS
+//! `logger` worker.
+//!
+//! Entries are queued on a bounded in-memory ring (oldest discarded when full)
+//! and flushed by `run` every `flush_interval`, or as soon as `max_batch`
+//! entries are waiting. If the broker advertises the `log_batch` feature, a
+//! batch is sent on a single request; otherwise entries are posted one by one,
+//! as older brokers expect. Entries not delivered because broker is unreachable
+//! are kept on the ring for next flush, and handed back by `run` on stop.
+//!
+//! Flood protection (60 events / 60 s per source) lives here too, so the
+//! forwarder and the client logs get the same guard.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::broker::api::{
+    BrokerApi, consts,
+    types::{LogEntry, LogLevel, RestError},
+};
+use crate::sync::OnceSignal;
+
+const RING_CAPACITY: usize = 1000;
+const MAX_BATCH: usize = 50;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+const MAX_PER_WINDOW: u32 = 60;
+const WINDOW_SECS: u64 = 60;
+
+/// Where a log entry comes from. Each source has its own flood guard, so a
+/// noisy client cannot starve the service logs (and vice versa)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSource {
+    Service,
+    Client,
+}
+
+/// Flood guard: `MAX_PER_WINDOW` events per `WINDOW_SECS` window.
+///
+/// Packs `count` in the low 32 bits and `window_start_unix_secs` in the high
+/// 32 bits. `allow()` does a single CAS loop, so it can be used from tracing
+/// layers on any thread without blocking.
+#[derive(Debug, Default)]
+pub struct FloodGuard {
+    pack: AtomicU64,
+}
+
+impl FloodGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(&self) -> bool {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.allow_at(now)
+    }
+
+    /// Returns true exactly `MAX_PER_WINDOW` times per `WINDOW_SECS`-second
+    /// window. After that, returns false until the window slides.
+    pub fn allow_at(&self, now_unix_secs: u64) -> bool {
+        let now = now_unix_secs;
+        loop {
+            let pack = self.pack.load(Ordering::Relaxed);
+            let count = pack as u32;
+            let win_start = pack >> 32;
+
+            let new_pack = if win_start == 0 || now.saturating_sub(win_start) >= WINDOW_SECS {
+                // Reset window.
+                (now << 32) | 1
+            } else if count < MAX_PER_WINDOW {
+                (win_start << 32) | (count as u64 + 1)
+            } else {
+                return false;
+            };
+
+            if self
+                .pack
+                .compare_exchange(pack, new_pack, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                return true;
+            }
+            // CAS failed: another thread updated the pack. Reload and retry.
+        }
+    }
+}
+
+pub struct LogShipper {
+    api: Arc<tokio::sync::RwLock<dyn BrokerApi>>,
+    ring: Mutex<VecDeque<LogEntry>>,
+    notify: tokio::sync::Notify,
+    service_guard: FloodGuard,
+    client_guard: FloodGuard,
+    max_batch: usize,
+    flush_interval: Duration,
+}
+
+impl LogShipper {
+    pub fn new(api: Arc<tokio::sync::RwLock<dyn BrokerApi>>) -> Self {
+        Self::new_with_params(api, MAX_BATCH, FLUSH_INTERVAL)
+    }
+
+    pub fn new_with_params(
+        api: Arc<tokio::sync::RwLock<dyn BrokerApi>>,
+        max_batch: usize,
+        flush_interval: Duration,
+    ) -> Self {
+        Self {
+            api,
+            ring: Mutex::new(VecDeque::new()),
+            notify: tokio::sync::Notify::new(),
+            service_guard: FloodGuard::new(),
+            client_guard: FloodGuard::new(),
+            max_batch: max_batch.max(1),
+            flush_interval,
+        }
+    }
+
+    /// Queues a log entry for the broker. Returns false if it was dropped by
+    /// the flood guard. Never blocks on the broker, so it is safe to call
+    /// from tracing layers.
+    pub fn push(&self, source: LogSource, level: LogLevel, message: &str) -> bool {
+        let guard = match source {
+            LogSource::Service => &self.service_guard,
+            LogSource::Client => &self.client_guard,
+        };
+        if !guard.allow() {
+            crate::metrics::metrics().record_log_dropped(source);
+            return false;
+        }
+        self.enqueue(LogEntry {
+            level,
+            message: message.to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+        });
+        true
+    }
+
+    /// Number of entries waiting to be shipped
+    pub fn pending(&self) -> usize {
+        self.ring.lock().unwrap().len()
+    }
+
+    // Note: nothing here may use tracing while holding the ring lock, as the
+    // forwarder layer would re-enter push
+    fn enqueue(&self, entry: LogEntry) {
+        let mut ring = self.ring.lock().unwrap();
+        if ring.len() >= RING_CAPACITY {
+            ring.pop_front();
+        }
+        ring.push_back(entry);
+        let full = ring.len() >= self.max_batch;
+        drop(ring);
+        if full {
+            self.notify.notify_one();
+        }
+    }
+
+    // Puts back undelivered entries, in front of the ones queued meanwhile
+    fn requeue(&self, entries: Vec<LogEntry>) {
+        let mut ring = self.ring.lock().unwrap();
+        for entry in entries.into_iter().rev() {
+            ring.push_front(entry);
+        }
+        while ring.len() > RING_CAPACITY {
+            ring.pop_front();
+        }
+    }
+
+    /// Sends all queued entries. Stops on first transient error, keeping the
+    /// undelivered entries for next flush
+    pub async fn flush(&self) {
+        loop {
+            let batch: Vec<LogEntry> = {
+                let mut ring = self.ring.lock().unwrap();
+                let count = ring.len().min(self.max_batch);
+                ring.drain(..count).collect()
+            };
+            if batch.is_empty() {
+                return;
+            }
+            if let Err((undelivered, e)) = self.ship(batch).await {
+                // We can't use tracing here without feeding the forwarder;
+                // write to stderr as a last resort.
+                eprintln!(
+                    "udsactor: log shipping to broker failed, {} entries kept: {e}",
+                    undelivered.len()
+                );
+                self.requeue(undelivered);
+                return;
+            }
+        }
+    }
+
+    // Ships a batch. On transient error, returns the entries not delivered.
+    // Entries rejected by broker are discarded, they will not succeed on retry.
+    // The api lock is taken per request, so writers are not held back by a
+    // whole batch of single posts.
+    async fn ship(&self, batch: Vec<LogEntry>) -> Result<(), (Vec<LogEntry>, RestError)> {
+        let has_batch = self
+            .api
+            .read()
+            .await
+            .capabilities()
+            .has_feature(consts::FEATURE_LOG_BATCH);
+        if has_batch {
+            let result = self.api.read().await.log_batch(&batch).await;
+            return match result {
+                Err(e) if e.is_retryable() => Err((batch, e)),
+                Err(e) => {
+                    eprintln!("udsactor: broker rejected {} log entries: {e}", batch.len());
+                    Ok(())
+                }
+                Ok(_) => Ok(()),
+            };
+        }
+        // Older brokers, one request per entry
+        for (idx, entry) in batch.iter().enumerate() {
+            let result = self.api.read().await.log(entry.level, &entry.message).await;
+            match result {
+                Err(e) if e.is_retryable() => return Err((batch[idx..].to_vec(), e)),
+                Err(e) => eprintln!("udsactor: broker rejected log entry: {e}"),
+                Ok(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes queued entries every `flush_interval`, or as soon as a batch is
+    /// full, until stop is signaled. Pending entries are flushed once more on stop,
+    /// and the ones still undelivered are returned, so they can be kept for later.
+    pub async fn run(self: Arc<Self>, stop: OnceSignal) -> Vec<LogEntry> {
+        loop {
+            tokio::select! {
+                _ = self.notify.notified() => {}
+                _ = tokio::time::sleep(self.flush_interval) => {}
+                _ = stop.wait() => break,
+            }
+            self.flush().await;
+        }
+        self.flush().await;
+        self.ring.lock().unwrap().drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broker::api::types::BrokerCapabilities;
+    use crate::testing::mock::{BrokerApiMock, Calls};
+
+    fn shipper(
+        batch_feature: bool,
+        max_batch: usize,
+        flush_interval: Duration,
+    ) -> (
+        Calls,
+        Arc<tokio::sync::RwLock<BrokerApiMock>>,
+        Arc<LogShipper>,
+    ) {
+        let calls = Calls::new();
+        let mut mock = BrokerApiMock::new(calls.clone());
+        if batch_feature {
+            mock.capabilities = BrokerCapabilities {
+                features: vec![consts::FEATURE_LOG_BATCH.to_string()],
+                ..Default::default()
+            };
+        }
+        let mock = Arc::new(tokio::sync::RwLock::new(mock));
+        let shipper = Arc::new(LogShipper::new_with_params(
+            mock.clone(),
+            max_batch,
+            flush_interval,
+        ));
+        (calls, mock, shipper)
+    }
+
+    #[test]
+    fn flood_guard_quota_is_enforced() {
+        let guard = FloodGuard::new();
+        let now = 1_700_000_000;
+        for _ in 0..MAX_PER_WINDOW {
+            assert!(guard.allow_at(now));
+        }
+        // After saturation, the same window keeps rejecting.
+        assert!(!guard.allow_at(now));
+        assert!(!guard.allow_at(now + WINDOW_SECS - 1));
+        // Past the window: must be allowed again.
+        assert!(guard.allow_at(now + WINDOW_SECS));
+    }
+
+    #[test]
+    fn flood_guard_is_per_source() {
+        let (_calls, _mock, shipper) = shipper(false, MAX_BATCH, FLUSH_INTERVAL);
+        for _ in 0..MAX_PER_WINDOW {
+            assert!(shipper.push(LogSource::Client, LogLevel::Info, "client"));
+        }
+        assert!(!shipper.push(LogSource::Client, LogLevel::Info, "client"));
+        assert!(shipper.push(LogSource::Service, LogLevel::Warn, "service"));
+        assert_eq!(shipper.pending(), MAX_PER_WINDOW as usize + 1);
+    }
+
+    #[test]
+    fn ring_discards_oldest_when_full() {
+        let (_calls, _mock, shipper) = shipper(false, MAX_BATCH, FLUSH_INTERVAL);
+        for i in 0..=RING_CAPACITY {
+            shipper.enqueue(LogEntry {
+                level: LogLevel::Info,
+                message: format!("msg {i}"),
+                timestamp: 0,
+            });
+        }
+        let ring = shipper.ring.lock().unwrap();
+        assert_eq!(ring.len(), RING_CAPACITY);
+        assert_eq!(ring.front().unwrap().message, "msg 1");
+    }
+
+    #[tokio::test]
+    async fn flush_uses_batch_endpoint_if_advertised() {
+        let (calls, _mock, shipper) = shipper(true, 2, FLUSH_INTERVAL);
+        for msg in ["a", "b", "c"] {
+            shipper.push(LogSource::Service, LogLevel::Warn, msg);
+        }
+        shipper.flush().await;
+        assert_eq!(
+            calls.dump(),
+            vec!["broker_api::log_batch(a, b)", "broker_api::log_batch(c)"]
+        );
+        assert_eq!(shipper.pending(), 0);
+    }
+
+    #[tokio::test]
+    async fn flush_falls_back_to_single_posts() {
+        let (calls, _mock, shipper) = shipper(false, MAX_BATCH, FLUSH_INTERVAL);
+        for msg in ["a", "b"] {
+            shipper.push(LogSource::Client, LogLevel::Info, msg);
+        }
+        shipper.flush().await;
+        assert_eq!(
+            calls.dump(),
+            vec!["broker_api::log(Info, a)", "broker_api::log(Info, b)"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_broker_keeps_entries() {
+        let (calls, mock, shipper) = shipper(true, MAX_BATCH, FLUSH_INTERVAL);
+        mock.write().await.fail_with = Some(RestError::transport("connection refused"));
+        for msg in ["a", "b"] {
+            shipper.push(LogSource::Service, LogLevel::Error, msg);
+        }
+        shipper.flush().await;
+        assert_eq!(shipper.pending(), 2);
+
+        // Rejected by broker, discarded
+        mock.write().await.fail_with = Some(RestError::from_status(403, ""));
+        shipper.flush().await;
+        assert_eq!(shipper.pending(), 0);
+        calls.assert_called("broker_api::log_batch(a, b)");
+        assert_eq!(calls.count_calls("broker_api::log_batch("), 2);
+    }
+
+    #[tokio::test]
+    async fn run_flushes_on_full_batch_and_on_stop() {
+        let (calls, _mock, shipper) = shipper(true, 2, Duration::from_secs(3600));
+        let stop = OnceSignal::new();
+        let handle = tokio::spawn(shipper.clone().run(stop.clone()));
+
+        for msg in ["a", "b"] {
+            shipper.push(LogSource::Service, LogLevel::Warn, msg);
+        }
+        for _ in 0..50 {
+            if shipper.pending() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        calls.assert_called("broker_api::log_batch(a, b)");
+
+        shipper.push(LogSource::Service, LogLevel::Warn, "c");
+        stop.set();
+        assert!(handle.await.unwrap().is_empty());
+        calls.assert_called("broker_api::log_batch(c)");
+    }
+
+    #[tokio::test]
+    async fn run_returns_undelivered_on_stop() {
+        let (_calls, mock, shipper) = shipper(true, MAX_BATCH, Duration::from_secs(3600));
+        mock.write().await.fail_with = Some(RestError::transport("connection refused"));
+        let stop = OnceSignal::new();
+        let handle = tokio::spawn(shipper.clone().run(stop.clone()));
+
+        for msg in ["a", "b"] {
+            shipper.push(LogSource::Service, LogLevel::Error, msg);
+        }
+        stop.set();
+        let undelivered = handle.await.unwrap();
+        let messages: Vec<&str> = undelivered.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, vec!["a", "b"]);
+        assert_eq!(shipper.pending(), 0);
+    }
+}
@@ -0,0 +1,288 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions in the documentation and/or other materials
+//      provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+
+//! Process wide counters for the metrics endpoint, in Prometheus text format
+//! (also understood by OpenMetrics scrapers).
+//!
+//! Counters are recorded where things happen (broker api, log shipper,
+//! broadcast receivers). Gauges are read from their owners when the endpoint is
+//! scraped, see `ws::server::metrics`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::log_shipper::LogSource;
+
+// Upper bounds (seconds) of the broker call latency buckets
+const LATENCY_BUCKETS: [f64; 9] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()], // Cumulative, as exposed
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    broker_calls: Mutex<BTreeMap<(String, &'static str), u64>>, // (endpoint, outcome) → count
+    broker_latency: Mutex<BTreeMap<String, Histogram>>,
+    broadcast_lagged: AtomicU64,
+    service_logs_dropped: AtomicU64,
+    client_logs_dropped: AtomicU64,
+}
+
+impl Metrics {
+    /// A broker call finished, `outcome` is "ok" or the kind of error
+    pub fn record_broker_call(&self, endpoint: &str, outcome: &'static str, elapsed: Duration) {
+        *self
+            .broker_calls
+            .lock()
+            .unwrap()
+            .entry((endpoint.to_string(), outcome))
+            .or_default() += 1;
+        self.broker_latency
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_string())
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Messages a broadcast receiver skipped because it was too slow
+    pub fn record_lagged(&self, skipped: u64) {
+        self.broadcast_lagged.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    /// Log not shipped to broker because of the flood guard
+    pub fn record_log_dropped(&self, source: LogSource) {
+        match source {
+            LogSource::Service => &self.service_logs_dropped,
+            LogSource::Client => &self.client_logs_dropped,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Appends the counters to `out`
+    pub fn render(&self, out: &mut String) {
+        write_header(
+            out,
+            "udsactor_broker_calls_total",
+            "counter",
+            "Broker calls by endpoint and outcome",
+        );
+        for ((endpoint, outcome), count) in self.broker_calls.lock().unwrap().iter() {
+            write_sample(
+                out,
+                "udsactor_broker_calls_total",
+                &[("endpoint", endpoint), ("outcome", outcome)],
+                *count as f64,
+            );
+        }
+
+        write_header(
+            out,
+            "udsactor_broker_call_duration_seconds",
+            "histogram",
+            "Broker call latency, retries included",
+        );
+        for (endpoint, histogram) in self.broker_latency.lock().unwrap().iter() {
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                write_sample(
+                    out,
+                    "udsactor_broker_call_duration_seconds_bucket",
+                    &[("endpoint", endpoint), ("le", &bound.to_string())],
+                    count as f64,
+                );
+            }
+            let labels = [("endpoint", endpoint.as_str())];
+            write_sample(
+                out,
+                "udsactor_broker_call_duration_seconds_bucket",
+                &[("endpoint", endpoint), ("le", "+Inf")],
+                histogram.count as f64,
+            );
+            write_sample(
+                out,
+                "udsactor_broker_call_duration_seconds_sum",
+                &labels,
+                histogram.sum,
+            );
+            write_sample(
+                out,
+                "udsactor_broker_call_duration_seconds_count",
+                &labels,
+                histogram.count as f64,
+            );
+        }
+
+        write_header(
+            out,
+            "udsactor_broadcast_lagged_total",
+            "counter",
+            "Messages skipped by workers too slow to keep up with the WS clients",
+        );
+        write_sample(
+            out,
+            "udsactor_broadcast_lagged_total",
+            &[],
+            self.broadcast_lagged.load(Ordering::Relaxed) as f64,
+        );
+
+        write_header(
+            out,
+            "udsactor_logs_dropped_total",
+            "counter",
+            "Logs not sent to broker because of the flood guard",
+        );
+        for (source, counter) in [
+            ("service", &self.service_logs_dropped),
+            ("client", &self.client_logs_dropped),
+        ] {
+            write_sample(
+                out,
+                "udsactor_logs_dropped_total",
+                &[("source", source)],
+                counter.load(Ordering::Relaxed) as f64,
+            );
+        }
+    }
+}
+
+/// The process wide metrics
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// Appends a gauge, with one sample per label set
+pub fn write_gauge(out: &mut String, name: &str, help: &str, samples: &[(&[(&str, &str)], f64)]) {
+    write_header(out, name, "gauge", help);
+    for (labels, value) in samples {
+        write_sample(out, name, labels, *value);
+    }
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn write_sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(label, value)| {
+                let value = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n");
+                format!("{label}=\"{value}\"")
+            })
+            .collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let metrics = Metrics::default();
+        metrics.record_broker_call("login", "ok", Duration::from_millis(200));
+        metrics.record_broker_call("login", "ok", Duration::from_secs(3));
+        metrics.record_broker_call("login", "transport", Duration::from_secs(60));
+        metrics.record_lagged(5);
+        metrics.record_log_dropped(LogSource::Client);
+
+        let mut out = String::new();
+        metrics.render(&mut out);
+        assert!(out.contains("# TYPE udsactor_broker_calls_total counter\n"));
+        assert!(out.contains("udsactor_broker_calls_total{endpoint=\"login\",outcome=\"ok\"} 2\n"));
+        assert!(
+            out.contains(
+                "udsactor_broker_calls_total{endpoint=\"login\",outcome=\"transport\"} 1\n"
+            )
+        );
+        assert!(out.contains(
+            "udsactor_broker_call_duration_seconds_bucket{endpoint=\"login\",le=\"0.25\"} 1\n"
+        ));
+        assert!(out.contains(
+            "udsactor_broker_call_duration_seconds_bucket{endpoint=\"login\",le=\"5\"} 2\n"
+        ));
+        assert!(out.contains(
+            "udsactor_broker_call_duration_seconds_bucket{endpoint=\"login\",le=\"+Inf\"} 3\n"
+        ));
+        assert!(
+            out.contains("udsactor_broker_call_duration_seconds_count{endpoint=\"login\"} 3\n")
+        );
+        assert!(out.contains("udsactor_broadcast_lagged_total 5\n"));
+        assert!(out.contains("udsactor_logs_dropped_total{source=\"client\"} 1\n"));
+        assert!(out.contains("udsactor_logs_dropped_total{source=\"service\"} 0\n"));
+    }
+
+    #[test]
+    fn test_gauge_label_escaping() {
+        let mut out = String::new();
+        write_gauge(
+            &mut out,
+            "udsactor_test",
+            "Test gauge",
+            &[(&[("user", "a\"b\\c")], 1.5), (&[], 2.0)],
+        );
+        assert_eq!(
+            out,
+            "# HELP udsactor_test Test gauge\n# TYPE udsactor_test gauge\n\
+             udsactor_test{user=\"a\\\"b\\\\c\"} 1.5\nudsactor_test 2\n"
+        );
+    }
+}
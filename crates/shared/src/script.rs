@@ -0,0 +1,417 @@
+// Copyright (c) 2025 Virtual Cable S.L.U.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without modification,
+// are permitted provided that the following conditions are met:
+//
+//    * Redistributions of source code must retain the above copyright notice,
+//      this list of conditions and the following disclaimer.
+//    * Redistributions in binary form must reproduce the above copyright notice,
+//      this list of conditions in the documentation and/or other materials
+//      provided with the distribution.
+//    * Neither the name of Virtual Cable S.L.U. nor the names of its contributors
+//      may be used to endorse or promote products derived from this software
+//      without specific prior written permission.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+// AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+// IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+// DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+// FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+// DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+// SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+// CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+// OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+/*!
+Author: Adolfo Gómez, dkmaster at dkmon dot com
+*/
+// Script execution, used by the service (privileged) and by the client (user session)
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use rand::prelude::*;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::{Child, Command},
+};
+
+use crate::{
+    log,
+    ws::types::{ScriptContext, ScriptExecRequest, ScriptExecResponse},
+};
+
+/// Max bytes kept of stdout and of stderr, the rest is read but discarded
+pub const MAX_OUTPUT_SIZE: usize = 64 * 1024;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(3600);
+
+// Time given to a killed script to finish and release its output
+const KILL_GRACE: Duration = Duration::from_secs(2);
+// Time given to read the remaining output once the script has finished
+const OUTPUT_GRACE: Duration = Duration::from_millis(500);
+
+struct Interpreter {
+    program: &'static str,
+    args: &'static [&'static str],
+    extension: &'static str, // Some interpreters (powershell, cmd) need the right one
+}
+
+#[cfg(not(target_os = "windows"))]
+fn interpreter(script_type: &str) -> Option<Interpreter> {
+    let (program, args, extension): (_, &'static [&'static str], _) = match script_type {
+        "sh" => ("/bin/sh", &[], "sh"),
+        "bash" => ("bash", &[], "sh"),
+        "python" | "python3" => ("python3", &[], "py"),
+        "powershell" | "pwsh" => ("pwsh", &["-NoProfile", "-NonInteractive", "-File"], "ps1"),
+        _ => return None,
+    };
+    Some(Interpreter {
+        program,
+        args,
+        extension,
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn interpreter(script_type: &str) -> Option<Interpreter> {
+    let (program, args, extension): (_, &'static [&'static str], _) = match script_type {
+        "powershell" => (
+            "powershell.exe",
+            &[
+                "-NoProfile",
+                "-NonInteractive",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-File",
+            ],
+            "ps1",
+        ),
+        "pwsh" => (
+            "pwsh.exe",
+            &[
+                "-NoProfile",
+                "-NonInteractive",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-File",
+            ],
+            "ps1",
+        ),
+        "cmd" | "bat" => ("cmd.exe", &["/C"], "cmd"),
+        "python" | "python3" => ("python.exe", &[], "py"),
+        _ => return None,
+    };
+    Some(Interpreter {
+        program,
+        args,
+        extension,
+    })
+}
+
+#[cfg(target_os = "windows")]
+const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+
+// Kills the script and everything it started, so nothing is left running (or holding its output)
+#[cfg(not(target_os = "windows"))]
+async fn kill_tree(child: &mut Child) {
+    if let Some(pid) = child.id() {
+        // The script is the leader of its process group
+        unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+    }
+    child.start_kill().ok();
+}
+
+#[cfg(target_os = "windows")]
+async fn kill_tree(child: &mut Child) {
+    if let Some(pid) = child.id() {
+        let killed = Command::new("taskkill.exe")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .creation_flags(CREATE_NO_WINDOW)
+            .status()
+            .await;
+        if let Err(e) = killed {
+            log::warn!("Cannot kill script process tree: {}", e);
+        }
+    }
+    child.start_kill().ok();
+}
+
+/// Whether scripts of this type can be run on this platform
+pub fn is_supported(script_type: &str) -> bool {
+    interpreter(&script_type.to_lowercase()).is_some()
+}
+
+/// Timeout requested for the script, limited to MAX_TIMEOUT
+pub fn timeout(req: &ScriptExecRequest) -> Duration {
+    req.timeout
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TIMEOUT)
+        .clamp(Duration::from_secs(1), MAX_TIMEOUT)
+}
+
+#[derive(Default)]
+struct Output {
+    data: Vec<u8>,
+    truncated: bool,
+}
+
+impl Output {
+    // Reads until EOF, keeping up to MAX_OUTPUT_SIZE bytes
+    async fn read_from(&mut self, mut reader: impl AsyncRead + Unpin) {
+        let mut buf = [0u8; 8192];
+        loop {
+            match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let room = MAX_OUTPUT_SIZE - self.data.len();
+                    self.data.extend_from_slice(&buf[..n.min(room)]);
+                    self.truncated |= n > room;
+                }
+            }
+        }
+    }
+
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+}
+
+// Script file, removed when dropped
+struct ScriptFile(PathBuf);
+
+impl ScriptFile {
+    fn create(script: &str, extension: &str) -> Result<Self> {
+        use std::io::Write;
+
+        let name: String = rand::rng()
+            .sample_iter(&rand::distr::Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+        let path = std::env::temp_dir().join(format!("udsactor-{}.{}", name, extension));
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("cannot create script file {}", path.display()))?;
+        file.write_all(script.as_bytes())?;
+        Ok(Self(path))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Runs the script and waits for it (or for its timeout).
+/// Only fails if the script cannot be started; a failed or killed script is a valid response.
+pub async fn run(req: &ScriptExecRequest, context: ScriptContext) -> Result<ScriptExecResponse> {
+    let script_type = req.script_type.to_lowercase();
+    let interpreter = interpreter(&script_type)
+        .with_context(|| format!("unsupported script type: {}", req.script_type))?;
+    let file = ScriptFile::create(&req.script, interpreter.extension)?;
+    let timeout = timeout(req);
+
+    log::debug!(
+        "Running {} script ({:?}) with timeout {:?}",
+        script_type,
+        context,
+        timeout
+    );
+    let started = Instant::now();
+    let mut command = Command::new(interpreter.program);
+    command
+        .args(interpreter.args)
+        .arg(file.path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // Own process group, so the whole tree can be killed on timeout
+    #[cfg(not(target_os = "windows"))]
+    command.process_group(0);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("cannot start {}", interpreter.program))?;
+
+    let stdout = child.stdout.take().context("no stdout")?;
+    let stderr = child.stderr.take().context("no stderr")?;
+    let mut out = Output::default();
+    let mut err = Output::default();
+    let (exit_code, timed_out) = {
+        // Output is read while running, so a verbose script does not block on a full pipe.
+        // On timeout, whatever was read so far is kept
+        let mut reading = std::pin::pin!(async {
+            tokio::join!(out.read_from(stdout), err.read_from(stderr));
+        });
+        let mut read_done = false;
+        // Only the script itself is waited for, processes it left in background
+        // may keep its output open for much longer
+        let finished = tokio::time::timeout(timeout, async {
+            loop {
+                tokio::select! {
+                    status = child.wait() => break status,
+                    _ = &mut reading, if !read_done => read_done = true,
+                }
+            }
+        })
+        .await;
+
+        let result = match finished {
+            Ok(status) => (status?.code(), false),
+            Err(_) => {
+                log::warn!("Script timed out after {:?}, killing it", timeout);
+                kill_tree(&mut child).await;
+                let status = tokio::time::timeout(KILL_GRACE, child.wait()).await;
+                (
+                    status.ok().and_then(|s| s.ok()).and_then(|s| s.code()),
+                    true,
+                )
+            }
+        };
+        if !read_done
+            && tokio::time::timeout(OUTPUT_GRACE, &mut reading)
+                .await
+                .is_err()
+        {
+            log::debug!("Script output still open after it finished, not waiting for it");
+        }
+        result
+    };
+
+    Ok(ScriptExecResponse {
+        context,
+        exit_code,
+        stdout: out.text(),
+        stderr: err.text(),
+        truncated: out.truncated || err.truncated,
+        timed_out,
+        duration: started.elapsed().as_secs_f64(),
+    })
+}
+
+#[cfg(all(test, not(target_os = "windows")))]
+mod tests {
+    use super::*;
+
+    fn request(script_type: &str, script: &str, timeout: Option<u64>) -> ScriptExecRequest {
+        ScriptExecRequest {
+            script_type: script_type.into(),
+            script: script.into(),
+            service: true,
+            timeout,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let res = run(
+            &request("sh", "echo hello; echo oops >&2; exit 3", None),
+            ScriptContext::Service,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.exit_code, Some(3));
+        assert_eq!(res.stdout, "hello\n");
+        assert_eq!(res.stderr, "oops\n");
+        assert_eq!(res.context, ScriptContext::Service);
+        assert!(!res.truncated && !res.timed_out);
+    }
+
+    #[tokio::test]
+    async fn test_run_timeout() {
+        let res = run(
+            &request("sh", "echo started; sleep 30", Some(1)),
+            ScriptContext::User,
+        )
+        .await
+        .unwrap();
+        assert!(res.timed_out);
+        assert_eq!(res.exit_code, None);
+        assert_eq!(res.stdout, "started\n");
+        assert!(res.duration < 10.0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_run_timeout_kills_tree() {
+        let res = run(
+            &request("sh", "sleep 30 & echo $!; wait", Some(1)),
+            ScriptContext::Service,
+        )
+        .await
+        .unwrap();
+        assert!(res.timed_out);
+        let pid = res.stdout.trim();
+        // Gone, or waiting to be reaped
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+        assert!(stat.is_empty() || stat.contains(") Z "), "{}", stat);
+    }
+
+    #[tokio::test]
+    async fn test_run_background_process() {
+        // Background process keeps stdout open, but the script itself is done
+        let res = run(
+            &request("sh", "echo done; sleep 30 &", Some(10)),
+            ScriptContext::Service,
+        )
+        .await
+        .unwrap();
+        assert!(!res.timed_out);
+        assert_eq!(res.exit_code, Some(0));
+        assert_eq!(res.stdout, "done\n");
+        assert!(res.duration < 5.0);
+    }
+
+    #[tokio::test]
+    async fn test_run_truncated() {
+        let res = run(
+            &request("sh", "head -c 200000 /dev/zero | tr '\\0' 'x'", None),
+            ScriptContext::Service,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.exit_code, Some(0));
+        assert!(res.truncated);
+        assert_eq!(res.stdout.len(), MAX_OUTPUT_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_run_unsupported() {
+        assert!(!is_supported("cobol"));
+        assert!(is_supported("SH"));
+        assert!(
+            run(&request("cobol", "", None), ScriptContext::Service)
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        assert_eq!(timeout(&request("sh", "", None)), DEFAULT_TIMEOUT);
+        assert_eq!(timeout(&request("sh", "", Some(0))), Duration::from_secs(1));
+        assert_eq!(timeout(&request("sh", "", Some(99999))), MAX_TIMEOUT);
+    }
+}
@@ -195,6 +195,11 @@ pub trait System: Send + Sync {
     /// On Unix, this typically sets permissions to 600. On Windows, it modifies the ACLs.
     fn protect_file_for_owner_only(&self, path: &str) -> Result<()>;
 
+    /// Protect a file so that only the given user can read/write it (the service keeps access).
+    /// Used to hand per-session credentials to the client running on that user session.
+    /// On Unix, the file is chowned to the user and set to 600. On Windows, it modifies the ACLs.
+    fn protect_file_for_user(&self, path: &str, user: &str) -> Result<()>;
+
     // Make whatever is is needed to allow the user to connect via RDP
     // This may include enabling RDP, configuring firewall, etc.
     // On windows, basically ensures that the user is in the "Remote Desktop Users" group
@@ -278,4 +283,4 @@ mod tests {
             .collect();
         assert_eq!(not_in_subnet.len(), 1);
     }
-}
\ No newline at end of file
+}
@@ -220,6 +220,14 @@ impl System for OperationsMock {
         Ok(())
     }
 
+    fn protect_file_for_user(&self, path: &str, user: &str) -> anyhow::Result<()> {
+        self.calls.push(format!(
+            "operations::protect_file_for_user({}, {})",
+            path, user
+        ));
+        Ok(())
+    }
+
     fn ensure_user_can_rdp(&self, user: &str) -> anyhow::Result<()> {
         self.calls
             .push(format!("operations::ensure_user_can_rdp({})", user));
@@ -239,6 +247,9 @@ pub struct BrokerApiMock {
     secret: Option<String>,
     token: Option<String>,
     pub init_response: api::types::InitializationResponse,
+    pub init_error: Option<api::types::RestError>, // If set, initialize will fail with this error
+    pub fail_with: Option<api::types::RestError>, // If set, logout and log will fail with this error
+    pub capabilities: api::types::BrokerCapabilities,
 }
 
 impl BrokerApiMock {
@@ -252,7 +263,12 @@ impl BrokerApiMock {
                 token: Some("init_token".into()),
                 unique_id: Some("init_unique_id".into()),
                 os: None,
+                heartbeat_interval: None,
+                allowed_networks: None,
             },
+            init_error: None,
+            fail_with: None,
+            capabilities: api::types::BrokerCapabilities::default(),
         }
     }
 }
@@ -272,7 +288,7 @@ impl api::BrokerApi for BrokerApiMock {
         self.calls.push("broker_api::get_secret()");
         self.secret
             .as_deref()
-            .ok_or_else(|| api::types::RestError::Other("No secret set".into()))
+            .ok_or_else(|| api::types::RestError::other("No secret set"))
     }
 
     fn set_token(&mut self, token: &str) {
@@ -330,6 +346,9 @@ impl api::BrokerApi for BrokerApiMock {
     ) -> Result<api::types::InitializationResponse, api::types::RestError> {
         self.calls
             .push(format!("broker_api::initialize({:?})", interfaces));
+        if let Some(err) = &self.init_error {
+            return Err(err.clone());
+        }
         Ok(self.init_response.clone())
     }
     async fn ready(&self, ip: &str, port: u16) -> Result<CertificateInfo, api::types::RestError> {
@@ -386,6 +405,9 @@ impl api::BrokerApi for BrokerApiMock {
             "broker_api::logout({:?}, {}, {}, {})",
             interfaces, username, session_type, session_id
         ));
+        if let Some(err) = &self.fail_with {
+            return Err(err.clone());
+        }
         Ok("Logged out".into())
     }
     async fn log(
@@ -395,10 +417,51 @@ impl api::BrokerApi for BrokerApiMock {
     ) -> Result<String, api::types::RestError> {
         self.calls
             .push(format!("broker_api::log({:?}, {})", level, message));
+        if let Some(err) = &self.fail_with {
+            return Err(err.clone());
+        }
         Ok("Log received".into())
     }
+    async fn log_batch(
+        &self,
+        entries: &[api::types::LogEntry],
+    ) -> Result<String, api::types::RestError> {
+        let messages: Vec<&str> = entries.iter().map(|e| e.message.as_str()).collect();
+        self.calls
+            .push(format!("broker_api::log_batch({})", messages.join(", ")));
+        if let Some(err) = &self.fail_with {
+            return Err(err.clone());
+        }
+        Ok("Logs received".into())
+    }
     async fn test(&self) -> Result<String, api::types::RestError> {
         self.calls.push("broker_api::test()");
         Ok("Test successful".into())
     }
+    async fn heartbeat(
+        &self,
+        status: &api::types::ActorStatus,
+    ) -> Result<String, api::types::RestError> {
+        self.calls.push(format!(
+            "broker_api::heartbeat({:?}, {})",
+            status.user, status.client_connected
+        ));
+        if let Some(err) = &self.fail_with {
+            return Err(err.clone());
+        }
+        Ok("ok".into())
+    }
+    async fn probe_capabilities(
+        &self,
+    ) -> Result<api::types::BrokerCapabilities, api::types::RestError> {
+        self.calls.push("broker_api::probe_capabilities()");
+        Ok(self.capabilities.clone())
+    }
+    fn capabilities(&self) -> api::types::BrokerCapabilities {
+        self.capabilities.clone()
+    }
+
+    fn last_call(&self) -> Option<api::types::BrokerCallResult> {
+        None
+    }
 }
@@ -4,7 +4,24 @@ use crate::tls::CertificateInfo;
 const CERT_PEM: &[u8] = include_bytes!("../../../../assets/testcerts/cert.pem");
 const KEY_PEM: &[u8] = include_bytes!("../../../../assets/testcerts/key.pem");
 const KEY_PEM_WITH_PASS: &[u8] = include_bytes!("../../../../assets/testcerts/key_pass.pem");
-const KEY_PASSWORD: &str = "test_password";
+pub const KEY_PASSWORD: &str = "test_password";
+// Certificate signed by a testing CA, valid for localhost, 127.0.0.1 and ::1
+const BROKER_CERT_PEM: &[u8] = include_bytes!("../../../../assets/testcerts/broker.pem");
+const BROKER_KEY_PEM: &[u8] = include_bytes!("../../../../assets/testcerts/broker_key.pem");
+pub const CA_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../assets/testcerts/ca.pem");
+// Client certificate signed by the testing CA, for mTLS (key encrypted with KEY_PASSWORD on _pass)
+pub const CLIENT_CERT_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../assets/testcerts/client.pem"
+);
+pub const CLIENT_KEY_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../assets/testcerts/client_key.pem"
+);
+pub const CLIENT_KEY_PASS_PATH: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../assets/testcerts/client_key_pass.pem"
+);
 const TESTING_CIPHERS: &str =
     "TLS_AES_128_GCM_SHA256:TLS_AES_256_GCM_SHA384:TLS_CHACHA20_POLY1305_SHA256";
 
@@ -28,3 +45,12 @@ pub fn test_certinfo_with_pass() -> CertificateInfo {
         ciphers: Some(TESTING_CIPHERS.to_string()),
     }
 }
+
+pub fn test_broker_certinfo() -> CertificateInfo {
+    CertificateInfo {
+        key: String::from_utf8(BROKER_KEY_PEM.to_vec()).unwrap(),
+        certificate: String::from_utf8(BROKER_CERT_PEM.to_vec()).unwrap(),
+        password: None,
+        ciphers: Some(TESTING_CIPHERS.to_string()),
+    }
+}
@@ -15,22 +15,45 @@ use rustls_pki_types::pem::PemObject;
 use super::{CertificateInfo, ciphers};
 
 pub fn rustls_config_from_pem(cert_info: CertificateInfo) -> Result<RustlsConfig> {
-    // Infor for error logging
-    let err_cert_info = cert_info.clone();
+    let config = server_config_from_pem(&cert_info)?;
+    Ok(RustlsConfig::from_config(config))
+}
+
+/// Server TLS config for the certificate, also used to reload it on a running server
+pub fn server_config_from_pem(cert_info: &CertificateInfo) -> Result<Arc<ServerConfig>> {
+    let (cert_chain, private_key) = certified_key_from_pem(cert_info)?;
+
+    let provider = ciphers::provider(cert_info.ciphers.as_deref());
+
+    let config: ServerConfig = ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&[&TLS13, &TLS12])?
+        .with_no_client_auth()
+        .with_single_cert(cert_chain, private_key)?;
 
+    Ok(Arc::new(config))
+}
+
+/// Parses the certificate chain and the private key (decrypting it if password is provided)
+/// Used both for our server certificate and for the client certificate presented to broker
+pub fn certified_key_from_pem(
+    cert_info: &CertificateInfo,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
     let cert_chain: Vec<CertificateDer<'static>> =
         CertificateDer::pem_slice_iter(cert_info.certificate.as_bytes())
             .collect::<Result<Vec<_>, _>>()?;
+    if cert_chain.is_empty() {
+        anyhow::bail!("No certificate found");
+    }
 
-    let key_pem: Vec<u8> = cert_info.key.into();
+    let key_pem = cert_info.key.as_bytes();
     // Extract private key, (possibly decrypting it first if password is Some)
     let private_key: PrivateKeyDer<'static> = match cert_info.password {
         Some(ref pass) if !pass.is_empty() => {
-            let pem_block = pem::parse(key_pem.as_slice())?;
+            let pem_block = pem::parse(key_pem)?;
             let epki = EncryptedPrivateKeyInfo::from_der(pem_block.contents())
                 .map_err(|e| anyhow::anyhow!("Failed to parse encrypted private key: {:?}", e))?;
             let doc = epki.decrypt(pass).map_err(|e| {
-                log::info!("Invalid TLS certificate info: {:?}", err_cert_info);
+                log::info!("Invalid password for TLS private key: {:?}", e);
                 anyhow::anyhow!(
                     "Failed to decrypt private key with provided password: {:?}",
                     e
@@ -39,17 +62,10 @@ pub fn rustls_config_from_pem(cert_info: CertificateInfo) -> Result<RustlsConfig
             // doc.as_bytes() is PKCS#8 DER; convert to PrivatePkcs8KeyDer
             PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(doc.as_bytes().to_vec()))
         }
-        _ => parse_unencrypted_key(key_pem.as_slice())?,
+        _ => parse_unencrypted_key(key_pem)?,
     };
 
-    let provider = ciphers::provider(cert_info.ciphers.as_deref());
-
-    let config: ServerConfig = ServerConfig::builder_with_provider(Arc::new(provider))
-        .with_protocol_versions(&[&TLS13, &TLS12])?
-        .with_no_client_auth()
-        .with_single_cert(cert_chain, private_key)?;
-
-    Ok(RustlsConfig::from_config(Arc::new(config)))
+    Ok((cert_chain, private_key))
 }
 
 fn parse_unencrypted_key(key_pem: &[u8]) -> Result<PrivateKeyDer<'static>> {
@@ -5,8 +5,9 @@ use crate::log;
 pub mod certool;
 pub mod ciphers;
 pub mod noverify;
+pub mod trust;
 
-#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct CertificateInfo {
     pub key: String,
     pub certificate: String,
@@ -14,6 +15,18 @@ pub struct CertificateInfo {
     pub ciphers: Option<String>,
 }
 
+// Private key and password are never shown, so cert info can be safely logged
+impl std::fmt::Debug for CertificateInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertificateInfo")
+            .field("key", &"<redacted>")
+            .field("certificate", &self.certificate)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("ciphers", &self.ciphers)
+            .finish()
+    }
+}
+
 // Ensure only one initialization happens
 static INIT: std::sync::Once = std::sync::Once::new();
 
@@ -0,0 +1,431 @@
+use anyhow::Result;
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    client::{
+        WebPkiServerVerifier,
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    },
+    crypto::{CryptoProvider, WebPkiSupportedAlgorithms},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+use rustls_pki_types::pem::PemObject;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+
+use super::{CertificateInfo, certool};
+use crate::log;
+
+/// How the broker certificate was trusted on last handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrustPath {
+    SystemRoots { pinned: bool },
+    CaBundle { pinned: bool },
+    SpkiPin,    // Only pin checked, chain not validated (verify_ssl disabled)
+    Unverified, // verify_ssl disabled
+}
+
+impl std::fmt::Display for TrustPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (path, pinned) = match self {
+            TrustPath::SystemRoots { pinned } => ("system root certificates", *pinned),
+            TrustPath::CaBundle { pinned } => ("custom CA bundle", *pinned),
+            TrustPath::SpkiPin => ("SPKI pin", false),
+            TrustPath::Unverified => ("not verified", false),
+        };
+        write!(f, "{}", path)?;
+        if pinned {
+            write!(f, " and SPKI pin")?;
+        }
+        Ok(())
+    }
+}
+
+// Process default provider if installed, aws_lc_rs otherwise
+fn provider() -> Arc<CryptoProvider> {
+    CryptoProvider::get_default()
+        .cloned()
+        .unwrap_or_else(|| Arc::new(rustls::crypto::aws_lc_rs::default_provider()))
+}
+
+/// SHA-256 of the SubjectPublicKeyInfo of a certificate, as used on pins
+pub fn spki_sha256(cert: &CertificateDer<'_>) -> Result<[u8; 32]> {
+    let cert = webpki::EndEntityCert::try_from(cert)
+        .map_err(|e| anyhow::anyhow!("Invalid certificate: {:?}", e))?;
+    Ok(Sha256::digest(cert.subject_public_key_info().as_ref()).into())
+}
+
+/// Parses a pin, base64 of the SHA-256 of the SPKI, optionally prefixed with "sha256/" or
+/// "sha256//" (curl --pinnedpubkey format)
+pub fn parse_pin(pin: &str) -> Result<[u8; 32]> {
+    let b64 = pin
+        .trim()
+        .trim_start_matches("sha256/")
+        .trim_start_matches('/');
+    STANDARD
+        .decode(b64)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Invalid SPKI pin {}: not a SHA-256 hash", pin))
+}
+
+// DER SEQUENCE around the content, as trust anchors keep their SPKI without it
+fn der_sequence(content: &[u8]) -> Vec<u8> {
+    let len = content.len();
+    let mut der = vec![0x30];
+    if len < 0x80 {
+        der.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|b| *b == 0)
+            .collect();
+        der.push(0x80 | bytes.len() as u8);
+        der.extend(bytes);
+    }
+    der.extend_from_slice(content);
+    der
+}
+
+// Chain validation against a set of roots
+#[derive(Debug)]
+struct ChainVerifier {
+    verifier: Arc<WebPkiServerVerifier>,
+    roots: Arc<RootCertStore>, // To rebuild the validated path, for pins
+}
+
+impl ChainVerifier {
+    fn new(roots: RootCertStore, provider: &Arc<CryptoProvider>) -> Result<Self> {
+        let roots = Arc::new(roots);
+        Ok(Self {
+            verifier: WebPkiServerVerifier::builder_with_provider(roots.clone(), provider.clone())
+                .build()?,
+            roots,
+        })
+    }
+}
+
+/// Verifier for broker connections.
+/// Validates the chain against the CA bundle (if any) and then the system roots (if verify_ssl),
+/// and requires the SPKI of some certificate of the validated path to match a pin (if any pin
+/// configured). Without chain validation, only the end entity certificate can match a pin.
+/// The path that validated the last handshake is recorded, so it can be reported.
+#[derive(Debug)]
+pub struct BrokerVerifier {
+    ca_bundle: Option<ChainVerifier>,
+    system: Option<ChainVerifier>,
+    pins: Vec<[u8; 32]>,
+    algorithms: WebPkiSupportedAlgorithms,
+    last_path: Mutex<Option<TrustPath>>,
+}
+
+impl BrokerVerifier {
+    pub fn new(ca_bundle: Option<&str>, pins: &[String], verify_ssl: bool) -> Result<Self> {
+        let provider = provider();
+
+        let ca_bundle = if let Some(path) = ca_bundle {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read CA bundle {}: {}", path, e))?
+            {
+                roots.add(cert?)?;
+            }
+            if roots.is_empty() {
+                anyhow::bail!("CA bundle {} does not contain any certificate", path);
+            }
+            Some(ChainVerifier::new(roots, &provider)?)
+        } else {
+            None
+        };
+
+        let system = if verify_ssl {
+            let mut roots = RootCertStore::empty();
+            let native = rustls_native_certs::load_native_certs();
+            for err in native.errors {
+                log::warn!("Error loading system root certificate: {}", err);
+            }
+            roots.add_parsable_certificates(native.certs);
+            Some(ChainVerifier::new(roots, &provider)?)
+        } else {
+            None
+        };
+
+        let pins = pins
+            .iter()
+            .map(|pin| parse_pin(pin))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            ca_bundle,
+            system,
+            pins,
+            algorithms: provider.signature_verification_algorithms,
+            last_path: Mutex::new(None),
+        })
+    }
+
+    pub fn last_path(&self) -> Option<TrustPath> {
+        *self.last_path.lock().unwrap()
+    }
+
+    fn record(&self, path: TrustPath) -> Result<ServerCertVerified, rustls::Error> {
+        log::debug!("Broker certificate trusted via {}", path);
+        self.last_path.lock().unwrap().replace(path);
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn is_pinned(&self, spki: &[u8]) -> bool {
+        self.pins.contains(&Sha256::digest(spki).into())
+    }
+
+    // Whether some path to the roots, built only from certificates that chain up, contains
+    // a pinned key. Extra certificates sent by the server are not trusted for this
+    fn validated_path_pinned(
+        &self,
+        chain: &ChainVerifier,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> bool {
+        let Ok(cert) = webpki::EndEntityCert::try_from(end_entity) else {
+            return false;
+        };
+        let pinned_path = |path: &webpki::VerifiedPath<'_>| {
+            let found = self.is_pinned(path.end_entity().subject_public_key_info().as_ref())
+                || path
+                    .intermediate_certificates()
+                    .any(|c| self.is_pinned(c.subject_public_key_info().as_ref()))
+                || self.is_pinned(&der_sequence(
+                    path.anchor().subject_public_key_info.as_ref(),
+                ));
+            // Any error makes the path builder try another path
+            if found {
+                Ok(())
+            } else {
+                Err(webpki::Error::UnknownIssuer)
+            }
+        };
+        cert.verify_for_usage(
+            self.algorithms.all,
+            &chain.roots.roots,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+            None,
+            Some(&pinned_path),
+        )
+        .is_ok()
+    }
+}
+
+fn pin_mismatch() -> rustls::Error {
+    rustls::Error::General("Broker certificate does not match any configured SPKI pin".into())
+}
+
+impl ServerCertVerifier for BrokerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // Only a successful handshake leaves a path
+        self.last_path.lock().unwrap().take();
+        let pinned = !self.pins.is_empty();
+
+        let mut chain_error = None;
+        for (chain, path) in [
+            (&self.ca_bundle, TrustPath::CaBundle { pinned }),
+            (&self.system, TrustPath::SystemRoots { pinned }),
+        ] {
+            if let Some(chain) = chain {
+                match chain.verifier.verify_server_cert(
+                    end_entity,
+                    intermediates,
+                    server_name,
+                    ocsp_response,
+                    now,
+                ) {
+                    Ok(_)
+                        if pinned
+                            && !self.validated_path_pinned(
+                                chain,
+                                end_entity,
+                                intermediates,
+                                now,
+                            ) =>
+                    {
+                        chain_error = Some(pin_mismatch())
+                    }
+                    Ok(_) => return self.record(path),
+                    Err(e) => chain_error = Some(e),
+                }
+            }
+        }
+        if let Some(e) = chain_error {
+            return Err(e);
+        }
+
+        if !pinned {
+            return self.record(TrustPath::Unverified);
+        }
+        // Chain not validated, so the intermediates may be anything
+        match spki_sha256(end_entity) {
+            Ok(hash) if self.pins.contains(&hash) => self.record(TrustPath::SpkiPin),
+            _ => Err(pin_mismatch()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Client configuration that uses the given verifier for server certificates, presenting
+/// the client certificate (mTLS) if provided
+pub fn client_config(
+    verifier: Arc<BrokerVerifier>,
+    client_cert: Option<&CertificateInfo>,
+) -> Result<ClientConfig> {
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+    Ok(match client_cert {
+        Some(cert_info) => {
+            let (chain, key) = certool::certified_key_from_pem(cert_info)?;
+            builder.with_client_auth_cert(chain, key)?
+        }
+        None => builder.with_no_client_auth(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pin() {
+        let hash = [7u8; 32];
+        let b64 = STANDARD.encode(hash);
+        assert_eq!(parse_pin(&b64).unwrap(), hash);
+        assert_eq!(parse_pin(&format!("sha256/{}", b64)).unwrap(), hash);
+        assert_eq!(parse_pin(&format!("sha256//{}", b64)).unwrap(), hash);
+        assert!(parse_pin("sha256/AAAA").is_err());
+        assert!(parse_pin("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_invalid_ca_bundle() {
+        assert!(BrokerVerifier::new(Some("/nonexistent/ca.pem"), &[], true).is_err());
+    }
+
+    fn der(pem: &str) -> CertificateDer<'static> {
+        CertificateDer::from_pem_slice(pem.as_bytes()).unwrap()
+    }
+
+    fn pin_of(cert: &CertificateDer<'_>) -> String {
+        STANDARD.encode(spki_sha256(cert).unwrap())
+    }
+
+    fn verify(
+        verifier: &BrokerVerifier,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            &ServerName::try_from("localhost").unwrap(),
+            &[],
+            UnixTime::now(),
+        )
+    }
+
+    // Certificates: broker (signed by the testing CA), ca and a self signed one (the "attacker")
+    fn certs() -> (
+        CertificateDer<'static>,
+        CertificateDer<'static>,
+        CertificateDer<'static>,
+    ) {
+        let ca = CertificateDer::pem_file_iter(crate::testing::test_certs::CA_PATH)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        (
+            der(&crate::testing::test_certs::test_broker_certinfo().certificate),
+            ca,
+            der(&crate::testing::test_certs::test_certinfo().certificate),
+        )
+    }
+
+    #[test]
+    fn test_pin_only_matches_end_entity() {
+        crate::tls::init_tls(None);
+        let (broker, _, attacker) = certs();
+        let verifier = BrokerVerifier::new(None, &[pin_of(&broker)], false).unwrap();
+
+        assert!(verify(&verifier, &broker, &[]).is_ok());
+        assert_eq!(verifier.last_path(), Some(TrustPath::SpkiPin));
+        // Pinned certificate appended as an intermediate is not enough
+        assert!(verify(&verifier, &attacker, std::slice::from_ref(&broker)).is_err());
+        assert_eq!(verifier.last_path(), None);
+    }
+
+    #[test]
+    fn test_pin_on_validated_path() {
+        crate::tls::init_tls(None);
+        let (broker, ca, attacker) = certs();
+        let ca_bundle = Some(crate::testing::test_certs::CA_PATH);
+
+        // The CA (trust anchor) of the validated path can be pinned
+        let verifier = BrokerVerifier::new(ca_bundle, &[pin_of(&ca)], false).unwrap();
+        assert!(verify(&verifier, &broker, &[]).is_ok());
+        assert_eq!(
+            verifier.last_path(),
+            Some(TrustPath::CaBundle { pinned: true })
+        );
+
+        // Extra certificates, not on the validated path, are not
+        let verifier = BrokerVerifier::new(ca_bundle, &[pin_of(&attacker)], false).unwrap();
+        assert!(verify(&verifier, &broker, std::slice::from_ref(&attacker)).is_err());
+        assert_eq!(verifier.last_path(), None);
+    }
+
+    #[test]
+    fn test_failed_handshake_clears_path() {
+        crate::tls::init_tls(None);
+        let (broker, _, attacker) = certs();
+        let verifier =
+            BrokerVerifier::new(Some(crate::testing::test_certs::CA_PATH), &[], false).unwrap();
+        assert!(verify(&verifier, &broker, &[]).is_ok());
+        assert_eq!(
+            verifier.last_path(),
+            Some(TrustPath::CaBundle { pinned: false })
+        );
+
+        assert!(verify(&verifier, &attacker, &[]).is_err());
+        assert_eq!(verifier.last_path(), None);
+    }
+}
@@ -77,3 +77,11 @@ impl Configuration for UnixConfig {
 pub fn new_config_storage() -> Box<dyn Configuration> {
     Box::new(UnixConfig::default())
 }
+
+/// Folder for files that live next to the configuration (i.e. client certificate)
+pub fn config_dir() -> std::path::PathBuf {
+    std::path::Path::new(&get_config_file())
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_else(|| std::path::PathBuf::from(CONFIG_PATH))
+}
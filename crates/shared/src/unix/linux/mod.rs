@@ -183,6 +183,10 @@ impl crate::system::System for LinuxSystem {
         }
     }
 
+    fn protect_file_for_user(&self, path: &str, user: &str) -> Result<()> {
+        crate::unix::protect_file_for_user(path, user)
+    }
+
     fn ensure_user_can_rdp(&self, _user: &str) -> Result<()> {
         // On linux, all users can RDP by default
         Ok(())
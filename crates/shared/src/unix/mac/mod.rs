@@ -190,6 +190,11 @@ impl crate::system::System for MacSystem {
         Ok(())
     }
 
+    fn protect_file_for_user(&self, path: &str, user: &str) -> Result<()> {
+        log::debug!("MacSystem::protect_file_for_user called: {} {}", path, user);
+        crate::unix::protect_file_for_user(path, user)
+    }
+
     fn ensure_user_can_rdp(&self, user: &str) -> Result<()> {
         log::debug!("MacSystem::ensure_user_can_rdp called: {}", user);
         Ok(())
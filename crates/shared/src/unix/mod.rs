@@ -42,3 +42,48 @@ pub mod mac;
 pub use mac as system;
 #[cfg(target_os = "macos")]
 pub use mac::installer;
+
+/// Gives the ownership of the file to `user`, with read/write for it only (600)
+pub(crate) fn protect_file_for_user(path: &str, user: &str) -> anyhow::Result<()> {
+    let c_path = std::ffi::CString::new(path)?;
+    let c_user = std::ffi::CString::new(user)?;
+    unsafe {
+        let mut pwd: libc::passwd = std::mem::zeroed();
+        let mut buf = vec![0 as libc::c_char; 4096];
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        let rc = libc::getpwnam_r(
+            c_user.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        );
+        if rc != 0 || result.is_null() {
+            anyhow::bail!("Unknown user {}", user);
+        }
+        // chmod first, so the file is never readable by others while owned by the user
+        if libc::chmod(c_path.as_ptr(), 0o600) != 0 {
+            anyhow::bail!("chmod failed: {}", std::io::Error::last_os_error());
+        }
+        if libc::chown(c_path.as_ptr(), pwd.pw_uid, pwd.pw_gid) != 0 {
+            anyhow::bail!("chown failed: {}", std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Name of the user with the given uid
+pub fn username_from_uid(uid: u32) -> anyhow::Result<String> {
+    unsafe {
+        let mut pwd: libc::passwd = std::mem::zeroed();
+        let mut buf = vec![0 as libc::c_char; 4096];
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        let rc = libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result);
+        if rc != 0 || result.is_null() {
+            anyhow::bail!("Unknown uid {}", uid);
+        }
+        Ok(std::ffi::CStr::from_ptr(pwd.pw_name)
+            .to_string_lossy()
+            .into_owned())
+    }
+}
@@ -250,3 +250,15 @@ impl Configuration for WindowsConfig {
 pub fn new_config_storage() -> Box<dyn Configuration> {
     Box::new(WindowsConfig::default())
 }
+
+/// Folder for files that live next to the configuration (i.e. client certificate)
+/// Configuration itself is on registry, so we use ProgramData
+pub fn config_dir() -> std::path::PathBuf {
+    if std::env::var("UDS_ACTOR_TEST").is_ok() {
+        std::env::temp_dir()
+    } else {
+        let program_data =
+            std::env::var("ProgramData").unwrap_or_else(|_| "C:\\ProgramData".to_string());
+        std::path::Path::new(&program_data).join("UDSActor")
+    }
+}
@@ -32,7 +32,7 @@ use anyhow::{Context, Result};
 use widestring::{U16CStr, U16CString};
 use windows::{
     Win32::{
-        Foundation::{CloseHandle, HANDLE},
+        Foundation::{CloseHandle, HANDLE, HLOCAL, LocalFree},
         NetworkManagement::{
             IpHelper::{
                 GET_ADAPTERS_ADDRESSES_FLAGS, GetAdaptersAddresses, IP_ADAPTER_ADDRESSES_LH,
@@ -51,8 +51,9 @@ use windows::{
             ACL, ACL_REVISION, AddAccessAllowedAce, AdjustTokenPrivileges,
             Authorization::{ConvertStringSidToSidW, SE_FILE_OBJECT, SetNamedSecurityInfoW},
             DACL_SECURITY_INFORMATION, EqualSid, InitializeAcl, LookupAccountNameW,
-            LookupAccountSidW, LookupPrivilegeValueW, PSID, SE_PRIVILEGE_ENABLED, SE_SHUTDOWN_NAME,
-            SID_NAME_USE, SidTypeUnknown, TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+            LookupAccountSidW, LookupPrivilegeValueW, PROTECTED_DACL_SECURITY_INFORMATION, PSID,
+            SE_PRIVILEGE_ENABLED, SE_SHUTDOWN_NAME, SID_NAME_USE, SidTypeUnknown,
+            TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
         },
         Storage::FileSystem::FILE_ALL_ACCESS,
         System::{
@@ -187,6 +188,91 @@ impl WindowsOperations {
         }
     }
 
+    // Replaces the DACL of the file so only `user` (and LocalSystem if `allow_system`) can access it
+    fn protect_file_for_account(path: &str, user: &str, allow_system: bool) -> Result<()> {
+        unsafe {
+            // Convert path to UTF-16
+            let path_w = U16CString::from_str(path)
+                .context("failed to convert path to UTF-16 for SetNamedSecurityInfoW")?;
+
+            // 1. Resolve the user SID
+            let mut sid: [u8; 256] = [0; 256];
+            let mut sid_size = sid.len() as u32;
+            let mut domain: [u16; 256] = [0; 256];
+            let mut domain_size = domain.len() as u32;
+            let mut sid_name_use = SidTypeUnknown;
+
+            let user_w =
+                U16CString::from_str(user).context("failed to convert username to UTF-16")?;
+
+            LookupAccountNameW(
+                None,
+                PCWSTR(user_w.as_ptr()),
+                Some(PSID(sid.as_mut_ptr() as _)),
+                &mut sid_size,
+                Some(PWSTR(domain.as_mut_ptr())),
+                &mut domain_size,
+                &mut sid_name_use,
+            )
+            .map_err(|e| anyhow::anyhow!("LookupAccountNameW failed: {}", e))?;
+
+            // 2. Create ACL with an ACE that grants full access to the SID
+            let mut acl_buf = vec![0u8; 1024];
+            let acl = acl_buf.as_mut_ptr() as *mut ACL;
+            InitializeAcl(acl, acl_buf.len() as u32, ACL_REVISION)
+                .map_err(|e| anyhow::anyhow!("InitializeAcl failed: {}", e))?;
+
+            AddAccessAllowedAce(
+                acl,
+                ACL_REVISION,
+                FILE_ALL_ACCESS.0,
+                PSID(sid.as_mut_ptr() as _),
+            )
+            .map_err(|e| anyhow::anyhow!("AddAccessAllowedAce failed: {}", e))?;
+
+            // Files owned by other users must still be reachable by the service
+            // Well known SID for LocalSystem, S-1-5-18
+            let mut security_info = DACL_SECURITY_INFORMATION;
+            if allow_system {
+                let mut system_sid: PSID = PSID::default();
+                ConvertStringSidToSidW(w!("S-1-5-18"), &mut system_sid)
+                    .map_err(|e| anyhow::anyhow!("ConvertStringSidToSidW failed: {}", e))?;
+                // The ACE keeps its own copy of the SID, so it can be freed right away
+                let added = AddAccessAllowedAce(acl, ACL_REVISION, FILE_ALL_ACCESS.0, system_sid);
+                LocalFree(Some(HLOCAL(system_sid.0)));
+                added.map_err(|e| anyhow::anyhow!("AddAccessAllowedAce failed: {}", e))?;
+                // Do not inherit anything from the parent folder
+                security_info |= PROTECTED_DACL_SECURITY_INFORMATION;
+            }
+
+            // 3. Apply the new DACL to the file
+            let err = SetNamedSecurityInfoW(
+                PCWSTR(path_w.as_ptr()),
+                SE_FILE_OBJECT,
+                security_info,
+                None,
+                None,
+                Some(acl),
+                None,
+            );
+
+            if err.0 != 0 {
+                let detail = Self::format_net_error(err.0 as u32);
+                log::error!(
+                    "SetNamedSecurityInfoW for path '{}' failed: {}",
+                    path,
+                    detail
+                );
+                return Err(anyhow::anyhow!(
+                    "SetNamedSecurityInfoW failed: {}",
+                    detail
+                ));
+            }
+
+            Ok(())
+        }
+    }
+
     /// Translate a Win32 / netapi32 error code into a human-readable message
     /// using `FormatMessageW`. Falls back to the numeric code if the message
     /// cannot be resolved (e.g. custom HRESULT, driver-specific codes).
@@ -197,7 +283,7 @@ impl WindowsOperations {
     fn format_net_error(code: u32) -> String {
         unsafe {
             use windows::Win32::System::Diagnostics::Debug::{
-                FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS,
+                FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS, FormatMessageW,
             };
             let mut buf = [0u16; 512];
             let len = FormatMessageW(
@@ -712,73 +798,12 @@ impl System for WindowsOperations {
     }
 
     fn protect_file_for_owner_only(&self, path: &str) -> Result<()> {
-        unsafe {
-            // Convert path to UTF-16
-            let path_w = U16CString::from_str(path)
-                .context("failed to convert path to UTF-16 for SetNamedSecurityInfoW")?;
-
-            // 1. Resolve the current user SID
-            let mut sid: [u8; 256] = [0; 256];
-            let mut sid_size = sid.len() as u32;
-            let mut domain: [u16; 256] = [0; 256];
-            let mut domain_size = domain.len() as u32;
-            let mut sid_name_use = SidTypeUnknown;
-
-            let user = self.get_current_user()?;
-            let user_w =
-                U16CString::from_str(&user).context("failed to convert username to UTF-16")?;
-
-            LookupAccountNameW(
-                None,
-                PCWSTR(user_w.as_ptr()),
-                Some(PSID(sid.as_mut_ptr() as _)),
-                &mut sid_size,
-                Some(PWSTR(domain.as_mut_ptr())),
-                &mut domain_size,
-                &mut sid_name_use,
-            )
-            .map_err(|e| anyhow::anyhow!("LookupAccountNameW failed: {}", e))?;
-
-            // 2. Create ACL with an ACE that grants full access to the SID
-            let mut acl_buf = vec![0u8; 1024];
-            let acl = acl_buf.as_mut_ptr() as *mut ACL;
-            InitializeAcl(acl, acl_buf.len() as u32, ACL_REVISION)
-                .map_err(|e| anyhow::anyhow!("InitializeAcl failed: {}", e))?;
-
-            AddAccessAllowedAce(
-                acl,
-                ACL_REVISION,
-                FILE_ALL_ACCESS.0,
-                PSID(sid.as_mut_ptr() as _),
-            )
-            .map_err(|e| anyhow::anyhow!("AddAccessAllowedAce failed: {}", e))?;
-
-            // 3. Apply the new DACL to the file
-            let err = SetNamedSecurityInfoW(
-                PCWSTR(path_w.as_ptr()),
-                SE_FILE_OBJECT,
-                DACL_SECURITY_INFORMATION,
-                None,
-                None,
-                Some(acl),
-                None,
-            );
-
-            if err.0 != 0 {
-                let detail = Self::format_net_error(err.0 as u32);
-                log::error!(
-                    "SetNamedSecurityInfoW for path '{}' failed: {}",
-                    path,
-                    detail
-                );
-                return Err(anyhow::anyhow!(
-                    "SetNamedSecurityInfoW failed: {}",
-                    detail
-                ));
-            }
+        let user = self.get_current_user()?;
+        Self::protect_file_for_account(path, &user, false)
+    }
 
-            Ok(())
-        }
+    fn protect_file_for_user(&self, path: &str, user: &str) -> Result<()> {
+        Self::protect_file_for_account(path, user, true)
     }
 
     fn ensure_user_can_rdp(&self, user: &str) -> Result<()> {
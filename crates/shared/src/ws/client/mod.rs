@@ -1,111 +1,587 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
 use anyhow::Result;
+use async_trait::async_trait;
 use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::{broadcast, mpsc};
-use tokio_tungstenite::{Connector, tungstenite::protocol::Message};
+use tokio_tungstenite::{
+    Connector, MaybeTlsStream, WebSocketStream,
+    tungstenite::{client::IntoClientRequest, protocol::Message},
+};
 
 use crate::{
     log,
-    ws::types::{Close, RpcEnvelope, RpcMessage},
+    ws::{
+        credentials::{TOKEN_HEADER, read_credential},
+        request_tracker::RequestTracker,
+        rpc::RpcContext,
+        types::{Close, Hello, HelloAck, PeerInfo, ResumeSession, RpcEnvelope, RpcMessage},
+        unparseable_reply,
+    },
 };
 
+// Services that predate the handshake never answer the Hello
+const HELLO_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Reconnection backoff, doubling from initial to max delay. If the service is not back
+// after RECONNECT_GIVE_UP, the connection is considered closed.
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+const RECONNECT_GIVE_UP: Duration = Duration::from_secs(300);
+
+type SharedPeer = Arc<Mutex<Option<PeerInfo>>>;
+type SharedSession = Arc<Mutex<Option<ResumeSession>>>;
+
 #[derive(Clone, Debug)]
 pub struct WsClient {
     pub from_ws: broadcast::Sender<RpcEnvelope<RpcMessage>>,
     pub to_ws: mpsc::Sender<RpcEnvelope<RpcMessage>>,
+    service: SharedPeer,     // From the last HelloAck, None for legacy services
+    session: SharedSession,  // Re-announced after reconnecting
+    tracker: RequestTracker, // Our calls waiting for the service response
+}
+
+impl WsClient {
+    pub fn new(
+        from_ws: broadcast::Sender<RpcEnvelope<RpcMessage>>,
+        to_ws: mpsc::Sender<RpcEnvelope<RpcMessage>>,
+    ) -> Self {
+        WsClient {
+            from_ws,
+            to_ws,
+            service: Arc::new(Mutex::new(None)),
+            session: Arc::new(Mutex::new(None)),
+            tracker: RequestTracker::new(),
+        }
+    }
+
+    /// What the service told about itself on the last handshake
+    pub fn service(&self) -> Option<PeerInfo> {
+        self.service.lock().unwrap().clone()
+    }
+
+    /// Whether the service handles the message kind. Legacy services are assumed to.
+    pub fn service_supports(&self, kind: &str) -> bool {
+        self.service
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|s| s.supports(kind))
+    }
+
+    /// Session to re-announce to the service if the connection is lost and recovered
+    pub fn set_session(&self, session: Option<ResumeSession>) {
+        *self.session.lock().unwrap() = session;
+    }
+
+    pub fn session(&self) -> Option<ResumeSession> {
+        self.session.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl RpcContext for WsClient {
+    fn tracker(&self) -> &RequestTracker {
+        &self.tracker
+    }
+
+    async fn send(&self, env: RpcEnvelope<RpcMessage>) -> Result<()> {
+        self.to_ws.send(env).await?;
+        Ok(())
+    }
+}
+
+// Where (and as who) to connect, kept for reconnections
+struct ServiceEndpoint {
+    port: u16,
+    username: String,
+    hello: PeerInfo,
+}
+
+// Connection through any of the supported transports (boxed, streams are big)
+enum Connection {
+    #[cfg(target_os = "linux")]
+    Unix(Box<WebSocketStream<tokio::net::UnixStream>>),
+    Tcp(Box<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>>),
+}
+
+impl Connection {
+    async fn send(&mut self, env: &RpcEnvelope<RpcMessage>) -> Result<()> {
+        let msg = Message::Text(serde_json::to_string(env)?.into());
+        match self {
+            #[cfg(target_os = "linux")]
+            Connection::Unix(ws_stream) => ws_stream.send(msg).await?,
+            Connection::Tcp(ws_stream) => ws_stream.send(msg).await?,
+        }
+        Ok(())
+    }
+}
+
+// Message a legacy service sent instead of answering the handshake, to be processed as any other
+type Early = Option<RpcEnvelope<RpcMessage>>;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Ended {
+    Lost,     // Connection gone, try to reconnect
+    Finished, // Nobody can send anymore, we are done
+}
+
+/// Asks the local service to write our session credential, and reads it back.
+/// The file is only readable by `username`, so this proves who we are.
+async fn get_credential(port: u16, username: &str) -> Result<String> {
+    let client = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true) // Localhost, same as the WebSocket itself
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?;
+    client
+        .post(format!("https://localhost:{}/ws/credential", port))
+        .json(&serde_json::json!({ "username": username }))
+        .send()
+        .await?
+        .error_for_status()?;
+    read_credential(username)
+}
+
+/// Connects over TLS to the local TCP listener, presenting our session credential
+async fn connect_tcp(
+    port: u16,
+    username: &str,
+) -> Result<WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>> {
+    let connector = Connector::Rustls(crate::tls::noverify::client_config());
+    let token = get_credential(port, username).await.map_err(|e| {
+        log::error!("Cannot get session credential from service: {}", e);
+        e
+    })?;
+    let mut request = format!("wss://localhost:{}/ws", port).into_client_request()?;
+    request.headers_mut().insert(TOKEN_HEADER, token.parse()?);
+
+    let (ws_stream, _resonse) =
+        tokio_tungstenite::connect_async_tls_with_config(request, None, true, Some(connector))
+            .await
+            .map_err(|e| {
+                log::error!("WebSocket connection error: {}", e);
+                e
+            })?;
+    Ok(ws_stream)
+}
+
+/// Connects to the service unix socket, if present. The service identifies us by our uid.
+#[cfg(target_os = "linux")]
+async fn connect_unix(port: u16) -> Option<WebSocketStream<tokio::net::UnixStream>> {
+    let path = crate::ws::unix_socket::socket_path(port);
+    if !path.exists() {
+        return None;
+    }
+    let stream = match tokio::net::UnixStream::connect(&path).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            log::warn!("Cannot connect to {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match tokio_tungstenite::client_async("ws://localhost/ws", stream).await {
+        Ok((ws_stream, _response)) => Some(ws_stream),
+        Err(e) => {
+            log::warn!("WebSocket handshake on {} failed: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Sends our Hello and waits for the service answer.
+/// Fails if the service refuses us or is too old; a service that does not answer
+/// predates the handshake, so we go on without knowing its capabilities.
+/// Anything else received first is returned, to be processed once connected.
+async fn handshake<S>(
+    ws_stream: &mut WebSocketStream<S>,
+    hello: &PeerInfo,
+) -> Result<(Option<PeerInfo>, Early)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let hello = RpcEnvelope {
+        id: None,
+        msg: RpcMessage::Hello(Hello(hello.clone())),
+        client: None,
+    };
+    ws_stream
+        .send(Message::Text(serde_json::to_string(&hello)?.into()))
+        .await?;
+
+    let reply = tokio::time::timeout(HELLO_TIMEOUT, async {
+        while let Some(msg) = ws_stream.next().await {
+            if let Message::Text(txt) = msg? {
+                match serde_json::from_str::<RpcEnvelope<RpcMessage>>(&txt) {
+                    Ok(env) => return Ok(Some(env)),
+                    Err(_) => log::warn!("Invalid WS JSON during handshake: {txt}"),
+                }
+            }
+        }
+        Ok::<_, anyhow::Error>(None)
+    })
+    .await;
+
+    match reply {
+        Ok(Ok(Some(RpcEnvelope {
+            msg: RpcMessage::HelloAck(HelloAck(service)),
+            ..
+        }))) => {
+            if !service.is_compatible() {
+                anyhow::bail!(
+                    "Service speaks protocol {} (build {}), too old for us",
+                    service.protocol_version,
+                    service.build
+                );
+            }
+            log::info!(
+                "Service speaks protocol {} (build {}), handles {:?}",
+                service.protocol_version,
+                service.build,
+                service.capabilities
+            );
+            Ok((Some(service), None))
+        }
+        Ok(Ok(Some(RpcEnvelope {
+            msg: RpcMessage::Error(err),
+            ..
+        }))) => {
+            anyhow::bail!("Service refused the connection: {}", err.message)
+        }
+        Ok(Ok(Some(env))) => {
+            log::warn!(
+                "Received {} before handshake, assuming a legacy service",
+                env.msg.kind()
+            );
+            Ok((None, Some(env)))
+        }
+        Ok(Ok(None)) => anyhow::bail!("Connection closed during handshake"),
+        Ok(Err(e)) => Err(e),
+        Err(_) => {
+            log::warn!("Service did not answer the handshake, assuming a legacy service");
+            Ok((None, None))
+        }
+    }
+}
+
+// Connects (unix socket preferred on Linux, else TLS over TCP) and does the handshake
+async fn connect(
+    endpoint: &ServiceEndpoint,
+    service: &Mutex<Option<PeerInfo>>,
+) -> Result<(Connection, Early)> {
+    #[cfg(target_os = "linux")]
+    if let Some(mut ws_stream) = connect_unix(endpoint.port).await {
+        log::debug!("Connected to service through unix socket");
+        let (peer, early) = handshake(&mut ws_stream, &endpoint.hello).await?;
+        *service.lock().unwrap() = peer;
+        return Ok((Connection::Unix(Box::new(ws_stream)), early));
+    }
+
+    let mut ws_stream = connect_tcp(endpoint.port, &endpoint.username).await?;
+    let (peer, early) = handshake(&mut ws_stream, &endpoint.hello).await?;
+    *service.lock().unwrap() = peer;
+    Ok((Connection::Tcp(Box::new(ws_stream)), early))
 }
 
-/// Connects to a local WebSocket server over TLS and spawns a reader and a writer task.
+// Reconnects with exponential backoff, re-announcing our session (if any) on success.
+// Returns None if the service does not come back in time.
+async fn reconnect(
+    endpoint: &ServiceEndpoint,
+    service: &Mutex<Option<PeerInfo>>,
+    session: &Mutex<Option<ResumeSession>>,
+) -> Option<(Connection, Early)> {
+    let started = std::time::Instant::now();
+    let mut delay = RECONNECT_INITIAL_DELAY;
+    while started.elapsed() < RECONNECT_GIVE_UP {
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+
+        let (mut connection, early) = match connect(endpoint, service).await {
+            Ok(connected) => connected,
+            Err(e) => {
+                log::warn!("Reconnection failed: {}. Retrying in {:?}", e, delay);
+                continue;
+            }
+        };
+        log::info!("Reconnected to service");
+        // Session is already logged in on the broker, just bind it to this connection
+        let supported = service
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|s| s.supports("ResumeSession"));
+        let session = session.lock().unwrap().clone();
+        if let Some(session) = session
+            && supported
+        {
+            let env = RpcEnvelope {
+                id: None,
+                msg: RpcMessage::ResumeSession(session),
+                client: None,
+            };
+            if let Err(e) = connection.send(&env).await {
+                log::warn!("Cannot resume session: {}. Retrying in {:?}", e, delay);
+                continue;
+            }
+        }
+        return Some((connection, early));
+    }
+    None
+}
+
+/// Connects to a local WebSocket server and spawns a task that keeps the connection alive.
+/// On Linux, the service unix socket is preferred; otherwise (or if not available) TLS over TCP is used.
 /// Every incoming message is parsed into a typed RpcMessage and forwarded into a broadcast channel.
+/// If the connection is lost (i.e. the service restarts), it is reestablished with exponential backoff
+/// and the session set with `WsClient::set_session` is resumed. Only if that fails a Close is broadcast.
 ///
 /// # Arguments
 /// * `port` - Local port where the WebSocket server is listening.
 /// * `capacity` - Maximum buffer size of the broadcast channel (e.g. 32 or 64).
+/// * `username` - User of this session, the connection is authenticated as this user.
+/// * `capabilities` - Message kinds the caller handles, announced to the service.
 ///
 /// # Returns
 /// A `WsClient` instance that can be used to send and receive messages.
-pub async fn websocket_client_tasks(port: u16, capacity: usize) -> Result<WsClient> {
+pub async fn websocket_client_tasks(
+    port: u16,
+    capacity: usize,
+    username: &str,
+    capabilities: &[&str],
+) -> Result<WsClient> {
     let (from_ws, _rx) = broadcast::channel::<RpcEnvelope<RpcMessage>>(capacity);
-    let (to_ws, mut from_clients) = mpsc::channel::<RpcEnvelope<RpcMessage>>(capacity);
+    let (to_ws, from_clients) = mpsc::channel::<RpcEnvelope<RpcMessage>>(capacity);
+    let ws_client = WsClient::new(from_ws, to_ws);
 
-    let connector = Connector::Rustls(crate::tls::noverify::client_config());
-    let url = format!("wss://localhost:{}/ws", port);
+    let endpoint = ServiceEndpoint {
+        port,
+        username: username.to_string(),
+        hello: PeerInfo::current(capabilities),
+    };
+    // First connection must succeed, later ones are retried
+    let (connection, early) = connect(&endpoint, &ws_client.service).await?;
+    tokio::spawn(connection_task(
+        endpoint,
+        connection,
+        early,
+        ClientLink::new(&ws_client),
+        from_clients,
+    ));
 
-    let (ws_stream, _resonse) =
-        tokio_tungstenite::connect_async_tls_with_config(url, None, true, Some(connector))
-            .await
-            .map_err(|e| {
-                log::error!("WebSocket connection error: {}", e);
-                e
-            })?;
+    Ok(ws_client)
+}
 
-    let (mut write, mut read) = ws_stream.split();
+// What the connection task shares with the WsClient. Only a weak sender is kept,
+// so the task ends once all clients are gone.
+struct ClientLink {
+    from_ws: broadcast::Sender<RpcEnvelope<RpcMessage>>,
+    to_ws: mpsc::WeakSender<RpcEnvelope<RpcMessage>>,
+    tracker: RequestTracker,
+    service: SharedPeer,
+    session: SharedSession,
+}
 
-    // Receiver task, from websocket to broadcast
-    tokio::spawn({
-        let from_ws = from_ws.clone();
-        let mut close_sent = false;
-        async move {
-            while let Some(msg) = read.next().await {
-                let env = match msg {
-                    Ok(Message::Text(txt)) => {
-                        if let Ok(env) = serde_json::from_str::<RpcEnvelope<RpcMessage>>(&txt) {
-                            env
-                        } else {
-                            log::warn!("Invalid WS JSON: {txt}");
-                            continue;
-                        }
-                    }
-                    Ok(Message::Binary(_bin)) => {
-                        // Not supported, log and skip
-                        log::warn!("Binary frame received, ignored.");
-                        continue;
-                    }
-                    Ok(Message::Close(_)) => {
-                        close_sent = true;
-                        RpcEnvelope {
-                            id: None,
-                            msg: RpcMessage::Close(Close),
-                        }
-                    }
-                    Ok(Message::Ping(data)) => RpcEnvelope {
-                        id: None,
-                        msg: RpcMessage::Ping(crate::ws::types::Ping(data.to_vec())),
-                    },
-                    _ => continue,
-                };
-                if let Err(e) = from_ws.send(env) {
-                    log::warn!("Failed to broadcast WS message: {e}");
-                    break;
-                }
+impl ClientLink {
+    fn new(ws_client: &WsClient) -> Self {
+        ClientLink {
+            from_ws: ws_client.from_ws.clone(),
+            to_ws: ws_client.to_ws.downgrade(),
+            tracker: ws_client.tracker.clone(),
+            service: ws_client.service.clone(),
+            session: ws_client.session.clone(),
+        }
+    }
+
+    // Resolves answers to our own calls, broadcasts everything else
+    async fn deliver(&self, env: RpcEnvelope<RpcMessage>) {
+        // Requests from the service carry ids too, but of its own
+        if let Some(id) = env.id
+            && env.msg.is_response()
+            && self.tracker.resolve_ok(id, env.msg.clone()).await.is_ok()
+        {
+            return;
+        }
+        if let Err(e) = self.from_ws.send(env) {
+            log::warn!("Failed to broadcast WS message: {e}");
+        }
+    }
+}
+
+async fn connection_task(
+    endpoint: ServiceEndpoint,
+    mut connection: Connection,
+    mut early: Early,
+    link: ClientLink,
+    mut from_clients: mpsc::Receiver<RpcEnvelope<RpcMessage>>,
+) {
+    loop {
+        if let Some(env) = early.take() {
+            link.deliver(env).await;
+        }
+        let ended = match connection {
+            #[cfg(target_os = "linux")]
+            Connection::Unix(ws_stream) => {
+                run_connection(*ws_stream, &link, &mut from_clients).await
+            }
+            Connection::Tcp(ws_stream) => {
+                run_connection(*ws_stream, &link, &mut from_clients).await
+            }
+        };
+        if ended == Ended::Finished {
+            break;
+        }
+
+        log::warn!("WebSocket connection lost, reconnecting");
+        match reconnect(&endpoint, &link.service, &link.session).await {
+            Some((new_connection, new_early)) => {
+                connection = new_connection;
+                early = new_early;
             }
-            if !close_sent {
-                log::info!("WebSocket connection closed, sending Close message");
-                let _ = from_ws.send(RpcEnvelope {
+            None => {
+                log::error!("Cannot reconnect to service, sending Close message");
+                let _ = link.from_ws.send(RpcEnvelope {
                     id: None,
                     msg: RpcMessage::Close(Close),
+                    client: None,
                 });
+                break;
             }
         }
-    });
+    }
+}
+
+// Pumps messages in both directions until the connection ends
+async fn run_connection<S>(
+    ws_stream: WebSocketStream<S>,
+    link: &ClientLink,
+    from_clients: &mut mpsc::Receiver<RpcEnvelope<RpcMessage>>,
+) -> Ended
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut write, mut read) = ws_stream.split();
 
-    // Sender task, from client to websocket
-    tokio::spawn({
-        async move {
-            while let Some(env) = from_clients.recv().await {
-                let msg_text = match serde_json::to_string(&env) {
-                    Ok(txt) => txt,
-                    Err(e) => {
-                        log::warn!("Failed to serialize WS message: {e}");
+    // From websocket to broadcast
+    let reader = async {
+        while let Some(msg) = read.next().await {
+            let env = match msg {
+                Ok(Message::Text(txt)) => {
+                    if let Ok(env) = serde_json::from_str::<RpcEnvelope<RpcMessage>>(&txt) {
+                        env
+                    } else {
+                        log::warn!("Invalid or unsupported WS JSON: {txt}");
+                        if let Some(reply) = unparseable_reply(&txt)
+                            && let Some(to_ws) = link.to_ws.upgrade()
+                        {
+                            let _ = to_ws.send(reply).await;
+                        }
                         continue;
                     }
-                };
-                if let Err(e) = write.send(Message::Text(msg_text.into())).await {
-                    log::warn!("Failed to send WS message: {e}");
+                }
+                Ok(Message::Binary(_bin)) => {
+                    // Not supported, log and skip
+                    log::warn!("Binary frame received, ignored.");
+                    continue;
+                }
+                Ok(Message::Close(_)) => {
+                    log::info!("WebSocket connection closed by service");
+                    break;
+                }
+                Ok(Message::Ping(data)) => RpcEnvelope {
+                    id: None,
+                    msg: RpcMessage::Ping(crate::ws::types::Ping(data.to_vec())),
+                    client: None,
+                },
+                Ok(_) => continue,
+                Err(e) => {
+                    log::warn!("WebSocket error: {e}");
                     break;
                 }
+            };
+            link.deliver(env).await;
+        }
+    };
+
+    // From clients to websocket
+    let writer = async {
+        while let Some(env) = from_clients.recv().await {
+            let msg_text = match serde_json::to_string(&env) {
+                Ok(txt) => txt,
+                Err(e) => {
+                    log::warn!("Failed to serialize WS message: {e}");
+                    continue;
+                }
+            };
+            if let Err(e) = write.send(Message::Text(msg_text.into())).await {
+                log::warn!("Failed to send WS message {}: {e}", env.msg.kind());
+                return Ended::Lost;
             }
         }
-    });
+        Ended::Finished
+    };
+
+    tokio::select! {
+        _ = reader => Ended::Lost,
+        ended = writer => ended,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    use super::*;
+    use crate::ws::types::LogoffRequest;
+
+    #[tokio::test]
+    async fn test_handshake_keeps_early_message() {
+        let (client_io, service_io) = tokio::io::duplex(4096);
+        let mut client = WebSocketStream::from_raw_socket(client_io, Role::Client, None).await;
+        let mut service = WebSocketStream::from_raw_socket(service_io, Role::Server, None).await;
+
+        // Legacy service, ignores the Hello and sends a request of its own
+        let legacy = tokio::spawn(async move {
+            let _hello = service.next().await;
+            let env = RpcEnvelope {
+                id: Some(5),
+                msg: RpcMessage::LogoffRequest(LogoffRequest),
+                client: None,
+            };
+            service
+                .send(Message::Text(serde_json::to_string(&env).unwrap().into()))
+                .await
+                .unwrap();
+            service
+        });
+
+        let (peer, early) = handshake(&mut client, &PeerInfo::current(&[]))
+            .await
+            .unwrap();
+        assert!(peer.is_none());
+        let early = early.unwrap();
+        assert_eq!(early.id, Some(5));
+        assert!(matches!(early.msg, RpcMessage::LogoffRequest(_)));
+        let _service = legacy.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_early_message_delivered() {
+        let (from_ws, mut rx) = broadcast::channel(4);
+        let (to_ws, _to_ws_rx) = mpsc::channel(4);
+        let link = ClientLink::new(&WsClient::new(from_ws, to_ws));
 
-    Ok(WsClient { from_ws, to_ws })
+        link.deliver(RpcEnvelope {
+            id: None,
+            msg: RpcMessage::LogoffRequest(LogoffRequest),
+            client: None,
+        })
+        .await;
+        assert!(matches!(
+            rx.try_recv().unwrap().msg,
+            RpcMessage::LogoffRequest(_)
+        ));
+    }
 }
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use rand::prelude::*;
+
+use crate::{log, system::System};
+
+/// Header used by the local client to present its session credential on the `/ws` upgrade
+pub const TOKEN_HEADER: &str = "x-uds-token";
+
+const TOKEN_LENGTH: usize = 48;
+
+/// Folder where the per session credentials are stored.
+/// Every file is readable only by the user it belongs to.
+pub fn credentials_dir() -> PathBuf {
+    if std::env::var("UDS_ACTOR_TEST").is_ok() {
+        return std::env::temp_dir().join("udsactor-clients");
+    }
+    #[cfg(target_os = "windows")]
+    {
+        crate::config::config_dir().join("clients")
+    }
+    #[cfg(target_family = "unix")]
+    {
+        PathBuf::from("/run/udsactor")
+    }
+}
+
+fn check_username(username: &str) -> Result<()> {
+    // Username is used as file name, so avoid anything that could escape the folder
+    if username.is_empty()
+        || username.len() > 256
+        || username.starts_with('.')
+        || username.contains(['/', '\\', '\0', ':'])
+    {
+        anyhow::bail!("Invalid username for credential: {:?}", username);
+    }
+    Ok(())
+}
+
+pub fn credential_path(dir: &std::path::Path, username: &str) -> Result<PathBuf> {
+    check_username(username)?;
+    Ok(dir.join(format!("{}.token", username)))
+}
+
+/// Client side: reads the credential the service issued for our user
+pub fn read_credential(username: &str) -> Result<String> {
+    let path = credential_path(&credentials_dir(), username)?;
+    let token = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("Cannot read credential {}: {}", path.display(), e))?;
+    Ok(token.trim().to_string())
+}
+
+// Constant time comparison, to not leak token contents through timing
+pub(crate) fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// Service side: issues and verifies the per session credentials of the local clients.
+/// Tokens only live in memory; a new one is written on the first request after a restart.
+#[derive(Clone)]
+pub struct ClientCredentials {
+    dir: PathBuf,
+    system: Arc<dyn System>,
+    tokens: Arc<Mutex<HashMap<String, String>>>, // username → token
+}
+
+impl ClientCredentials {
+    pub fn new(dir: PathBuf, system: Arc<dyn System>) -> Self {
+        Self {
+            dir,
+            system,
+            tokens: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn prepare_dir(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // Users must be able to reach their own file, but not list the others
+            std::fs::set_permissions(&self.dir, std::fs::Permissions::from_mode(0o711))?;
+        }
+        Ok(())
+    }
+
+    /// Writes (if not already done) the credential file for the user, returning its path
+    pub fn issue(&self, username: &str) -> Result<PathBuf> {
+        let path = credential_path(&self.dir, username)?;
+        if self.tokens.lock().unwrap().contains_key(username) && path.exists() {
+            return Ok(path);
+        }
+
+        let token: String = rand::rng()
+            .sample_iter(&rand::distr::Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+
+        self.prepare_dir()?;
+        let _ = std::fs::remove_file(&path);
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options.open(&path)?;
+        // Protect before writing the token, so it is never readable by anyone else
+        self.system
+            .protect_file_for_user(&path.to_string_lossy(), username)?;
+        file.write_all(token.as_bytes())?;
+
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(username.to_string(), token);
+        log::info!("Issued client credential for user {}", username);
+        Ok(path)
+    }
+
+    /// Returns the user the token was issued to, if valid
+    pub fn authenticate(&self, token: &str) -> Option<String> {
+        let tokens = self.tokens.lock().unwrap();
+        // Check all of them, so timing does not depend on which one matches
+        tokens.iter().fold(None, |found, (user, t)| {
+            if token_eq(t, token) {
+                Some(user.clone())
+            } else {
+                found
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::mock::{Calls, OperationsMock};
+
+    fn credentials(name: &str) -> (ClientCredentials, Calls) {
+        let calls = Calls::new();
+        let dir = std::env::temp_dir().join(format!("udsactor-creds-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        (
+            ClientCredentials::new(dir, Arc::new(OperationsMock::new(calls.clone()))),
+            calls,
+        )
+    }
+
+    #[test]
+    fn test_issue_and_authenticate() {
+        let (creds, calls) = credentials("issue");
+        let path = creds.issue("alice").unwrap();
+        let token = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(token.len(), TOKEN_LENGTH);
+        calls.assert_called(&format!(
+            "operations::protect_file_for_user({}, alice)",
+            path.display()
+        ));
+
+        // Issuing again keeps the same token
+        creds.issue("alice").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), token);
+        assert_eq!(calls.count_calls("operations::protect_file_for_user("), 1);
+
+        let bob_token = std::fs::read_to_string(creds.issue("bob").unwrap()).unwrap();
+        assert_ne!(token, bob_token);
+
+        assert_eq!(creds.authenticate(&token).as_deref(), Some("alice"));
+        assert_eq!(creds.authenticate(&bob_token).as_deref(), Some("bob"));
+        assert_eq!(creds.authenticate("invalid"), None);
+        assert_eq!(creds.authenticate(""), None);
+    }
+
+    #[test]
+    fn test_invalid_usernames() {
+        let (creds, _calls) = credentials("invalid");
+        for user in ["", "..", "../etc/passwd", "a/b", "a\\b", ".hidden", "c:x"] {
+            assert!(creds.issue(user).is_err(), "{user:?} accepted");
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_credential_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let (creds, _calls) = credentials("mode");
+        let path = creds.issue("alice").unwrap();
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -5,14 +5,25 @@ use tokio::sync::{broadcast, oneshot};
 use crate::{
     log,
     sync::OnceSignal,
-    ws::types::{RpcEnvelope, RpcMessage},
+    ws::types::{ERR_BAD_REQUEST, ERR_NOT_SUPPORTED, RpcEnvelope, RpcError, RpcMessage},
 };
 
 pub mod client;
+pub mod credentials;
 pub mod rcptraits;
 pub mod request_tracker;
+pub mod rpc;
 pub mod server;
 pub mod types;
+#[cfg(target_os = "linux")]
+pub mod unix_socket;
+
+pub use rpc::{RpcContext, call, call_client};
+
+/// HTTP status for an error answered by a peer (codes follow HTTP meaning)
+pub fn error_status(err: &RpcError) -> StatusCode {
+    StatusCode::from_u16(err.code as u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
 
 /// Wait for a response from the tracker (oneshot channel).
 pub async fn wait_response<T>(
@@ -41,6 +52,11 @@ where
         // Normal response
         res = rx => {
             match res {
+                // Errors from the peer keep their code (e.g. not supported)
+                Ok(RpcMessage::Error(err)) => {
+                    log::warn!("Request failed: {} ({})", err.message, err.code);
+                    Err(error_status(&err))
+                }
                 Ok(msg) => match T::try_from(msg) {
                     Ok(val) => Ok(Json(val)),
                     Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
@@ -51,6 +67,26 @@ where
     }
 }
 
+/// Answer for a text frame that could not be parsed, usually a message kind this build
+/// does not know (sent by a newer peer). Only requests (with id) are answered,
+/// so the peer fails fast instead of waiting for a timeout.
+pub fn unparseable_reply(txt: &str) -> Option<RpcEnvelope<RpcMessage>> {
+    let value = serde_json::from_str::<serde_json::Value>(txt).ok()?;
+    let id = value.get("id").and_then(|id| id.as_u64())?;
+    let (code, message) = match value.get("kind").and_then(|k| k.as_str()) {
+        Some(kind) => (
+            ERR_NOT_SUPPORTED,
+            format!("Message kind {} not supported", kind),
+        ),
+        None => (ERR_BAD_REQUEST, "Malformed message".to_string()),
+    };
+    Some(RpcEnvelope {
+        id: Some(id),
+        msg: RpcMessage::Error(RpcError { code, message }),
+        client: None,
+    })
+}
+
 /// Wait until receiving a `RpcEnvelope<T>` from the broadcast channel.
 /// Cancels if the `stop` is triggered.
 pub async fn wait_message_arrival<T>(
@@ -81,12 +117,14 @@ where
                             return Some(RpcEnvelope {
                                 id: env.id,
                                 msg: inner,
+                                client: env.client,
                             });
                         }
                     }
                     Err(broadcast::error::RecvError::Lagged(count)) => {
                         // Skipping messages
                         log::warn!("Skipped {} messages", count);
+                        crate::metrics::metrics().record_lagged(count);
                     }
                     Err(e) => {
                         log::warn!("Broadcast receive error: {e}");
@@ -117,6 +155,7 @@ mod tests {
             let msg = RpcEnvelope {
                 id: None,
                 msg: RpcMessage::Ping(Ping(Vec::new())),
+                client: None,
             };
             let _ = tx.send(msg);
         }
@@ -132,4 +171,30 @@ mod tests {
 
         assert!(env.is_some());
     }
+
+    #[test]
+    fn unparseable_reply_answers_requests_only() {
+        let reply = unparseable_reply(r#"{"id": 7, "kind": "FutureRequest", "msg": {}}"#).unwrap();
+        assert_eq!(reply.id, Some(7));
+        assert!(matches!(
+            reply.msg,
+            RpcMessage::Error(RpcError {
+                code: ERR_NOT_SUPPORTED,
+                ..
+            })
+        ));
+
+        let reply = unparseable_reply(r#"{"id": 8}"#).unwrap();
+        assert!(matches!(
+            reply.msg,
+            RpcMessage::Error(RpcError {
+                code: ERR_BAD_REQUEST,
+                ..
+            })
+        ));
+
+        // Notifications and garbage are just dropped
+        assert!(unparseable_reply(r#"{"id": null, "kind": "FutureNotification"}"#).is_none());
+        assert!(unparseable_reply("not json").is_none());
+    }
 }
@@ -1,9 +1,11 @@
 use crate::ws::types::{
-    Close, LogRequest, LoginRequest, LoginResponse, LogoffRequest, LogoutRequest, MessageRequest,
-    Ping, Pong, PreConnect, RpcError, RpcMessage, ScreenshotRequest, ScreenshotResponse,
-    ScriptExecRequest, ScriptExecResponse, UUidRequest, UUidResponse,
+    Close, Hello, HelloAck, LogRequest, LoginRequest, LoginResponse, LogoffRequest, LogoutRequest,
+    MessageAck, MessageRequest, Ping, Pong, PreConnect, ResumeSession, RpcError, RpcMessage,
+    ScreenshotRequest, ScreenshotResponse, ScriptExecRequest, ScriptExecResponse, SessionStats,
+    UUidRequest, UUidResponse,
 };
 
+// TryFrom (to extract) and From (to wrap) between RpcMessage and its payloads
 macro_rules! impl_tryfrom {
     ($($variant:ident => $ty:ty),* $(,)?) => {
         $(
@@ -17,6 +19,12 @@ macro_rules! impl_tryfrom {
                     }
                 }
             }
+
+            impl From<$ty> for RpcMessage {
+                fn from(inner: $ty) -> Self {
+                    RpcMessage::$variant(inner)
+                }
+            }
         )*
     };
 }
@@ -39,4 +47,9 @@ impl_tryfrom! {
     ScreenshotRequest => ScreenshotRequest,
     ScriptExecRequest => ScriptExecRequest,
     MessageRequest => MessageRequest,
+    MessageAck => MessageAck,
+    Hello => Hello,
+    HelloAck => HelloAck,
+    ResumeSession => ResumeSession,
+    SessionStats => SessionStats,
 }
@@ -11,7 +11,7 @@ use tokio::sync::{Mutex, oneshot};
 
 use crate::{
     log,
-    ws::types::{RequestId, RpcError, RpcMessage},
+    ws::types::{ERR_TIMEOUT, RequestId, RpcError, RpcMessage},
 };
 
 static NEXT_ID: AtomicU64 = AtomicU64::new(1);
@@ -22,19 +22,21 @@ fn next_id() -> u64 {
 
 /// Represents a pending request waiting for a response.
 /// Stores the creation time and the oneshot sender to notify the waiter.
+#[derive(Debug)]
 struct Pending {
     created: Instant,
     tx: oneshot::Sender<RpcMessage>,
 }
 
 /// Internal state holding all pending requests.
+#[derive(Debug)]
 struct TrackerState {
     pending: HashMap<RequestId, Pending>,
 }
 
 /// Public request manager that wraps the internal state in Arc<Mutex<...>>.
 /// Provides methods to register, resolve and cleanup requests.
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct RequestTracker {
     inner: Arc<Mutex<TrackerState>>,
     timeout: std::time::Duration,
@@ -83,6 +85,16 @@ impl RequestTracker {
         }
     }
 
+    /// Removes a request that may be already resolved, without complaining.
+    pub async fn discard(&self, id: RequestId) {
+        self.inner.lock().await.pending.remove(&id);
+    }
+
+    /// Number of requests waiting for a response.
+    pub async fn pending(&self) -> usize {
+        self.inner.lock().await.pending.len()
+    }
+
     /// Resolve a request by id with a successful payload.
     pub async fn resolve_ok(&self, id: RequestId, message: RpcMessage) -> Result<()> {
         log::debug!("Resolving request id {} with success", id);
@@ -120,7 +132,7 @@ impl RequestTracker {
         for id in expired {
             if let Some(p) = guard.pending.remove(&id) {
                 let _ = p.tx.send(RpcMessage::Error(RpcError {
-                    code: 408,
+                    code: ERR_TIMEOUT,
                     message: "timeout".into(),
                 }));
             }
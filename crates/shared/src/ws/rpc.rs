@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::{
+    log,
+    ws::{
+        request_tracker::RequestTracker,
+        types::{
+            ClientId, ERR_INTERNAL, ERR_TIMEOUT, ERR_UNAVAILABLE, RequestId, RpcEnvelope, RpcError,
+            RpcMessage,
+        },
+    },
+};
+
+/// Anything that can send requests and gets their responses resolved on a tracker
+#[async_trait]
+pub trait RpcContext: Sync {
+    fn tracker(&self) -> &RequestTracker;
+    async fn send(&self, env: RpcEnvelope<RpcMessage>) -> Result<()>;
+}
+
+// Removes the request from the tracker however the call ends (response, timeout or dropped future)
+struct PendingGuard {
+    tracker: RequestTracker,
+    id: RequestId,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let tracker = self.tracker.clone();
+        let id = self.id;
+        tokio::spawn(async move { tracker.discard(id).await });
+    }
+}
+
+/// Sends a request and waits for its typed response.
+/// Errors sent back by the peer are returned as is; local failures use the ERR_* codes.
+pub async fn call<Req, Resp>(
+    ctx: &impl RpcContext,
+    req: Req,
+    timeout: Duration,
+) -> Result<Resp, RpcError>
+where
+    Req: Into<RpcMessage>,
+    Resp: TryFrom<RpcMessage>,
+{
+    call_client(ctx, None, req, timeout).await
+}
+
+/// Same as `call`, but for a concrete WebSocket client (None is the default one)
+pub async fn call_client<Req, Resp>(
+    ctx: &impl RpcContext,
+    client: Option<ClientId>,
+    req: Req,
+    timeout: Duration,
+) -> Result<Resp, RpcError>
+where
+    Req: Into<RpcMessage>,
+    Resp: TryFrom<RpcMessage>,
+{
+    let tracker = ctx.tracker();
+    let (rx, id) = tracker.register().await;
+    let _pending = PendingGuard {
+        tracker: tracker.clone(),
+        id,
+    };
+
+    let msg = req.into();
+    let kind = msg.kind();
+    ctx.send(RpcEnvelope {
+        id: Some(id),
+        msg,
+        client,
+    })
+    .await
+    .map_err(|e| RpcError {
+        code: ERR_UNAVAILABLE,
+        message: format!("Cannot send {}: {}", kind, e),
+    })?;
+    log::debug!("Sent {} with id {}", kind, id);
+
+    match tokio::time::timeout(timeout, rx).await {
+        Ok(Ok(RpcMessage::Error(err))) => Err(err),
+        Ok(Ok(msg)) => {
+            let got = msg.kind();
+            Resp::try_from(msg).map_err(|_| RpcError {
+                code: ERR_INTERNAL,
+                message: format!("Unexpected {} as response to {}", got, kind),
+            })
+        }
+        Ok(Err(_)) => Err(RpcError {
+            code: ERR_INTERNAL,
+            message: format!("Request {} for {} dropped", id, kind),
+        }),
+        Err(_) => Err(RpcError {
+            code: ERR_TIMEOUT,
+            message: format!("Timeout waiting for response to {}", kind),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::ws::types::{ERR_NOT_SUPPORTED, Ping, Pong, UUidRequest, UUidResponse};
+
+    // Answers every request with the given function, recording what was sent
+    #[derive(Clone)]
+    struct Answering {
+        tracker: RequestTracker,
+        sent: Arc<Mutex<Vec<RpcEnvelope<RpcMessage>>>>,
+        answer: fn(&RpcMessage) -> Option<RpcMessage>,
+    }
+
+    #[async_trait]
+    impl RpcContext for Answering {
+        fn tracker(&self) -> &RequestTracker {
+            &self.tracker
+        }
+
+        async fn send(&self, env: RpcEnvelope<RpcMessage>) -> Result<()> {
+            self.sent.lock().unwrap().push(env.clone());
+            if let Some(reply) = (self.answer)(&env.msg) {
+                self.tracker.resolve_ok(env.id.unwrap(), reply).await?;
+            }
+            Ok(())
+        }
+    }
+
+    fn context(answer: fn(&RpcMessage) -> Option<RpcMessage>) -> Answering {
+        Answering {
+            tracker: RequestTracker::new(),
+            sent: Arc::new(Mutex::new(Vec::new())),
+            answer,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_call_ok() {
+        let ctx = context(|_| Some(RpcMessage::UUidResponse(UUidResponse("uuid".into()))));
+        let resp: UUidResponse = call(&ctx, UUidRequest, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(resp.0, "uuid");
+
+        let sent = ctx.sent.lock().unwrap();
+        assert!(sent[0].id.is_some());
+        assert!(matches!(sent[0].msg, RpcMessage::UUidRequest(_)));
+    }
+
+    #[tokio::test]
+    async fn test_call_errors() {
+        // Error from the peer
+        let ctx = context(|_| {
+            Some(RpcMessage::Error(RpcError {
+                code: ERR_NOT_SUPPORTED,
+                message: "no".into(),
+            }))
+        });
+        let err = call::<_, UUidResponse>(&ctx, UUidRequest, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, ERR_NOT_SUPPORTED);
+
+        // Wrong response type
+        let ctx = context(|_| Some(RpcMessage::Pong(Pong(vec![]))));
+        let err = call::<_, UUidResponse>(&ctx, UUidRequest, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, ERR_INTERNAL);
+
+        // No response
+        let ctx = context(|_| None);
+        let err = call::<_, UUidResponse>(&ctx, Ping(vec![]), Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, ERR_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_call_never_leaks_ids() {
+        let ctx = context(|_| None);
+        let _ = call::<_, UUidResponse>(&ctx, UUidRequest, Duration::from_millis(10)).await;
+
+        // Cancelled by the caller
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(10),
+            call::<_, UUidResponse>(&ctx, UUidRequest, Duration::from_secs(60)),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(ctx.tracker.pending().await, 0);
+    }
+}
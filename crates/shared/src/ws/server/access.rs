@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use ipnetwork::IpNetwork;
+
+use crate::log;
+
+// Failed secret attempts allowed per ip and window, before blocking it
+const MAX_FAILURES: u32 = 10;
+const FAILURES_WINDOW: Duration = Duration::from_secs(60);
+const BLOCK_TIME: Duration = Duration::from_secs(300);
+
+struct Failures {
+    count: u32,
+    window_start: Instant,
+    blocked_until: Option<Instant>,
+}
+
+impl Failures {
+    fn is_blocked(&self, now: Instant) -> bool {
+        self.blocked_until.is_some_and(|until| now < until)
+    }
+
+    fn is_expired(&self, now: Instant, window: Duration) -> bool {
+        !self.is_blocked(now) && now.duration_since(self.window_start) >= window
+    }
+}
+
+/// Who may reach the actor endpoints: the source ip allowlist, and the per ip
+/// limit of failed secret attempts (so the secret cannot be brute forced)
+#[derive(Clone)]
+pub(super) struct AccessGuard {
+    allowed: Arc<RwLock<Option<Vec<IpNetwork>>>>, // Empty allows any, None only local clients
+    failures: Arc<Mutex<HashMap<IpAddr, Failures>>>,
+    max_failures: u32,
+    window: Duration,
+    block_time: Duration,
+}
+
+impl Default for AccessGuard {
+    fn default() -> Self {
+        Self::new(MAX_FAILURES, FAILURES_WINDOW, BLOCK_TIME)
+    }
+}
+
+impl AccessGuard {
+    pub fn new(max_failures: u32, window: Duration, block_time: Duration) -> Self {
+        Self {
+            allowed: Arc::new(RwLock::new(Some(Vec::new()))),
+            failures: Arc::new(Mutex::new(HashMap::new())),
+            max_failures,
+            window,
+            block_time,
+        }
+    }
+
+    pub fn set_allowed(&self, networks: Vec<IpNetwork>) {
+        log::info!("Allowed networks: {:?}", networks);
+        *self.allowed.write().unwrap() = Some(networks);
+    }
+
+    /// Only local clients are allowed, used when the allowlist is invalid (fail closed)
+    pub fn set_local_only(&self) {
+        log::warn!("Only local clients are allowed");
+        *self.allowed.write().unwrap() = None;
+    }
+
+    /// Local clients are always allowed, the rest must be on the allowlist (if any)
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        let allowed = self.allowed.read().unwrap();
+        ip.is_loopback()
+            || allowed
+                .as_ref()
+                .is_some_and(|nets| nets.is_empty() || nets.iter().any(|net| net.contains(ip)))
+    }
+
+    pub fn is_blocked(&self, ip: IpAddr) -> bool {
+        let failures = self.failures.lock().unwrap();
+        failures
+            .get(&ip.to_canonical())
+            .is_some_and(|f| f.is_blocked(Instant::now()))
+    }
+
+    pub fn record_failure(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        // Forget the ones that no longer count, so the map does not grow forever
+        failures.retain(|_, f| !f.is_expired(now, self.window));
+        let entry = failures.entry(ip.to_canonical()).or_insert(Failures {
+            count: 0,
+            window_start: now,
+            blocked_until: None,
+        });
+        if entry.blocked_until.is_some_and(|until| now >= until) {
+            // Block expired, start again
+            entry.count = 0;
+            entry.window_start = now;
+            entry.blocked_until = None;
+        }
+        entry.count += 1;
+        if entry.count >= self.max_failures && entry.blocked_until.is_none() {
+            log::warn!(
+                "Too many failed secret attempts from {}, blocking it for {:?}",
+                ip,
+                self.block_time
+            );
+            entry.blocked_until = Some(now + self.block_time);
+        }
+    }
+
+    /// A valid request resets the failed attempts of its source
+    pub fn record_success(&self, ip: IpAddr) {
+        self.failures.lock().unwrap().remove(&ip.to_canonical());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowlist() {
+        let guard = AccessGuard::default();
+        let remote: IpAddr = "192.168.1.10".parse().unwrap();
+        assert!(guard.is_allowed(remote));
+
+        guard.set_allowed(vec!["10.0.0.0/8".parse().unwrap()]);
+        assert!(!guard.is_allowed(remote));
+        assert!(guard.is_allowed("10.1.2.3".parse().unwrap()));
+        assert!(guard.is_allowed("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(guard.is_allowed("127.0.0.1".parse().unwrap()));
+        assert!(guard.is_allowed("::1".parse().unwrap()));
+
+        // Invalid allowlist, only local clients
+        guard.set_local_only();
+        assert!(!guard.is_allowed(remote));
+        assert!(!guard.is_allowed("10.1.2.3".parse().unwrap()));
+        assert!(guard.is_allowed("127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_success_resets_failures() {
+        let guard = AccessGuard::new(3, Duration::from_secs(60), Duration::from_secs(60));
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        for _ in 0..2 {
+            guard.record_failure(ip);
+        }
+        guard.record_success(ip);
+        for _ in 0..2 {
+            guard.record_failure(ip);
+        }
+        assert!(!guard.is_blocked(ip));
+        guard.record_failure(ip);
+        assert!(guard.is_blocked(ip));
+    }
+
+    #[test]
+    fn test_failed_attempts_block() {
+        let guard = AccessGuard::new(3, Duration::from_secs(60), Duration::from_millis(100));
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        let other: IpAddr = "192.168.1.11".parse().unwrap();
+        for _ in 0..2 {
+            guard.record_failure(ip);
+        }
+        assert!(!guard.is_blocked(ip));
+        guard.record_failure(ip);
+        assert!(guard.is_blocked(ip));
+        assert!(!guard.is_blocked(other));
+
+        // Unblocked once the block time is over
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(!guard.is_blocked(ip));
+        guard.record_failure(ip);
+        assert!(!guard.is_blocked(ip));
+    }
+
+    #[test]
+    fn test_failures_window_expires() {
+        let guard = AccessGuard::new(2, Duration::from_millis(50), Duration::from_secs(60));
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        guard.record_failure(ip);
+        std::thread::sleep(Duration::from_millis(80));
+        // First one no longer counts
+        guard.record_failure(ip);
+        assert!(!guard.is_blocked(ip));
+        guard.record_failure(ip);
+        assert!(guard.is_blocked(ip));
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::{
+    log,
+    tls::{CertificateInfo, certool},
+};
+
+struct Loaded {
+    tls: RustlsConfig,
+    ciphers: Option<String>, // Ours, used if the broker does not send any with the certificate
+}
+
+/// Server certificate, replaceable while running: new TLS handshakes get the new one,
+/// established connections (as the WS clients) keep going with the one they started with
+#[derive(Clone, Default)]
+pub(super) struct CertificateSlot(Arc<RwLock<Option<Loaded>>>);
+
+fn with_ciphers(mut cert_info: CertificateInfo, ciphers: Option<&String>) -> CertificateInfo {
+    if cert_info.ciphers.is_none() {
+        cert_info.ciphers = ciphers.cloned();
+    }
+    cert_info
+}
+
+impl CertificateSlot {
+    /// Initial certificate, the returned config is the one the listeners are created with
+    pub fn load(
+        &self,
+        cert_info: CertificateInfo,
+        ciphers: Option<String>,
+    ) -> Result<RustlsConfig> {
+        let cert_info = with_ciphers(cert_info, ciphers.as_ref());
+        let tls = RustlsConfig::from_config(certool::server_config_from_pem(&cert_info)?);
+        *self.0.write().unwrap() = Some(Loaded {
+            tls: tls.clone(),
+            ciphers,
+        });
+        Ok(tls)
+    }
+
+    /// Replaces the certificate. If invalid, the current one is kept
+    pub fn reload(&self, cert_info: CertificateInfo) -> Result<()> {
+        let loaded = self.0.read().unwrap();
+        let Some(loaded) = loaded.as_ref() else {
+            anyhow::bail!("Server is not running");
+        };
+        let cert_info = with_ciphers(cert_info, loaded.ciphers.as_ref());
+        loaded
+            .tls
+            .reload_from_config(certool::server_config_from_pem(&cert_info)?);
+        log::info!("Server certificate reloaded");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_certs;
+
+    fn current(slot: &CertificateSlot) -> Arc<rustls::ServerConfig> {
+        slot.0.read().unwrap().as_ref().unwrap().tls.get_inner()
+    }
+
+    #[test]
+    fn test_reload_needs_running_server() {
+        crate::tls::init_tls(None);
+        let slot = CertificateSlot::default();
+        assert!(slot.reload(test_certs::test_certinfo()).is_err());
+    }
+
+    #[test]
+    fn test_reload() {
+        crate::tls::init_tls(None);
+        let slot = CertificateSlot::default();
+        let tls = slot.load(test_certs::test_certinfo(), None).unwrap();
+        let initial = current(&slot);
+
+        slot.reload(test_certs::test_broker_certinfo()).unwrap();
+        let reloaded = current(&slot);
+        assert!(!Arc::ptr_eq(&initial, &reloaded));
+        // Same config the listeners use
+        assert!(Arc::ptr_eq(&tls.get_inner(), &reloaded));
+
+        // Invalid one keeps the current certificate
+        let mut invalid = test_certs::test_certinfo();
+        invalid.certificate = "not a certificate".into();
+        assert!(slot.reload(invalid).is_err());
+        assert!(Arc::ptr_eq(&current(&slot), &reloaded));
+    }
+}
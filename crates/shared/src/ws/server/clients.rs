@@ -0,0 +1,397 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    log,
+    ws::types::{ClientId, PeerInfo, RpcEnvelope, RpcMessage, SessionStats},
+};
+
+// Per client queue size (workers → WS client)
+const CLIENT_QUEUE_SIZE: usize = 128;
+
+/// Identity of the user session a WebSocket client belongs to.
+/// Set by the login workers once the broker accepts the login.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionIdentity {
+    pub username: String,
+    pub session_type: String,
+    pub session_id: Option<String>,
+}
+
+struct ClientEntry {
+    user: String, // Authenticated on connection
+    // None once the WS connection is gone (the session may still need cleanup)
+    tx: Option<mpsc::Sender<RpcEnvelope<RpcMessage>>>,
+    session: Option<SessionIdentity>,
+    peer: Option<PeerInfo>,      // From its Hello, None for legacy clients
+    stats: Option<SessionStats>, // Last reported by the client
+    connected_seq: u64,
+    session_seq: u64,
+}
+
+#[derive(Default)]
+struct ClientsState {
+    next_id: ClientId,
+    seq: u64,
+    clients: HashMap<ClientId, ClientEntry>,
+}
+
+impl ClientsState {
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    // Client used for messages without an explicit target:
+    // the most recently logged in session, or else the most recently connected client
+    fn default_client(&self) -> Option<ClientId> {
+        let connected = || self.clients.iter().filter(|(_, c)| c.tx.is_some());
+        connected()
+            .filter(|(_, c)| c.session.is_some())
+            .max_by_key(|(_, c)| c.session_seq)
+            .or_else(|| connected().max_by_key(|(_, c)| c.connected_seq))
+            .map(|(id, _)| *id)
+    }
+}
+
+/// Registry of the WebSocket clients connected to the server.
+/// Every connection gets its own outbound queue, so messages can be routed to a concrete
+/// client (user session) instead of whichever one happens to read first.
+#[derive(Clone, Default)]
+pub struct WsClients {
+    state: Arc<Mutex<ClientsState>>,
+}
+
+impl WsClients {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new connection of an authenticated user,
+    /// returning its id and the receiver for its outbound messages
+    pub fn register(&self, user: &str) -> (ClientId, mpsc::Receiver<RpcEnvelope<RpcMessage>>) {
+        let (tx, rx) = mpsc::channel(CLIENT_QUEUE_SIZE);
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        let connected_seq = state.next_seq();
+        state.clients.insert(
+            id,
+            ClientEntry {
+                user: user.to_string(),
+                tx: Some(tx),
+                session: None,
+                peer: None,
+                stats: None,
+                connected_seq,
+                session_seq: 0,
+            },
+        );
+        log::debug!("WS client {} registered", id);
+        (id, rx)
+    }
+
+    /// Marks the connection as gone. The session identity (if any) is kept
+    /// until taken, so the close worker can still log it out.
+    pub fn disconnect(&self, id: ClientId) {
+        let mut state = self.state.lock().unwrap();
+        let remove = match state.clients.get_mut(&id) {
+            Some(entry) => {
+                entry.tx = None;
+                entry.session.is_none()
+            }
+            None => false,
+        };
+        if remove {
+            state.clients.remove(&id);
+        }
+        log::debug!("WS client {} disconnected", id);
+    }
+
+    /// Sets (or clears) the session identity of a client
+    pub fn set_session(&self, id: ClientId, session: Option<SessionIdentity>) {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.next_seq();
+        let remove = match state.clients.get_mut(&id) {
+            Some(entry) => {
+                entry.session = session;
+                entry.session_seq = seq;
+                entry.session.is_none() && entry.tx.is_none()
+            }
+            None => false,
+        };
+        if remove {
+            state.clients.remove(&id);
+        }
+    }
+
+    /// Checks that the client was authenticated as `username`
+    pub fn is_user(&self, id: ClientId, username: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state.clients.get(&id).is_some_and(|c| c.user == username)
+    }
+
+    /// Stores what the client told about itself on the handshake
+    pub fn set_peer(&self, id: ClientId, peer: PeerInfo) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.clients.get_mut(&id) {
+            entry.peer = Some(peer);
+        }
+    }
+
+    /// Stores the session timers last reported by the client
+    pub fn set_stats(&self, id: ClientId, stats: SessionStats) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.clients.get_mut(&id) {
+            entry.stats = Some(stats);
+        }
+    }
+
+    /// Session timers of the connected clients that reported them, with their id and user
+    pub fn stats(&self) -> Vec<(ClientId, String, SessionStats)> {
+        let state = self.state.lock().unwrap();
+        let mut stats: Vec<_> = state
+            .clients
+            .iter()
+            .filter(|(_, c)| c.tx.is_some())
+            .filter_map(|(id, c)| c.stats.map(|stats| (*id, c.user.clone(), stats)))
+            .collect();
+        stats.sort_by_key(|(id, _, _)| *id);
+        stats
+    }
+
+    /// Whether the target client (or the default one) can handle the message kind.
+    /// Clients without handshake predate capabilities, so they are assumed to handle anything.
+    pub fn supports(&self, id: Option<ClientId>, kind: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        id.or_else(|| state.default_client())
+            .and_then(|id| state.clients.get(&id))
+            .and_then(|c| c.peer.as_ref())
+            .is_none_or(|peer| peer.supports(kind))
+    }
+
+    pub fn session(&self, id: ClientId) -> Option<SessionIdentity> {
+        let state = self.state.lock().unwrap();
+        state.clients.get(&id).and_then(|c| c.session.clone())
+    }
+
+    /// Takes the session identity of a client, forgetting the client if already disconnected
+    pub fn take_session(&self, id: ClientId) -> Option<SessionIdentity> {
+        let session = self.session(id);
+        if session.is_some() {
+            self.set_session(id, None);
+        }
+        session
+    }
+
+    /// Finds the connected client that belongs to the given broker session id
+    pub fn find_session(&self, session_id: &str) -> Option<ClientId> {
+        let state = self.state.lock().unwrap();
+        state
+            .clients
+            .iter()
+            .filter(|(_, c)| c.tx.is_some())
+            .find(|(_, c)| {
+                c.session
+                    .as_ref()
+                    .and_then(|s| s.session_id.as_deref())
+                    .is_some_and(|s| s == session_id)
+            })
+            .map(|(id, _)| *id)
+    }
+
+    /// Number of connected clients
+    pub fn connected(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.clients.values().filter(|c| c.tx.is_some()).count()
+    }
+
+    /// Routes the message to its target client (`env.client`), or to the default one if not set.
+    /// Never waits: if the client is not draining its queue, the message is dropped, so a
+    /// stuck client cannot stall the messages for the others
+    pub fn send(&self, env: RpcEnvelope<RpcMessage>) {
+        let target = {
+            let state = self.state.lock().unwrap();
+            let id = env.client.or_else(|| state.default_client());
+            id.and_then(|id| {
+                state
+                    .clients
+                    .get(&id)
+                    .and_then(|c| c.tx.clone())
+                    .map(|tx| (id, tx))
+            })
+        };
+        match target {
+            Some((id, tx)) => match tx.try_send(env) {
+                Err(mpsc::error::TrySendError::Full(env)) => {
+                    log::warn!("Queue of WS client {} is full, dropping {:?}", id, env);
+                }
+                Err(e) => log::warn!("Failed to send message to WS client {}: {}", id, e),
+                Ok(()) => {}
+            },
+            None => log::warn!("No WS client to deliver message to: {:?}", env),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ws::types::{LogoffRequest, Ping};
+
+    fn envelope(client: Option<ClientId>) -> RpcEnvelope<RpcMessage> {
+        RpcEnvelope {
+            id: None,
+            msg: RpcMessage::LogoffRequest(LogoffRequest),
+            client,
+        }
+    }
+
+    fn session(username: &str, session_id: &str) -> Option<SessionIdentity> {
+        Some(SessionIdentity {
+            username: username.into(),
+            session_type: "thin".into(),
+            session_id: Some(session_id.into()),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_targeted_routing() {
+        let clients = WsClients::new();
+        let (a, mut rx_a) = clients.register("user");
+        let (b, mut rx_b) = clients.register("user");
+        assert_eq!(clients.connected(), 2);
+
+        clients.send(envelope(Some(a)));
+        clients.send(envelope(Some(b)));
+        clients.send(envelope(Some(b)));
+
+        assert_eq!(rx_a.recv().await.unwrap().client, Some(a));
+        assert!(rx_a.try_recv().is_err());
+        assert_eq!(rx_b.recv().await.unwrap().client, Some(b));
+        assert_eq!(rx_b.recv().await.unwrap().client, Some(b));
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_messages() {
+        let clients = WsClients::new();
+        let (stuck, _rx_stuck) = clients.register("user");
+        let (other, mut rx_other) = clients.register("user");
+
+        // Must not block on the stuck client
+        for _ in 0..=CLIENT_QUEUE_SIZE {
+            clients.send(envelope(Some(stuck)));
+        }
+        clients.send(envelope(Some(other)));
+        assert_eq!(rx_other.recv().await.unwrap().client, Some(other));
+    }
+
+    #[test]
+    fn test_authenticated_user() {
+        let clients = WsClients::new();
+        let (a, _rx_a) = clients.register("alice");
+        assert!(clients.is_user(a, "alice"));
+        assert!(!clients.is_user(a, "bob"));
+        assert!(!clients.is_user(a + 1, "alice"));
+    }
+
+    #[tokio::test]
+    async fn test_default_routing_prefers_logged_in_session() {
+        let clients = WsClients::new();
+        let (a, mut rx_a) = clients.register("user");
+        let (_b, mut rx_b) = clients.register("user");
+
+        // No session: most recently connected one
+        clients.send(envelope(None));
+        assert!(rx_b.try_recv().is_ok());
+        assert!(rx_a.try_recv().is_err());
+
+        clients.set_session(a, session("user-a", "s-a"));
+        clients.send(envelope(None));
+        assert!(rx_a.try_recv().is_ok());
+        assert!(rx_b.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_find_session_and_disconnect() {
+        let clients = WsClients::new();
+        let (a, _rx_a) = clients.register("user");
+        let (b, mut rx_b) = clients.register("user");
+        clients.set_session(a, session("user-a", "s-a"));
+        clients.set_session(b, session("user-b", "s-b"));
+
+        assert_eq!(clients.find_session("s-a"), Some(a));
+        assert_eq!(clients.find_session("s-b"), Some(b));
+        assert_eq!(clients.find_session("s-c"), None);
+
+        // Disconnected client keeps its session until taken, but is no longer routable
+        clients.disconnect(a);
+        assert_eq!(clients.connected(), 1);
+        assert_eq!(clients.find_session("s-a"), None);
+        clients.send(envelope(None));
+        assert!(rx_b.try_recv().is_ok());
+
+        let taken = clients.take_session(a).unwrap();
+        assert_eq!(taken.username, "user-a");
+        assert!(clients.take_session(a).is_none());
+        assert!(clients.session(b).is_some());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let clients = WsClients::new();
+        let (legacy, _rx_legacy) = clients.register("user");
+        assert!(clients.supports(Some(legacy), "ScreenshotRequest"));
+
+        let (a, _rx_a) = clients.register("user");
+        clients.set_peer(a, PeerInfo::current(&["LogoffRequest"]));
+        assert!(clients.supports(Some(a), "LogoffRequest"));
+        assert!(!clients.supports(Some(a), "ScreenshotRequest"));
+        // Default client is the most recently connected one
+        assert!(!clients.supports(None, "ScreenshotRequest"));
+        assert!(clients.supports(Some(legacy), "ScreenshotRequest"));
+    }
+
+    #[tokio::test]
+    async fn test_send_to_gone_client_is_dropped() {
+        let clients = WsClients::new();
+        let (a, rx_a) = clients.register("user");
+        drop(rx_a);
+        clients.disconnect(a);
+        // Must not block nor panic
+        clients.send(RpcEnvelope {
+            id: None,
+            msg: RpcMessage::Ping(Ping(vec![])),
+            client: Some(a),
+        });
+        assert_eq!(clients.connected(), 0);
+    }
+
+    #[test]
+    fn test_stats_of_connected_clients() {
+        let clients = WsClients::new();
+        let (a, _rx_a) = clients.register("alice");
+        let (b, _rx_b) = clients.register("bob");
+        let (_c, _rx_c) = clients.register("carol"); // Never reports
+        let stats = SessionStats {
+            idle: Some(30),
+            deadline: None,
+        };
+        clients.set_stats(a, stats);
+        clients.set_stats(b, stats);
+        assert_eq!(
+            clients.stats(),
+            vec![
+                (a, "alice".to_string(), stats),
+                (b, "bob".to_string(), stats)
+            ]
+        );
+
+        clients.disconnect(a);
+        assert_eq!(clients.stats(), vec![(b, "bob".to_string(), stats)]);
+    }
+}
@@ -0,0 +1,96 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use axum::{Extension, Router, http::header, response::IntoResponse, routing::get};
+
+use super::ServerContext;
+use crate::{
+    log,
+    metrics::{metrics, write_gauge},
+    sync::OnceSignal,
+};
+
+// Prometheus text exposition format
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Process counters, plus the gauges read from the server state
+pub async fn render(context: &ServerContext) -> String {
+    let mut out = String::new();
+    metrics().render(&mut out);
+
+    write_gauge(
+        &mut out,
+        "udsactor_pending_requests",
+        "Requests waiting for a WS client answer",
+        &[(&[], context.tracker.pending().await as f64)],
+    );
+    let connected = context.clients.connected();
+    write_gauge(
+        &mut out,
+        "udsactor_ws_clients",
+        "Connected WS clients",
+        &[(&[], connected as f64)],
+    );
+    write_gauge(
+        &mut out,
+        "udsactor_ws_connected",
+        "Whether any WS client is connected",
+        &[(&[], if connected > 0 { 1.0 } else { 0.0 })],
+    );
+
+    // Session timers, as last reported by every client
+    let stats = context.clients.stats();
+    let ids: Vec<String> = stats.iter().map(|(id, _, _)| id.to_string()).collect();
+    let labels: Vec<[(&str, &str); 2]> = stats
+        .iter()
+        .zip(&ids)
+        .map(|((_, user, _), id)| [("client", id.as_str()), ("user", user.as_str())])
+        .collect();
+    let samples = |value: fn(&crate::ws::types::SessionStats) -> Option<u64>| {
+        stats
+            .iter()
+            .zip(&labels)
+            .filter_map(|((_, _, stats), labels)| {
+                value(stats).map(|v| (labels.as_slice(), v as f64))
+            })
+            .collect::<Vec<_>>()
+    };
+    write_gauge(
+        &mut out,
+        "udsactor_session_idle_seconds",
+        "Seconds the session user has been idle",
+        &samples(|s| s.idle),
+    );
+    write_gauge(
+        &mut out,
+        "udsactor_session_deadline_remaining_seconds",
+        "Seconds until the session deadline",
+        &samples(|s| s.deadline),
+    );
+    out
+}
+
+async fn get_metrics(Extension(context): Extension<ServerContext>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, CONTENT_TYPE)],
+        render(&context).await,
+    )
+}
+
+/// Serves GET /metrics on plain http until stop is signaled.
+/// Not authenticated, so it is kept off the actor port and should be bound to a trusted address
+pub async fn serve_metrics(
+    context: ServerContext,
+    addr: SocketAddr,
+    stop: OnceSignal,
+) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(get_metrics))
+        .layer(Extension(context));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    log::info!("Serving metrics on http://{}/metrics", addr);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move { stop.wait().await })
+        .await?;
+    Ok(())
+}
@@ -1,12 +1,10 @@
 use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
-    sync::{
-        Arc,
-        atomic::{AtomicBool, Ordering},
-    },
+    sync::Arc,
 };
 
 use anyhow::Result;
+use async_trait::async_trait;
 use axum::{
     Extension, Router,
     body::Body,
@@ -14,10 +12,10 @@ use axum::{
         ConnectInfo,
         ws::{Message, WebSocket, WebSocketUpgrade},
     },
-    http::{HeaderValue, Request, StatusCode},
+    http::{HeaderMap, HeaderValue, Request, StatusCode},
     middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post},
 };
 use futures_util::{SinkExt, StreamExt};
 use socket2::{Domain, Socket, Type};
@@ -29,72 +27,173 @@ use tokio::{
 use crate::{
     log,
     sync::OnceSignal,
-    tls::{CertificateInfo, certool},
+    tls::CertificateInfo,
     ws::{
+        credentials::{ClientCredentials, TOKEN_HEADER},
         request_tracker::RequestTracker,
-        types::{Close, Ping, Pong, RpcEnvelope, RpcMessage},
+        rpc::RpcContext,
+        types::{
+            ClientId, Close, ERR_FORBIDDEN, ERR_PROTOCOL_MISMATCH, Hello, HelloAck,
+            MIN_PROTOCOL_VERSION, PeerInfo, Ping, Pong, RpcEnvelope, RpcError, RpcMessage,
+        },
+        unparseable_reply,
     },
 };
 
+mod access;
+mod certificate;
+mod clients;
+mod metrics;
 mod routes;
+mod signing;
+mod status;
+#[cfg(target_os = "linux")]
+mod unix;
+
+pub use clients::{SessionIdentity, WsClients};
+pub use metrics::serve_metrics;
+pub use signing::{NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER, sign_request};
+pub use status::{ServiceStatus, StatusResponse, StatusSource};
 
 #[derive(Clone)]
 pub struct ServerContext {
     pub to_ws: mpsc::Sender<RpcEnvelope<RpcMessage>>,
     pub from_ws: broadcast::Sender<RpcEnvelope<RpcMessage>>,
     pub tracker: RequestTracker,
+    pub clients: WsClients, // Connected WS clients, one per user session
+    status: status::StatusSlot,
+    access: access::AccessGuard,
+}
+
+impl ServerContext {
+    pub fn new(
+        to_ws: mpsc::Sender<RpcEnvelope<RpcMessage>>,
+        from_ws: broadcast::Sender<RpcEnvelope<RpcMessage>>,
+        tracker: RequestTracker,
+        clients: WsClients,
+    ) -> Self {
+        ServerContext {
+            to_ws,
+            from_ws,
+            tracker,
+            clients,
+            status: status::StatusSlot::default(),
+            access: access::AccessGuard::default(),
+        }
+    }
+
+    pub fn is_client_connected(&self) -> bool {
+        self.clients.connected() > 0
+    }
+
+    /// Sets where the status endpoint gets the service side information from
+    pub fn set_status_source(&self, source: Arc<dyn StatusSource>) {
+        self.status.set(source);
+    }
+
+    /// Restricts the source ips that can reach the server (loopback is always allowed).
+    /// Empty allows any
+    pub fn set_allowed_networks(&self, networks: Vec<ipnetwork::IpNetwork>) {
+        self.access.set_allowed(networks);
+    }
+
+    /// Only local clients can reach the server, used when the allowlist is invalid
+    pub fn set_local_only(&self) {
+        self.access.set_local_only();
+    }
+}
+
+// Workers calling the WS clients
+#[async_trait]
+impl RpcContext for ServerContext {
+    fn tracker(&self) -> &RequestTracker {
+        &self.tracker
+    }
+
+    async fn send(&self, env: RpcEnvelope<RpcMessage>) -> Result<()> {
+        self.to_ws.send(env).await?;
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
 struct ServerStartInfo {
     pub cert_info: CertificateInfo,
     pub port: u16,
-    pub workers_to_wsclient: Arc<tokio::sync::Mutex<mpsc::Receiver<RpcEnvelope<RpcMessage>>>>, // unique receiver
+    pub workers_to_wsclient: Arc<tokio::sync::Mutex<mpsc::Receiver<RpcEnvelope<RpcMessage>>>>, // unique receiver, read by the dispatcher
     pub wsclient_to_workers: broadcast::Sender<RpcEnvelope<RpcMessage>>, // WS client → workers
     pub tracker: RequestTracker,
     pub stop: OnceSignal,
     pub secret: String,
     pub ciphers: Option<String>,
+    pub clients: WsClients,
+    pub credentials: ClientCredentials,
+    pub peer_info: PeerInfo, // Ours, sent on HelloAck
+    pub status: status::StatusSlot,
+    pub access: access::AccessGuard,
+    pub certificate: certificate::CertificateSlot,
+    pub started: std::time::Instant,
+    #[cfg(target_os = "linux")]
+    pub unix_socket: Option<std::path::PathBuf>, // Also serve WS here, peers identified by SO_PEERCRED
 }
 
 #[derive(Clone)]
 pub struct ServerState {
-    pub workers_to_wsclient: Arc<tokio::sync::Mutex<mpsc::Receiver<RpcEnvelope<RpcMessage>>>>,
     pub wsclient_to_workers: broadcast::Sender<RpcEnvelope<RpcMessage>>,
     pub tracker: RequestTracker,
     pub stop: OnceSignal,
     pub secret: String,
-    pub is_ws_active: Arc<AtomicBool>,
+    pub clients: WsClients,
+    pub credentials: ClientCredentials,
+    pub peer_info: PeerInfo,
+    status: status::StatusSlot,
+    access: access::AccessGuard,
+    certificate: certificate::CertificateSlot,
+    signatures: signing::SignatureVerifier,
+    started: std::time::Instant,
+}
+
+// HTTP routes calling the workers
+#[async_trait]
+impl RpcContext for ServerState {
+    fn tracker(&self) -> &RequestTracker {
+        &self.tracker
+    }
+
+    async fn send(&self, env: RpcEnvelope<RpcMessage>) -> Result<()> {
+        self.wsclient_to_workers.send(env)?;
+        Ok(())
+    }
 }
 
 impl From<&ServerStartInfo> for ServerState {
     fn from(info: &ServerStartInfo) -> Self {
         ServerState {
-            workers_to_wsclient: info.workers_to_wsclient.clone(),
             wsclient_to_workers: info.wsclient_to_workers.clone(),
             tracker: info.tracker.clone(),
             stop: info.stop.clone(),
             secret: info.secret.clone(),
-            is_ws_active: Arc::new(AtomicBool::new(false)),
+            clients: info.clients.clone(),
+            credentials: info.credentials.clone(),
+            peer_info: info.peer_info.clone(),
+            status: info.status.clone(),
+            access: info.access.clone(),
+            certificate: info.certificate.clone(),
+            signatures: signing::SignatureVerifier::default(),
+            started: info.started,
         }
     }
 }
 
-// To ensure ws_active is released on drop
-struct WsActiveGuard {
-    ws_active: Arc<AtomicBool>,
-}
-
-impl WsActiveGuard {
-    fn new(ws_active: Arc<AtomicBool>) -> Self {
-        ws_active.store(true, Ordering::Relaxed);
-        WsActiveGuard { ws_active }
-    }
+// To ensure the client is unregistered on drop
+struct WsClientGuard {
+    clients: WsClients,
+    id: ClientId,
 }
 
-impl Drop for WsActiveGuard {
+impl Drop for WsClientGuard {
     fn drop(&mut self) {
-        self.ws_active.store(false, Ordering::Relaxed);
+        self.clients.disconnect(self.id);
     }
 }
 
@@ -102,17 +201,40 @@ impl Drop for WsActiveGuard {
 async fn check_secret_middleware(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Extension(state): Extension<ServerState>,
-    req: Request<Body>,
+    mut req: Request<Body>,
     next: Next,
 ) -> Result<Response, axum::http::StatusCode> {
-    let path = req.uri().path();
+    let ip = addr.ip();
+    if !state.access.is_allowed(ip) {
+        log::warn!("Request from {} rejected, not on the allowed networks", ip);
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let path = req.uri().path().to_string();
     let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
     match segments.first() {
         Some(&"") => {} // Root path, allow
         Some(&"actor") => {
-            if segments.get(1).map(|s| s == &state.secret) != Some(true) {
-                log::warn!("Invalid or missing secret in actor request");
-                return Err(StatusCode::FORBIDDEN);
+            if state.access.is_blocked(ip) {
+                return Err(StatusCode::TOO_MANY_REQUESTS);
+            }
+            // Newer brokers sign the request, older ones put the secret on the path
+            let checked = if signing::is_signed(req.headers()) {
+                state.signatures.verify(&state.secret, req).await
+            } else if segments.get(1) == Some(&state.secret.as_str()) {
+                Ok(req)
+            } else {
+                Err("Invalid or missing secret")
+            };
+            match checked {
+                Ok(checked) => {
+                    state.access.record_success(ip);
+                    req = checked;
+                }
+                Err(e) => {
+                    log::warn!("Actor request from {} rejected: {}", ip, e);
+                    state.access.record_failure(ip);
+                    return Err(StatusCode::FORBIDDEN);
+                }
             }
         }
         Some(&"ws") if addr.ip().is_loopback() => {
@@ -132,43 +254,90 @@ async fn check_secret_middleware(
     Ok(response)
 }
 
-async fn ws_handler(ws: WebSocketUpgrade, Extension(state): Extension<ServerState>) -> Response {
-    let ws_active = state.is_ws_active.clone();
-    if ws_active.load(Ordering::Relaxed) {
-        // ya estaba true → hay cliente activo
-        log::warn!("WebSocket connection attempt while another is active");
-        return (StatusCode::CONFLICT, "Another WebSocket client is active").into_response();
-    }
-
-    let wsclient_to_workers = state.wsclient_to_workers.clone();
-    let stop = state.stop.clone();
+async fn ws_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Extension(state): Extension<ServerState>,
+) -> Response {
+    // Local clients must present the credential issued for their user (see ws::credentials)
+    let user = headers
+        .get(TOKEN_HEADER)
+        .and_then(|token| token.to_str().ok())
+        .and_then(|token| state.credentials.authenticate(token));
+    let Some(user) = user else {
+        log::warn!("WebSocket connection attempt without a valid credential");
+        return (StatusCode::UNAUTHORIZED, "Invalid credential").into_response();
+    };
+    upgrade_client(ws, state, user)
+}
 
+// Upgrades the connection of an already authenticated user
+fn upgrade_client(ws: WebSocketUpgrade, state: ServerState, user: String) -> Response {
     ws.on_upgrade(move |socket| {
-        websocket_loop(
-            socket,
-            state.workers_to_wsclient,
-            wsclient_to_workers,
-            stop,
-            ws_active,
-            state.tracker.clone(),
-        )
+        // One client per user session, each one with its own outbound queue
+        let (client_id, to_ws) = state.clients.register(&user);
+        log::info!(
+            "WebSocket client {} connected for user {} ({} active)",
+            client_id,
+            user,
+            state.clients.connected()
+        );
+        websocket_loop(socket, client_id, to_ws, state)
     })
 }
 
+// Answers the client Hello: records its capabilities, or refuses it if too old
+async fn handshake(state: &ServerState, client_id: ClientId, id: Option<u64>, peer: PeerInfo) {
+    let reply = if peer.is_compatible() {
+        log::info!(
+            "WS client {} speaks protocol {} (build {}), handles {:?}",
+            client_id,
+            peer.protocol_version,
+            peer.build,
+            peer.capabilities
+        );
+        state.clients.set_peer(client_id, peer);
+        RpcMessage::HelloAck(HelloAck(state.peer_info.clone()))
+    } else {
+        log::warn!(
+            "WS client {} speaks protocol {} (build {}), minimum is {}. Refusing it",
+            client_id,
+            peer.protocol_version,
+            peer.build,
+            MIN_PROTOCOL_VERSION
+        );
+        RpcMessage::Error(RpcError {
+            code: ERR_PROTOCOL_MISMATCH,
+            message: format!(
+                "Protocol version {} not supported, minimum is {}",
+                peer.protocol_version, MIN_PROTOCOL_VERSION
+            ),
+        })
+    };
+    state.clients.send(RpcEnvelope {
+        id,
+        msg: reply,
+        client: Some(client_id),
+    });
+}
+
 pub async fn websocket_loop(
     socket: WebSocket,
-    to_ws: Arc<tokio::sync::Mutex<mpsc::Receiver<RpcEnvelope<RpcMessage>>>>,
-    from_ws: broadcast::Sender<RpcEnvelope<RpcMessage>>,
-    stop: OnceSignal,
-    ws_active: Arc<AtomicBool>,
-    tracker: RequestTracker,
+    client_id: ClientId,
+    mut to_ws: mpsc::Receiver<RpcEnvelope<RpcMessage>>,
+    state: ServerState,
 ) {
-    let _guard = WsActiveGuard::new(ws_active.clone());
+    let _guard = WsClientGuard {
+        clients: state.clients.clone(),
+        id: client_id,
+    };
+    let stop = state.stop.clone();
+    let tracker = state.tracker.clone();
     let (mut ws_sender, mut ws_receiver) = socket.split();
 
     // Task A: WS client → workers
     let mut tx_task = {
-        let wsclient_to_workers = from_ws.clone();
+        let wsclient_to_workers = state.wsclient_to_workers.clone();
         tokio::spawn(async move {
             let mut closed = false;
             while let Some(Ok(msg)) = ws_receiver.next().await {
@@ -180,17 +349,25 @@ pub async fn websocket_loop(
                             log::debug!("Parsed WS JSON: {:?}", env);
                             env
                         } else {
-                            log::warn!("Invalid WS JSON: {txt}");
+                            log::warn!("Invalid or unsupported WS JSON: {txt}");
+                            if let Some(reply) = unparseable_reply(&txt) {
+                                state.clients.send(RpcEnvelope {
+                                    client: Some(client_id),
+                                    ..reply
+                                });
+                            }
                             continue;
                         }
                     }
                     Message::Ping(data) => RpcEnvelope {
                         id: None,
                         msg: RpcMessage::Ping(Ping(data.to_vec())),
+                        client: None,
                     },
                     Message::Pong(data) => RpcEnvelope {
                         id: None,
                         msg: RpcMessage::Pong(Pong(data.to_vec())),
+                        client: None,
                     },
                     // Not sent to us by client, but handle gracefully
                     Message::Close(_) => {
@@ -198,6 +375,7 @@ pub async fn websocket_loop(
                         RpcEnvelope {
                             id: None,
                             msg: RpcMessage::Close(Close),
+                            client: None,
                         }
                     }
                     Message::Binary(_) => {
@@ -205,6 +383,17 @@ pub async fn websocket_loop(
                         continue;
                     }
                 };
+                // Tag with the originating client, so workers can reply to it
+                let env = RpcEnvelope {
+                    client: Some(client_id),
+                    ..env
+                };
+
+                // Handshake is answered here, workers never see it
+                if let RpcMessage::Hello(Hello(peer)) = env.msg {
+                    handshake(&state, client_id, env.id, peer).await;
+                    continue;
+                }
 
                 if let Some(id) = env.id
                     && tracker.resolve_ok(id, env.msg.clone()).await.is_ok()
@@ -213,7 +402,25 @@ pub async fn websocket_loop(
                     log::debug!("Resolved internal request id {}", id);
                     continue;
                 }
-                // Not resolved, forward to workers
+                // Not resolved, forward to workers, but only what a client may send us
+                if !client_may_send(&state.peer_info, &env.msg) {
+                    log::warn!(
+                        "WS client {} sent {}, not accepted from clients",
+                        client_id,
+                        env.msg.kind()
+                    );
+                    if let Some(id) = env.id {
+                        state.clients.send(RpcEnvelope {
+                            id: Some(id),
+                            msg: RpcMessage::Error(RpcError {
+                                code: ERR_FORBIDDEN,
+                                message: format!("{} not accepted from clients", env.msg.kind()),
+                            }),
+                            client: Some(client_id),
+                        });
+                    }
+                    continue;
+                }
 
                 if let Err(e) = wsclient_to_workers.send(env) {
                     log::warn!("Failed to broadcast WS->workers: {e}");
@@ -225,6 +432,7 @@ pub async fn websocket_loop(
                 let _ = wsclient_to_workers.send(RpcEnvelope {
                     id: None,
                     msg: RpcMessage::Close(Close),
+                    client: Some(client_id),
                 });
             }
             log::info!("WebSocket receiver task ended");
@@ -233,12 +441,8 @@ pub async fn websocket_loop(
 
     // Task B: workers → WS client
     let mut rx_task = {
-        let workers_rx = to_ws.clone();
         tokio::spawn(async move {
-            loop {
-                let msg_opt = { workers_rx.lock().await.recv().await };
-                let Some(env) = msg_opt else { break };
-
+            while let Some(env) = to_ws.recv().await {
                 match serde_json::to_string(&env) {
                     Ok(txt) => {
                         if ws_sender.send(Message::Text(txt.into())).await.is_err() {
@@ -247,6 +451,15 @@ pub async fn websocket_loop(
                     }
                     Err(e) => log::warn!("Failed to serialize RpcEnvelope: {e}"),
                 }
+                // Refused on handshake, nothing else to talk about
+                if let RpcMessage::Error(RpcError {
+                    code: ERR_PROTOCOL_MISMATCH,
+                    ..
+                }) = env.msg
+                {
+                    let _ = ws_sender.send(Message::Close(None)).await;
+                    break;
+                }
             }
         })
     };
@@ -260,7 +473,35 @@ pub async fn websocket_loop(
             rx_task.abort();
         }
     }
-    ws_active.store(false, Ordering::SeqCst);
+    log::info!("WebSocket client {} loop ended", client_id);
+}
+
+// Clients may only send responses to our requests, the messages our workers handle
+// (our capabilities) and the connection control ones. Requests meant for the broker
+// side (scripts, screenshots, ...) are never taken from a client
+fn client_may_send(ours: &PeerInfo, msg: &RpcMessage) -> bool {
+    msg.is_response()
+        || matches!(msg, RpcMessage::Close(_) | RpcMessage::Pong(_))
+        || ours.supports(msg.kind())
+}
+
+// Routes the messages from workers to the right WS client
+async fn dispatcher(
+    workers_rx: Arc<tokio::sync::Mutex<mpsc::Receiver<RpcEnvelope<RpcMessage>>>>,
+    clients: WsClients,
+    stop: OnceSignal,
+) {
+    let mut workers_rx = workers_rx.lock().await;
+    loop {
+        tokio::select! {
+            env = workers_rx.recv() => {
+                let Some(env) = env else { break };
+                clients.send(env);
+            }
+            _ = stop.wait() => break,
+        }
+    }
+    log::debug!("WS dispatcher stopped");
 }
 
 /// Main server function
@@ -268,13 +509,11 @@ async fn server(config: &ServerStartInfo) -> Result<()> {
     log::debug!("Initializing server {}", config.port);
     let state = ServerState::from(config);
 
-    let mut cert_info = config.cert_info.clone();
     // If certificate info from broker doesn't have ciphers, use the ones from our config
-    if cert_info.ciphers.is_none() {
-        cert_info.ciphers = config.ciphers.clone();
-    }
-
-    let tls_config = certool::rustls_config_from_pem(cert_info)?;
+    // Kept reloadable, so the certificate can be replaced while running
+    let tls_config = config
+        .certificate
+        .load(config.cert_info.clone(), config.ciphers.clone())?;
     log::debug!("TLS configuration loaded");
 
     let handle = axum_server::Handle::new();
@@ -283,6 +522,7 @@ async fn server(config: &ServerStartInfo) -> Result<()> {
     let app = Router::new()
         .merge(routes::routes())
         .route("/ws", get(ws_handler))
+        .route("/ws/credential", post(routes::post_ws_credential))
         .route_layer(middleware::from_fn(check_secret_middleware));
 
     // Create A DEBUG build with request/response logging, but not in release to avoid overhead and potential sensitive data in logs
@@ -308,8 +548,19 @@ async fn server(config: &ServerStartInfo) -> Result<()> {
     );
     // TODO until here, join app also :)
 
+    #[cfg(target_os = "linux")]
+    if let Some(path) = &config.unix_socket {
+        unix::spawn(path.clone(), state.clone(), config.stop.clone());
+    }
+
     let app = app.layer(Extension(state));
 
+    tokio::spawn(dispatcher(
+        config.workers_to_wsclient.clone(),
+        config.clients.clone(),
+        config.stop.clone(),
+    ));
+
     tokio::spawn({
         let handle = handle.clone();
         async move {
@@ -357,22 +608,37 @@ pub async fn start_server(
     secret: String,
     port: Option<u16>,
     ciphers: Option<String>,
+    credentials: ClientCredentials,
+    capabilities: &[&str], // Message kinds the workers handle, announced to the clients
 ) -> Result<(ServerContext, tokio::task::JoinHandle<()>)> {
     // Create channels
     let (to_ws, from_workers) = mpsc::channel::<RpcEnvelope<RpcMessage>>(128);
     let (from_ws, _) = broadcast::channel::<RpcEnvelope<RpcMessage>>(128);
     let tracker = RequestTracker::new();
+    let clients = WsClients::new();
+    let context = ServerContext::new(to_ws, from_ws.clone(), tracker.clone(), clients.clone());
+
+    let port = port.unwrap_or(crate::consts::UDS_PORT);
 
     // Armar ServerInfo
     let info = ServerStartInfo {
         cert_info,
-        port: port.unwrap_or(crate::consts::UDS_PORT),
+        port,
         workers_to_wsclient: Arc::new(tokio::sync::Mutex::new(from_workers)),
         wsclient_to_workers: from_ws.clone(),
         tracker: tracker.clone(),
         stop: stop.clone(),
         secret,
         ciphers,
+        clients: clients.clone(),
+        credentials,
+        peer_info: PeerInfo::current(capabilities),
+        status: context.status.clone(),
+        access: context.access.clone(),
+        certificate: certificate::CertificateSlot::default(),
+        started: std::time::Instant::now(),
+        #[cfg(target_os = "linux")]
+        unix_socket: Some(crate::ws::unix_socket::socket_path(port)),
     };
 
     // Launch the server task
@@ -384,14 +650,7 @@ pub async fn start_server(
         }
     });
 
-    Ok((
-        ServerContext {
-            to_ws,
-            from_ws,
-            tracker,
-        },
-        handle,
-    ))
+    Ok((context, handle))
 }
 
 #[cfg(test)]
@@ -418,6 +677,7 @@ mod tests;
 //           | Workers → WS (mpsc)                       |
 //           |                                           v
 // +---------+---------+                       +---------+---------+
-// |   WebSocket       |                       |   WebSocket       |
-// |   Client          |                       |   Loop (server)   |
+// |   WebSocket       |                       |   Dispatcher      |
+// |   Clients (N)     |  per client mpsc      |   (WsClients,     |
+// |                   | <-------------------- |   env.client)     |
 // +-------------------+                       +-------------------+
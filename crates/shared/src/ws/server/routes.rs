@@ -2,88 +2,128 @@ use anyhow::Result;
 use axum::http::StatusCode;
 use axum::{
     Extension, Json, Router,
-    response::Html,
+    extract::Query,
+    response::{Html, IntoResponse, Response},
     routing::{get, post},
 };
-use chrono::Utc;
+use serde::Deserialize;
 
-use crate::ws::types::{LogoffRequest, PreConnect, RpcEnvelope};
+use super::StatusResponse;
+use crate::ws::types::{ClientId, LogoffRequest, PreConnect, RpcEnvelope};
 use crate::{
-    log,
+    log::{self, LogRecord},
+    script,
+    tls::CertificateInfo,
     ws::{
+        call, call_client, error_status,
         types::{
             MessageRequest, RpcMessage, ScreenshotRequest, ScreenshotResponse, ScriptExecRequest,
-            UUidRequest, UUidResponse,
+            ScriptExecResponse, UUidRequest, UUidResponse,
         },
-        wait_response,
     },
 };
 
+/// Optional `?session_id=` query, to target the WS client of a concrete user session.
+/// Without it, requests go to the most recently logged in session.
+#[derive(Debug, Default, Deserialize)]
+pub struct SessionTarget {
+    pub session_id: Option<String>,
+}
+
+// Resolves the target client, failing fast if it told us (on handshake) it cannot handle `kind`
+fn target_client(
+    state: &super::ServerState,
+    target: &SessionTarget,
+    kind: &str,
+) -> Result<Option<ClientId>, StatusCode> {
+    let client = match &target.session_id {
+        Some(session_id) => match state.clients.find_session(session_id) {
+            Some(client) => Some(client),
+            None => {
+                log::warn!("No WebSocket client for session {}", session_id);
+                return Err(StatusCode::NOT_FOUND);
+            }
+        },
+        None => None,
+    };
+    if !state.clients.supports(client, kind) {
+        log::warn!("WebSocket client does not support {}", kind);
+        return Err(StatusCode::NOT_IMPLEMENTED);
+    }
+    Ok(client)
+}
+
 /// GET /actor/{secret}/screenshot
 pub async fn get_screenshot(
     Extension(state): Extension<super::ServerState>,
+    Query(target): Query<SessionTarget>,
 ) -> Result<Json<ScreenshotResponse>, StatusCode> {
-    let tracker = state.tracker.clone();
-    let wsclient_to_workers = state.wsclient_to_workers.clone();
-
-    // Register the request
-    let (resolver_rx, id) = tracker.register().await;
-
-    // Build the envelope with the typed request
-    let envelope = RpcEnvelope {
-        id: Some(id),
-        msg: RpcMessage::ScreenshotRequest(ScreenshotRequest),
-    };
-
-    // Serialize and broadcast
-    if let Err(e) = wsclient_to_workers.send(envelope) {
-        log::warn!("Failed to broadcast ScreenshotRequest to workers: {e}");
-    }
+    let client = target_client(&state, &target, "ScreenshotRequest")?;
 
     // Wait for response, with a timeout of 5 seconds. It's more than enough for a screenshot,
     // And more taking into account that we will communicate with the client using WebSocket that
     // is istantaneous (almost :P)
-    wait_response::<ScreenshotResponse>(resolver_rx, None, Some(std::time::Duration::from_secs(5)))
-        .await
+    call_client(
+        &state,
+        client,
+        ScreenshotRequest,
+        std::time::Duration::from_secs(5),
+    )
+    .await
+    .map(Json)
+    .map_err(|e| {
+        log::warn!("Screenshot failed: {}", e.message);
+        error_status(&e)
+    })
 }
 
 // GET /actor/{secret}/uuid
 pub async fn get_uuid(
     Extension(state): Extension<super::ServerState>,
 ) -> Result<String, StatusCode> {
-    let tracker = state.tracker.clone();
-    let wsclient_to_workers = state.wsclient_to_workers.clone();
-
-    // Generate a unique id
-    let id = Utc::now().timestamp_millis() as u64;
-    log::debug!("UUID requested via WebSocket API with id {}", id);
-
-    // Register the request
-    let (resolver_rx, id) = tracker.register().await;
-
-    // Build the envelope with the typed request
-    let envelope = RpcEnvelope {
-        id: Some(id),
-        msg: RpcMessage::UUidRequest(UUidRequest),
-    };
-
-    // Serialize and broadcast
-    if let Err(e) = wsclient_to_workers.send(envelope) {
-        log::warn!("Failed to broadcast UUidRequest to workers: {e}");
-    }
+    log::debug!("UUID requested via WebSocket API");
 
     // Wait for response, and convert to String
     // Timeout of 2 seconds should much much much more than enough :)
-    let val =
-        wait_response::<UUidResponse>(resolver_rx, None, Some(std::time::Duration::from_secs(2)))
-            .await;
-    if let Ok(uuid) = &val {
-        Ok(uuid.0.0.clone())
-    } else {
-        Err(StatusCode::INTERNAL_SERVER_ERROR)
+    match call::<_, UUidResponse>(&state, UUidRequest, std::time::Duration::from_secs(2)).await {
+        Ok(uuid) => Ok(uuid.0),
+        Err(e) => {
+            log::warn!("UUID request failed: {}", e.message);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
 
+/// GET /actor/{secret}/status
+pub async fn get_status(Extension(state): Extension<super::ServerState>) -> Json<StatusResponse> {
+    Json(StatusResponse {
+        version: crate::consts::VERSION.to_string(),
+        build: crate::consts::BUILD.to_string(),
+        uptime: state.started.elapsed().as_secs(),
+        is_ws_active: state.clients.connected() > 0,
+        ws_clients: state.clients.connected(),
+        pending_requests: state.tracker.pending().await,
+        service: state.status.service_status().await,
+    })
+}
+
+/// `?level=&since=` query of the logs endpoint
+#[derive(Debug, Default, Deserialize)]
+pub struct LogsQuery {
+    pub level: Option<String>, // Minimum level (error, warn, info, debug, trace)
+    pub since: Option<i64>,    // Unix timestamp
+}
+
+/// GET /actor/{secret}/logs
+pub async fn get_logs(Query(query): Query<LogsQuery>) -> Result<Json<Vec<LogRecord>>, StatusCode> {
+    let level = query
+        .level
+        .as_deref()
+        .map(|level| log::parse_level(level).ok_or(StatusCode::BAD_REQUEST))
+        .transpose()?;
+    Ok(Json(log::log_buffer().records(level, query.since)))
+}
+
 pub async fn get_information() -> Result<Html<String>, StatusCode> {
     Ok(Html(format!(
         "<h1>UDS Actor {}.{}</h1>",
@@ -94,11 +134,13 @@ pub async fn get_information() -> Result<Html<String>, StatusCode> {
 
 pub async fn post_logout(
     Extension(state): Extension<super::ServerState>,
+    Query(target): Query<SessionTarget>,
 ) -> Result<&'static str, StatusCode> {
     log::info!("Logout requested via WebSocket API");
     let envelope = RpcEnvelope {
         id: None,
         msg: RpcMessage::LogoffRequest(LogoffRequest),
+        client: target_client(&state, &target, "LogoffRequest")?,
     };
 
     if let Err(e) = state.wsclient_to_workers.send(envelope) {
@@ -110,12 +152,14 @@ pub async fn post_logout(
 
 pub async fn post_message(
     Extension(state): Extension<super::ServerState>,
+    Query(target): Query<SessionTarget>,
     Json(req): Json<MessageRequest>,
 ) -> Result<&'static str, StatusCode> {
     log::info!("Message display requested via WebSocket API");
     let envelope = RpcEnvelope {
         id: None,
         msg: RpcMessage::MessageRequest(req),
+        client: target_client(&state, &target, "MessageRequest")?,
     };
 
     if let Err(e) = state.wsclient_to_workers.send(envelope) {
@@ -125,24 +169,52 @@ pub async fn post_message(
     Ok("ok")
 }
 
+/// `?wait=true` on the script endpoint waits for the script to end and returns its result
+#[derive(Debug, Default, Deserialize)]
+pub struct ScriptWait {
+    #[serde(default)]
+    pub wait: bool,
+}
+
+// Extra time over the script timeout, for the script to be killed and its result to arrive
+const SCRIPT_RESULT_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+
 pub async fn post_script(
     Extension(state): Extension<super::ServerState>,
+    Query(target): Query<SessionTarget>,
+    Query(wait): Query<ScriptWait>,
     Json(req): Json<ScriptExecRequest>,
-) -> Result<&'static str, StatusCode> {
+) -> Result<Response, StatusCode> {
     log::info!("Script execution requested via WebSocket API");
+    // Only scripts for the service context (explicitly requested) do not go to a WS client
+    let client = if req.service {
+        None
+    } else {
+        target_client(&state, &target, "ScriptExecRequest")?
+    };
+
+    if wait.wait {
+        let timeout = script::timeout(&req) + SCRIPT_RESULT_GRACE;
+        return call_client::<_, ScriptExecResponse>(&state, client, req, timeout)
+            .await
+            .map(|res| Json(res).into_response())
+            .map_err(|e| {
+                log::warn!("Script execution failed: {}", e.message);
+                error_status(&e)
+            });
+    }
+
     let envelope = RpcEnvelope {
         id: None,
-        msg: RpcMessage::ScriptExecRequest(ScriptExecRequest {
-            script_type: req.script_type,
-            script: req.script,
-        }),
+        msg: RpcMessage::ScriptExecRequest(req),
+        client,
     };
 
     if let Err(e) = state.wsclient_to_workers.send(envelope) {
         log::warn!("Failed to broadcast ScriptExecRequest to workers: {e}");
     }
 
-    Ok("ok")
+    Ok("ok".into_response())
 }
 
 pub async fn post_pre_connect(
@@ -159,6 +231,7 @@ pub async fn post_pre_connect(
             hostname: req.hostname,
             udsuser: req.udsuser,
         }),
+        client: None,
     };
 
     if let Err(e) = state.wsclient_to_workers.send(envelope) {
@@ -168,13 +241,59 @@ pub async fn post_pre_connect(
     Ok("ok")
 }
 
-pub fn routes() -> Router {
+/// POST /actor/{secret}/certificate
+/// Replaces the server certificate, without dropping the connected WS clients
+pub async fn post_certificate(
+    Extension(state): Extension<super::ServerState>,
+    Json(cert_info): Json<CertificateInfo>,
+) -> Result<&'static str, StatusCode> {
+    log::info!("Certificate reload requested via WebSocket API");
+    match state.certificate.reload(cert_info) {
+        Ok(_) => Ok("ok"),
+        Err(e) => {
+            log::warn!("Cannot reload certificate: {}", e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CredentialRequest {
+    pub username: String,
+}
+
+/// POST /ws/credential (localhost only)
+/// Writes the credential for the user into a file only that user can read.
+/// Anyone can ask for it, but only the user itself can use it.
+pub async fn post_ws_credential(
+    Extension(state): Extension<super::ServerState>,
+    Json(req): Json<CredentialRequest>,
+) -> Result<&'static str, StatusCode> {
+    match state.credentials.issue(&req.username) {
+        Ok(_) => Ok("ok"),
+        Err(e) => {
+            log::warn!("Cannot issue credential for {}: {}", req.username, e);
+            Err(StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+fn actor_routes(prefix: &str) -> Router {
     Router::new()
-        .route("/actor/{secret}/screenshot", get(get_screenshot))
-        .route("/actor/{secret}/uuid", get(get_uuid))
-        .route("/actor/{secret}/logout", post(post_logout))
-        .route("/actor/{secret}/message", post(post_message))
-        .route("/actor/{secret}/script", post(post_script))
-        .route("/actor/{secret}/preconnect", post(post_pre_connect))
+        .route(&format!("{prefix}/screenshot"), get(get_screenshot))
+        .route(&format!("{prefix}/uuid"), get(get_uuid))
+        .route(&format!("{prefix}/status"), get(get_status))
+        .route(&format!("{prefix}/logs"), get(get_logs))
+        .route(&format!("{prefix}/logout"), post(post_logout))
+        .route(&format!("{prefix}/message"), post(post_message))
+        .route(&format!("{prefix}/script"), post(post_script))
+        .route(&format!("{prefix}/preconnect"), post(post_pre_connect))
+        .route(&format!("{prefix}/certificate"), post(post_certificate))
+}
+
+pub fn routes() -> Router {
+    // Signed requests (see signing) do not need the secret on the path
+    actor_routes("/actor/{secret}")
+        .merge(actor_routes("/actor"))
         .route("/", get(get_information))
 }
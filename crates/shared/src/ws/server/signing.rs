@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request},
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::ws::credentials::token_eq;
+
+// Signed requests carry these instead of the secret on the path
+pub const SIGNATURE_HEADER: &str = "x-uds-signature";
+pub const TIMESTAMP_HEADER: &str = "x-uds-timestamp";
+pub const NONCE_HEADER: &str = "x-uds-nonce";
+
+// Seconds a signed request is valid, before or after its timestamp
+const MAX_CLOCK_SKEW: i64 = 300;
+const MAX_NONCE_LENGTH: usize = 128;
+// Body must be read to check its hash, scripts are the biggest ones
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+// Requests are not signed with the secret itself, but with a key derived from it
+const KEY_CONTEXT: &[u8] = b"uds-actor-request-signing";
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn signing_key(secret: &str) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+    mac.update(KEY_CONTEXT);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Signature of a broker request: hex encoded HMAC-SHA256 (keyed with the key derived
+/// from the secret) of `METHOD\npath?query\nhex(sha256(body))\ntimestamp\nnonce`
+pub fn sign_request(
+    secret: &str,
+    method: &str,
+    path: &str,
+    body: &[u8],
+    timestamp: i64,
+    nonce: &str,
+) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(&signing_key(secret)).expect("HMAC takes any key size");
+    mac.update(
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            method.to_uppercase(),
+            path,
+            hex(&Sha256::digest(body)),
+            timestamp,
+            nonce
+        )
+        .as_bytes(),
+    );
+    hex(&mac.finalize().into_bytes())
+}
+
+/// Whether the request uses signature instead of the path secret
+pub fn is_signed(headers: &HeaderMap) -> bool {
+    headers.contains_key(SIGNATURE_HEADER)
+}
+
+/// Verifies signed requests, remembering the nonces seen within the validity window
+/// so a captured request cannot be replayed
+#[derive(Clone, Default)]
+pub(super) struct SignatureVerifier {
+    seen: Arc<Mutex<HashMap<String, i64>>>, // nonce → timestamp
+}
+
+impl SignatureVerifier {
+    /// Checks the signature, returning the request back (its body had to be read)
+    pub async fn verify(
+        &self,
+        secret: &str,
+        req: Request<Body>,
+    ) -> Result<Request<Body>, &'static str> {
+        let (parts, body) = req.into_parts();
+        let header = |name: &str| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let (Some(signature), Some(timestamp), Some(nonce)) = (
+            header(SIGNATURE_HEADER),
+            header(TIMESTAMP_HEADER),
+            header(NONCE_HEADER),
+        ) else {
+            return Err("Missing signature headers");
+        };
+        let timestamp: i64 = timestamp.parse().map_err(|_| "Invalid timestamp")?;
+        let now = chrono::Utc::now().timestamp();
+        if (now - timestamp).abs() > MAX_CLOCK_SKEW {
+            return Err("Timestamp out of the allowed window");
+        }
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LENGTH {
+            return Err("Invalid nonce");
+        }
+
+        let body = axum::body::to_bytes(body, MAX_BODY_SIZE)
+            .await
+            .map_err(|_| "Cannot read body")?;
+        let path = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        let expected = sign_request(
+            secret,
+            parts.method.as_str(),
+            path,
+            &body,
+            timestamp,
+            &nonce,
+        );
+        if !token_eq(&expected, &signature.to_lowercase()) {
+            return Err("Invalid signature");
+        }
+
+        // Only valid requests get here, so nonces cannot be flooded by anyone without the key
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, ts| (now - *ts).abs() <= MAX_CLOCK_SKEW);
+        if seen.insert(nonce, timestamp).is_some() {
+            return Err("Replayed request");
+        }
+        Ok(Request::from_parts(parts, Body::from(body)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed(
+        secret: &str,
+        method: &str,
+        path: &str,
+        body: &str,
+        timestamp: i64,
+        nonce: &str,
+    ) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header(
+                SIGNATURE_HEADER,
+                sign_request(secret, method, path, body.as_bytes(), timestamp, nonce),
+            )
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(NONCE_HEADER, nonce)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_valid_signature() {
+        let verifier = SignatureVerifier::default();
+        let now = chrono::Utc::now().timestamp();
+        let req = signed("secret", "POST", "/actor/message", "{}", now, "n1");
+        assert!(is_signed(req.headers()));
+        let req = verifier.verify("secret", req).await.unwrap();
+        // Body is still there for the handler
+        let body = axum::body::to_bytes(req.into_body(), 16).await.unwrap();
+        assert_eq!(&body[..], b"{}");
+    }
+
+    #[tokio::test]
+    async fn test_tampered_or_wrong_key() {
+        let verifier = SignatureVerifier::default();
+        let now = chrono::Utc::now().timestamp();
+        let req = signed("other", "GET", "/actor/status", "", now, "n1");
+        assert!(verifier.verify("secret", req).await.is_err());
+
+        let mut req = signed("secret", "GET", "/actor/status", "", now, "n2");
+        *req.uri_mut() = "/actor/logs".parse().unwrap();
+        assert_eq!(
+            verifier.verify("secret", req).await.unwrap_err(),
+            "Invalid signature"
+        );
+
+        let req = signed("secret", "POST", "/actor/script", "rm -rf", now, "n3");
+        let (parts, _) = req.into_parts();
+        let req = Request::from_parts(parts, Body::from("echo"));
+        assert!(verifier.verify("secret", req).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_replay_and_stale() {
+        let verifier = SignatureVerifier::default();
+        let now = chrono::Utc::now().timestamp();
+        let req = signed("secret", "GET", "/actor/status", "", now, "n1");
+        verifier.verify("secret", req).await.unwrap();
+        let req = signed("secret", "GET", "/actor/status", "", now, "n1");
+        assert_eq!(
+            verifier.verify("secret", req).await.unwrap_err(),
+            "Replayed request"
+        );
+
+        let stale = now - MAX_CLOCK_SKEW - 10;
+        let req = signed("secret", "GET", "/actor/status", "", stale, "n2");
+        assert!(verifier.verify("secret", req).await.is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::{broker::api::types::BrokerCallResult, config::ActorType};
+
+/// Service side part of the actor status: what only the service knows about
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ServiceStatus {
+    pub actor_type: Option<ActorType>,
+    pub username: Option<String>,
+    pub session_type: Option<String>,
+    pub session_id: Option<String>,
+    pub last_broker_call: Option<BrokerCallResult>,
+}
+
+/// Provides the service side status, set by the service once its workers are running
+#[async_trait]
+pub trait StatusSource: Send + Sync {
+    async fn service_status(&self) -> ServiceStatus;
+}
+
+/// GET /actor/{secret}/status response
+#[derive(Debug, Serialize)]
+pub struct StatusResponse {
+    pub version: String,
+    pub build: String,
+    pub uptime: u64,        // Seconds since server started
+    pub is_ws_active: bool, // Whether any WS client is connected
+    pub ws_clients: usize,
+    pub pending_requests: usize, // Requests waiting for a WS client answer
+    #[serde(flatten)]
+    pub service: ServiceStatus,
+}
+
+// Slot for the status source, shared by the server context (that sets it) and the routes
+#[derive(Clone, Default)]
+pub(super) struct StatusSlot(Arc<RwLock<Option<Arc<dyn StatusSource>>>>);
+
+impl StatusSlot {
+    pub fn set(&self, source: Arc<dyn StatusSource>) {
+        *self.0.write().unwrap() = Some(source);
+    }
+
+    pub async fn service_status(&self) -> ServiceStatus {
+        let source = self.0.read().unwrap().clone();
+        match source {
+            Some(source) => source.service_status().await,
+            None => ServiceStatus::default(),
+        }
+    }
+}
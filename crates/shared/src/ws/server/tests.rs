@@ -1,7 +1,10 @@
 use anyhow::Result;
 use tokio::sync::{broadcast, mpsc};
 
-use tokio_tungstenite::{Connector, connect_async_tls_with_config, tungstenite::Message};
+use tokio_tungstenite::{
+    Connector, connect_async_tls_with_config,
+    tungstenite::{Message, client::IntoClientRequest},
+};
 
 use super::*;
 use crate::ws::{request_tracker::RequestTracker, types::RpcMessage};
@@ -13,6 +16,30 @@ type ServerTaskResult = (
     tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
 );
 
+fn test_credentials(port: u16) -> ClientCredentials {
+    let dir = std::env::temp_dir().join(format!("udsactor-ws-creds-{}", port));
+    ClientCredentials::new(
+        dir,
+        std::sync::Arc::new(crate::testing::mock::OperationsMock::default()),
+    )
+}
+
+// Request to /ws with the credential header for the user
+fn ws_request(
+    server_info: &ServerStartInfo,
+    user: &str,
+) -> tokio_tungstenite::tungstenite::handshake::client::Request {
+    let path = server_info.credentials.issue(user).unwrap();
+    let token = std::fs::read_to_string(path).unwrap();
+    let mut request = format!("wss://localhost:{}/ws", server_info.port)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert(TOKEN_HEADER, token.parse().unwrap());
+    request
+}
+
 fn create_test_server_task(port: u16, secret: &str) -> ServerTaskResult {
     log::setup_logging("debug", crate::log::LogType::Tests);
     crate::tls::init_tls(None);
@@ -36,6 +63,15 @@ fn create_test_server_task(port: u16, secret: &str) -> ServerTaskResult {
         stop: stop.clone(),
         secret: secret.into(),
         ciphers: None,
+        clients: WsClients::new(),
+        credentials: test_credentials(port),
+        peer_info: PeerInfo::current(&["LoginRequest"]),
+        status: status::StatusSlot::default(),
+        access: access::AccessGuard::default(),
+        certificate: certificate::CertificateSlot::default(),
+        started: std::time::Instant::now(),
+        #[cfg(target_os = "linux")]
+        unix_socket: None,
     };
 
     let server_info_task = server_info.clone();
@@ -77,16 +113,16 @@ async fn test_server_stops_on_ws_client_connected() {
     // Wait a moment for the server to start
     tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 
-    // Build the WebSocket URL (TLS enabled, but self-signed)
-    let url = format!("wss://localhost:{}/ws", server_info.port);
+    // WebSocket request (TLS enabled, but self-signed) with our credential
+    let request = ws_request(&server_info, "testuser");
 
     // Create a connector that disables certificate verification
     let connector = Connector::Rustls(crate::tls::noverify::client_config());
 
     // Perform the WebSocket handshake with custom TLS config
     let (mut ws_stream, _resp) = connect_async_tls_with_config(
-        url,
-        None, // no additional request headers
+        request,
+        None, // no additional config
         true, // allow insecure
         Some(connector),
     )
@@ -110,3 +146,39 @@ async fn test_server_stops_on_ws_client_connected() {
         .expect("Server task panicked")
         .expect("Server returned an error");
 }
+
+#[tokio::test]
+async fn test_ws_rejects_missing_or_invalid_credential() {
+    let (server_info, server_task) = create_test_server_task(32434, "-secret-");
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    let connect = |request| {
+        connect_async_tls_with_config(
+            request,
+            None,
+            true,
+            Some(Connector::Rustls(crate::tls::noverify::client_config())),
+        )
+    };
+
+    // No credential at all
+    let url = format!("wss://localhost:{}/ws", server_info.port);
+    assert!(connect(url.into_client_request().unwrap()).await.is_err());
+
+    // Invalid credential
+    let mut request = ws_request(&server_info, "testuser");
+    request
+        .headers_mut()
+        .insert(TOKEN_HEADER, "not-a-valid-token".parse().unwrap());
+    assert!(connect(request).await.is_err());
+
+    // Valid one, authenticated as its user
+    let (_ws_stream, _resp) = connect(ws_request(&server_info, "testuser"))
+        .await
+        .expect("WebSocket handshake failed");
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(server_info.clients.connected(), 1);
+
+    server_info.stop.set();
+    let _ = tokio::time::timeout(tokio::time::Duration::from_secs(5), server_task).await;
+}
@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use axum::{
+    Extension, Router,
+    extract::{ConnectInfo, WebSocketUpgrade, connect_info::Connected},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    serve::IncomingStream,
+};
+use tokio::net::UnixListener;
+
+use crate::{log, sync::OnceSignal};
+
+use super::{ServerState, upgrade_client};
+
+/// Credentials of the process on the other side of the socket (SO_PEERCRED)
+#[derive(Clone, Debug)]
+pub struct PeerCred {
+    pub uid: Option<u32>,
+    pub pid: Option<i32>,
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for PeerCred {
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self {
+        match stream.io().peer_cred() {
+            Ok(cred) => PeerCred {
+                uid: Some(cred.uid()),
+                pid: cred.pid(),
+            },
+            Err(e) => {
+                log::warn!("Cannot get peer credentials: {}", e);
+                PeerCred {
+                    uid: None,
+                    pid: None,
+                }
+            }
+        }
+    }
+}
+
+async fn ws_unix_handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(peer): ConnectInfo<PeerCred>,
+    Extension(state): Extension<ServerState>,
+) -> Response {
+    // The kernel tells us who is connecting, no credential needed
+    let user = peer
+        .uid
+        .and_then(|uid| crate::unix::username_from_uid(uid).ok());
+    let Some(user) = user else {
+        log::warn!("Unix socket connection from unknown peer {:?}", peer);
+        return (StatusCode::UNAUTHORIZED, "Unknown peer").into_response();
+    };
+    log::debug!("Unix socket connection from {} (pid {:?})", user, peer.pid);
+    upgrade_client(ws, state, user)
+}
+
+fn bind(path: &Path) -> Result<UnixListener> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Stale socket from a previous run
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    // Any local user may connect, peer credentials tell who it is
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))?;
+    Ok(listener)
+}
+
+/// Serves the WebSocket endpoint on the unix socket until stop.
+/// Failing to bind is not fatal, clients will keep using the TCP listener.
+pub fn spawn(path: PathBuf, state: ServerState, stop: OnceSignal) {
+    let listener = match bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::warn!("Cannot listen on unix socket {}: {}", path.display(), e);
+            return;
+        }
+    };
+    log::info!("Listening on unix socket {}", path.display());
+
+    let app = Router::new()
+        .route("/ws", get(ws_unix_handler))
+        .layer(Extension(state));
+
+    tokio::spawn(async move {
+        let serve = axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<PeerCred>(),
+        )
+        .with_graceful_shutdown({
+            let stop = stop.clone();
+            async move { stop.wait().await }
+        });
+        if let Err(e) = serve.await {
+            log::error!("Unix socket server failed: {}", e);
+        }
+        let _ = std::fs::remove_file(&path);
+    });
+}
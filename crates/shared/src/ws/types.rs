@@ -5,12 +5,31 @@ use serde::{Deserialize, Serialize};
 pub use crate::broker::api::types::{LogLevel, LoginResponse};
 
 pub type RequestId = u64;
+pub type ClientId = u64;
+
+// Version of the WS protocol spoken by this build. Bump on incompatible changes,
+// and raise MIN_PROTOCOL_VERSION when older peers can no longer be served.
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+// Error codes sent back on RpcMessage::Error (same meaning as their HTTP counterparts)
+pub const ERR_BAD_REQUEST: u32 = 400;
+pub const ERR_FORBIDDEN: u32 = 403;
+pub const ERR_TIMEOUT: u32 = 408;
+pub const ERR_PROTOCOL_MISMATCH: u32 = 426;
+pub const ERR_INTERNAL: u32 = 500;
+pub const ERR_NOT_SUPPORTED: u32 = 501;
+pub const ERR_UNAVAILABLE: u32 = 503;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RpcEnvelope<T> {
     pub id: Option<RequestId>,
     #[serde(flatten)]
     pub msg: T,
+    // Server side only: WebSocket client the message comes from or must be routed to.
+    // None means "the default client" (see ws::server::clients)
+    #[serde(skip)]
+    pub client: Option<ClientId>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -32,9 +51,14 @@ pub enum RpcMessage {
     LoginResponse(LoginResponse),
     ScreenshotResponse(ScreenshotResponse),
     ScriptExecResponse(ScriptExecResponse),
+    MessageAck(MessageAck), // For MessageRequest that require acknowledgement
     // Message does not have a response
     UUidResponse(UUidResponse), // UUID as string
 
+    // Handshake, first message of the client and the service answer
+    Hello(Hello),
+    HelloAck(HelloAck),
+
     // Notifications (no id)
     Ping(Ping),                   // Used to maintain connection alive
     Pong(Pong),                   // Response to Ping, same payload
@@ -42,6 +66,8 @@ pub enum RpcMessage {
     PreConnect(PreConnect),       // From broker for server
     LogoutRequest(LogoutRequest), // From client ws for the broker
     LogRequest(LogRequest),       // From client ws for the broker
+    ResumeSession(ResumeSession), // From client ws after reconnecting
+    SessionStats(SessionStats),   // From client ws, periodically
     MessageRequest(MessageRequest),
     Close(Close), // From client ws to server
 
@@ -49,6 +75,83 @@ pub enum RpcMessage {
     Error(RpcError),
 }
 
+impl RpcMessage {
+    /// Kind of the message, as serialized on the wire (and listed on capabilities)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RpcMessage::LoginRequest(_) => "LoginRequest",
+            RpcMessage::ScreenshotRequest(_) => "ScreenshotRequest",
+            RpcMessage::ScriptExecRequest(_) => "ScriptExecRequest",
+            RpcMessage::UUidRequest(_) => "UUidRequest",
+            RpcMessage::LoginResponse(_) => "LoginResponse",
+            RpcMessage::ScreenshotResponse(_) => "ScreenshotResponse",
+            RpcMessage::ScriptExecResponse(_) => "ScriptExecResponse",
+            RpcMessage::UUidResponse(_) => "UUidResponse",
+            RpcMessage::Hello(_) => "Hello",
+            RpcMessage::HelloAck(_) => "HelloAck",
+            RpcMessage::Ping(_) => "Ping",
+            RpcMessage::Pong(_) => "Pong",
+            RpcMessage::LogoffRequest(_) => "LogoffRequest",
+            RpcMessage::PreConnect(_) => "PreConnect",
+            RpcMessage::LogoutRequest(_) => "LogoutRequest",
+            RpcMessage::LogRequest(_) => "LogRequest",
+            RpcMessage::ResumeSession(_) => "ResumeSession",
+            RpcMessage::SessionStats(_) => "SessionStats",
+            RpcMessage::MessageRequest(_) => "MessageRequest",
+            RpcMessage::MessageAck(_) => "MessageAck",
+            RpcMessage::Close(_) => "Close",
+            RpcMessage::Error(_) => "Error",
+        }
+    }
+
+    /// Whether the message answers a request (so it resolves a pending call)
+    pub fn is_response(&self) -> bool {
+        matches!(
+            self,
+            RpcMessage::LoginResponse(_)
+                | RpcMessage::ScreenshotResponse(_)
+                | RpcMessage::ScriptExecResponse(_)
+                | RpcMessage::UUidResponse(_)
+                | RpcMessage::MessageAck(_)
+                | RpcMessage::Error(_)
+        )
+    }
+}
+
+/// What a peer tells about itself on the handshake
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PeerInfo {
+    pub protocol_version: u32,
+    pub build: String,             // Actor version of the peer
+    pub capabilities: Vec<String>, // Message kinds the peer handles
+}
+
+impl PeerInfo {
+    /// Info about ourselves, handling the given message kinds
+    pub fn current(capabilities: &[&str]) -> Self {
+        PeerInfo {
+            protocol_version: PROTOCOL_VERSION,
+            build: format!("{}.{}", crate::consts::VERSION, crate::consts::BUILD),
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// Whether we can talk to this peer at all
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version >= MIN_PROTOCOL_VERSION
+    }
+
+    pub fn supports(&self, kind: &str) -> bool {
+        self.capabilities.iter().any(|c| c == kind)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Hello(pub PeerInfo);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HelloAck(pub PeerInfo);
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct LoginRequest {
     pub username: String,
@@ -65,16 +168,34 @@ pub struct ScreenshotResponse {
     pub result: String, // base64 encoded image
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct ScriptExecRequest {
     #[serde(rename = "type")]
     pub script_type: String,
     pub script: String,
+    #[serde(default)]
+    pub service: bool, // Opt-in: run by the service (privileged) instead of in the user session
+    #[serde(default)]
+    pub timeout: Option<u64>, // Seconds, default if not set (see script::DEFAULT_TIMEOUT)
+}
+
+/// Where a script was run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptContext {
+    Service, // By the service, as a privileged user
+    User,    // By the client, in the user session
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ScriptExecResponse {
-    pub result: String,
+    pub context: ScriptContext,
+    pub exit_code: Option<i32>, // None if killed (timeout, signal)
+    pub stdout: String,         // Size capped, see `truncated`
+    pub stderr: String,
+    pub truncated: bool, // Output was longer than script::MAX_OUTPUT_SIZE
+    pub timed_out: bool,
+    pub duration: f64, // Seconds
 }
 
 /// Payload for logout
@@ -85,15 +206,62 @@ pub struct LogoutRequest {
     pub session_id: String,
 }
 
+/// Binds a new connection to an already logged in session (no new broker login)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResumeSession {
+    pub username: String,
+    pub session_type: String,
+    pub session_id: Option<String>,
+}
+
+/// Session timers only the client knows about, reported for the metrics endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionStats {
+    pub idle: Option<u64>, // Seconds the user has been idle, None if not tracked
+    pub deadline: Option<u64>, // Seconds until the session deadline, None if no deadline
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogRequest {
     pub level: LogLevel, // Log level
     pub message: String, // Log message
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageSeverity {
+    #[default]
+    Info,
+    Warning,
+    Error,
+}
+
+impl MessageSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageSeverity::Info => "info",
+            MessageSeverity::Warning => "warning",
+            MessageSeverity::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MessageRequest {
     pub message: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub severity: MessageSeverity,
+    #[serde(default)]
+    pub timeout: Option<u64>, // Seconds until dismissed automatically, never if not set
+    #[serde(default)]
+    pub require_ack: bool, // If the user acknowledgement (or its lack) is answered with a MessageAck
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageAck {
+    pub acknowledged: bool, // False if dismissed (or timed out) without acknowledgement
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
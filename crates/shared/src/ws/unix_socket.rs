@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+/// Unix socket where the service also serves the local WebSocket protocol.
+/// Peer credentials (SO_PEERCRED) identify the connecting user, so no TLS nor token is needed.
+/// The port is part of the name, so several listeners (i.e. tests) can coexist.
+pub fn socket_path(port: u16) -> PathBuf {
+    if std::env::var("UDS_ACTOR_TEST").is_ok() {
+        std::env::temp_dir().join(format!("udsactor-ws-{}.sock", port))
+    } else {
+        PathBuf::from(format!("/run/udsactor/ws-{}.sock", port))
+    }
+}
@@ -1,26 +1,49 @@
-use std::sync::atomic::AtomicU16;
+use std::sync::{Arc, OnceLock, atomic::AtomicU16};
 
 use anyhow::Result;
 
-use futures_util::sink::SinkExt;
+use futures_util::{SinkExt, StreamExt};
 use local_ip_address::{local_ip, local_ipv6};
-use tokio_tungstenite::{Connector, connect_async_tls_with_config, tungstenite::Message};
+use tokio_tungstenite::{
+    Connector, connect_async_tls_with_config,
+    tungstenite::{Message, client::IntoClientRequest, handshake::client::Request},
+};
 
 use reqwest::Client;
 use shared::{
+    config::ActorType,
     log,
+    log_shipper::LogSource,
     sync::OnceSignal,
-    testing::test_certs,
+    testing::{mock::OperationsMock, test_certs},
     ws::{
-        server::{ServerContext, start_server},
+        client::websocket_client_tasks,
+        credentials::{ClientCredentials, TOKEN_HEADER, credential_path, credentials_dir},
+        server::{
+            NONCE_HEADER, SIGNATURE_HEADER, ServerContext, ServiceStatus, SessionIdentity,
+            StatusSource, TIMESTAMP_HEADER, serve_metrics, sign_request, start_server,
+        },
         types::{
-            LogoffRequest, MessageRequest, Ping, PreConnect, RpcEnvelope, RpcMessage,
-            ScreenshotRequest, ScreenshotResponse, ScriptExecRequest, UUidRequest, UUidResponse,
+            Close, ERR_FORBIDDEN, ERR_NOT_SUPPORTED, ERR_PROTOCOL_MISMATCH, Hello, HelloAck,
+            LogoffRequest, MIN_PROTOCOL_VERSION, MessageRequest, PROTOCOL_VERSION, PeerInfo, Ping,
+            PreConnect, ResumeSession, RpcEnvelope, RpcMessage, ScreenshotRequest,
+            ScreenshotResponse, ScriptContext, ScriptExecRequest, ScriptExecResponse, SessionStats,
+            UUidRequest, UUidResponse,
         },
         wait_message_arrival, wait_response,
     },
 };
 
+// Same as the service workers
+const SERVICE_CAPABILITIES: &[&str] = &[
+    "LoginRequest",
+    "LogoutRequest",
+    "LogRequest",
+    "Ping",
+    "ResumeSession",
+    "SessionStats",
+];
+
 // Port counter to avoid collisions
 static NEXT_PORT: AtomicU16 = AtomicU16::new(32420);
 
@@ -28,19 +51,36 @@ async fn create_test_server_task(
     secret: &str,
 ) -> (ServerContext, tokio::task::JoinHandle<()>, u16) {
     let port = NEXT_PORT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let (server_info, handle, _stop) = create_test_server_on_port(secret, port).await;
+    (server_info, handle, port)
+}
+
+async fn create_test_server_on_port(
+    secret: &str,
+    port: u16,
+) -> (ServerContext, tokio::task::JoinHandle<()>, OnceSignal) {
+    // Credentials and unix socket go to the temp dir
+    unsafe { std::env::set_var("UDS_ACTOR_TEST", "1") };
     log::setup_logging("debug", crate::log::LogType::Tests);
     shared::tls::init_tls(None);
 
     let stop = OnceSignal::new();
     let cert_info = test_certs::test_certinfo();
 
-    let (server_info, handle) =
-        start_server(cert_info, stop.clone(), secret.into(), Some(port), None)
-            .await
-            .unwrap();
+    let (server_info, handle) = start_server(
+        cert_info,
+        stop.clone(),
+        secret.into(),
+        Some(port),
+        None,
+        test_credentials(),
+        SERVICE_CAPABILITIES,
+    )
+    .await
+    .unwrap();
     // Wait a moment for the server to start
     tokio::time::sleep(std::time::Duration::from_millis(400)).await;
-    (server_info, handle, port)
+    (server_info, handle, stop)
 }
 
 async fn get_request(url: &str) -> Result<String> {
@@ -76,6 +116,36 @@ async fn post_request<U: serde::Serialize>(url: &str, json: &U) -> Result<String
     Ok(body)
 }
 
+// Shared by all test servers, so clients can get a credential from any of them
+fn test_credentials() -> ClientCredentials {
+    static CREDENTIALS: OnceLock<ClientCredentials> = OnceLock::new();
+    CREDENTIALS
+        .get_or_init(|| {
+            ClientCredentials::new(credentials_dir(), Arc::new(OperationsMock::default()))
+        })
+        .clone()
+}
+
+// Asks the service for a credential, and builds the /ws request presenting it
+async fn ws_request(port: u16, user: &str) -> Request {
+    post_request(
+        &format!("https://localhost:{}/ws/credential", port),
+        &serde_json::json!({ "username": user }),
+    )
+    .await
+    .unwrap();
+    let path = credential_path(&credentials_dir(), user).unwrap();
+    let token = std::fs::read_to_string(path).unwrap();
+
+    let mut request = format!("wss://localhost:{}/ws", port)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert(TOKEN_HEADER, token.parse().unwrap());
+    request
+}
+
 #[tokio::test]
 async fn test_get_screenshot() {
     let (server_info, server_task, port) = create_test_server_task("-secret-").await;
@@ -174,6 +244,136 @@ async fn test_information() {
     server_task.abort();
 }
 
+struct FakeStatus;
+
+#[async_trait::async_trait]
+impl StatusSource for FakeStatus {
+    async fn service_status(&self) -> ServiceStatus {
+        ServiceStatus {
+            actor_type: Some(ActorType::Unmanaged),
+            username: Some("user".into()),
+            session_type: Some("RDP".into()),
+            ..Default::default()
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_status() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let url = format!("https://localhost:{}/actor/-secret-/status", port);
+
+    let status: serde_json::Value =
+        serde_json::from_str(&get_request(&url).await.unwrap()).unwrap();
+    assert_eq!(status["version"], shared::consts::VERSION);
+    assert_eq!(status["is_ws_active"], false);
+    assert_eq!(status["pending_requests"], 0);
+    assert!(status["username"].is_null());
+
+    server_info.set_status_source(Arc::new(FakeStatus));
+    let (_client, _client_rx) = server_info.clients.register("user");
+    let status: serde_json::Value =
+        serde_json::from_str(&get_request(&url).await.unwrap()).unwrap();
+    assert_eq!(status["is_ws_active"], true);
+    assert_eq!(status["ws_clients"], 1);
+    assert_eq!(status["actor_type"], "unmanaged");
+    assert_eq!(status["username"], "user");
+    assert_eq!(status["session_type"], "RDP");
+
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_metrics() {
+    let (server_info, server_task, _port) = create_test_server_task("-secret-").await;
+    let metrics_port = NEXT_PORT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let stop = OnceSignal::new();
+    let metrics_task = tokio::spawn(serve_metrics(
+        server_info.clone(),
+        ([127, 0, 0, 1], metrics_port).into(),
+        stop.clone(),
+    ));
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let (client, _client_rx) = server_info.clients.register("user");
+    server_info.clients.set_stats(
+        client,
+        SessionStats {
+            idle: Some(42),
+            deadline: None,
+        },
+    );
+    shared::metrics::metrics().record_lagged(3);
+
+    // Plain http, not on the actor port
+    let resp = reqwest::get(format!("http://127.0.0.1:{}/metrics", metrics_port))
+        .await
+        .unwrap();
+    assert!(resp.status().is_success());
+    assert!(
+        resp.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain; version=0.0.4")
+    );
+    let body = resp.text().await.unwrap();
+    assert!(body.contains("udsactor_ws_clients 1\n"), "{body}");
+    assert!(body.contains("udsactor_ws_connected 1\n"), "{body}");
+    assert!(body.contains("udsactor_pending_requests 0\n"), "{body}");
+    assert!(body.contains(&format!(
+        "udsactor_session_idle_seconds{{client=\"{client}\",user=\"user\"}} 42\n"
+    )));
+    assert!(!body.contains("udsactor_session_deadline_remaining_seconds{"));
+    assert!(body.contains("# TYPE udsactor_broker_calls_total counter\n"));
+    assert!(body.contains("udsactor_broadcast_lagged_total "));
+
+    stop.set();
+    tokio::time::timeout(std::time::Duration::from_secs(2), metrics_task)
+        .await
+        .expect("Metrics server did not stop")
+        .unwrap()
+        .unwrap();
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_logs() {
+    let (_server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let url = format!("https://localhost:{}/actor/-secret-/logs", port);
+    log::log_buffer().push(
+        LogSource::Client,
+        log::Level::ERROR,
+        "client",
+        "remote logs test",
+    );
+
+    let records: serde_json::Value =
+        serde_json::from_str(&get_request(&format!("{}?level=error", url)).await.unwrap()).unwrap();
+    let records = records.as_array().unwrap();
+    assert!(records.iter().all(|r| r["level"] == "ERROR"));
+    assert!(records.iter().any(|r| r["message"] == "remote logs test"
+        && r["source"] == "client"
+        && r["target"] == "client"));
+
+    let since = chrono::Utc::now().timestamp() + 60;
+    let records = get_request(&format!("{}?since={}", url, since))
+        .await
+        .unwrap();
+    assert_eq!(records, "[]");
+
+    let resp = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+        .get(format!("{}?level=bogus", url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::BAD_REQUEST);
+
+    server_task.abort();
+}
+
 #[tokio::test]
 async fn test_post_logout() {
     let (server_info, server_task, port) = create_test_server_task("-secret-").await;
@@ -214,6 +414,9 @@ pub async fn test_post_message() {
         &format!("https://localhost:{}/actor/-secret-/message", port),
         &MessageRequest {
             message: "test message".into(),
+            title: Some("test title".into()),
+            require_ack: true,
+            ..Default::default()
         },
     )
     .await
@@ -226,6 +429,8 @@ pub async fn test_post_message() {
             .await
             .unwrap();
         assert_eq!(res.msg.message, "test message");
+        assert_eq!(res.msg.title.as_deref(), Some("test title"));
+        assert!(res.msg.require_ack);
     })
     .await
     .unwrap(); // Fail if timeout
@@ -246,6 +451,8 @@ pub async fn test_post_script() {
         &ScriptExecRequest {
             script_type: "script_type".into(),
             script: "test script".into(),
+            service: false,
+            timeout: None,
         },
     )
     .await
@@ -267,6 +474,59 @@ pub async fn test_post_script() {
     server_task.abort();
 }
 
+#[tokio::test]
+pub async fn test_post_script_wait() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+
+    let tracker = server_info.tracker.clone();
+    // Fake service worker that runs the script
+    tokio::spawn({
+        let mut rx = server_info.from_ws.subscribe();
+        async move {
+            if let Some(env) = wait_message_arrival::<ScriptExecRequest>(&mut rx, None).await
+                && let Some(id) = env.id
+            {
+                tracker
+                    .resolve_ok(
+                        id,
+                        RpcMessage::ScriptExecResponse(ScriptExecResponse {
+                            context: ScriptContext::Service,
+                            exit_code: Some(0),
+                            stdout: env.msg.script,
+                            stderr: String::new(),
+                            truncated: false,
+                            timed_out: false,
+                            duration: 0.5,
+                        }),
+                    )
+                    .await
+                    .ok();
+            }
+        }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let body = post_request(
+        &format!("https://localhost:{}/actor/-secret-/script?wait=true", port),
+        &ScriptExecRequest {
+            script_type: "sh".into(),
+            script: "echo hello".into(),
+            service: true,
+            timeout: Some(1),
+        },
+    )
+    .await
+    .unwrap();
+
+    let result: ScriptExecResponse =
+        serde_json::from_str(&body).unwrap_or_else(|_| panic!("Error on response:\n{body}"));
+    assert_eq!(result.context, ScriptContext::Service);
+    assert_eq!(result.exit_code, Some(0));
+    assert_eq!(result.stdout, "echo hello");
+
+    server_task.abort();
+}
+
 #[tokio::test]
 pub async fn test_post_pre_connect() {
     let (server_info, server_task, port) = create_test_server_task("-secret-").await;
@@ -323,6 +583,174 @@ async fn test_secret_invalid() {
     server_task.abort();
 }
 
+#[tokio::test]
+#[ignore = "Requires network access"]
+async fn test_allowed_networks() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let local_ip = local_ip().unwrap();
+    let url = format!("https://{}:{}/actor/-secret-/status", local_ip, port);
+    get_request(&url).await.unwrap();
+
+    server_info.set_allowed_networks(vec!["198.51.100.0/24".parse().unwrap()]);
+    let resp = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+    // Local requests are always allowed
+    get_request(&format!("https://localhost:{}/actor/-secret-/status", port))
+        .await
+        .unwrap();
+
+    // Invalid allowlist, remote clients are denied
+    server_info.set_local_only();
+    let resp = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+        .get(&url)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+    get_request(&format!("https://localhost:{}/actor/-secret-/status", port))
+        .await
+        .unwrap();
+
+    server_task.abort();
+}
+
+// Request signed as newer brokers do, without the secret on the path
+fn signed_request(
+    client: &Client,
+    method: reqwest::Method,
+    port: u16,
+    path: &str,
+    body: &str,
+    nonce: &str,
+) -> reqwest::RequestBuilder {
+    let timestamp = chrono::Utc::now().timestamp();
+    let signature = sign_request(
+        "-secret-",
+        method.as_str(),
+        path,
+        body.as_bytes(),
+        timestamp,
+        nonce,
+    );
+    client
+        .request(method, format!("https://localhost:{}{}", port, path))
+        .header(SIGNATURE_HEADER, signature)
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(NONCE_HEADER, nonce)
+        .header("content-type", "application/json")
+        .body(body.to_string())
+}
+
+#[tokio::test]
+async fn test_signed_requests() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let client = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let mut rx = server_info.from_ws.subscribe();
+
+    let resp = signed_request(
+        &client,
+        reqwest::Method::GET,
+        port,
+        "/actor/status",
+        "",
+        "n1",
+    )
+    .send()
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+    // Replayed
+    let resp = signed_request(
+        &client,
+        reqwest::Method::GET,
+        port,
+        "/actor/status",
+        "",
+        "n1",
+    )
+    .send()
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+    // Neither signed nor with secret
+    let resp = client
+        .get(format!("https://localhost:{}/actor/status", port))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::FORBIDDEN);
+
+    // Body reaches the handler once verified
+    let body = serde_json::to_string(&MessageRequest {
+        message: "signed message".into(),
+        ..Default::default()
+    })
+    .unwrap();
+    let resp = signed_request(
+        &client,
+        reqwest::Method::POST,
+        port,
+        "/actor/message",
+        &body,
+        "n2",
+    )
+    .send()
+    .await
+    .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::OK);
+    let env = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        wait_message_arrival::<MessageRequest>(&mut rx, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(env.msg.message, "signed message");
+
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_secret_brute_force_is_blocked() {
+    let (_server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let client = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let status = |secret: &'static str| {
+        let request = client
+            .get(format!(
+                "https://localhost:{}/actor/{}/status",
+                port, secret
+            ))
+            .send();
+        async move { request.await.unwrap().status() }
+    };
+    for _ in 0..10 {
+        assert_eq!(status("-wrong-").await, reqwest::StatusCode::FORBIDDEN);
+    }
+    // Blocked, even with the right secret
+    assert_eq!(
+        status("-secret-").await,
+        reqwest::StatusCode::TOO_MANY_REQUESTS
+    );
+
+    server_task.abort();
+}
+
 #[tokio::test]
 #[ignore = "Requires network access"]
 async fn test_ws_no_localhost_ipv4() {
@@ -381,16 +809,13 @@ async fn test_ws_connect_insecure_tls() {
     // Wait a moment for the server to start
     tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 
-    // Build the WebSocket URL (TLS enabled, but self-signed)
-    let url = format!("wss://localhost:{}/ws", port);
-
     // Create a connector that disables certificate verification
     let connector = Connector::Rustls(shared::tls::noverify::client_config());
 
-    // Perform the WebSocket handshake with custom TLS config
+    // Perform the WebSocket handshake with custom TLS config (TLS enabled, but self-signed)
     let (mut ws_stream, _resp) = connect_async_tls_with_config(
-        url,
-        None, // no additional request headers
+        ws_request(port, "testuser").await,
+        None, // no additional config
         true, // allow insecure
         Some(connector),
     )
@@ -415,6 +840,77 @@ async fn test_ws_connect_insecure_tls() {
     server_task.abort();
 }
 
+// Certificate presented by the server on a new TLS handshake
+async fn server_certificate(port: u16) -> Vec<u8> {
+    let connector = tokio_rustls::TlsConnector::from(shared::tls::noverify::client_config());
+    let tcp = tokio::net::TcpStream::connect(("localhost", port))
+        .await
+        .unwrap();
+    let tls = connector
+        .connect("localhost".try_into().unwrap(), tcp)
+        .await
+        .unwrap();
+    tls.get_ref().1.peer_certificates().unwrap()[0].to_vec()
+}
+
+fn certificate_der(cert_info: &shared::tls::CertificateInfo) -> Vec<u8> {
+    shared::tls::certool::certified_key_from_pem(cert_info)
+        .unwrap()
+        .0[0]
+        .to_vec()
+}
+
+#[tokio::test]
+#[ignore = "Requires network access"]
+async fn test_certificate_reload() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let mut rx = server_info.from_ws.subscribe();
+    let mut ws_stream = connect_ws_client(port, "testuser").await;
+    assert_eq!(
+        server_certificate(port).await,
+        certificate_der(&test_certs::test_certinfo())
+    );
+
+    // Broker sends a new one
+    let new_cert = test_certs::test_broker_certinfo();
+    post_request(
+        &format!("https://localhost:{}/actor/-secret-/certificate", port),
+        &new_cert,
+    )
+    .await
+    .unwrap();
+    assert_eq!(server_certificate(port).await, certificate_der(&new_cert));
+
+    // An invalid one is refused, and the last good one is kept
+    let mut invalid = test_certs::test_certinfo();
+    invalid.key = "not a key".into();
+    assert!(
+        post_request(
+            &format!("https://localhost:{}/actor/-secret-/certificate", port),
+            &invalid,
+        )
+        .await
+        .is_err()
+    );
+    assert_eq!(server_certificate(port).await, certificate_der(&new_cert));
+
+    // Connection established with the old certificate is still alive
+    ws_stream
+        .send(Message::Ping("still here".into()))
+        .await
+        .unwrap();
+    tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        wait_message_arrival::<Ping>(&mut rx, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(server_info.clients.connected(), 1);
+
+    server_task.abort();
+}
+
 #[tokio::test]
 #[ignore = "Requires network access"]
 async fn test_ws_msg_with_envelope_id() {
@@ -423,16 +919,13 @@ async fn test_ws_msg_with_envelope_id() {
     // Wait a moment for the server to start
     tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 
-    // Build the WebSocket URL (TLS enabled, but self-signed)
-    let url = format!("wss://localhost:{}/ws", port);
-
     // Create a connector that disables certificate verification
     let connector = Connector::Rustls(shared::tls::noverify::client_config());
 
-    // Perform the WebSocket handshake with custom TLS config
+    // Perform the WebSocket handshake with custom TLS config (TLS enabled, but self-signed)
     let (mut ws_stream, _resp) = connect_async_tls_with_config(
-        url,
-        None, // no additional request headers
+        ws_request(port, "testuser").await,
+        None, // no additional config
         true, // allow insecure
         Some(connector),
     )
@@ -447,6 +940,7 @@ async fn test_ws_msg_with_envelope_id() {
         serde_json::to_string(&RpcEnvelope {
             id: Some(id),
             msg: RpcMessage::Ping(Ping("ping".into())),
+            client: None,
         })
         .unwrap()
         .into(),
@@ -467,3 +961,479 @@ async fn test_ws_msg_with_envelope_id() {
 
     server_task.abort();
 }
+
+#[tokio::test]
+#[ignore = "Requires network access"]
+async fn test_ws_client_cannot_request_service_scripts() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let mut workers_rx = server_info.from_ws.subscribe();
+    let mut ws_stream = connect_ws_client(port, "testuser").await;
+
+    // A local user asking the service (root/SYSTEM) to run a script
+    let request = RpcEnvelope {
+        id: Some(42),
+        msg: RpcMessage::ScriptExecRequest(ScriptExecRequest {
+            script_type: "sh".into(),
+            script: "id".into(),
+            service: true,
+            ..Default::default()
+        }),
+        client: None,
+    };
+    ws_stream
+        .send(Message::Text(
+            serde_json::to_string(&request).unwrap().into(),
+        ))
+        .await
+        .unwrap();
+
+    let reply = next_text(&mut ws_stream, std::time::Duration::from_secs(3))
+        .await
+        .expect("No answer to the refused request");
+    let reply: RpcEnvelope<RpcMessage> = serde_json::from_str(&reply).unwrap();
+    assert_eq!(reply.id, Some(42));
+    let RpcMessage::Error(err) = reply.msg else {
+        panic!("Unexpected message: {:?}", reply.msg);
+    };
+    assert_eq!(err.code, ERR_FORBIDDEN);
+
+    // Never reaches the workers
+    let forwarded = tokio::time::timeout(
+        std::time::Duration::from_millis(500),
+        wait_message_arrival::<ScriptExecRequest>(&mut workers_rx, None),
+    )
+    .await;
+    assert!(forwarded.is_err());
+
+    server_task.abort();
+}
+
+async fn connect_ws_client(
+    port: u16,
+    user: &str,
+) -> tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>> {
+    let connector = Connector::Rustls(shared::tls::noverify::client_config());
+    let request = ws_request(port, user).await;
+    let (ws_stream, _resp) = connect_async_tls_with_config(request, None, true, Some(connector))
+        .await
+        .expect("WebSocket handshake failed");
+    ws_stream
+}
+
+// Next text message received, skipping control frames (Pongs for our pings)
+async fn next_text(
+    ws_stream: &mut tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >,
+    timeout: std::time::Duration,
+) -> Option<String> {
+    tokio::time::timeout(timeout, async {
+        while let Some(Ok(msg)) = ws_stream.next().await {
+            if let Message::Text(txt) = msg {
+                return Some(txt.to_string());
+            }
+        }
+        None
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+#[tokio::test]
+#[ignore = "Requires network access"]
+async fn test_ws_multiple_clients_routing() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let mut rx = server_info.from_ws.subscribe();
+
+    // Both clients are accepted
+    let mut client_a = connect_ws_client(port, "user-a").await;
+    let mut client_b = connect_ws_client(port, "user-b").await;
+
+    // Messages from each client are tagged with its id
+    let mut ids = Vec::new();
+    for ws_stream in [&mut client_a, &mut client_b] {
+        ws_stream
+            .send(Message::Ping("ping".into()))
+            .await
+            .expect("Failed to send message");
+        let env = tokio::time::timeout(
+            std::time::Duration::from_secs(3),
+            wait_message_arrival::<Ping>(&mut rx, None),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        ids.push(env.client.expect("Message not tagged with client"));
+    }
+    let (id_a, id_b) = (ids[0], ids[1]);
+    assert_ne!(id_a, id_b);
+    assert_eq!(server_info.clients.connected(), 2);
+
+    // Targeted message only reaches its client
+    server_info
+        .to_ws
+        .send(RpcEnvelope {
+            id: None,
+            msg: RpcMessage::LogoffRequest(LogoffRequest),
+            client: Some(id_b),
+        })
+        .await
+        .unwrap();
+    let msg = next_text(&mut client_b, std::time::Duration::from_secs(3))
+        .await
+        .unwrap();
+    assert!(msg.contains("LogoffRequest"), "{msg}");
+    assert!(
+        next_text(&mut client_a, std::time::Duration::from_millis(300))
+            .await
+            .is_none()
+    );
+
+    // Broker requests can target a session
+    server_info.clients.set_session(
+        id_a,
+        Some(SessionIdentity {
+            username: "user-a".into(),
+            session_type: "RDP".into(),
+            session_id: Some("session-a".into()),
+        }),
+    );
+    post_request(
+        &format!(
+            "https://localhost:{}/actor/-secret-/logout?session_id=session-a",
+            port
+        ),
+        &(),
+    )
+    .await
+    .unwrap();
+    let env = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        wait_message_arrival::<LogoffRequest>(&mut rx, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(env.client, Some(id_a));
+
+    // Unknown session
+    assert!(
+        post_request(
+            &format!(
+                "https://localhost:{}/actor/-secret-/logout?session_id=other",
+                port
+            ),
+            &(),
+        )
+        .await
+        .is_err()
+    );
+
+    // Closing a client sends a tagged Close and unregisters it
+    client_a.close(None).await.unwrap();
+    let env = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        wait_message_arrival::<Close>(&mut rx, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(env.client, Some(id_a));
+    assert_eq!(server_info.clients.connected(), 1);
+
+    server_task.abort();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_ws_unix_socket_peer_credentials() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let mut rx = server_info.from_ws.subscribe();
+
+    let path = shared::ws::unix_socket::socket_path(port);
+    assert!(path.exists(), "Unix socket not created");
+
+    // No token needed, the peer uid identifies us
+    let stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    let (mut ws_stream, _resp) = tokio_tungstenite::client_async("ws://localhost/ws", stream)
+        .await
+        .expect("WebSocket handshake failed");
+    ws_stream
+        .send(Message::Ping("ping".into()))
+        .await
+        .expect("Failed to send message");
+    let env = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        wait_message_arrival::<Ping>(&mut rx, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    let client = env.client.expect("Message not tagged with client");
+    let user = shared::unix::username_from_uid(unsafe { libc::getuid() }).unwrap();
+    assert!(server_info.clients.is_user(client, &user));
+
+    server_task.abort();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_ws_client_prefers_unix_socket() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let mut rx = server_info.from_ws.subscribe();
+    let user = shared::unix::username_from_uid(unsafe { libc::getuid() }).unwrap();
+
+    let ws_client = websocket_client_tasks(port, 32, &user, &["LogoffRequest"])
+        .await
+        .unwrap();
+    // Handshake done, both sides know each other
+    assert!(ws_client.service_supports("LoginRequest"));
+    assert!(!ws_client.service_supports("ScreenshotRequest"));
+    ws_client
+        .to_ws
+        .send(RpcEnvelope {
+            id: None,
+            msg: RpcMessage::Ping(Ping(b"unix".to_vec())),
+            client: None,
+        })
+        .await
+        .unwrap();
+    let env = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        wait_message_arrival::<Ping>(&mut rx, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(server_info.clients.is_user(env.client.unwrap(), &user));
+    assert_eq!(server_info.clients.connected(), 1);
+
+    server_task.abort();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+#[ignore = "Requires network access"]
+async fn test_ws_client_falls_back_to_tcp() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let mut rx = server_info.from_ws.subscribe();
+
+    // Without the socket, the client goes through TLS with its session credential
+    std::fs::remove_file(shared::ws::unix_socket::socket_path(port)).unwrap();
+    let ws_client = websocket_client_tasks(port, 32, "tcp-user", &["LogoffRequest"])
+        .await
+        .unwrap();
+    assert!(ws_client.service().is_some());
+    ws_client
+        .to_ws
+        .send(RpcEnvelope {
+            id: None,
+            msg: RpcMessage::Ping(Ping(b"tcp".to_vec())),
+            client: None,
+        })
+        .await
+        .unwrap();
+    let env = tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        wait_message_arrival::<Ping>(&mut rx, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(server_info.clients.is_user(env.client.unwrap(), "tcp-user"));
+
+    server_task.abort();
+}
+
+#[tokio::test]
+#[ignore = "Requires network access"]
+async fn test_ws_hello_handshake() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let mut rx = server_info.from_ws.subscribe();
+    let mut ws_stream = connect_ws_client(port, "user").await;
+
+    let hello = RpcEnvelope {
+        id: None,
+        msg: RpcMessage::Hello(Hello(PeerInfo::current(&["LogoffRequest"]))),
+        client: None,
+    };
+    ws_stream
+        .send(Message::Text(serde_json::to_string(&hello).unwrap().into()))
+        .await
+        .unwrap();
+    let reply = next_text(&mut ws_stream, std::time::Duration::from_secs(3))
+        .await
+        .unwrap();
+    let env: RpcEnvelope<RpcMessage> = serde_json::from_str(&reply).unwrap();
+    let RpcMessage::HelloAck(HelloAck(service)) = env.msg else {
+        panic!("Unexpected reply: {reply}");
+    };
+    assert_eq!(service.protocol_version, PROTOCOL_VERSION);
+    assert!(service.supports("LoginRequest"));
+
+    // Handshake is not for the workers
+    assert!(
+        tokio::time::timeout(
+            std::time::Duration::from_millis(300),
+            wait_message_arrival::<Hello>(&mut rx, None)
+        )
+        .await
+        .is_err()
+    );
+
+    // Client said it cannot take screenshots, so do not wait for it
+    let client = Client::builder()
+        .danger_accept_invalid_certs(true)
+        .timeout(std::time::Duration::from_secs(3))
+        .build()
+        .unwrap();
+    let resp = client
+        .get(format!(
+            "https://localhost:{}/actor/-secret-/screenshot",
+            port
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(resp.status(), reqwest::StatusCode::NOT_IMPLEMENTED);
+
+    // Unknown requests get an error back instead of silence
+    ws_stream
+        .send(Message::Text(
+            r#"{"id": 42, "kind": "FutureRequest", "msg": {}}"#.into(),
+        ))
+        .await
+        .unwrap();
+    let reply = next_text(&mut ws_stream, std::time::Duration::from_secs(3))
+        .await
+        .unwrap();
+    let env: RpcEnvelope<RpcMessage> = serde_json::from_str(&reply).unwrap();
+    assert_eq!(env.id, Some(42));
+    assert!(
+        matches!(env.msg, RpcMessage::Error(ref e) if e.code == ERR_NOT_SUPPORTED),
+        "{reply}"
+    );
+
+    server_task.abort();
+}
+
+#[tokio::test]
+#[ignore = "Requires network access"]
+async fn test_ws_hello_refuses_old_protocol() {
+    let (server_info, server_task, port) = create_test_server_task("-secret-").await;
+    let mut ws_stream = connect_ws_client(port, "user").await;
+
+    let mut peer = PeerInfo::current(&["LogoffRequest"]);
+    peer.protocol_version = MIN_PROTOCOL_VERSION - 1;
+    let hello = RpcEnvelope {
+        id: None,
+        msg: RpcMessage::Hello(Hello(peer)),
+        client: None,
+    };
+    ws_stream
+        .send(Message::Text(serde_json::to_string(&hello).unwrap().into()))
+        .await
+        .unwrap();
+    let reply = next_text(&mut ws_stream, std::time::Duration::from_secs(3))
+        .await
+        .unwrap();
+    let env: RpcEnvelope<RpcMessage> = serde_json::from_str(&reply).unwrap();
+    assert!(
+        matches!(env.msg, RpcMessage::Error(ref e) if e.code == ERR_PROTOCOL_MISMATCH),
+        "{reply}"
+    );
+
+    // And the connection is closed
+    let closed = tokio::time::timeout(std::time::Duration::from_secs(3), async {
+        while let Some(Ok(msg)) = ws_stream.next().await {
+            if msg.is_close() {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok());
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(server_info.clients.connected(), 0);
+
+    server_task.abort();
+}
+
+#[tokio::test]
+async fn test_ws_client_reconnects_and_resumes_session() {
+    let port = NEXT_PORT.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let (server_info, server_task, stop) = create_test_server_on_port("-secret-", port).await;
+    let user = shared::system::new_system().get_current_user().unwrap();
+
+    let ws_client = websocket_client_tasks(port, 32, &user, &["LogoffRequest"])
+        .await
+        .unwrap();
+    let session = ResumeSession {
+        username: user.clone(),
+        session_type: "test".into(),
+        session_id: Some("session-1".into()),
+    };
+    ws_client.set_session(Some(session.clone()));
+    let mut client_rx = ws_client.from_ws.subscribe();
+    assert_eq!(server_info.clients.connected(), 1);
+
+    // Service restarts
+    stop.set();
+    let _ = tokio::time::timeout(std::time::Duration::from_secs(5), server_task).await;
+    let (server_info, server_task, _stop) = create_test_server_on_port("-secret-", port).await;
+    let mut rx = server_info.from_ws.subscribe();
+
+    // Client comes back by itself, announcing its session
+    let env = tokio::time::timeout(
+        std::time::Duration::from_secs(10),
+        wait_message_arrival::<ResumeSession>(&mut rx, None),
+    )
+    .await
+    .expect("Client did not reconnect")
+    .unwrap();
+    assert_eq!(env.msg, session);
+    assert!(server_info.clients.is_user(env.client.unwrap(), &user));
+
+    // Messages keep flowing both ways
+    ws_client
+        .to_ws
+        .send(RpcEnvelope {
+            id: None,
+            msg: RpcMessage::Ping(Ping(b"again".to_vec())),
+            client: None,
+        })
+        .await
+        .unwrap();
+    tokio::time::timeout(
+        std::time::Duration::from_secs(3),
+        wait_message_arrival::<Ping>(&mut rx, None),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    server_info
+        .to_ws
+        .send(RpcEnvelope {
+            id: None,
+            msg: RpcMessage::LogoffRequest(LogoffRequest),
+            client: None,
+        })
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    tokio::time::timeout(std::time::Duration::from_secs(3), async {
+        while let Ok(env) = client_rx.recv().await {
+            received.push(env.msg.kind());
+            if matches!(env.msg, RpcMessage::LogoffRequest(_)) {
+                break;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    // And the client was never told the connection was closed
+    assert!(!received.contains(&"Close"), "{received:?}");
+
+    server_task.abort();
+}